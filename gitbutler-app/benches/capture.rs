@@ -0,0 +1,229 @@
+// benchmarks of the capture pipeline: building the working directory tree and flushing a
+// session, on projects of different sizes.
+//
+//     cargo bench -p gitbutler-app --bench capture
+//
+// the 100k files projects take a while to set up, filter them out with a pattern when iterating,
+// for example `cargo bench --bench capture -- /10000`.
+
+use std::{path, time};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use gblib::{gb_repository, git, project_repository, projects};
+
+const SIZES: &[usize] = &[100, 10_000, 100_000];
+// files per directory, so that trees are nested like they are in real projects
+const FILES_PER_DIR: usize = 100;
+const LARGE_FILES: usize = 10;
+const LARGE_FILE_SIZE: usize = 4 * 1024 * 1024;
+const LFS_THRESHOLD: usize = 1024 * 1024;
+
+struct Fixture {
+    // kept so that the directories are removed when the fixture is dropped
+    _project_dir: tempfile::TempDir,
+    _data_dir: tempfile::TempDir,
+    project_path: path::PathBuf,
+    file_paths: Vec<path::PathBuf>,
+    project_repository: project_repository::Repository,
+    gb_repository: gb_repository::Repository,
+}
+
+impl Fixture {
+    fn new(size: usize, large_files: bool) -> Self {
+        let project_dir = tempfile::tempdir().expect("failed to create project dir");
+        let data_dir = tempfile::tempdir().expect("failed to create data dir");
+        let project_path = project_dir.path().to_path_buf();
+
+        let repository = git::Repository::init(&project_path).expect("failed to init repository");
+        let tree_oid = repository
+            .index()
+            .expect("failed to get index")
+            .write_tree()
+            .expect("failed to write tree");
+        let signature = git::Signature::now("test", "test@email.com").unwrap();
+        repository
+            .commit(
+                Some(&"refs/heads/master".parse().unwrap()),
+                &signature,
+                &signature,
+                "Initial commit",
+                &repository.find_tree(tree_oid).unwrap(),
+                &[],
+            )
+            .expect("failed to commit");
+        let mut config = repository.config().expect("failed to open config");
+        config
+            .set_str("gitbutler.lfsThreshold", &LFS_THRESHOLD.to_string())
+            .unwrap();
+
+        let mut file_paths = Vec::with_capacity(size);
+        for i in 0..size {
+            let file_path = path::PathBuf::from(format!("dir-{}/file-{i}.txt", i / FILES_PER_DIR));
+            let abs_path = project_path.join(&file_path);
+            if i % FILES_PER_DIR == 0 {
+                std::fs::create_dir_all(abs_path.parent().unwrap()).unwrap();
+            }
+            std::fs::write(&abs_path, format!("content of file {i}\n").repeat(16)).unwrap();
+            file_paths.push(file_path);
+        }
+        if large_files {
+            std::fs::create_dir_all(project_path.join("large")).unwrap();
+            for i in 0..LARGE_FILES {
+                let content = (0..LARGE_FILE_SIZE)
+                    .map(|byte| u8::try_from((byte * 31 + i) % 251).unwrap())
+                    .collect::<Vec<_>>();
+                std::fs::write(project_path.join(format!("large/file-{i}.bin")), content).unwrap();
+            }
+        }
+
+        let projects = projects::Controller::try_from(&data_dir.path().to_path_buf())
+            .expect("failed to open projects");
+        let project = projects.add(&project_path).expect("failed to add project");
+        let project_repository =
+            project_repository::Repository::open(&project).expect("failed to open project");
+        let gb_repository =
+            gb_repository::Repository::open(data_dir.path(), &project_repository, None)
+                .expect("failed to open gitbutler repository");
+
+        Self {
+            _project_dir: project_dir,
+            _data_dir: data_dir,
+            project_path,
+            file_paths,
+            project_repository,
+            gb_repository,
+        }
+    }
+
+    fn set_index_cache(&self, enabled: bool) {
+        self.project_repository
+            .git_repository
+            .config()
+            .unwrap()
+            .set_bool("gitbutler.indexCache", enabled)
+            .unwrap();
+    }
+
+    // the index cache skips files that were modified just now, so files are backdated to make
+    // them cacheable. every call uses a different time, so that the files look changed.
+    fn backdate(&self, file_paths: &[path::PathBuf], generation: u64) {
+        let mtime = filetime::FileTime::from_system_time(
+            time::SystemTime::now() - time::Duration::from_secs(3600 + generation),
+        );
+        for file_path in file_paths {
+            filetime::set_file_mtime(self.project_path.join(file_path), mtime).unwrap();
+        }
+    }
+
+    fn build_live_wd_tree(&self) -> git::Oid {
+        self.gb_repository
+            .build_live_wd_tree(&self.project_repository)
+            .expect("failed to build wd tree")
+    }
+}
+
+fn variants() -> impl Iterator<Item = (usize, bool)> {
+    SIZES
+        .iter()
+        .flat_map(|size| [(*size, false), (*size, true)])
+}
+
+fn parameter(size: usize, large_files: bool) -> String {
+    if large_files {
+        format!("{size}+large")
+    } else {
+        size.to_string()
+    }
+}
+
+fn build_wd_tree(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build_wd_tree");
+    group.sample_size(10);
+    for (size, large_files) in variants() {
+        let fixture = Fixture::new(size, large_files);
+        group.bench_function(
+            BenchmarkId::from_parameter(parameter(size, large_files)),
+            |b| {
+                b.iter(|| fixture.build_live_wd_tree());
+            },
+        );
+    }
+    group.finish();
+}
+
+// a session with a few changed files, like a flush after a burst of edits
+fn flush(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flush");
+    group.sample_size(10);
+    for (size, large_files) in variants() {
+        let fixture = Fixture::new(size, large_files);
+        let mut generation = 0;
+        group.bench_function(
+            BenchmarkId::from_parameter(parameter(size, large_files)),
+            |b| {
+                b.iter_batched(
+                    || {
+                        generation += 1;
+                        for file_path in fixture.file_paths.iter().take(10) {
+                            std::fs::write(
+                                fixture.project_path.join(file_path),
+                                format!("changed {generation}\n"),
+                            )
+                            .unwrap();
+                        }
+                        fixture
+                            .gb_repository
+                            .get_or_create_current_session()
+                            .expect("failed to create session")
+                    },
+                    |session| {
+                        fixture
+                            .gb_repository
+                            .flush_session(&fixture.project_repository, &session, None)
+                            .expect("failed to flush session")
+                    },
+                    BatchSize::PerIteration,
+                );
+            },
+        );
+    }
+    group.finish();
+}
+
+// the metadata fast path of gitbutler.indexCache, with none, some and all of the files changed
+// since the last capture. `disabled` is the baseline without the cache.
+fn index_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_cache");
+    group.sample_size(10);
+    for size in SIZES {
+        let fixture = Fixture::new(*size, false);
+        fixture.backdate(&fixture.file_paths, 0);
+
+        fixture.set_index_cache(false);
+        group.bench_function(BenchmarkId::new("disabled", size), |b| {
+            b.iter(|| fixture.build_live_wd_tree());
+        });
+
+        fixture.set_index_cache(true);
+        // warms the cache up
+        fixture.build_live_wd_tree();
+        for (name, changed_percent) in [("hit-100", 0), ("hit-90", 10), ("hit-0", 100)] {
+            let changed = &fixture.file_paths[..fixture.file_paths.len() * changed_percent / 100];
+            let mut generation = 0;
+            group.bench_function(BenchmarkId::new(name, size), |b| {
+                b.iter_batched(
+                    || {
+                        generation += 1;
+                        fixture.backdate(changed, generation);
+                    },
+                    |()| fixture.build_live_wd_tree(),
+                    BatchSize::PerIteration,
+                );
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, build_wd_tree, flush, index_cache);
+criterion_main!(benches);
@@ -180,6 +180,9 @@ fn main() {
                     commands::git_set_global_config,
                     commands::git_get_global_config,
                     commands::project_flush_and_push,
+                    commands::project_flush_status,
+                    commands::project_capture_stats,
+                    commands::scan_stats,
                     zip::commands::get_logs_archive_path,
                     zip::commands::get_project_archive_path,
                     zip::commands::get_project_data_archive_path,
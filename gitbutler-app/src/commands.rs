@@ -7,7 +7,7 @@ use tracing::instrument;
 use crate::{
     app,
     error::{Code, Error},
-    gb_repository, git, project_repository, projects, reader,
+    fs, gb_repository, git, project_repository, projects, reader,
     sessions::SessionId,
     users, watcher,
 };
@@ -134,6 +134,50 @@ pub async fn git_get_global_config(
     Ok(result)
 }
 
+#[tauri::command(async)]
+#[instrument(skip(handle))]
+pub async fn project_flush_status(
+    handle: tauri::AppHandle,
+    id: &str,
+) -> Result<watcher::FlushStatus, Error> {
+    let project_id = id.parse().map_err(|_| Error::UserError {
+        code: Code::Validation,
+        message: "Malformed project id".into(),
+    })?;
+
+    let status = handle
+        .state::<watcher::Watchers>()
+        .flush_status(&project_id)
+        .context("failed to get flush status")?;
+    Ok(status)
+}
+
+#[tauri::command(async)]
+#[instrument(skip(handle))]
+pub async fn project_capture_stats(
+    handle: tauri::AppHandle,
+    id: &str,
+) -> Result<Option<gb_repository::CaptureStats>, Error> {
+    let project_id = id.parse().map_err(|_| Error::UserError {
+        code: Code::Validation,
+        message: "Malformed project id".into(),
+    })?;
+
+    let stats = handle
+        .state::<watcher::Watchers>()
+        .capture_stats(&project_id)
+        .context("failed to get capture stats")?;
+    Ok(stats)
+}
+
+// a cheap look at a directory before it's added as a project
+#[tauri::command(async)]
+#[instrument]
+pub async fn scan_stats(path: &path::Path) -> Result<fs::ScanStats, Error> {
+    let stats = fs::scan_stats(path).context("failed to scan directory")?;
+    Ok(stats)
+}
+
 #[tauri::command(async)]
 #[instrument(skip(handle))]
 pub async fn project_flush_and_push(handle: tauri::AppHandle, id: &str) -> Result<(), Error> {
@@ -0,0 +1 @@
+ALTER TABLE `sessions` ADD `sparse_checkout` TEXT;
@@ -6,7 +6,7 @@ use crate::{
     projects::ProjectId,
     reader,
     sessions::{self, SessionId},
-    virtual_branches,
+    virtual_branches, watcher,
 };
 
 #[derive(Clone)]
@@ -106,6 +106,29 @@ impl Event {
         }
     }
 
+    pub fn file_changed(
+        project_id: &ProjectId,
+        relative_file_path: &std::path::Path,
+        change: &str,
+    ) -> Self {
+        Event {
+            name: format!("project://{}/file_changed", project_id),
+            payload: serde_json::json!({
+                "filePath": relative_file_path,
+                "change": change,
+            }),
+            project_id: *project_id,
+        }
+    }
+
+    pub fn secrets(project_id: &ProjectId, file_paths: &[std::path::PathBuf]) -> Self {
+        Event {
+            name: format!("project://{}/secrets", project_id),
+            payload: serde_json::json!({ "filePaths": file_paths }),
+            project_id: *project_id,
+        }
+    }
+
     pub fn session(project_id: &ProjectId, session: &sessions::Session) -> Self {
         Event {
             name: format!("project://{}/sessions", project_id),
@@ -114,6 +137,38 @@ impl Event {
         }
     }
 
+    pub fn session_skipped(
+        project_id: &ProjectId,
+        session_id: &SessionId,
+        reason: &watcher::SkipReason,
+    ) -> Self {
+        Event {
+            name: format!("project://{}/skipped", project_id),
+            payload: serde_json::json!({
+                "sessionId": session_id,
+                "reason": reason,
+            }),
+            project_id: *project_id,
+        }
+    }
+
+    // the watcher stopped capturing because the project directory is not available
+    pub fn watcher_suspended(project_id: &ProjectId, path: &std::path::Path) -> Self {
+        Event {
+            name: format!("project://{}/watcher/suspended", project_id),
+            payload: serde_json::json!({ "path": path }),
+            project_id: *project_id,
+        }
+    }
+
+    pub fn watcher_resumed(project_id: &ProjectId) -> Self {
+        Event {
+            name: format!("project://{}/watcher/resumed", project_id),
+            payload: serde_json::json!({}),
+            project_id: *project_id,
+        }
+    }
+
     pub fn deltas(
         project_id: &ProjectId,
         session_id: &SessionId,
@@ -1,30 +1,367 @@
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use walkdir::WalkDir;
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{git, project_repository};
+
+// directories nested deeper than this are not listed by default
+pub const DEFAULT_MAX_DEPTH: usize = 1024;
 
 // Returns an ordered list of relative paths for files inside a directory recursively.
 pub fn list_files<P: AsRef<Path>>(dir_path: P, ignore_prefixes: &[P]) -> Result<Vec<PathBuf>> {
+    list_files_with_max_depth(dir_path, ignore_prefixes, DEFAULT_MAX_DEPTH)
+}
+
+// Same as list_files, but skips directories that are nested deeper than max_depth.
+//
+// Directories are walked with an explicit queue instead of recursion, so any depth is safe.
+pub fn list_files_with_max_depth<P: AsRef<Path>>(
+    dir_path: P,
+    ignore_prefixes: &[P],
+    max_depth: usize,
+) -> Result<Vec<PathBuf>> {
+    walk_files(dir_path, ignore_prefixes, max_depth, &|_| false)
+}
+
+// Same as list_files, but directories for which skip_dir returns true are not walked into. It
+// gets relative paths of directories.
+//
+// This is how ignored directories are left out: checking every file in them instead is slow on
+// big ignored trees, and git doesn't look into them either.
+pub fn list_files_pruned<P: AsRef<Path>>(
+    dir_path: P,
+    ignore_prefixes: &[P],
+    skip_dir: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    walk_files(dir_path, ignore_prefixes, DEFAULT_MAX_DEPTH, skip_dir)
+}
+
+fn walk_files<P: AsRef<Path>>(
+    dir_path: P,
+    ignore_prefixes: &[P],
+    max_depth: usize,
+    skip_dir: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
     let mut files = vec![];
     let dir_path = dir_path.as_ref();
     if !dir_path.exists() {
         return Ok(files);
     }
-    for entry in WalkDir::new(dir_path) {
-        let entry = entry?;
-        if !entry.file_type().is_dir() {
-            let path = entry.path();
-            let path = path.strip_prefix(dir_path)?;
-            let path = path.to_path_buf();
-            if ignore_prefixes
-                .iter()
-                .any(|prefix| path.starts_with(prefix.as_ref()))
-            {
+
+    let is_ignored = |path: &Path| {
+        ignore_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_ref()))
+    };
+
+    // relative paths of directories left to visit, together with their depth
+    let mut queue = vec![(PathBuf::new(), 0_usize)];
+    while let Some((relative_dir_path, depth)) = queue.pop() {
+        let absolute_dir_path = dir_path.join(&relative_dir_path);
+        let entries = std::fs::read_dir(&absolute_dir_path)
+            .with_context(|| format!("failed to read {}", absolute_dir_path.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let path = relative_dir_path.join(entry.file_name());
+            if is_ignored(&path) {
                 continue;
             }
-            files.push(path);
+            // file_type doesn't follow symlinks, so links to directories are listed as files
+            if entry.file_type()?.is_dir() {
+                if skip_dir(&path) {
+                    continue;
+                }
+                if depth < max_depth {
+                    queue.push((path, depth + 1));
+                } else {
+                    tracing::warn!(
+                        path = %dir_path.join(&path).display(),
+                        max_depth,
+                        "directory is nested too deep, skipping"
+                    );
+                }
+            } else {
+                files.push(path);
+            }
         }
     }
+
     files.sort();
     Ok(files)
 }
+
+// Returns an ordered list of relative paths for directories inside a directory recursively
+// that have nothing in them. Like in list_files_pruned, directories for which skip_dir returns
+// true are not walked into, and not listed.
+pub fn list_empty_dirs<P: AsRef<Path>>(
+    dir_path: P,
+    ignore_prefixes: &[P],
+    skip_dir: &dyn Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![];
+    let dir_path = dir_path.as_ref();
+    if !dir_path.exists() {
+        return Ok(dirs);
+    }
+
+    let is_ignored = |path: &Path| {
+        ignore_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_ref()))
+    };
+
+    let mut queue = vec![(PathBuf::new(), 0_usize)];
+    while let Some((relative_dir_path, depth)) = queue.pop() {
+        let absolute_dir_path = dir_path.join(&relative_dir_path);
+        let mut is_empty = true;
+        for entry in std::fs::read_dir(&absolute_dir_path)
+            .with_context(|| format!("failed to read {}", absolute_dir_path.display()))?
+        {
+            let entry = entry?;
+            is_empty = false;
+            let path = relative_dir_path.join(entry.file_name());
+            if entry.file_type()?.is_dir()
+                && !is_ignored(&path)
+                && !skip_dir(&path)
+                && depth < DEFAULT_MAX_DEPTH
+            {
+                queue.push((path, depth + 1));
+            }
+        }
+        // the root is never listed, it's the directory that is being listed
+        if is_empty && depth > 0 {
+            dirs.push(relative_dir_path);
+        }
+    }
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+// Returns the total size of the files inside a directory recursively. Symlinks are not followed,
+// their size is the size of the target path.
+pub fn dir_size<P: AsRef<Path>>(dir_path: P) -> Result<u64> {
+    let mut size = 0;
+    let mut queue = vec![dir_path.as_ref().to_path_buf()];
+    while let Some(dir_path) = queue.pop() {
+        let entries = match std::fs::read_dir(&dir_path) {
+            Ok(entries) => entries,
+            // removed while walking
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read {}", dir_path.display()))
+            }
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(error).with_context(|| {
+                        format!("failed to read metadata of {}", entry.path().display())
+                    })
+                }
+            };
+            if metadata.is_dir() {
+                queue.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanStats {
+    pub file_count: usize,
+    pub total_bytes: u64,
+    // files that would be stored as lfs objects
+    pub lfs_file_count: usize,
+}
+
+// Returns what capturing the directory would have to deal with, without hashing anything.
+//
+// Files are walked like they are when the working directory is captured. When the directory is
+// a git repository, ignored files are skipped and the lfs threshold is read from its config.
+pub fn scan_stats<P: AsRef<Path>>(dir_path: P) -> Result<ScanStats> {
+    let dir_path = dir_path.as_ref();
+    let git_repository = git::Repository::open(dir_path).ok();
+    let lfs_threshold = match &git_repository {
+        Some(git_repository) => project_repository::resolve_lfs_threshold(
+            &project_repository::Config::from(git_repository),
+        )?,
+        None => project_repository::DEFAULT_LFS_THRESHOLD,
+    };
+
+    let is_ignored_dir = |dir: &Path| {
+        git_repository.as_ref().map_or(false, |git_repository| {
+            git_repository
+                .is_path_ignored(dir.join(""))
+                .unwrap_or(false)
+        })
+    };
+    let mut stats = ScanStats::default();
+    for file_path in list_files_pruned(dir_path, &[Path::new(".git")], &is_ignored_dir)? {
+        if let Some(git_repository) = &git_repository {
+            if git_repository.is_path_ignored(&file_path).unwrap_or(true) {
+                continue;
+            }
+        }
+        // symlinks are captured as links, their size is the size of the target path
+        let metadata = match std::fs::symlink_metadata(dir_path.join(&file_path)) {
+            Ok(metadata) => metadata,
+            // removed while walking
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("failed to read metadata of {}", file_path.display()))
+            }
+        };
+        stats.file_count += 1;
+        stats.total_bytes += metadata.len();
+        if metadata.len() > lfs_threshold || metadata.len() > project_repository::GIT_MAX_BLOB_SIZE
+        {
+            stats.lfs_file_count += 1;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("a/b"))?;
+        std::fs::create_dir_all(dir.path().join(".git"))?;
+        std::fs::write(dir.path().join("a/b/file.txt"), "")?;
+        std::fs::write(dir.path().join("a/file.txt"), "")?;
+        std::fs::write(dir.path().join("file.txt"), "")?;
+        std::fs::write(dir.path().join(".git/HEAD"), "")?;
+
+        assert_eq!(
+            list_files(dir.path(), &[Path::new(".git")])?,
+            vec![
+                PathBuf::from("a/b/file.txt"),
+                PathBuf::from("a/file.txt"),
+                PathBuf::from("file.txt"),
+            ]
+        );
+        assert!(list_files(dir.path().join("missing"), &[])?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_files_pruned() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("build/nested"))?;
+        std::fs::create_dir_all(dir.path().join("src/build"))?;
+        std::fs::write(dir.path().join("build/nested/out.o"), "")?;
+        std::fs::write(dir.path().join("src/build/main.rs"), "")?;
+        std::fs::write(dir.path().join("build.rs"), "")?;
+
+        // pruned directories are left out with everything in them, paths that only start the same
+        // are listed
+        assert_eq!(
+            list_files_pruned(dir.path(), &[], &|dir| dir == Path::new("build"))?,
+            vec![
+                PathBuf::from("build.rs"),
+                PathBuf::from("src/build/main.rs")
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_empty_dirs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("a/b"))?;
+        std::fs::create_dir_all(dir.path().join("logs"))?;
+        std::fs::create_dir_all(dir.path().join("src"))?;
+        std::fs::create_dir_all(dir.path().join(".git/refs"))?;
+        std::fs::create_dir_all(dir.path().join("node_modules/empty"))?;
+        std::fs::write(dir.path().join("src/main.rs"), "")?;
+
+        assert_eq!(
+            list_empty_dirs(dir.path(), &[Path::new(".git")], &|dir| {
+                dir == Path::new("node_modules")
+            })?,
+            vec![PathBuf::from("a/b"), PathBuf::from("logs")]
+        );
+        assert!(list_empty_dirs(dir.path().join("logs"), &[], &|_| false)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_size() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("a/b"))?;
+        std::fs::write(dir.path().join("a/b/file.txt"), "12345")?;
+        std::fs::write(dir.path().join("a/file.txt"), "123")?;
+        std::fs::write(dir.path().join("file.txt"), "1")?;
+
+        assert_eq!(dir_size(dir.path())?, 9);
+        assert_eq!(dir_size(dir.path().join("a"))?, 8);
+        assert_eq!(dir_size(dir.path().join("missing"))?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_files_deep() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let depth = 1000;
+        let deep_dir = (0..depth).fold(PathBuf::new(), |path, _| path.join("d"));
+        std::fs::create_dir_all(dir.path().join(&deep_dir))?;
+        std::fs::write(dir.path().join(&deep_dir).join("file.txt"), "")?;
+        std::fs::write(dir.path().join("file.txt"), "")?;
+
+        assert_eq!(
+            list_files(dir.path(), &[])?,
+            vec![deep_dir.join("file.txt"), PathBuf::from("file.txt")]
+        );
+
+        // deeper directories are skipped
+        assert_eq!(
+            list_files_with_max_depth(dir.path(), &[], 10)?,
+            vec![PathBuf::from("file.txt")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_stats() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let git_repository = git::Repository::init(dir.path())?;
+        git_repository
+            .config()?
+            .set_str("gitbutler.lfsThreshold", "10")?;
+        std::fs::write(dir.path().join(".gitignore"), "target\n")?;
+        std::fs::create_dir_all(dir.path().join("src"))?;
+        std::fs::create_dir_all(dir.path().join("target"))?;
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}")?;
+        std::fs::write(dir.path().join("small.txt"), "small")?;
+        std::fs::write(dir.path().join("target/build.bin"), "ignored build output")?;
+
+        assert_eq!(
+            scan_stats(dir.path())?,
+            ScanStats {
+                file_count: 3,
+                total_bytes: 7 + 12 + 5,
+                lfs_file_count: 1,
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -1,6 +1,21 @@
+mod anonymize;
+mod clock;
+mod conflicts;
+mod index_cache;
+mod line_endings;
+mod pending;
 mod repository;
+mod secrets;
+mod status_command;
+mod store;
+mod trailers;
 
 #[cfg(test)]
 mod repository_tests;
 
-pub use repository::{Error, RemoteError, Repository};
+pub(crate) use anonymize::first_parent_history;
+pub use clock::{Clock, MockClock, SystemClock};
+pub(crate) use pending::queued_trees;
+pub use repository::{CaptureStats, Error, RemoteError, Repository};
+pub use store::SessionStore;
+pub use trailers::{TrailerContext, TrailerProvider};
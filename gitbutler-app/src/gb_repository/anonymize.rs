@@ -0,0 +1,317 @@
+// session commits are pushed and exported rewritten to their first parent chain. anchored sessions
+// carry the project head as an extra parent, which would upload the whole project history with
+// them. the local history keeps its extra parents.
+//
+// when gitbutler.pushAuthorName and gitbutler.pushAuthorEmail are configured, pushed commits are
+// also rewritten with that anonymous author and committer instead of the identity they were
+// committed with.
+//
+// the rewrite is deterministic, commits keep their times, messages and trees, so the same history
+// is always rewritten into the same commits and pushes fast-forward. sessions without extra
+// parents that keep their identity are rewritten into themselves.
+
+use anyhow::{Context, Result};
+
+use crate::git;
+
+// the rewritten history that is pushed, and the commit of the local history it was rewritten
+// from. only sessions that are newer than that are rewritten on the next push.
+const REWRITTEN_REFNAME: &str = "refs/push/rewritten";
+const SOURCE_REFNAME: &str = "refs/push/source";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    fn signature(&self, time: &git2::Time) -> Result<git::Signature<'static>> {
+        git::Signature::new(&self.name, &self.email, time).context("invalid push author")
+    }
+
+    fn is_author_of(&self, commit: &git::Commit) -> bool {
+        let author = commit.author();
+        author.name() == Some(self.name.as_str()) && author.email() == Some(self.email.as_str())
+    }
+}
+
+// rewrites the history of refs/heads/current for pushing, with the identity if there is one.
+// returns the name of the ref that points to the rewritten history.
+pub fn rewrite(
+    git_repository: &git::Repository,
+    identity: Option<&Identity>,
+) -> Result<git::Refname> {
+    let rewritten_refname: git::Refname = REWRITTEN_REFNAME.parse()?;
+    let source_refname: git::Refname = SOURCE_REFNAME.parse()?;
+
+    let current = git_repository
+        .find_reference(&"refs/heads/current".parse()?)
+        .context("failed to find current reference")?
+        .peel_to_commit()
+        .context("failed to find current commit")?;
+
+    // the last rewrite is only built on when it was made with the same identity and the history
+    // was not rewritten since, by a squash for example
+    let (source, base) = match (
+        find_commit(git_repository, &source_refname)?,
+        find_commit(git_repository, &rewritten_refname)?,
+    ) {
+        (Some(source), Some(rewritten)) if same_identity(identity, &source, &rewritten) => {
+            (Some(source.id()), Some(rewritten))
+        }
+        _ => (None, None),
+    };
+
+    let current_id = current.id();
+    let (commits, reached_source) = first_parent_chain(current, source)?;
+    let base = if reached_source { base } else { None };
+    let rewritten = replay(git_repository, &commits, base, identity)?.context("nothing to push")?;
+
+    git_repository
+        .reference(
+            &rewritten_refname,
+            rewritten.id(),
+            true,
+            "gitbutler: rewrite pushed sessions",
+        )
+        .context("failed to update rewritten reference")?;
+    git_repository
+        .reference(
+            &source_refname,
+            current_id,
+            true,
+            "gitbutler: rewrite pushed sessions",
+        )
+        .context("failed to update source reference")?;
+
+    Ok(rewritten_refname)
+}
+
+// rewrites the history up to head to its first parent chain, without any identity or refs
+// involved. used for exports, where the rewrite is not kept.
+pub fn first_parent_history(git_repository: &git::Repository, head: git::Oid) -> Result<git::Oid> {
+    let head = git_repository
+        .find_commit(head)
+        .context("failed to find head commit")?;
+    let (commits, _) = first_parent_chain(head, None)?;
+    let rewritten = replay(git_repository, &commits, None, None)?.context("nothing to rewrite")?;
+    Ok(rewritten.id())
+}
+
+fn same_identity(
+    identity: Option<&Identity>,
+    source: &git::Commit,
+    rewritten: &git::Commit,
+) -> bool {
+    match identity {
+        Some(identity) => identity.is_author_of(rewritten),
+        None => {
+            let (source, rewritten) = (source.author(), rewritten.author());
+            source.name() == rewritten.name() && source.email() == rewritten.email()
+        }
+    }
+}
+
+// first parent chain of the history, newest first, down to the stop commit. returns whether it
+// was reached.
+fn first_parent_chain(
+    head: git::Commit<'_>,
+    stop: Option<git::Oid>,
+) -> Result<(Vec<git::Commit<'_>>, bool)> {
+    let mut commits = vec![];
+    let mut next = Some(head);
+    while let Some(commit) = next {
+        if Some(commit.id()) == stop {
+            return Ok((commits, true));
+        }
+        next = if commit.parent_count() > 0 {
+            Some(commit.parent(0)?)
+        } else {
+            None
+        };
+        commits.push(commit);
+    }
+    Ok((commits, false))
+}
+
+// writes the commits, newest first, oldest on top of parent. returns the last written commit.
+fn replay<'repo>(
+    git_repository: &'repo git::Repository,
+    commits: &[git::Commit<'_>],
+    mut parent: Option<git::Commit<'repo>>,
+    identity: Option<&Identity>,
+) -> Result<Option<git::Commit<'repo>>> {
+    for commit in commits.iter().rev() {
+        let tree = git_repository
+            .find_tree(commit.tree_id())
+            .context("failed to find tree")?;
+        // extra parents, like the project head of anchored sessions, are dropped
+        let parents = parent.iter().collect::<Vec<_>>();
+        let oid = match identity {
+            Some(identity) => git_repository.commit(
+                None,
+                &identity.signature(&commit.author().when())?,
+                &identity.signature(&commit.committer().when())?,
+                commit.message().unwrap_or_default(),
+                &tree,
+                &parents,
+            ),
+            None => git_repository.commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
+                &tree,
+                &parents,
+            ),
+        }
+        .context("failed to write rewritten commit")?;
+        parent = Some(git_repository.find_commit(oid)?);
+    }
+    Ok(parent)
+}
+
+fn find_commit<'repo>(
+    git_repository: &'repo git::Repository,
+    refname: &git::Refname,
+) -> Result<Option<git::Commit<'repo>>> {
+    match git_repository.find_reference(refname) {
+        Ok(reference) => match reference.peel_to_commit() {
+            Ok(commit) => Ok(Some(commit)),
+            // the rewrite was garbage collected, it is made again
+            Err(git::Error::NotFound(_)) => Ok(None),
+            Err(error) => Err(error.into()),
+        },
+        Err(git::Error::NotFound(_)) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{Case, SessionBuilder, Suite};
+
+    use super::*;
+
+    fn chain(git_repository: &git::Repository, oid: git::Oid) -> Result<Vec<git::Commit<'_>>> {
+        let mut chain = vec![];
+        let mut next = Some(git_repository.find_commit(oid)?);
+        while let Some(commit) = next {
+            next = if commit.parent_count() > 0 {
+                Some(commit.parent(0)?)
+            } else {
+                None
+            };
+            chain.push(commit);
+        }
+        Ok(chain)
+    }
+
+    fn target(git_repository: &git::Repository, refname: &str) -> Result<git::Oid> {
+        Ok(git_repository
+            .find_reference(&refname.parse()?)?
+            .peel_to_commit()?
+            .id())
+    }
+
+    #[test]
+    fn test_rewrite() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let git_repository = gb_repository.git_repository();
+        let identity = Identity {
+            name: "anonymous".to_string(),
+            email: "anonymous@example.com".to_string(),
+        };
+
+        SessionBuilder::new(&gb_repository)
+            .wd_file("file.txt", "first")
+            .build()?;
+        SessionBuilder::new(&gb_repository)
+            .wd_file("file.txt", "second")
+            .build()?;
+
+        let refname = rewrite(git_repository, Some(&identity))?;
+        let anonymized = target(git_repository, &refname.to_string())?;
+        let current = target(git_repository, "refs/heads/current")?;
+
+        let local = chain(git_repository, current)?;
+        let pushed = chain(git_repository, anonymized)?;
+        assert_eq!(local.len(), pushed.len());
+        for (local, pushed) in local.iter().zip(&pushed) {
+            assert!(identity.is_author_of(pushed));
+            assert_eq!(pushed.committer().name(), Some("anonymous"));
+            assert!(!identity.is_author_of(local));
+            assert_eq!(pushed.tree_id(), local.tree_id());
+            assert_eq!(pushed.message(), local.message());
+            assert_eq!(pushed.author().when(), local.author().when());
+        }
+
+        // the same history is rewritten into the same commits
+        rewrite(git_repository, Some(&identity))?;
+        assert_eq!(target(git_repository, &refname.to_string())?, anonymized);
+
+        // and new sessions on top of the last rewrite
+        SessionBuilder::new(&gb_repository)
+            .wd_file("file.txt", "third")
+            .build()?;
+        rewrite(git_repository, Some(&identity))?;
+        let pushed = chain(
+            git_repository,
+            target(git_repository, &refname.to_string())?,
+        )?;
+        assert_eq!(pushed[1].id(), anonymized);
+
+        // another identity rewrites everything
+        let other = Identity {
+            name: "other".to_string(),
+            ..identity
+        };
+        rewrite(git_repository, Some(&other))?;
+        let pushed = chain(
+            git_repository,
+            target(git_repository, &refname.to_string())?,
+        )?;
+        assert!(pushed.iter().all(|commit| other.is_author_of(commit)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_drops_extra_parents() -> Result<()> {
+        let Case {
+            gb_repository,
+            project_repository,
+            ..
+        } = Suite::default().new_case();
+        let git_repository = gb_repository.git_repository();
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let unanchored = gb_repository.flush_session(&project_repository, &session, None)?;
+        project_repository
+            .git_repository
+            .config()?
+            .set_bool("gitbutler.anchorSessions", true)?;
+        let session = gb_repository.get_or_create_current_session()?;
+        let anchored = gb_repository.flush_session(&project_repository, &session, None)?;
+        let anchored = git_repository.find_commit(anchored.hash.unwrap())?;
+        assert_eq!(anchored.parent_count(), 2);
+
+        let refname = rewrite(git_repository, None)?;
+        let rewritten = target(git_repository, &refname.to_string())?;
+        let pushed = chain(git_repository, rewritten)?;
+        assert!(pushed.iter().all(|commit| commit.parent_count() <= 1));
+        assert_eq!(pushed[0].tree_id(), anchored.tree_id());
+        assert_eq!(pushed[0].author().name(), anchored.author().name());
+        // sessions without extra parents are kept as they are
+        assert_eq!(pushed[1].id(), unanchored.hash.unwrap());
+
+        // exports are rewritten the same way
+        assert_eq!(
+            first_parent_history(git_repository, anchored.id())?,
+            rewritten
+        );
+
+        Ok(())
+    }
+}
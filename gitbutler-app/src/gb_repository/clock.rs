@@ -0,0 +1,49 @@
+// where session timestamps and commit times come from. repositories use the system clock, tests
+// set a mock clock with Repository::set_clock to move time around deterministically.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time,
+};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::SystemTime;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::SystemTime {
+        time::SystemTime::now()
+    }
+}
+
+// a clock that only moves when it's told to. clones share the same time, so a test can keep one
+// and hand another to the repository.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<time::SystemTime>>,
+}
+
+impl MockClock {
+    pub fn new(now: time::SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: time::SystemTime) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    pub fn advance(&self, duration: time::Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> time::SystemTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,74 @@
+// finds files with merge conflict markers in them, so that sessions can tell which files were
+// conflicted when they were captured.
+
+use std::{fs::File, io::Read, path};
+
+use anyhow::{Context, Result};
+
+// only the beginning of a file is scanned, like for secrets
+const SCAN_LIMIT: u64 = 1024 * 1024;
+// files with a nul byte this early are binary, like git decides it
+const BINARY_CHECK_LEN: usize = 8000;
+
+// returns true if the file has a conflict in it, that is an opening, a separator and a closing
+// marker at the start of their lines, in that order
+pub fn has_conflict_markers(file_path: &path::Path) -> Result<bool> {
+    let mut content = vec![];
+    File::open(file_path)
+        .with_context(|| format!("failed to open {}", file_path.display()))?
+        .take(SCAN_LIMIT)
+        .read_to_end(&mut content)
+        .with_context(|| format!("failed to read {}", file_path.display()))?;
+    if content.iter().take(BINARY_CHECK_LEN).any(|byte| *byte == 0) {
+        return Ok(false);
+    }
+
+    let mut markers = [b"<<<<<<<".as_slice(), b"=======", b">>>>>>>"].into_iter();
+    let mut next = markers.next();
+    for line in content.split(|byte| *byte == b'\n') {
+        let Some(marker) = next else {
+            break;
+        };
+        if is_marker(line, marker) {
+            next = markers.next();
+        }
+    }
+    Ok(next.is_none())
+}
+
+// markers are followed by a label, or by nothing for the separator
+fn is_marker(line: &[u8], marker: &[u8]) -> bool {
+    let Some(rest) = line.strip_prefix(marker) else {
+        return false;
+    };
+    matches!(rest.first(), None | Some(b' ' | b'\r' | b'\t'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_conflict_markers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let check = |content: &[u8]| -> Result<bool> {
+            let file_path = dir.path().join("file");
+            std::fs::write(&file_path, content)?;
+            has_conflict_markers(&file_path)
+        };
+
+        assert!(check(
+            b"before\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> branch\nafter\n"
+        )?);
+        assert!(check(
+            b"<<<<<<< HEAD\r\nours\r\n=======\r\ntheirs\r\n>>>>>>> branch\r\n"
+        )?);
+        // markers out of order, or not at the start of the line
+        assert!(!check(b"=======\n<<<<<<< HEAD\n>>>>>>> branch\n")?);
+        assert!(!check(b"  <<<<<<< HEAD\n=======\n>>>>>>> branch\n")?);
+        // a heading underline is not a conflict
+        assert!(!check(b"title\n========\n")?);
+        assert!(!check(b"\0<<<<<<< HEAD\n=======\n>>>>>>> branch\n")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashMap, path, time};
+
+use anyhow::{Context, Result};
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::git;
+
+// files modified this recently might still change without their size or mtime changing, so they
+// are not cached
+const RACY_WINDOW: time::Duration = time::Duration::from_secs(2);
+
+// a persistent cache of blob ids of project files, keyed by their relative path.
+//
+// a file whose size and mtime match its cache entry is not hashed again. unlike the project's own
+// index, the cache survives app restarts and is not touched by git.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct IndexCache {
+    entries: HashMap<String, Entry>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_seconds: i64,
+    mtime_nanos: u32,
+    oid: git::Oid,
+}
+
+impl IndexCache {
+    // a missing or unreadable cache is empty, it's rebuilt on the next flush
+    pub(crate) fn load(path: &path::Path) -> Self {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(error) => {
+                tracing::warn!(path = %path.display(), ?error, "failed to read index cache");
+                return Self::default();
+            }
+        };
+        serde_json::from_slice(&content).unwrap_or_else(|error| {
+            tracing::warn!(path = %path.display(), ?error, "failed to parse index cache");
+            Self::default()
+        })
+    }
+
+    pub(crate) fn save(&mut self, path: &path::Path) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let content = serde_json::to_vec(self).context("failed to serialize index cache")?;
+        // write to a temporary file first, so that a crash doesn't leave a truncated cache behind
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, content)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename {}", tmp_path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // returns the cached blob id of the file if it wasn't modified since it was cached. entries
+    // with a different size are removed.
+    pub(crate) fn get(&mut self, key: &str, metadata: &std::fs::Metadata) -> Option<git::Oid> {
+        let entry = self.entries.get(key)?;
+        if entry.size != metadata.len() {
+            self.entries.remove(key);
+            self.dirty = true;
+            return None;
+        }
+        let mtime = FileTime::from_last_modification_time(metadata);
+        if entry.mtime_seconds != mtime.unix_seconds() || entry.mtime_nanos != mtime.nanoseconds() {
+            return None;
+        }
+        Some(entry.oid)
+    }
+
+    pub(crate) fn insert(&mut self, key: String, metadata: &std::fs::Metadata, oid: git::Oid) {
+        let is_racy = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map_or(true, |elapsed| elapsed < RACY_WINDOW);
+        if is_racy {
+            return;
+        }
+
+        let mtime = FileTime::from_last_modification_time(metadata);
+        let entry = Entry {
+            size: metadata.len(),
+            mtime_seconds: mtime.unix_seconds(),
+            mtime_nanos: mtime.nanoseconds(),
+            oid,
+        };
+        if self.entries.insert(key, entry) != Some(entry) {
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn old_file(dir: &path::Path, name: &str, content: &str) -> Result<std::fs::Metadata> {
+        let path = dir.join(name);
+        std::fs::write(&path, content)?;
+        filetime::set_file_mtime(&path, FileTime::from_unix_time(1_000_000_000, 0))?;
+        Ok(std::fs::metadata(path)?)
+    }
+
+    #[test]
+    fn test_get() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let oid: git::Oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32".parse()?;
+        let metadata = old_file(dir.path(), "file.txt", "hello")?;
+
+        let mut cache = IndexCache::default();
+        cache.insert("file.txt".to_string(), &metadata, oid);
+        assert_eq!(cache.get("file.txt", &metadata), Some(oid));
+
+        // same size, different mtime
+        filetime::set_file_mtime(
+            dir.path().join("file.txt"),
+            FileTime::from_unix_time(1_000_000_001, 0),
+        )?;
+        let touched = std::fs::metadata(dir.path().join("file.txt"))?;
+        assert_eq!(cache.get("file.txt", &touched), None);
+        assert_eq!(cache.get("file.txt", &metadata), Some(oid));
+
+        // different size invalidates the entry
+        let resized = old_file(dir.path(), "file.txt", "hello world")?;
+        assert_eq!(cache.get("file.txt", &resized), None);
+        assert_eq!(cache.get("file.txt", &metadata), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_racy_files_are_not_cached() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let oid: git::Oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32".parse()?;
+        std::fs::write(dir.path().join("file.txt"), "hello")?;
+        let metadata = std::fs::metadata(dir.path().join("file.txt"))?;
+
+        let mut cache = IndexCache::default();
+        cache.insert("file.txt".to_string(), &metadata, oid);
+        assert_eq!(cache.get("file.txt", &metadata), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache_path = dir.path().join("index-cache");
+        let oid: git::Oid = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32".parse()?;
+        let metadata = old_file(dir.path(), "file.txt", "hello")?;
+
+        assert_eq!(
+            IndexCache::load(&cache_path).get("file.txt", &metadata),
+            None
+        );
+
+        let mut cache = IndexCache::default();
+        cache.insert("file.txt".to_string(), &metadata, oid);
+        cache.save(&cache_path)?;
+        assert_eq!(
+            IndexCache::load(&cache_path).get("file.txt", &metadata),
+            Some(oid)
+        );
+
+        // a corrupted cache is ignored
+        std::fs::write(&cache_path, "not json")?;
+        assert_eq!(
+            IndexCache::load(&cache_path).get("file.txt", &metadata),
+            None
+        );
+
+        Ok(())
+    }
+}
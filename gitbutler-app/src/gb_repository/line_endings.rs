@@ -0,0 +1,137 @@
+// normalizes line endings of captured files the way `git add` does, so that blobs of text files
+// are the same as the ones git would store. the gitbutler repository is bare, so libgit2 doesn't
+// apply the project's filters when it writes blobs from disk.
+//
+// only the crlf conversion is applied. it follows the text and eol attributes and core.autocrlf,
+// like git does, except that the index is not checked for files that are committed with crlf.
+
+use std::path;
+
+use anyhow::{Context, Result};
+
+use crate::project_repository;
+
+// git looks at this many bytes to decide if a file is binary
+const BINARY_CHECK_SIZE: usize = 8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conversion {
+    // crlf is converted to lf
+    Text,
+    // crlf is converted to lf if the file looks like text
+    Auto,
+}
+
+pub struct Normalizer {
+    // the project repository, for its attributes
+    repository: git2::Repository,
+    autocrlf: bool,
+}
+
+impl Normalizer {
+    pub fn new(project_repository: &project_repository::Repository) -> Result<Self> {
+        let repository = git2::Repository::open(project_repository.root())
+            .context("failed to open project repository")?;
+        let autocrlf = project_repository
+            .config()
+            .autocrlf()
+            .context("failed to read core.autocrlf")?;
+        Ok(Self {
+            repository,
+            autocrlf,
+        })
+    }
+
+    fn conversion(&self, rel_file_path: &path::Path) -> Result<Option<Conversion>> {
+        let attr = |name: &str| -> Result<git2::AttrValue> {
+            Ok(git2::AttrValue::from_string(
+                self.repository
+                    .get_attr(rel_file_path, name, git2::AttrCheckFlags::default())
+                    .with_context(|| format!("failed to read {name} attribute"))?,
+            ))
+        };
+        Ok(match attr("text")? {
+            git2::AttrValue::True => Some(Conversion::Text),
+            git2::AttrValue::String("auto") => Some(Conversion::Auto),
+            git2::AttrValue::False => None,
+            // setting eol makes a file text
+            _ => match attr("eol")? {
+                git2::AttrValue::String("lf" | "crlf") => Some(Conversion::Text),
+                _ if self.autocrlf => Some(Conversion::Auto),
+                _ => None,
+            },
+        })
+    }
+
+    // returns the content of the file as git would store it, or None if git stores it as it is
+    pub fn normalize(
+        &self,
+        rel_file_path: &path::Path,
+        file_path: &path::Path,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(conversion) = self.conversion(rel_file_path)? else {
+            return Ok(None);
+        };
+        let content = std::fs::read(file_path)
+            .with_context(|| format!("failed to read {}", file_path.display()))?;
+        Ok(Some(normalize(content, conversion)))
+    }
+}
+
+fn normalize(content: Vec<u8>, conversion: Conversion) -> Vec<u8> {
+    if !content.contains(&b'\r') {
+        return content;
+    }
+    // files with lone carriage returns are not text, converting them would not round trip
+    if conversion == Conversion::Auto
+        && (content
+            .iter()
+            .take(BINARY_CHECK_SIZE)
+            .any(|byte| *byte == 0)
+            || has_lone_cr(&content))
+    {
+        return content;
+    }
+
+    let mut normalized = Vec::with_capacity(content.len());
+    let mut bytes = content.iter().peekable();
+    while let Some(byte) = bytes.next() {
+        if *byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        normalized.push(*byte);
+    }
+    normalized
+}
+
+fn has_lone_cr(content: &[u8]) -> bool {
+    content
+        .iter()
+        .enumerate()
+        .any(|(i, byte)| *byte == b'\r' && content.get(i + 1) != Some(&b'\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        for (content, conversion, expected) in [
+            ("a\r\nb\r\n", Conversion::Text, "a\nb\n"),
+            ("a\r\nb\r\n", Conversion::Auto, "a\nb\n"),
+            ("a\nb\n", Conversion::Text, "a\nb\n"),
+            // lone carriage returns are kept in text files, and make auto files binary
+            ("a\rb\r\n", Conversion::Text, "a\rb\n"),
+            ("a\rb\r\n", Conversion::Auto, "a\rb\r\n"),
+            ("a\0\r\n", Conversion::Auto, "a\0\r\n"),
+        ] {
+            assert_eq!(
+                normalize(content.as_bytes().to_vec(), conversion),
+                expected.as_bytes(),
+                "{}",
+                content.escape_debug()
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{git, sessions::SessionId};
+
+// a session that was captured, but failed to be committed.
+//
+// all of its trees are already in the object database, so only the commit is left to write.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PendingSession {
+    pub(crate) session_id: SessionId,
+    pub(crate) tree_id: git::Oid,
+    // commit time override, see gitbutler.commitTimestamp
+    pub(crate) timestamp_ms: Option<u128>,
+    // timezone override, see gitbutler.commitTimezone
+    #[serde(default)]
+    pub(crate) timezone_offset_minutes: Option<i32>,
+    // see gitbutler.anchorSessions
+    #[serde(default)]
+    pub(crate) anchor_commit: Option<git::Oid>,
+    // message with the trailers the session had when it was captured, see gitbutler.commitTrailer
+    #[serde(default)]
+    pub(crate) message: Option<String>,
+    // see gitbutler.auditLog
+    #[serde(default)]
+    pub(crate) audit_log: bool,
+}
+
+// where the queue of pending sessions of the repository is kept
+pub(crate) fn path(git_repository: &git::Repository) -> path::PathBuf {
+    git_repository.path().join("pending-sessions")
+}
+
+// trees of the queued sessions, oldest first. they are not reachable from any ref until their
+// sessions are committed, so everything they reference has to be kept.
+pub(crate) fn queued_trees(git_repository: &git::Repository) -> Result<Vec<git::Oid>> {
+    Ok(load(&path(git_repository))?
+        .into_iter()
+        .map(|pending_session| pending_session.tree_id)
+        .collect())
+}
+
+// reads the queue of pending sessions, oldest first. a missing queue is empty.
+pub(crate) fn load(path: &path::Path) -> Result<Vec<PendingSession>> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", path.display()))
+        }
+    };
+    serde_json::from_slice(&content).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+pub(crate) fn save(path: &path::Path, pending: &[PendingSession]) -> Result<()> {
+    if pending.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(error).with_context(|| format!("failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let content = serde_json::to_vec(pending).context("failed to serialize pending sessions")?;
+    // write to a temporary file first, so that a crash doesn't leave a truncated queue behind
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, content)
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to rename {}", tmp_path.display()))
+}
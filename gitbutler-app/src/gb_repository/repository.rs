@@ -1,8 +1,11 @@
 use std::{
-    collections::{HashMap, HashSet},
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs::File,
     io::{BufReader, Read},
-    path, time,
+    path,
+    sync::{Arc, Mutex},
+    time,
 };
 
 #[cfg(target_os = "windows")]
@@ -11,12 +14,24 @@ use crate::windows::MetadataShim;
 use std::os::unix::prelude::*;
 
 use anyhow::{anyhow, Context, Result};
+use chrono::TimeZone;
 use filetime::FileTime;
 use fslock::LockFile;
 use sha2::{Digest, Sha256};
 
+use super::{
+    anonymize,
+    clock::{Clock, SystemClock},
+    conflicts,
+    index_cache::IndexCache,
+    line_endings,
+    pending::{self, PendingSession},
+    secrets, status_command,
+    trailers::{self, TrailerProvider},
+    SessionStore,
+};
 use crate::{
-    deltas, fs, git, project_repository,
+    deltas, fs, git, lfs, logs, project_repository,
     projects::{self, ProjectId},
     reader, sessions,
     sessions::SessionId,
@@ -27,7 +42,39 @@ use crate::{
 pub struct Repository {
     git_repository: git::Repository,
     project: projects::Project,
+    // the current session and the virtual branches are written under this directory, see
+    // resolve_root
+    root: path::PathBuf,
     lock_path: path::PathBuf,
+    lfs_objects_dir: path::PathBuf,
+    read_only: bool,
+    // files that seemed to have secrets in them when they were captured, see take_secret_files
+    secret_files: Mutex<BTreeSet<path::PathBuf>>,
+    // how often the index cache was used by captures, see take_capture_stats
+    capture_stats: Mutex<CaptureStats>,
+    // called for trailers of every session commit, see add_trailer_provider
+    trailer_providers: Vec<TrailerProvider>,
+    // timestamps of sessions and their commits, see set_clock
+    clock: Arc<dyn Clock>,
+}
+
+// how often project files were taken from the index cache instead of being hashed again. only
+// counted when gitbutler.indexCache is enabled. a low hit ratio means that file metadata keeps
+// changing, for example on a file system with coarse or unstable timestamps.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureStats {
+    pub fast_path_hits: usize,
+    pub fast_path_misses: usize,
+}
+
+impl CaptureStats {
+    // None if the index cache wasn't consulted at all
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let total = self.fast_path_hits + self.fast_path_misses;
+        #[allow(clippy::cast_precision_loss)]
+        (total > 0).then(|| self.fast_path_hits as f64 / total as f64)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -36,12 +83,56 @@ pub enum Error {
     ProjectNotFound,
     #[error("path not found: {0}")]
     ProjectPathNotFound(path::PathBuf),
+    #[error("gitbutler repository not found: {0}")]
+    RepositoryNotFound(path::PathBuf),
+    #[error("repository is opened read-only")]
+    ReadOnly,
     #[error(transparent)]
     Git(#[from] git::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
     #[error("path has invalid utf-8 bytes: {0}")]
     InvalidUnicodePath(path::PathBuf),
+    #[error("session file is locked or unreadable: {0}")]
+    SessionLocked(path::PathBuf),
+    #[error("file is still being written: {0}")]
+    FileNotSettled(path::PathBuf),
+    #[error("subtree name is reserved or invalid: {0}")]
+    InvalidSubtree(String),
+    #[error("objects can't be written to {0}")]
+    ObjectsNotWritable(path::PathBuf),
+}
+
+// subtrees of a session commit that are written by the flush itself
+const BUILTIN_SUBTREES: &[&str] = &["session", "wd", "branches", "index", "logs"];
+
+// the root is a directory of the gitbutler repository, unless gitbutler.sessionRoot names another
+// one. only the directory moves, the session is committed with the same layout wherever it is.
+const DEFAULT_ROOT: &str = "gitbutler";
+// directories of the gitbutler repository that git and the lfs store use themselves
+const RESERVED_ROOTS: &[&str] = &["objects", "refs", "logs", "hooks", "info", "lfs"];
+
+fn resolve_root(
+    git_repository: &git::Repository,
+    project_repository: &project_repository::Repository,
+) -> Result<path::PathBuf> {
+    let root = project_repository
+        .config()
+        .session_root()
+        .context("failed to read gitbutler.sessionRoot, expected a directory")?
+        .map(|root| root.trim().to_string())
+        .filter(|root| !root.is_empty())
+        .map_or_else(|| path::PathBuf::from(DEFAULT_ROOT), path::PathBuf::from);
+    let is_reserved = root.components().next().map_or(false, |component| {
+        RESERVED_ROOTS.contains(&component.as_os_str().to_string_lossy().as_ref())
+    });
+    if !project_repository::is_inside_workdir(&root) || is_reserved {
+        return Err(anyhow!(
+            "invalid gitbutler.sessionRoot {}, expected a directory inside the gitbutler repository",
+            root.display()
+        ));
+    }
+    Ok(git_repository.path().join(root))
 }
 
 impl Repository {
@@ -51,7 +142,7 @@ impl Repository {
         user: Option<&users::User>,
     ) -> Result<Self, Error> {
         let project = project_repository.project();
-        let project_objects_path = project.path.join(".git/objects");
+        let project_objects_path = project_objects_path(project_repository);
         if !project_objects_path.exists() {
             return Err(Error::ProjectPathNotFound(project_objects_path));
         }
@@ -60,6 +151,12 @@ impl Repository {
 
         let path = projects_dir.join(project.id.to_string());
         let lock_path = projects_dir.join(format!("{}.lock", project.id));
+        // a configured store might be shared by several projects, each gets its own directory in it
+        let lfs_objects_dir = project_repository::Settings::resolve_lfs_objects_dir(
+            project,
+            &project_repository.config(),
+        )?
+        .map(|dir| dir.join(project.id.to_string()));
 
         if path.exists() {
             let git_repository = git::Repository::open(path.clone())
@@ -70,9 +167,17 @@ impl Repository {
                 .context("failed to add disk alternate")?;
 
             Result::Ok(Self {
+                lfs_objects_dir: lfs_objects_dir
+                    .unwrap_or_else(|| lfs::default_objects_dir(&git_repository)),
+                root: resolve_root(&git_repository, project_repository)?,
                 git_repository,
                 project: project.clone(),
                 lock_path,
+                read_only: false,
+                secret_files: Mutex::default(),
+                capture_stats: Mutex::default(),
+                trailer_providers: vec![],
+                clock: Arc::new(SystemClock),
             })
         } else {
             let git_repository = git::Repository::init_opts(
@@ -89,23 +194,176 @@ impl Repository {
                 .context("failed to add disk alternate")?;
 
             let gb_repository = Self {
+                lfs_objects_dir: lfs_objects_dir
+                    .unwrap_or_else(|| lfs::default_objects_dir(&git_repository)),
+                root: resolve_root(&git_repository, project_repository)?,
                 git_repository,
                 project: project.clone(),
                 lock_path,
+                read_only: false,
+                secret_files: Mutex::default(),
+                capture_stats: Mutex::default(),
+                trailer_providers: vec![],
+                clock: Arc::new(SystemClock),
             };
 
             let _lock = gb_repository.lock();
             let session = gb_repository.create_current_session(project_repository)?;
             drop(_lock);
 
+            // the initial flush doesn't wait for files to settle, the project is being added and
+            // can't be postponed. files that are still written to are captured by the next flush.
             gb_repository
-                .flush_session(project_repository, &session, user)
+                .flush_session_with(
+                    project_repository,
+                    &session,
+                    user,
+                    &gb_repository.git_repository,
+                    FlushMode {
+                        ignore_settle_window: true,
+                        ..FlushMode::default()
+                    },
+                    &[],
+                )
                 .context("failed to run initial flush")?;
 
             Result::Ok(gb_repository)
         }
     }
 
+    // opens the repository to read the session history only, nothing is captured and nothing is
+    // written to disk: the repository is not created if it doesn't exist, objects that are
+    // built while reading, for example by sessions::diff_live, are kept in memory, and the
+    // current session can't be written.
+    pub fn open_read_only(
+        root: &path::Path,
+        project_repository: &project_repository::Repository,
+    ) -> Result<Self, Error> {
+        let project = project_repository.project();
+        let project_objects_path = project_objects_path(project_repository);
+        if !project_objects_path.exists() {
+            return Err(Error::ProjectPathNotFound(project_objects_path));
+        }
+
+        let projects_dir = root.join("projects");
+        let path = projects_dir.join(project.id.to_string());
+        if !path.exists() {
+            return Err(Error::RepositoryNotFound(path));
+        }
+
+        let git_repository = git::Repository::open(path.clone())
+            .with_context(|| format!("{}: failed to open git repository", path.display()))?;
+        git_repository
+            .add_odb_alternate(project_objects_path.to_str().unwrap())
+            .context("failed to add alternate")?;
+        git_repository
+            .keep_new_objects_in_memory()
+            .context("failed to add in memory object store")?;
+
+        let lfs_objects_dir = project_repository::Settings::resolve_lfs_objects_dir(
+            project,
+            &project_repository.config(),
+        )?
+        .map_or_else(
+            || lfs::default_objects_dir(&git_repository),
+            |dir| dir.join(project.id.to_string()),
+        );
+
+        Ok(Self {
+            root: resolve_root(&git_repository, project_repository)?,
+            git_repository,
+            project: project.clone(),
+            lock_path: projects_dir.join(format!("{}.lock", project.id)),
+            lfs_objects_dir,
+            read_only: true,
+            secret_files: Mutex::default(),
+            capture_stats: Mutex::default(),
+            trailer_providers: vec![],
+            clock: Arc::new(SystemClock),
+        })
+    }
+
+    // returns the files that seemed to have secrets in them when they were captured since the
+    // last call, when gitbutler.scanSecrets is enabled. paths are relative to the project root.
+    pub fn take_secret_files(&self) -> Vec<path::PathBuf> {
+        let mut secret_files = self
+            .secret_files
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::mem::take(&mut *secret_files).into_iter().collect()
+    }
+
+    // adds trailers to the commits of sessions flushed from now on, after the configured ones
+    pub fn add_trailer_provider(&mut self, provider: TrailerProvider) {
+        self.trailer_providers.push(provider);
+    }
+
+    // replaces the system clock that sessions are timestamped and committed with
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn now(&self) -> time::SystemTime {
+        self.clock.now()
+    }
+
+    // returns how often the index cache was used by the captures since the last call
+    pub fn take_capture_stats(&self) -> CaptureStats {
+        let mut capture_stats = self
+            .capture_stats
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::mem::take(&mut *capture_stats)
+    }
+
+    fn count_fast_path(&self, hit: bool) {
+        let mut capture_stats = self
+            .capture_stats
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if hit {
+            capture_stats.fast_path_hits += 1;
+        } else {
+            capture_stats.fast_path_misses += 1;
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // fails if session objects can't be written, for example when the objects directory is on a
+    // read-only mount. a tiny blob that is unique to the check is written to find out, and
+    // removed again.
+    pub fn check_objects_writable(&self) -> Result<(), Error> {
+        let objects_dir = self.git_repository.path().join("objects");
+        let probe = format!(
+            "gitbutler write check {}\n",
+            self.now()
+                .duration_since(time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+        let oid = match self.git_repository.blob(probe.as_bytes()) {
+            Result::Ok(oid) => oid,
+            Err(error) => {
+                tracing::warn!(
+                    project_id = %self.project.id,
+                    objects_dir = %objects_dir.display(),
+                    ?error,
+                    "objects are not writable"
+                );
+                return Err(Error::ObjectsNotWritable(objects_dir));
+            }
+        };
+        let oid = oid.to_string();
+        let probe_path = objects_dir.join(&oid[..2]).join(&oid[2..]);
+        if let Err(error) = std::fs::remove_file(&probe_path) {
+            tracing::debug!(path = %probe_path.display(), ?error, "failed to remove write check object");
+        }
+        Ok(())
+    }
+
     pub fn get_project_id(&self) -> &ProjectId {
         &self.project.id
     }
@@ -225,7 +483,13 @@ impl Repository {
         let headers = &[auth_header.as_str()];
         push_options.custom_headers(headers);
 
-        let remote_refspec = format!("refs/heads/current:refs/heads/{}", self.project.id);
+        // sessions are pushed without the project history of anchored sessions, and with an
+        // anonymous identity instead when one is configured
+        let source_refname =
+            anonymize::rewrite(&self.git_repository, self.push_identity()?.as_ref())
+                .context("failed to rewrite pushed sessions")?
+                .to_string();
+        let remote_refspec = format!("{source_refname}:refs/heads/{}", self.project.id);
 
         // Push to the remote
         remote
@@ -242,8 +506,17 @@ impl Repository {
         Ok(())
     }
 
+    fn push_identity(&self) -> Result<Option<anonymize::Identity>> {
+        let project_repository = git::Repository::open(&self.project.path)
+            .context("failed to open project repository")?;
+        let push_author = project_repository::Config::from(&project_repository)
+            .push_author()
+            .context("failed to read gitbutler.pushAuthorName")?;
+        Ok(push_author.map(|(name, email)| anonymize::Identity { name, email }))
+    }
+
     // take branches from the last session and put them into the current session
-    fn copy_branches(&self) -> Result<()> {
+    pub(crate) fn copy_branches(&self) -> Result<()> {
         let last_session = self
             .get_sessions_iterator()
             .context("failed to get sessions iterator")?
@@ -312,23 +585,38 @@ impl Repository {
         &self,
         project_repository: &project_repository::Repository,
     ) -> Result<sessions::Session> {
-        let now_ms = time::SystemTime::now()
+        let now_ms = self
+            .now()
             .duration_since(time::UNIX_EPOCH)
             .unwrap()
             .as_millis();
 
         let meta = match project_repository.get_head() {
+            // a detached head resolves to HEAD itself, which is not a branch
             Result::Ok(head) => sessions::Meta {
+                version: sessions::META_VERSION,
                 start_timestamp_ms: now_ms,
                 last_timestamp_ms: now_ms,
-                branch: head.name().map(|name| name.to_string()),
+                branch: head
+                    .is_branch()
+                    .then(|| head.name().map(|name| name.to_string()))
+                    .flatten(),
                 commit: Some(head.peel_to_commit()?.id().to_string()),
+                detached: !head.is_branch(),
+                stashes: read_stashes(project_repository),
+                sparse_checkout: read_sparse_checkout(project_repository),
+                metadata: BTreeMap::new(),
             },
             Err(_) => sessions::Meta {
+                version: sessions::META_VERSION,
                 start_timestamp_ms: now_ms,
                 last_timestamp_ms: now_ms,
                 branch: None,
                 commit: None,
+                detached: false,
+                stashes: vec![],
+                sparse_checkout: None,
+                metadata: BTreeMap::new(),
             },
         };
 
@@ -350,7 +638,7 @@ impl Repository {
             "created new session"
         );
 
-        self.flush_gitbutler_file(&session.id)?;
+        self.flush_gitbutler_file(project_repository, &session.id)?;
 
         Ok(session)
     }
@@ -368,7 +656,8 @@ impl Repository {
 
         let updated_session = sessions::Session {
             meta: sessions::Meta {
-                last_timestamp_ms: time::SystemTime::now()
+                last_timestamp_ms: self
+                    .now()
                     .duration_since(time::UNIX_EPOCH)
                     .unwrap()
                     .as_millis(),
@@ -398,6 +687,10 @@ impl Repository {
     }
 
     pub fn get_or_create_current_session(&self) -> Result<sessions::Session> {
+        if self.read_only {
+            return Err(Error::ReadOnly.into());
+        }
+
         let _lock = self.lock();
 
         let reader = reader::Reader::open(&self.root())?;
@@ -442,44 +735,354 @@ impl Repository {
         session: &sessions::Session,
         user: Option<&users::User>,
     ) -> Result<sessions::Session> {
+        self.flush_session_to(project_repository, session, user, &self.git_repository)
+    }
+
+    // same as flush_session, but session objects are written into the given store
+    pub fn flush_session_to(
+        &self,
+        project_repository: &project_repository::Repository,
+        session: &sessions::Session,
+        user: Option<&users::User>,
+        store: &dyn SessionStore,
+    ) -> Result<sessions::Session> {
+        let flushed = self
+            .flush_session_with(
+                project_repository,
+                session,
+                user,
+                store,
+                FlushMode::default(),
+                &[],
+            )?
+            .expect("session is always flushed");
+        Ok(flushed)
+    }
+
+    // same as flush_session, with additional named subtrees in the session commit, next to the
+    // built-in ones. the trees must already exist in the gitbutler repository.
+    pub fn flush_session_with_subtrees(
+        &self,
+        project_repository: &project_repository::Repository,
+        session: &sessions::Session,
+        user: Option<&users::User>,
+        subtrees: &[(&str, git::Oid)],
+    ) -> Result<sessions::Session> {
+        let flushed = self
+            .flush_session_with(
+                project_repository,
+                session,
+                user,
+                &self.git_repository,
+                FlushMode::default(),
+                subtrees,
+            )?
+            .expect("session is always flushed");
+        Ok(flushed)
+    }
+
+    // same as flush_session, but a session that didn't change anything since the last one is
+    // discarded instead of committed, and None is returned.
+    //
+    // this is what happens after a checkout that only bumps mtimes: files are hashed again, but
+    // the resulting trees are the same as before.
+    pub fn flush_session_if_changed(
+        &self,
+        project_repository: &project_repository::Repository,
+        session: &sessions::Session,
+        user: Option<&users::User>,
+    ) -> Result<Option<sessions::Session>> {
+        self.flush_session_with(
+            project_repository,
+            session,
+            user,
+            &self.git_repository,
+            FlushMode {
+                skip_unchanged: true,
+                ..FlushMode::default()
+            },
+            &[],
+        )
+    }
+
+    fn flush_session_with(
+        &self,
+        project_repository: &project_repository::Repository,
+        session: &sessions::Session,
+        user: Option<&users::User>,
+        store: &dyn SessionStore,
+        mode: FlushMode,
+        subtrees: &[(&str, git::Oid)],
+    ) -> Result<Option<sessions::Session>> {
         if session.hash.is_some() {
-            return Ok(session.clone());
+            return Ok(Some(session.clone()));
         }
 
+        validate_subtrees(subtrees)?;
+
         if !self.root().exists() {
             return Err(anyhow!("nothing to flush"));
         }
 
         let _lock = self.lock();
 
-        // update last timestamp
         let session_writer =
             sessions::Writer::new(self).context("failed to create session writer")?;
+        let session = &self.write_session_meta(project_repository, session, &session_writer)?;
+
+        let mut options = self.capture_options(project_repository)?;
+        // a session that is too old is not postponed any longer, files are captured as they are
+        if mode.ignore_settle_window || session.is_too_old(self.now())? {
+            options.settle_window = None;
+        }
+
+        if project_repository
+            .config()
+            .branch_sessions()
+            .context("failed to read gitbutler.branchSessions")?
+        {
+            switch_branch_history(self, project_repository)
+                .context("failed to switch to the history of the branch")?;
+        }
+
+        // everything is captured on top of the last session, it doesn't move until the commit
+        let last_commit = find_current_commit(self)?;
+        check_settled(self, project_repository, &options, last_commit.as_ref())?;
+
+        self.load_last_capture(&options, last_commit.as_ref())?;
+        write_project_dirs(project_repository, &options, &session_writer)?;
+        let wd_tree = build_wd_tree(
+            self,
+            project_repository,
+            &options,
+            store,
+            last_commit.as_ref(),
+        )
+        .context("failed to build working directory tree")?;
+        write_captured_files(project_repository, &options, &session_writer)?;
+        self.write_status_output(project_repository, &options, &session_writer)?;
+        let branches_tree =
+            build_branches_tree(self, &options, store).context("failed to build branches tree")?;
+        let index_tree = if options.capture_index {
+            Some(
+                build_index_tree(project_repository, store)
+                    .context("failed to build index tree")?,
+            )
+        } else {
+            None
+        };
+        let logs_tree = if options.capture_reflog {
+            build_logs_tree(project_repository, &options, store)
+                .context("failed to build logs tree")?
+        } else {
+            None
+        };
+
+        // checked before the session tree is built, which is never the same
+        if mode.skip_unchanged
+            && is_unchanged(
+                self,
+                last_commit.as_ref(),
+                wd_tree,
+                branches_tree,
+                index_tree,
+                logs_tree,
+            )?
+        {
+            tracing::debug!(
+                project_id = %self.project.id,
+                session_id = %session.id,
+                "nothing changed since the last session, discarding it"
+            );
+            session_writer.remove()?;
+            return Ok(None);
+        }
+
+        let mut entries = vec![
+            (
+                "session",
+                build_session_tree(self, &options, store)
+                    .context("failed to build session tree")?,
+                git::FileMode::Tree,
+            ),
+            ("wd", wd_tree, git::FileMode::Tree),
+            ("branches", branches_tree, git::FileMode::Tree),
+        ];
+        if let Some(index_tree) = index_tree {
+            entries.push(("index", index_tree, git::FileMode::Tree));
+        }
+        if let Some(logs_tree) = logs_tree {
+            entries.push(("logs", logs_tree, git::FileMode::Tree));
+        }
+        for (name, tree) in subtrees {
+            entries.push((*name, *tree, git::FileMode::Tree));
+        }
+
+        let tree_id = store.write_tree(&entries).context("failed to write tree")?;
+
+        let message = self
+            .commit_message(&options, session, last_commit.as_ref(), wd_tree)
+            .context("failed to compose commit message")?;
+        let commit_oid = self.commit_session(
+            &options,
+            session,
+            &session_writer,
+            user,
+            store,
+            tree_id,
+            message,
+        )?;
+        self.after_commit(&options, session, commit_oid);
+
+        session_writer.remove()?;
+
+        let session = sessions::Session {
+            hash: Some(commit_oid),
+            ..session.clone()
+        };
+
+        Ok(Some(session))
+    }
+
+    // updates the last timestamp of the session and records what is captured with its meta,
+    // returns the session with it
+    fn write_session_meta(
+        &self,
+        project_repository: &project_repository::Repository,
+        session: &sessions::Session,
+        session_writer: &sessions::Writer,
+    ) -> Result<sessions::Session> {
+        repair_session_meta(self, session, session_writer)?;
         session_writer.write(session)?;
 
-        let mut tree_builder = self.git_repository.treebuilder(None);
+        // stashes are recorded as they are at capture time
+        let stashes = read_stashes(project_repository);
+        session_writer
+            .write_stashes(&stashes)
+            .context("failed to write stashes")?;
+        // and so is the sparse checkout, it's changed without moving the head
+        let sparse_checkout = read_sparse_checkout(project_repository);
+        session_writer
+            .write_sparse_checkout(sparse_checkout.as_ref())
+            .context("failed to write sparse checkout")?;
+        Ok(sessions::Session {
+            meta: sessions::Meta {
+                stashes,
+                sparse_checkout,
+                ..session.meta.clone()
+            },
+            ..session.clone()
+        })
+    }
 
-        tree_builder.upsert(
-            "session",
-            build_session_tree(self).context("failed to build session tree")?,
-            git::FileMode::Tree,
-        );
-        tree_builder.upsert(
-            "wd",
-            build_wd_tree(self, project_repository)
-                .context("failed to build working directory tree")?,
-            git::FileMode::Tree,
-        );
-        tree_builder.upsert(
-            "branches",
-            build_branches_tree(self).context("failed to build branches tree")?,
-            git::FileMode::Tree,
-        );
+    // loads what the last session recorded about files, for the files that are not captured again
+    fn load_last_capture(
+        &self,
+        options: &CaptureOptions,
+        last_commit: Option<&git::Commit>,
+    ) -> Result<()> {
+        let Some(last_commit) = last_commit else {
+            return Ok(());
+        };
+        // files that are not captured again keep the extended attributes they had
+        if let Some(xattrs) = &options.xattrs {
+            *xattrs.borrow_mut() = sessions::xattrs_from_commit(&self.git_repository, last_commit)
+                .context("failed to read xattrs of the last session")?;
+        }
+        *options.conflicted_files.borrow_mut() =
+            sessions::conflicted_files_from_commit(&self.git_repository, last_commit)
+                .context("failed to read conflicted files of the last session")?
+                .into_iter()
+                .collect();
+        Ok(())
+    }
+
+    // runs the status command, if there is one. the command failing doesn't fail the capture, how
+    // it failed is recorded instead.
+    fn write_status_output(
+        &self,
+        project_repository: &project_repository::Repository,
+        options: &CaptureOptions,
+        session_writer: &sessions::Writer,
+    ) -> Result<()> {
+        let status_output = options.status_command.as_ref().map(|(command, timeout)| {
+            status_command::run(command, project_repository.root(), *timeout)
+        });
+        if let Some(status_output) = &status_output {
+            if status_output.timed_out || status_output.exit_code != Some(0) {
+                tracing::warn!(
+                    project_id = %self.project.id,
+                    command = %status_output.command,
+                    exit_code = ?status_output.exit_code,
+                    timed_out = status_output.timed_out,
+                    "status command failed"
+                );
+            }
+        }
+        session_writer
+            .write_status_output(status_output.as_ref())
+            .context("failed to write status output")
+    }
 
-        let tree_id = tree_builder.write().context("failed to write tree")?;
+    // commits the session tree, after the sessions that failed to commit earlier to keep the
+    // history in order. a session that fails to commit is queued to be committed later.
+    #[allow(clippy::too_many_arguments)]
+    fn commit_session(
+        &self,
+        options: &CaptureOptions,
+        session: &sessions::Session,
+        session_writer: &sessions::Writer,
+        user: Option<&users::User>,
+        store: &dyn SessionStore,
+        tree_id: git::Oid,
+        message: String,
+    ) -> Result<git::Oid> {
+        let commit_timestamp_ms = options
+            .session_commit_timestamp
+            .then_some(session.meta.last_timestamp_ms);
+        let commit_result = self.commit_pending_sessions(user).and_then(|_| {
+            write_gb_commit(
+                tree_id,
+                self,
+                user,
+                &message,
+                commit_timestamp_ms,
+                options.commit_timezone,
+                options.anchor_commit,
+                store,
+            )
+        });
+        match commit_result {
+            Result::Ok(commit_oid) => Ok(commit_oid),
+            Err(error) => {
+                self.queue_pending_session(
+                    PendingSession {
+                        session_id: session.id,
+                        tree_id,
+                        timestamp_ms: commit_timestamp_ms,
+                        timezone_offset_minutes: options.commit_timezone,
+                        anchor_commit: options.anchor_commit,
+                        message: Some(message),
+                        audit_log: options.audit_log,
+                    },
+                    session_writer,
+                );
+                Err(error).context("failed to write gb commit")
+            }
+        }
+    }
 
-        let commit_oid =
-            write_gb_commit(tree_id, self, user).context("failed to write gb commit")?;
+    // bookkeeping once the session is committed, none of it fails the flush
+    fn after_commit(
+        &self,
+        options: &CaptureOptions,
+        session: &sessions::Session,
+        commit_oid: git::Oid,
+    ) {
+        self.save_index_cache(options);
+        if options.audit_log {
+            self.append_audit_log(&session.id, commit_oid);
+        }
 
         tracing::info!(
             project_id = %self.project.id,
@@ -488,14 +1091,207 @@ impl Repository {
             "flushed session"
         );
 
-        session_writer.remove()?;
+        // the session is committed already, a store that can't be trimmed is trimmed next time
+        if let Some(max_lfs_store_bytes) = options.max_lfs_store_bytes {
+            if let Err(error) = lfs::evict_locked(
+                &self.git_repository,
+                &self.lfs_objects_dir,
+                max_lfs_store_bytes,
+                options.lfs_keep_sessions,
+            ) {
+                tracing::warn!(
+                    project_id = %self.project.id,
+                    ?error,
+                    "failed to evict lfs objects"
+                );
+            }
+        }
+    }
 
-        let session = sessions::Session {
-            hash: Some(commit_oid),
-            ..session.clone()
+    // the subject of the session commit, followed by configured trailers and the ones of
+    // providers
+    fn commit_message(
+        &self,
+        options: &CaptureOptions,
+        session: &sessions::Session,
+        last_commit: Option<&git::Commit>,
+        wd_tree: git::Oid,
+    ) -> Result<String> {
+        if options.commit_trailers.is_empty() && self.trailer_providers.is_empty() {
+            return Ok(trailers::SUBJECT.to_string());
+        }
+        let changed_files = changed_files(self, last_commit, wd_tree)?;
+        let context = trailers::TrailerContext {
+            project_id: &self.project.id,
+            session,
+            changed_files: &changed_files,
         };
+        let mut commit_trailers = trailers::resolve(&options.commit_trailers, &context);
+        commit_trailers.extend(trailers::provide(&self.trailer_providers, &context));
+        Ok(trailers::message(&commit_trailers))
+    }
 
-        Ok(session)
+    // commits sessions that were captured, but failed to be committed, for example because the
+    // disk was full. returns ids of the written commits, oldest first.
+    pub fn flush_pending_sessions(&self, user: Option<&users::User>) -> Result<Vec<git::Oid>> {
+        let _lock = self.lock();
+        self.commit_pending_sessions(user)
+    }
+
+    fn commit_pending_sessions(&self, user: Option<&users::User>) -> Result<Vec<git::Oid>> {
+        let pending_sessions_path = self.pending_sessions_path();
+        let mut pending_sessions = pending::load(&pending_sessions_path)?;
+        if pending_sessions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut commit_oids = vec![];
+        let mut commit_error = None;
+        while let Some(pending_session) = pending_sessions.first() {
+            match write_gb_commit(
+                pending_session.tree_id,
+                self,
+                user,
+                pending_session
+                    .message
+                    .as_deref()
+                    .unwrap_or(trailers::SUBJECT),
+                pending_session.timestamp_ms,
+                pending_session.timezone_offset_minutes,
+                pending_session.anchor_commit,
+                &self.git_repository,
+            ) {
+                Result::Ok(commit_oid) => {
+                    if pending_session.audit_log {
+                        self.append_audit_log(&pending_session.session_id, commit_oid);
+                    }
+                    tracing::info!(
+                        project_id = %self.project.id,
+                        session_id = %pending_session.session_id,
+                        %commit_oid,
+                        "flushed pending session"
+                    );
+                    commit_oids.push(commit_oid);
+                    pending_sessions.remove(0);
+                }
+                Err(error) => {
+                    commit_error = Some(error);
+                    break;
+                }
+            }
+        }
+
+        pending::save(&pending_sessions_path, &pending_sessions)
+            .context("failed to save pending sessions")?;
+        match commit_error {
+            Some(error) => Err(error).context("failed to commit pending session"),
+            None => Ok(commit_oids),
+        }
+    }
+
+    // the session is committed already when it's appended, a failure leaves a gap in the log that
+    // is only logged
+    fn append_audit_log(&self, session_id: &SessionId, commit_oid: git::Oid) {
+        if let Err(error) = sessions::append_audit_log(&self.git_repository, session_id, commit_oid)
+        {
+            tracing::error!(
+                project_id = %self.project.id,
+                %session_id,
+                %commit_oid,
+                ?error,
+                "failed to append session to the audit log"
+            );
+        }
+    }
+
+    // trees of the session are kept, so that the session is committed later instead of being
+    // captured again. the session is removed, like after a successful flush.
+    fn queue_pending_session(
+        &self,
+        pending_session: PendingSession,
+        session_writer: &sessions::Writer,
+    ) {
+        // trees written into another store can't be committed later
+        if !self
+            .git_repository
+            .contains_object(pending_session.tree_id)
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        let pending_sessions_path = self.pending_sessions_path();
+        let session_id = pending_session.session_id;
+        let queued = pending::load(&pending_sessions_path).and_then(|mut pending_sessions| {
+            pending_sessions.push(pending_session);
+            pending::save(&pending_sessions_path, &pending_sessions)
+        });
+        if let Err(error) = queued.and_then(|()| session_writer.remove()) {
+            tracing::error!(
+                project_id = %self.project.id,
+                %session_id,
+                ?error,
+                "failed to queue pending session"
+            );
+            return;
+        }
+        tracing::warn!(
+            project_id = %self.project.id,
+            %session_id,
+            "failed to commit session, queued it to be committed later"
+        );
+    }
+
+    fn pending_sessions_path(&self) -> path::PathBuf {
+        pending::path(&self.git_repository)
+    }
+
+    // builds the working directory tree from all project files, without committing it. blobs of
+    // changed files are written into the object database.
+    //
+    // unlike flushing, this doesn't rely on deltas to find out what changed, so changes made while
+    // the app wasn't running are included too.
+    pub fn build_live_wd_tree(
+        &self,
+        project_repository: &project_repository::Repository,
+    ) -> Result<git::Oid> {
+        let _lock = self.lock();
+        let options = self.capture_options(project_repository)?;
+        let wd_tree =
+            build_wd_tree_from_repo(self, project_repository, &options, &self.git_repository)?;
+        self.save_index_cache(&options);
+        Ok(wd_tree)
+    }
+
+    fn capture_options(
+        &self,
+        project_repository: &project_repository::Repository,
+    ) -> Result<CaptureOptions> {
+        let mut options = CaptureOptions::try_from(project_repository)?;
+        if project_repository
+            .config()
+            .index_cache()
+            .context("failed to read gitbutler.indexCache")?
+        {
+            options.index_cache = Some(RefCell::new(IndexCache::load(&self.index_cache_path())));
+        }
+        Ok(options)
+    }
+
+    // the cache is an optimization, failing to save it is not worth failing the flush for
+    fn save_index_cache(&self, options: &CaptureOptions) {
+        if self.read_only {
+            return;
+        }
+        if let Some(index_cache) = &options.index_cache {
+            if let Err(error) = index_cache.borrow_mut().save(&self.index_cache_path()) {
+                tracing::warn!(project_id = %self.project.id, ?error, "failed to save index cache");
+            }
+        }
+    }
+
+    fn index_cache_path(&self) -> path::PathBuf {
+        self.git_repository.path().join("index-cache")
     }
 
     pub fn get_sessions_iterator(&self) -> Result<sessions::SessionsIterator<'_>> {
@@ -503,17 +1299,45 @@ impl Repository {
     }
 
     pub fn get_current_session(&self) -> Result<Option<sessions::Session>> {
+        // opening a reader creates the directory
+        if !self.root().exists() {
+            return Ok(None);
+        }
         let _lock = self.lock();
         let reader = reader::Reader::open(&self.root())?;
         match sessions::Session::try_from(&reader) {
             Ok(session) => Ok(Some(session)),
             Err(sessions::SessionError::NoSession) => Ok(None),
-            Err(sessions::SessionError::Other(err)) => Err(err),
+            Err(sessions::SessionError::Other(err)) if self.is_read_only() => Err(err),
+            // a crash while the meta was written leaves the session stuck, nothing would flush it
+            Err(sessions::SessionError::Other(error)) => {
+                let session = salvage_session_meta(self, &reader);
+                tracing::warn!(
+                    project_id = %self.project.id,
+                    session_id = %session.id,
+                    ?error,
+                    "session meta is corrupted, writing it again"
+                );
+                sessions::Writer::new(self)
+                    .context("failed to open session writer")?
+                    .rewrite(&session)
+                    .context("failed to repair session meta")?;
+                Ok(Some(session))
+            }
         }
     }
 
+    // makes sure directories that session readers and writers expect are there. this does not
+    // create a session.
+    pub fn ensure_scaffold(&self) -> Result<()> {
+        let session_path = self.session_path();
+        std::fs::create_dir_all(&session_path)
+            .with_context(|| format!("failed to create {}", session_path.display()))?;
+        Ok(())
+    }
+
     pub(crate) fn root(&self) -> std::path::PathBuf {
-        self.git_repository.path().join("gitbutler")
+        self.root.clone()
     }
 
     pub(crate) fn session_path(&self) -> std::path::PathBuf {
@@ -539,7 +1363,11 @@ impl Repository {
         }
     }
 
-    fn flush_gitbutler_file(&self, session_id: &SessionId) -> Result<()> {
+    fn flush_gitbutler_file(
+        &self,
+        project_repository: &project_repository::Repository,
+        session_id: &SessionId,
+    ) -> Result<()> {
         let gb_path = self.git_repository.path();
         let project_id = self.project.id.to_string();
         let gb_file_content = serde_json::json!({
@@ -549,7 +1377,10 @@ impl Repository {
             "api": self.project.api,
         });
 
-        let gb_file_path = self.project.path.join(".git/gitbutler.json");
+        let gb_file_path = project_repository
+            .git_repository
+            .path()
+            .join("gitbutler.json");
         std::fs::write(&gb_file_path, gb_file_content.to_string())?;
 
         tracing::debug!("gitbutler file updated: {:?}", gb_file_path);
@@ -560,33 +1391,788 @@ impl Repository {
     pub fn git_repository(&self) -> &git::Repository {
         &self.git_repository
     }
-}
 
-fn build_wd_tree(
-    gb_repository: &Repository,
-    project_repository: &project_repository::Repository,
-) -> Result<git::Oid> {
-    match gb_repository
-        .git_repository
-        .find_reference(&"refs/heads/current".parse().unwrap())
-    {
-        Result::Ok(reference) => build_wd_tree_from_reference(gb_repository, &reference)
-            .context("failed to build wd index"),
-        Err(git::Error::NotFound(_)) => build_wd_tree_from_repo(gb_repository, project_repository)
-            .context("failed to build wd index"),
-        Err(e) => Err(e.into()),
+    // large files of the project's sessions are stored here, by their sha256
+    pub(crate) fn lfs_objects_dir(&self) -> &path::Path {
+        &self.lfs_objects_dir
     }
 }
 
-fn build_wd_tree_from_reference(
-    gb_repository: &Repository,
-    reference: &git::Reference,
-) -> Result<git::Oid> {
-    // start off with the last tree as a base
-    let tree = reference.peel_to_tree()?;
-    let wd_tree_entry = tree.get_name("wd").unwrap();
-    let wd_tree = gb_repository.git_repository.find_tree(wd_tree_entry.id())?;
-    let mut index = git::Index::try_from(&wd_tree)?;
+// how a session is flushed, on top of what the project configures
+#[derive(Debug, Default, Clone, Copy)]
+struct FlushMode {
+    // a session that didn't change anything since the last one is discarded instead of committed
+    skip_unchanged: bool,
+    // files are captured as they are, even if they were modified within the settle window
+    ignore_settle_window: bool,
+}
+
+// options that affect what is captured when a session is flushed
+struct CaptureOptions {
+    // stream big files into the object database instead of reading them in one go, to bound memory
+    stream_large_files: bool,
+    // the filesystem is case insensitive (core.ignorecase), so Foo.txt and foo.txt are the same file
+    ignore_case: bool,
+    // windows paths that are too long are accessed with the extended-length prefix
+    // (core.longpaths)
+    long_paths: bool,
+    // also capture what is staged in the project's index, under the index subtree
+    capture_index: bool,
+    // also capture the reflog of the project's HEAD, under the logs subtree. only the newest
+    // entries are kept when there is a maximum.
+    capture_reflog: bool,
+    reflog_max_entries: Option<usize>,
+    // timestamp the commit with the last activity in the session instead of the current time
+    session_commit_timestamp: bool,
+    // offset of commit times in minutes, the local timezone is used when it's not set
+    commit_timezone: Option<i32>,
+    // trailers of the commit message, see gitbutler.commitTrailer
+    commit_trailers: Vec<trailers::Trailer>,
+    // working directory files modified within this window make the flush wait for the next cycle
+    settle_window: Option<time::Duration>,
+    // files bigger than this many bytes are stored as lfs objects
+    lfs_threshold: u64,
+    // files that are stored as blobs no matter how big they are, when any are configured
+    inline_paths: Option<git2::Pathspec>,
+    // lfs objects are encrypted with this key, when it's set
+    lfs_encryption_key: Option<lfs::EncryptionKey>,
+    // lfs objects that are not encrypted are stored as chunks
+    lfs_chunking: bool,
+    // the lfs store is kept below this many bytes after every flush, when it's set. objects of
+    // the newest lfs_keep_sessions sessions are never evicted.
+    max_lfs_store_bytes: Option<u64>,
+    lfs_keep_sessions: usize,
+    // keys of paths that are marked assume-unchanged or skip-worktree in the project's index,
+    // when those flags are respected
+    skipped_paths: HashSet<String>,
+    // blob ids of project files from previous flushes, when gitbutler.indexCache is enabled
+    index_cache: Option<RefCell<IndexCache>>,
+    // directories that are captured in full on every flush, even if they are ignored
+    force_capture_dirs: Vec<path::PathBuf>,
+    // record directories of the project that have nothing in them
+    capture_empty_dirs: bool,
+    // record ignored directories at the root of the project with their sizes
+    record_ignored_dirs: bool,
+    // extended attributes of captured files by path, when gitbutler.captureXattrs is enabled.
+    // starts off with the ones recorded with the last session.
+    xattrs: Option<RefCell<sessions::Xattrs>>,
+    // project files with merge conflict markers in them. newly hashed files are checked for
+    // markers, the others keep what was recorded with the last session.
+    conflicted_files: RefCell<BTreeSet<path::PathBuf>>,
+    // newly hashed files are checked for secrets with this, when gitbutler.scanSecrets is enabled
+    secret_scanner: Option<secrets::Scanner>,
+    // head commit of the project, recorded as the second parent of the session commit when
+    // gitbutler.anchorSessions is enabled
+    anchor_commit: Option<git::Oid>,
+    // append committed sessions to the audit log, see gitbutler.auditLog
+    audit_log: bool,
+    // shell command run in the project on every flush and how long it may take, see
+    // gitbutler.statusCommand
+    status_command: Option<(String, time::Duration)>,
+    // blobs of files that were already written during this capture, by absolute path
+    written_blobs: RefCell<HashMap<path::PathBuf, git::Oid>>,
+    // line endings of text files are normalized like git does it
+    line_endings: line_endings::Normalizer,
+}
+
+impl CaptureOptions {
+    // returns the key under which a path is tracked in the index. paths that point to the same
+    // file on disk have the same key.
+    fn path_key(&self, path: &path::Path) -> String {
+        let path = path.to_string_lossy();
+        if self.ignore_case {
+            path.to_lowercase()
+        } else {
+            path.to_string()
+        }
+    }
+
+    // writes a file into the store once per capture, a file that is reached by more than one
+    // tree builder is not read and hashed again
+    fn write_blob_path(&self, store: &dyn SessionStore, abs_path: &path::Path) -> Result<git::Oid> {
+        if let Some(blob) = self.written_blobs.borrow().get(abs_path) {
+            return Ok(*blob);
+        }
+        let blob = store.write_blob_path(abs_path)?;
+        self.written_blobs
+            .borrow_mut()
+            .insert(abs_path.to_path_buf(), blob);
+        Ok(blob)
+    }
+
+    // inline files are only stored as lfs objects when git can't store them
+    fn lfs_threshold(&self, path: &path::Path) -> u64 {
+        match &self.inline_paths {
+            Some(inline_paths) if inline_paths.matches_path(path, git2::PathspecFlags::DEFAULT) => {
+                project_repository::GIT_MAX_BLOB_SIZE
+            }
+            _ => self.lfs_threshold,
+        }
+    }
+
+    fn is_skipped(&self, path: &path::Path) -> bool {
+        self.skipped_paths.contains(&self.path_key(path))
+    }
+
+    fn is_force_captured(&self, path: &path::Path) -> bool {
+        self.force_capture_dirs
+            .iter()
+            .any(|dir| path.starts_with(dir))
+    }
+}
+
+// files bigger than this are streamed into the object database, when gitbutler.streamLargeFiles
+// is set. streaming only bounds the memory a capture needs to a fixed size buffer, it doesn't make
+// the capture faster: the file is still read and hashed in full. the threshold is a size below
+// which holding the file in memory is harmless, not a measured break-even point.
+const STREAM_THRESHOLD: u64 = 10_000_000;
+
+// assume-unchanged is stored in the flags, skip-worktree in the extended flags
+const INDEX_ENTRY_VALID: u16 = 0x8000;
+const INDEX_ENTRY_SKIP_WORKTREE: u16 = 0x4000;
+
+impl TryFrom<&project_repository::Repository> for CaptureOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(project_repository: &project_repository::Repository) -> Result<Self> {
+        let config = project_repository.config();
+        let settings = project_repository.settings()?;
+        let mut options = Self {
+            stream_large_files: config
+                .stream_large_files()
+                .context("failed to read gitbutler.streamLargeFiles")?,
+            ignore_case: config
+                .ignore_case()
+                .context("failed to read core.ignorecase")?,
+            long_paths: config
+                .long_paths()
+                .context("failed to read core.longpaths")?,
+            capture_index: config
+                .capture_index()
+                .context("failed to read gitbutler.captureIndex")?,
+            capture_reflog: config
+                .capture_reflog()
+                .context("failed to read gitbutler.captureReflog")?,
+            reflog_max_entries: config
+                .reflog_max_entries()
+                .context("failed to read gitbutler.reflogMaxEntries")?,
+            session_commit_timestamp: config
+                .session_commit_timestamp()
+                .context("failed to read gitbutler.commitTimestamp")?,
+            commit_timezone: config
+                .commit_timezone()
+                .context("failed to read gitbutler.commitTimezone")?
+                .map(|timezone| parse_commit_timezone(&timezone))
+                .transpose()?
+                .flatten(),
+            commit_trailers: config
+                .commit_trailers()
+                .context("failed to read gitbutler.commitTrailer")?
+                .iter()
+                .map(|trailer| trailers::Trailer::parse(trailer))
+                .collect::<Result<_>>()
+                .context("invalid gitbutler.commitTrailer")?,
+            settle_window: config
+                .settle_window()
+                .context("failed to read gitbutler.settleWindowMs")?,
+            lfs_threshold: settings.lfs_threshold,
+            inline_paths: (!settings.inline_paths.is_empty())
+                .then(|| git2::Pathspec::new(settings.inline_paths.iter().map(String::as_str)))
+                .transpose()
+                .context("invalid gitbutler.inlinePaths, expected a list of globs")?,
+            lfs_encryption_key: lfs::encryption_key(&config)?,
+            lfs_chunking: config
+                .lfs_chunking()
+                .context("failed to read gitbutler.lfsChunking")?,
+            max_lfs_store_bytes: settings.max_lfs_store_bytes,
+            lfs_keep_sessions: settings.lfs_keep_sessions,
+            skipped_paths: HashSet::new(),
+            index_cache: None,
+            force_capture_dirs: settings.force_capture_dirs,
+            capture_empty_dirs: config
+                .capture_empty_dirs()
+                .context("failed to read gitbutler.captureEmptyDirs")?,
+            record_ignored_dirs: config
+                .record_ignored_dirs()
+                .context("failed to read gitbutler.recordIgnoredDirs")?,
+            xattrs: config
+                .capture_xattrs()
+                .context("failed to read gitbutler.captureXattrs")?
+                .then(RefCell::default),
+            conflicted_files: RefCell::default(),
+            status_command: config
+                .status_command()
+                .context("failed to read gitbutler.statusCommand")?
+                .filter(|command| !command.trim().is_empty())
+                .map(|command| -> Result<_> {
+                    let timeout = config
+                        .status_command_timeout()
+                        .context("failed to read gitbutler.statusCommandTimeout")?
+                        .unwrap_or(status_command::DEFAULT_TIMEOUT);
+                    Ok((command, timeout))
+                })
+                .transpose()?,
+            secret_scanner: if config
+                .scan_secrets()
+                .context("failed to read gitbutler.scanSecrets")?
+            {
+                Some(secrets::Scanner::new(
+                    &config
+                        .secret_patterns()
+                        .context("failed to read gitbutler.secretPattern")?,
+                )?)
+            } else {
+                None
+            },
+            anchor_commit: if config
+                .anchor_sessions()
+                .context("failed to read gitbutler.anchorSessions")?
+            {
+                // there is nothing to anchor to before the first commit
+                project_repository
+                    .git_repository
+                    .head()
+                    .and_then(|head| head.peel_to_commit())
+                    .map(|commit| commit.id())
+                    .ok()
+            } else {
+                None
+            },
+            audit_log: config
+                .audit_log()
+                .context("failed to read gitbutler.auditLog")?,
+            written_blobs: RefCell::default(),
+            line_endings: line_endings::Normalizer::new(project_repository)?,
+        };
+
+        if settings.respect_index_flags {
+            // the index might be mid-write by another git process, for example during `git add`.
+            // capturing every file this time is better than not capturing at all.
+            match project_repository.git_repository.index() {
+                Result::Ok(project_index) => {
+                    options.skipped_paths = project_index
+                        .iter()
+                        .filter(|entry| {
+                            entry.flags & INDEX_ENTRY_VALID != 0
+                                || entry.flags_extended & INDEX_ENTRY_SKIP_WORKTREE != 0
+                        })
+                        .map(|entry| {
+                            options
+                                .path_key(path::Path::new(&*String::from_utf8_lossy(&entry.path)))
+                        })
+                        .collect();
+                }
+                Err(error) => {
+                    tracing::warn!(
+                        project_id = %project_repository.project().id,
+                        ?error,
+                        "failed to read project index, index flags are ignored this time"
+                    );
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+// the objects of the project, to be used as an alternate. projects added before non-standard
+// layouts were supported have .git/objects in their alternates, it's kept for them.
+fn project_objects_path(project_repository: &project_repository::Repository) -> path::PathBuf {
+    let dotgit_objects_path = project_repository.path().join(".git/objects");
+    if dotgit_objects_path.exists() {
+        dotgit_objects_path
+    } else {
+        project_repository
+            .git_repository
+            .commondir()
+            .join("objects")
+    }
+}
+
+fn validate_subtrees(subtrees: &[(&str, git::Oid)]) -> Result<(), Error> {
+    let mut names = HashSet::new();
+    for (name, _) in subtrees {
+        if name.is_empty()
+            || name.contains('/')
+            || BUILTIN_SUBTREES.contains(name)
+            || !names.insert(*name)
+        {
+            return Err(Error::InvalidSubtree((*name).to_string()));
+        }
+    }
+    Ok(())
+}
+
+// stashes are informational only, a repository that can't list them has none recorded
+fn read_stashes(project_repository: &project_repository::Repository) -> Vec<sessions::StashRef> {
+    match project_repository.git_repository.stashes() {
+        Result::Ok(stashes) => stashes
+            .into_iter()
+            .map(|(oid, message)| sessions::StashRef { oid, message })
+            .collect(),
+        Err(error) => {
+            tracing::warn!(
+                project_id = %project_repository.project().id,
+                ?error,
+                "failed to read stashes"
+            );
+            vec![]
+        }
+    }
+}
+
+// the sparse checkout is informational too, files that are not checked out are not captured
+// either way. it's only used to keep them when an older session is checked out.
+fn read_sparse_checkout(
+    project_repository: &project_repository::Repository,
+) -> Option<sessions::SparseCheckout> {
+    let read = || -> Result<Option<sessions::SparseCheckout>> {
+        let config = project_repository.config();
+        if !config.sparse_checkout()? {
+            return Ok(None);
+        }
+        let sparse_checkout_path = project_repository
+            .git_repository
+            .path()
+            .join("info")
+            .join("sparse-checkout");
+        let patterns = match std::fs::read_to_string(&sparse_checkout_path) {
+            Result::Ok(content) => content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(ToString::to_string)
+                .collect(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(error) => return Err(error).context("failed to read sparse-checkout"),
+        };
+        Ok(Some(sessions::SparseCheckout {
+            cone: config.sparse_checkout_cone()?,
+            patterns,
+        }))
+    };
+    match read() {
+        Result::Ok(sparse_checkout) => sparse_checkout,
+        Err(error) => {
+            tracing::warn!(
+                project_id = %project_repository.project().id,
+                ?error,
+                "failed to read sparse checkout"
+            );
+            None
+        }
+    }
+}
+
+// returns the empty directories of the project that would be captured if they had files in them
+fn read_empty_dirs(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+) -> Result<Vec<path::PathBuf>> {
+    let git_paths = project_repository.git_paths();
+    let skip_dir = |dir: &path::Path| is_ignored_dir(project_repository, options, dir);
+    let empty_dirs = fs::list_empty_dirs(project_repository.root(), &git_paths, &skip_dir)?
+        .into_iter()
+        .filter(|dir| !options.is_skipped(dir))
+        .filter(|dir| {
+            options.is_force_captured(dir)
+                || !project_repository
+                    .git_repository
+                    .is_path_ignored(dir)
+                    .unwrap_or(true)
+        })
+        .collect();
+    Ok(empty_dirs)
+}
+
+// returns the ignored directories at the root of the project, like target or node_modules, with
+// the total size of their files. directories that are force captured are not ignored.
+fn read_ignored_dirs(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+) -> Result<Vec<sessions::IgnoredDir>> {
+    let root = project_repository.root();
+    let git_paths = project_repository.git_paths();
+    let mut ignored_dirs = vec![];
+    for entry in
+        std::fs::read_dir(root).with_context(|| format!("failed to read {}", root.display()))?
+    {
+        let entry = entry?;
+        let dir = path::PathBuf::from(entry.file_name());
+        if !entry.file_type()?.is_dir()
+            || git_paths.iter().any(|git_path| dir.starts_with(git_path))
+            || options.is_force_captured(&dir)
+            || !project_repository
+                .git_repository
+                .is_path_ignored(&dir)
+                .unwrap_or(false)
+        {
+            continue;
+        }
+        let size = fs::dir_size(root.join(&dir))?;
+        ignored_dirs.push(sessions::IgnoredDir { path: dir, size });
+    }
+    ignored_dirs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ignored_dirs)
+}
+
+// git trees can't have empty directories, they are recorded with the session meta instead. ignored
+// directories are too big to capture, only their sizes are recorded.
+fn write_project_dirs(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    session_writer: &sessions::Writer,
+) -> Result<()> {
+    let empty_dirs = if options.capture_empty_dirs {
+        read_empty_dirs(project_repository, options).context("failed to list empty directories")?
+    } else {
+        vec![]
+    };
+    session_writer
+        .write_empty_dirs(&empty_dirs)
+        .context("failed to write empty directories")?;
+
+    let ignored_dirs = if options.record_ignored_dirs {
+        read_ignored_dirs(project_repository, options)
+            .context("failed to list ignored directories")?
+    } else {
+        vec![]
+    };
+    session_writer
+        .write_ignored_dirs(&ignored_dirs)
+        .context("failed to write ignored directories")
+}
+
+// records the extended attributes and conflicted files collected while capturing, without the
+// files that are gone
+fn write_captured_files(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    session_writer: &sessions::Writer,
+) -> Result<()> {
+    let exists = |path: &path::Path| {
+        project_repository
+            .root()
+            .join(path)
+            .symlink_metadata()
+            .is_ok()
+    };
+    if let Some(xattrs) = &options.xattrs {
+        let mut xattrs = xattrs.borrow_mut();
+        xattrs.retain(|path, _| exists(path));
+        session_writer
+            .write_xattrs(&xattrs)
+            .context("failed to write xattrs")?;
+    }
+    let mut conflicted_files = options.conflicted_files.borrow_mut();
+    conflicted_files.retain(|path| exists(path));
+    session_writer
+        .write_conflicted_files(&conflicted_files.iter().cloned().collect::<Vec<_>>())
+        .context("failed to write conflicted files")
+}
+
+// returns true if the current session has no deltas or metadata, was started on the same head as
+// the last session, and the given trees are the same as in the last session.
+fn is_unchanged(
+    gb_repository: &Repository,
+    last_commit: Option<&git::Commit>,
+    wd_tree: git::Oid,
+    branches_tree: git::Oid,
+    index_tree: Option<git::Oid>,
+    logs_tree: Option<git::Oid>,
+) -> Result<bool> {
+    let session_reader = reader::Reader::open(&gb_repository.root())?;
+    let current_session =
+        sessions::Session::try_from(&session_reader).context("failed to read current session")?;
+    if !current_session.meta.metadata.is_empty() {
+        return Ok(false);
+    }
+    let deltas = deltas::Reader::from(&session_reader)
+        .read(None)
+        .context("failed to read deltas")?;
+    if !deltas.is_empty() {
+        return Ok(false);
+    }
+
+    let Some(last_commit) = last_commit else {
+        return Ok(false);
+    };
+    // the bootstrap commit doesn't have a session to compare with
+    if last_commit.parent_count() == 0 {
+        return Ok(false);
+    }
+    let last_reader = reader::Reader::from_commit(&gb_repository.git_repository, last_commit)?;
+    let last_session = match sessions::Session::try_from(&last_reader) {
+        Result::Ok(session) => session,
+        Err(sessions::SessionError::NoSession) => return Ok(false),
+        Err(sessions::SessionError::Other(error)) => return Err(error),
+    };
+    if last_session.meta.branch != current_session.meta.branch
+        || last_session.meta.commit != current_session.meta.commit
+        || last_session.meta.detached != current_session.meta.detached
+        || last_session.meta.stashes != current_session.meta.stashes
+        || last_session.meta.sparse_checkout != current_session.meta.sparse_checkout
+    {
+        return Ok(false);
+    }
+
+    let last_tree = last_commit.tree()?;
+    let last_tree_id = |name: &str| last_tree.get_name(name).map(|entry| entry.id());
+    Ok(last_tree_id("wd") == Some(wd_tree)
+        && last_tree_id("branches") == Some(branches_tree)
+        && last_tree_id("index") == index_tree
+        && last_tree_id("logs") == logs_tree)
+}
+
+// files of the working directory tree that differ from the last flushed session, all of them
+// when there is none
+fn changed_files(
+    gb_repository: &Repository,
+    last_commit: Option<&git::Commit>,
+    wd_tree: git::Oid,
+) -> Result<Vec<path::PathBuf>> {
+    let repository = <&git2::Repository>::from(&gb_repository.git_repository);
+    let old_tree = match last_commit {
+        Some(commit) => commit
+            .tree()?
+            .get_name("wd")
+            .map(|entry| repository.find_tree(entry.id().into()))
+            .transpose()
+            .context("failed to find previous wd tree")?,
+        None => None,
+    };
+    let new_tree = repository
+        .find_tree(wd_tree.into())
+        .context("failed to find wd tree")?;
+    let diff = repository
+        .diff_tree_to_tree(old_tree.as_ref(), Some(&new_tree), None)
+        .context("failed to diff wd trees")?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| {
+            delta
+                .new_file()
+                .path()
+                .or_else(|| delta.old_file().path())
+                .map(path::Path::to_path_buf)
+        })
+        .collect())
+}
+
+fn build_wd_tree(
+    gb_repository: &Repository,
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+    last_commit: Option<&git::Commit>,
+) -> Result<git::Oid> {
+    match last_commit {
+        Some(commit) => build_wd_tree_from_commit(gb_repository, commit, options, store)
+            .context("failed to build wd index"),
+        None => build_wd_tree_from_repo(gb_repository, project_repository, options, store)
+            .context("failed to build wd index"),
+    }
+}
+
+// returns the commit of the last flushed session. a ref that points to a commit that doesn't
+// exist anymore, for example after an aggressive gc, is treated as missing, so that a new chain
+// of sessions is started instead of failing every flush.
+fn find_current_commit(gb_repository: &Repository) -> Result<Option<git::Commit<'_>>> {
+    let reference = match gb_repository
+        .git_repository
+        .find_reference(&"refs/heads/current".parse().unwrap())
+    {
+        Result::Ok(reference) => reference,
+        Err(git::Error::NotFound(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    match reference.peel_to_commit() {
+        Result::Ok(commit) => Ok(Some(commit)),
+        Err(git::Error::NotFound(error)) => {
+            tracing::warn!(
+                project_id = %gb_repository.project.id,
+                target = ?reference.target(),
+                ?error,
+                "current session commit is missing, starting a new chain"
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// name of the gitbutler repository config key that records which project branch the history in
+// refs/heads/current belongs to
+const HISTORY_BRANCH_KEY: &str = "gitbutler.historyBranch";
+
+// histories of branches that are not checked out are kept under this prefix, by branch name
+fn branch_history_refname(branch: &str) -> Result<git::Refname> {
+    format!("refs/gitbutler/branches/{branch}")
+        .parse()
+        .context("invalid branch history refname")
+}
+
+// refs/heads/current always holds the history of the checked out branch, everything that reads
+// sessions keeps working with it. when the project is on another branch than the history belongs
+// to, the history is parked under the branch it belongs to, and the history of the checked out
+// branch takes its place. a branch that has no history yet starts a new one.
+//
+// a detached head keeps appending to the history of the branch it was detached from.
+fn switch_branch_history(
+    gb_repository: &Repository,
+    project_repository: &project_repository::Repository,
+) -> Result<()> {
+    let head = match project_repository.get_head() {
+        Result::Ok(head) => head,
+        // unborn branch
+        Err(git::Error::NotFound(_)) => return Ok(()),
+        Err(error) => return Err(error).context("failed to read project head"),
+    };
+    let Some(branch) = head
+        .name()
+        .filter(|name| matches!(name, git::Refname::Local(_)))
+        .and_then(|name| name.branch().map(ToString::to_string))
+    else {
+        return Ok(());
+    };
+
+    let git_repository = &gb_repository.git_repository;
+    let mut config = git_repository
+        .config()
+        .context("failed to open gitbutler repository config")?;
+    let history_branch = config
+        .get_string(HISTORY_BRANCH_KEY)
+        .context("failed to read history branch")?;
+    if history_branch.as_deref() == Some(branch.as_str()) {
+        return Ok(());
+    }
+
+    let current_refname: git::Refname = "refs/heads/current".parse().unwrap();
+    // history that was recorded before branches were enabled is adopted by the first branch
+    if let Some(history_branch) = &history_branch {
+        if let Some(current) = find_current_commit(gb_repository)? {
+            git_repository
+                .reference(
+                    &branch_history_refname(history_branch)?,
+                    current.id(),
+                    true,
+                    "park branch history",
+                )
+                .context("failed to park branch history")?;
+        }
+
+        match git_repository.find_reference(&branch_history_refname(&branch)?) {
+            Result::Ok(mut parked) => {
+                let parked_oid = parked
+                    .target()
+                    .context("branch history ref is not a direct ref")?;
+                git_repository
+                    .reference(&current_refname, parked_oid, true, "switch branch history")
+                    .context("failed to switch branch history")?;
+                parked
+                    .delete()
+                    .context("failed to delete parked branch history")?;
+            }
+            Err(git::Error::NotFound(_)) => match git_repository.find_reference(&current_refname) {
+                Result::Ok(mut current) => {
+                    current.delete().context("failed to delete current ref")?
+                }
+                Err(git::Error::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    config
+        .set_str(HISTORY_BRANCH_KEY, &branch)
+        .context("failed to record history branch")?;
+
+    tracing::info!(
+        project_id = %gb_repository.project.id,
+        from = ?history_branch,
+        to = %branch,
+        "switched session history"
+    );
+
+    Ok(())
+}
+
+// builds a tree of what is currently staged in the project repository. staged blobs only exist in
+// the project repository, so they are copied over into the store.
+fn build_index_tree(
+    project_repository: &project_repository::Repository,
+    store: &dyn SessionStore,
+) -> Result<git::Oid> {
+    let project_index = project_repository
+        .git_repository
+        .index()
+        .context("failed to open project index")?;
+
+    let mut index = git::Index::new()?;
+    for entry in project_index.iter() {
+        // conflicting entries can't be written into a tree. stage is stored in the flags
+        if (entry.flags >> 12) & 0x3 != 0 {
+            continue;
+        }
+
+        // submodules point to commits in another repository, there is nothing to copy
+        if entry.mode != 0o160_000 {
+            let blob = project_repository
+                .git_repository
+                .find_blob(entry.id)
+                .with_context(|| {
+                    format!(
+                        "failed to find staged blob for {}",
+                        String::from_utf8_lossy(&entry.path)
+                    )
+                })?;
+            store
+                .write_blob(blob.content())
+                .context("failed to copy staged blob")?;
+        }
+
+        index
+            .add(&entry)
+            .with_context(|| format!("failed to add {}", String::from_utf8_lossy(&entry.path)))?;
+    }
+
+    let tree_oid = store
+        .write_index(&mut index)
+        .context("failed to write index tree")?;
+    Ok(tree_oid)
+}
+
+fn build_wd_tree_from_commit(
+    gb_repository: &Repository,
+    commit: &git::Commit,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<git::Oid> {
+    // start off with the last tree as a base
+    let tree = commit.tree()?;
+    let wd_tree_entry = tree.get_name("wd").unwrap();
+    let wd_tree = gb_repository.git_repository.find_tree(wd_tree_entry.id())?;
+    let mut index = git::Index::try_from(&wd_tree)?;
+
+    // paths as they are stored in the last tree, by key
+    let mut indexed_paths = if options.ignore_case {
+        index
+            .iter()
+            .filter_map(|entry| String::from_utf8(entry.path).ok())
+            .map(|entry_path| (options.path_key(path::Path::new(&entry_path)), entry_path))
+            .collect::<HashMap<_, _>>()
+    } else {
+        HashMap::new()
+    };
+
+    // force captured directories are not watched, so they are captured again in full. this
+    // happens before session files are added, as those are in sync with the deltas.
+    for dir in &options.force_capture_dirs {
+        add_force_captured_dir(&mut index, dir, gb_repository, options, store)
+            .with_context(|| format!("failed to capture {}", dir.display()))?;
+    }
 
     // write updated files on top of the last tree
     for file_path in fs::list_files(gb_repository.session_wd_path(), &[]).with_context(|| {
@@ -595,11 +2181,27 @@ fn build_wd_tree_from_reference(
             gb_repository.session_wd_path().display()
         )
     })? {
+        if options.is_skipped(&file_path) {
+            continue;
+        }
+
+        // the same file might be indexed with a different casing, replace it instead of
+        // adding a duplicate entry
+        if let Some(indexed_path) = indexed_paths.remove(&options.path_key(&file_path)) {
+            if path::Path::new(&indexed_path) != file_path {
+                index
+                    .remove_path(path::Path::new(&indexed_path))
+                    .context("failed to remove path")?;
+            }
+        }
+
         add_wd_path(
             &mut index,
             &gb_repository.session_wd_path(),
             &file_path,
             gb_repository,
+            options,
+            store,
         )
         .with_context(|| {
             format!(
@@ -622,23 +2224,55 @@ fn build_wd_tree_from_reference(
         .filter(|key| !wd_files.contains(key))
         .collect::<Vec<_>>();
 
-    for deleted_file in deleted_files {
+    for deleted_file in &deleted_files {
         index
             .remove_path(deleted_file)
             .context("failed to remove path")?;
     }
 
-    let wd_tree_oid = index
-        .write_tree_to(&gb_repository.git_repository)
+    let wd_tree_oid = store
+        .write_index(&mut index)
         .context("failed to write wd tree")?;
+    tracing::debug!(
+        target: logs::WATCHER,
+        project_id = %gb_repository.project.id,
+        changed_files = wd_files.len(),
+        deleted_files = deleted_files.len(),
+        %wd_tree_oid,
+        "built working directory tree from the last session"
+    );
     Ok(wd_tree_oid)
 }
 
+// whether a directory of the project is ignored as a whole. a pattern like `build/` matches
+// directories only, so the path is checked with a trailing slash. files in an ignored directory
+// can't be included again by a negated pattern, git doesn't look into it either.
+//
+// directories that lead to a force captured one, or are in one, are never ignored.
+fn is_ignored_dir(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    dir: &path::Path,
+) -> bool {
+    if options.force_capture_dirs.iter().any(|force_capture_dir| {
+        force_capture_dir.starts_with(dir) || dir.starts_with(force_capture_dir)
+    }) {
+        return false;
+    }
+    // when in doubt, the directory is walked and its files are checked instead
+    project_repository
+        .git_repository
+        .is_path_ignored(dir.join(""))
+        .unwrap_or(false)
+}
+
 // build wd index from the working directory files new session wd files
 // this is important because we want to make sure session files are in sync with session deltas
 fn build_wd_tree_from_repo(
     gb_repository: &Repository,
     project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
 ) -> Result<git::Oid> {
     let mut index = git::Index::new()?;
 
@@ -652,10 +2286,15 @@ fn build_wd_tree_from_repo(
             gb_repository.session_wd_path().display()
         )
     })? {
-        if project_repository
-            .git_repository
-            .is_path_ignored(&file_path)
-            .unwrap_or(true)
+        if options.is_skipped(&file_path) {
+            continue;
+        }
+
+        if !options.is_force_captured(&file_path)
+            && project_repository
+                .git_repository
+                .is_path_ignored(&file_path)
+                .unwrap_or(true)
         {
             continue;
         }
@@ -665,6 +2304,8 @@ fn build_wd_tree_from_repo(
             &gb_repository.session_wd_path(),
             &file_path,
             gb_repository,
+            options,
+            store,
         )
         .with_context(|| {
             format!(
@@ -672,27 +2313,13 @@ fn build_wd_tree_from_repo(
                 file_path.display()
             )
         })?;
-        added.insert(file_path.to_string_lossy().to_string(), true);
+        added.insert(options.path_key(&file_path), true);
     }
 
     // finally, add files from the working directory if they aren't already in the index
-    for file_path in fs::list_files(project_repository.root(), &[path::Path::new(".git")])
-        .with_context(|| {
-            format!(
-                "failed to working directory list files in {}",
-                project_repository.root().display()
-            )
-        })?
-    {
-        if added.contains_key(&file_path.to_string_lossy().to_string()) {
-            continue;
-        }
-
-        if project_repository
-            .git_repository
-            .is_path_ignored(&file_path)
-            .unwrap_or(true)
-        {
+    let mut project_files = 0;
+    for file_path in project_file_paths(project_repository, options)? {
+        if added.contains_key(&options.path_key(&file_path)) {
             continue;
         }
 
@@ -701,6 +2328,8 @@ fn build_wd_tree_from_repo(
             project_repository.root(),
             &file_path,
             gb_repository,
+            options,
+            store,
         )
         .with_context(|| {
             format!(
@@ -708,14 +2337,148 @@ fn build_wd_tree_from_repo(
                 file_path.display()
             )
         })?;
+        project_files += 1;
     }
 
-    let tree_oid = index
-        .write_tree_to(&gb_repository.git_repository)
+    let tree_oid = store
+        .write_index(&mut index)
         .context("failed to write tree to repo")?;
+    tracing::debug!(
+        target: logs::WATCHER,
+        project_id = %gb_repository.project.id,
+        session_files = added.len(),
+        project_files,
+        %tree_oid,
+        "built working directory tree from project files"
+    );
     Ok(tree_oid)
 }
 
+// project files that are captured when there is no last session to build on. ignored
+// directories are not walked into, the other files are checked one by one.
+fn project_file_paths(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+) -> Result<Vec<path::PathBuf>> {
+    let git_paths = project_repository.git_paths();
+    let skip_dir = |dir: &path::Path| is_ignored_dir(project_repository, options, dir);
+    let file_paths = fs::list_files_pruned(project_repository.root(), &git_paths, &skip_dir)
+        .with_context(|| {
+            format!(
+                "failed to working directory list files in {}",
+                project_repository.root().display()
+            )
+        })?;
+    Ok(file_paths
+        .into_iter()
+        .filter(|file_path| !options.is_skipped(file_path))
+        .filter(|file_path| {
+            options.is_force_captured(file_path)
+                || !project_repository
+                    .git_repository
+                    .is_path_ignored(file_path)
+                    .unwrap_or(true)
+        })
+        .collect())
+}
+
+// fails with Error::FileNotSettled when a project file that is about to be captured was modified
+// within the settle window. it's checked before anything is captured, so that a flush that has to
+// wait doesn't leave the blobs and lfs objects of the files before it behind.
+//
+// session wd files are written by us, so only project files might be incomplete.
+fn check_settled(
+    gb_repository: &Repository,
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    last_commit: Option<&git::Commit>,
+) -> Result<()> {
+    let Some(settle_window) = options.settle_window else {
+        return Ok(());
+    };
+
+    let project_root = project_repository.root();
+    let file_paths = if last_commit.is_some() {
+        // on top of the last session, only force captured directories are read from the project
+        let mut file_paths = vec![];
+        for dir in &options.force_capture_dirs {
+            for file_path in fs::list_files(project_root.join(dir), &[])? {
+                let file_path = dir.join(file_path);
+                if !options.is_skipped(&file_path) {
+                    file_paths.push(file_path);
+                }
+            }
+        }
+        file_paths
+    } else {
+        // files that have a session wd copy are captured from it instead
+        let session_files = fs::list_files(gb_repository.session_wd_path(), &[])?
+            .iter()
+            .map(|file_path| options.path_key(file_path))
+            .collect::<HashSet<_>>();
+        project_file_paths(project_repository, options)?
+            .into_iter()
+            .filter(|file_path| !session_files.contains(&options.path_key(file_path)))
+            .collect()
+    };
+
+    let now = gb_repository.now();
+    for file_path in file_paths {
+        let Some(file_path) = accessible_path(&project_root.join(file_path), options.long_paths)
+        else {
+            continue;
+        };
+        // files that are gone by now are left to the capture
+        let Ok(metadata) = std::fs::symlink_metadata(&file_path) else {
+            continue;
+        };
+        if is_recently_modified(&metadata, settle_window, now) {
+            return Err(Error::FileNotSettled(file_path).into());
+        }
+    }
+    Ok(())
+}
+
+// replaces everything under the directory in the index with what is currently in the project
+fn add_force_captured_dir(
+    index: &mut git::Index,
+    dir: &path::Path,
+    gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<()> {
+    let stale_paths = index
+        .iter()
+        .filter_map(|entry| String::from_utf8(entry.path).ok())
+        .map(path::PathBuf::from)
+        .filter(|entry_path| entry_path.starts_with(dir))
+        .collect::<Vec<_>>();
+    for stale_path in stale_paths {
+        index
+            .remove_path(&stale_path)
+            .context("failed to remove path")?;
+    }
+
+    let project_root = path::Path::new(&gb_repository.project.path);
+    for file_path in fs::list_files(project_root.join(dir), &[])? {
+        let file_path = dir.join(file_path);
+        if options.is_skipped(&file_path) {
+            continue;
+        }
+        add_wd_path(
+            index,
+            project_root,
+            &file_path,
+            gb_repository,
+            options,
+            store,
+        )
+        .with_context(|| format!("failed to add path {}", file_path.display()))?;
+    }
+
+    Ok(())
+}
+
 // take a file path we see and add it to our in-memory index
 // we call this from build_initial_wd_tree, which is smart about using the existing index to avoid rehashing files that haven't changed
 // and also looks for large files and puts in a placeholder hash in the LFS format
@@ -725,28 +2488,53 @@ fn add_wd_path(
     dir: &std::path::Path,
     rel_file_path: &std::path::Path,
     gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
 ) -> Result<()> {
-    let file_path = dir.join(rel_file_path);
+    // a path that is too long fails with errors that don't say why, and would fail the whole
+    // capture
+    let Some(file_path) = accessible_path(&dir.join(rel_file_path), options.long_paths) else {
+        tracing::warn!(
+            project_id = %gb_repository.project.id,
+            path = %dir.join(rel_file_path).display(),
+            max_len = MAX_PATH_LEN,
+            "path is too long, skipping"
+        );
+        return Ok(());
+    };
 
     let metadata = std::fs::symlink_metadata(&file_path).context("failed to get metadata for")?;
+
+    // fifos, sockets and devices can't be stored in git, and reading a fifo blocks until someone
+    // writes to it
+    if !metadata.is_file() && !metadata.is_symlink() {
+        tracing::trace!(
+            target: logs::WATCHER,
+            project_id = %gb_repository.project.id,
+            path = %file_path.display(),
+            file_type = ?metadata.file_type(),
+            "not a regular file, skipping"
+        );
+        return Ok(());
+    }
+
     let modify_time = FileTime::from_last_modification_time(&metadata);
     let create_time = FileTime::from_creation_time(&metadata).unwrap_or(modify_time);
 
-    // look for files that are bigger than 4GB, which are not supported by git
+    // look for files that are bigger than the lfs threshold, which git doesn't handle well
     // insert a pointer as the blob content instead
-    // TODO: size limit should be configurable
     let blob = if metadata.is_symlink() {
         // it's a symlink, make the content the path of the link
         let link_target = std::fs::read_link(&file_path)?;
         // if the link target is inside the project repository, make it relative
         let link_target = link_target.strip_prefix(dir).unwrap_or(&link_target);
-        gb_repository.git_repository.blob(
+        store.write_blob(
             link_target
                 .to_str()
                 .ok_or_else(|| Error::InvalidUnicodePath(link_target.into()))?
                 .as_bytes(),
         )?
-    } else if metadata.len() > 100_000_000 {
+    } else if is_lfs_file(metadata.len(), options.lfs_threshold(rel_file_path)) {
         tracing::warn!(
             project_id = %gb_repository.project.id,
             path = %file_path.display(),
@@ -756,26 +2544,51 @@ fn add_wd_path(
         // get a sha256 hash of the file first
         let sha = sha256_digest(&file_path)?;
 
-        // put togther a git lfs pointer file: https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md
-        let mut lfs_pointer = String::from("version https://git-lfs.github.com/spec/v1\n");
-        lfs_pointer.push_str("oid sha256:");
-        lfs_pointer.push_str(&sha);
-        lfs_pointer.push('\n');
-        lfs_pointer.push_str("size ");
-        lfs_pointer.push_str(&metadata.len().to_string());
-        lfs_pointer.push('\n');
-
-        // write the file to the .git/lfs/objects directory
-        // create the directory recursively if it doesn't exist
-        let lfs_objects_dir = gb_repository.git_repository.path().join("lfs/objects");
-        std::fs::create_dir_all(lfs_objects_dir.clone())?;
-        let lfs_path = lfs_objects_dir.join(sha);
-        std::fs::copy(file_path, lfs_path)?;
-
-        gb_repository.git_repository.blob(lfs_pointer.as_bytes())?
+        // write the file to the lfs store and put together a git lfs pointer file for it
+        let lfs_pointer = if gb_repository.read_only {
+            match options.lfs_encryption_key {
+                Some(_) => lfs::LfsPointer::new_encrypted(sha, metadata.len()),
+                None if options.lfs_chunking => lfs::LfsPointer::new_chunked(sha, metadata.len()),
+                None => lfs::LfsPointer::new(sha, metadata.len()),
+            }
+        } else if options.lfs_chunking && options.lfs_encryption_key.is_none() {
+            lfs::write_chunked_object(
+                &gb_repository.lfs_objects_dir,
+                &file_path,
+                &sha,
+                metadata.len(),
+            )?
+        } else {
+            lfs::write_object(
+                &gb_repository.lfs_objects_dir,
+                &file_path,
+                &sha,
+                metadata.len(),
+                options.lfs_encryption_key.as_ref(),
+            )?
+        };
+
+        store.write_blob(&lfs_pointer.to_bytes())?
     } else {
-        // read the file into a blob, get the object id
-        gb_repository.git_repository.blob_path(&file_path)?
+        write_file_blob(
+            dir,
+            rel_file_path,
+            &file_path,
+            &metadata,
+            gb_repository,
+            options,
+            store,
+        )?
+    };
+
+    // session wd files are copies written by us, their mode is taken from the project file
+    let mode = if dir == gb_repository.session_wd_path() {
+        match std::fs::metadata(gb_repository.project.path.join(rel_file_path)) {
+            Result::Ok(project_metadata) => git_file_mode(&project_metadata),
+            Err(_) => git_file_mode(&metadata),
+        }
+    } else {
+        git_file_mode(&metadata)
     };
 
     // create a new IndexEntry from the file metadata
@@ -787,20 +2600,260 @@ fn add_wd_path(
             mtime: modify_time,
             dev: metadata.dev() as u32,
             ino: metadata.ino() as u32,
-            mode: 33188,
+            mode,
             uid: metadata.uid(),
             gid: metadata.gid(),
             file_size: metadata.len() as u32,
             flags: 10, // normal flags for normal file (for the curious: https://git-scm.com/docs/index-format)
             flags_extended: 0, // no extended flags
-            path: rel_file_path.to_str().unwrap().to_string().into(),
+            path: index_path(rel_file_path)?.into(),
             id: blob,
         })
         .with_context(|| format!("failed to add index entry for {}", rel_file_path.display()))?;
 
+    if let Some(xattrs) = &options.xattrs {
+        record_xattrs(xattrs, dir, rel_file_path, &file_path, gb_repository);
+    }
+
     Ok(())
 }
 
+// records the extended attributes of a captured file. session wd files are copies written by us,
+// their attributes are taken from the project file. attributes that can't be read are kept as
+// they were recorded before, they don't fail the capture.
+fn record_xattrs(
+    xattrs: &RefCell<sessions::Xattrs>,
+    dir: &path::Path,
+    rel_file_path: &path::Path,
+    file_path: &path::Path,
+    gb_repository: &Repository,
+) {
+    let project_file_path = if dir == gb_repository.session_wd_path() {
+        gb_repository.project.path.join(rel_file_path)
+    } else {
+        file_path.to_path_buf()
+    };
+    match read_xattrs(&project_file_path) {
+        Result::Ok(file_xattrs) if file_xattrs.is_empty() => {
+            xattrs.borrow_mut().remove(rel_file_path);
+        }
+        Result::Ok(file_xattrs) => {
+            xattrs
+                .borrow_mut()
+                .insert(rel_file_path.to_path_buf(), file_xattrs);
+        }
+        Err(error) => {
+            tracing::debug!(
+                target: logs::WATCHER,
+                project_id = %gb_repository.project.id,
+                path = %project_file_path.display(),
+                %error,
+                "failed to read xattrs"
+            );
+        }
+    }
+}
+
+// reads the extended attributes of a file without following symlinks. attributes with names that
+// are not utf-8 are skipped.
+fn read_xattrs(file_path: &path::Path) -> std::io::Result<sessions::FileXattrs> {
+    let mut file_xattrs = sessions::FileXattrs::new();
+    for name in xattr::list(file_path)? {
+        let Some(key) = name.to_str() else {
+            continue;
+        };
+        // the attribute might have been removed since it was listed
+        if let Some(value) = xattr::get(file_path, &name)? {
+            file_xattrs.insert(key.to_string(), value);
+        }
+    }
+    Ok(file_xattrs)
+}
+
+// streams the file into a blob. returns None if the file was modified while it was being written,
+// in which case the blob might not match the file.
+fn stream_blob(
+    store: &dyn SessionStore,
+    file_path: &std::path::Path,
+    metadata: &std::fs::Metadata,
+) -> Result<Option<git::Oid>> {
+    let blob = store.write_blob_stream(file_path)?;
+    let metadata_after = std::fs::symlink_metadata(file_path)?;
+    let unchanged = metadata_after.len() == metadata.len()
+        && FileTime::from_last_modification_time(&metadata_after)
+            == FileTime::from_last_modification_time(metadata);
+    Ok(unchanged.then_some(blob))
+}
+
+// writes a regular file into the store. with the index cache enabled, project files that didn't
+// change since they were cached are not hashed again. cached blobs are looked up in the object
+// database of the gitbutler repository.
+#[allow(clippy::too_many_arguments)]
+fn write_file_blob(
+    dir: &std::path::Path,
+    rel_file_path: &std::path::Path,
+    file_path: &std::path::Path,
+    metadata: &std::fs::Metadata,
+    gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<git::Oid> {
+    // session wd files are rewritten all the time, there is nothing to gain from caching them
+    let index_cache = options
+        .index_cache
+        .as_ref()
+        .filter(|_| dir != gb_repository.session_wd_path());
+    let cache_key = options.path_key(rel_file_path);
+    if let Some(index_cache) = index_cache {
+        if let Some(blob) = index_cache.borrow_mut().get(&cache_key, metadata) {
+            if gb_repository.git_repository.contains_object(blob)? {
+                gb_repository.count_fast_path(true);
+                return Ok(blob);
+            }
+        }
+        gb_repository.count_fast_path(false);
+    }
+
+    let blob = if options.stream_large_files && metadata.len() > STREAM_THRESHOLD {
+        match stream_blob(store, file_path, metadata) {
+            Result::Ok(Some(blob)) => blob,
+            Result::Ok(None) => {
+                tracing::trace!(
+                    target: logs::WATCHER,
+                    project_id = %gb_repository.project.id,
+                    path = %file_path.display(),
+                    "file changed while streaming, reading it again"
+                );
+                options.write_blob_path(store, file_path)?
+            }
+            Err(error) => {
+                tracing::warn!(
+                    project_id = %gb_repository.project.id,
+                    path = %file_path.display(),
+                    ?error,
+                    "failed to stream file, reading it instead"
+                );
+                options.write_blob_path(store, file_path)?
+            }
+        }
+    } else {
+        match options
+            .line_endings
+            .normalize(rel_file_path, file_path)
+            .with_context(|| format!("failed to normalize {}", rel_file_path.display()))?
+        {
+            Some(content) => store.write_blob(&content)?,
+            // read the file into a blob, get the object id
+            None => options.write_blob_path(store, file_path)?,
+        }
+    };
+
+    if let Some(index_cache) = index_cache {
+        index_cache.borrow_mut().insert(cache_key, metadata, blob);
+    }
+
+    // a secret doesn't stop the file from being captured, it's only reported
+    if let Some(secret_scanner) = &options.secret_scanner {
+        match secret_scanner.scan(file_path) {
+            Result::Ok(true) => {
+                tracing::warn!(
+                    project_id = %gb_repository.project.id,
+                    path = %rel_file_path.display(),
+                    "file seems to have a secret in it"
+                );
+                gb_repository
+                    .secret_files
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .insert(rel_file_path.to_path_buf());
+            }
+            Result::Ok(false) => {}
+            Err(error) => {
+                tracing::warn!(
+                    project_id = %gb_repository.project.id,
+                    path = %file_path.display(),
+                    ?error,
+                    "failed to scan file for secrets"
+                );
+            }
+        }
+    }
+
+    if dir != gb_repository.session_wd_path() {
+        record_conflict_markers(rel_file_path, file_path, gb_repository, options);
+    }
+
+    Ok(blob)
+}
+
+// a file that can't be read keeps what was recorded for it, it is checked again when it's hashed
+// the next time
+fn record_conflict_markers(
+    rel_file_path: &path::Path,
+    file_path: &path::Path,
+    gb_repository: &Repository,
+    options: &CaptureOptions,
+) {
+    match conflicts::has_conflict_markers(file_path) {
+        Result::Ok(true) => {
+            options
+                .conflicted_files
+                .borrow_mut()
+                .insert(rel_file_path.to_path_buf());
+        }
+        Result::Ok(false) => {
+            options.conflicted_files.borrow_mut().remove(rel_file_path);
+        }
+        Err(error) => {
+            tracing::debug!(
+                project_id = %gb_repository.project.id,
+                path = %file_path.display(),
+                ?error,
+                "failed to check file for conflict markers"
+            );
+        }
+    }
+}
+
+// longest path that file system calls take on this platform
+#[cfg(target_os = "windows")]
+const MAX_PATH_LEN: usize = 260;
+#[cfg(target_os = "macos")]
+const MAX_PATH_LEN: usize = 1024;
+#[cfg(all(target_family = "unix", not(target_os = "macos")))]
+const MAX_PATH_LEN: usize = 4096;
+
+// returns the path to access a file with, or None if it's too long for this platform. on
+// windows, long absolute paths get the extended-length prefix when long paths are enabled.
+#[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+fn accessible_path(path: &path::Path, long_paths: bool) -> Option<path::PathBuf> {
+    if path.as_os_str().len() < MAX_PATH_LEN {
+        return Some(path.to_path_buf());
+    }
+    #[cfg(target_os = "windows")]
+    if long_paths && path.is_absolute() {
+        let path = path.to_string_lossy();
+        let extended = match path.strip_prefix(r"\\") {
+            Some(unc_path) => format!(r"\\?\UNC\{}", unc_path),
+            None => format!(r"\\?\{}", path),
+        };
+        return Some(path::PathBuf::from(extended));
+    }
+    None
+}
+
+fn is_recently_modified(
+    metadata: &std::fs::Metadata,
+    window: time::Duration,
+    now: time::SystemTime,
+) -> bool {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| now.duration_since(modified).ok())
+        .map_or(false, |elapsed| elapsed < window)
+}
+
 /// calculates sha256 digest of a large file as lowercase hex string via streaming buffer
 /// used to calculate the hash of large files that are not supported by git
 fn sha256_digest(path: &std::path::Path) -> Result<String> {
@@ -819,10 +2872,14 @@ fn sha256_digest(path: &std::path::Path) -> Result<String> {
         }
         hasher.finalize()
     };
-    Ok(format!("{:X}", digest))
+    Ok(format!("{:x}", digest))
 }
 
-fn build_branches_tree(gb_repository: &Repository) -> Result<git::Oid> {
+fn build_branches_tree(
+    gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<git::Oid> {
     let mut index = git::Index::new()?;
 
     let branches_dir = gb_repository.root().join("branches");
@@ -831,7 +2888,8 @@ fn build_branches_tree(gb_repository: &Repository) -> Result<git::Oid> {
     {
         let file_path = std::path::Path::new(&file_path);
         add_file_to_index(
-            gb_repository,
+            options,
+            store,
             &mut index,
             file_path,
             &branches_dir.join(file_path),
@@ -839,14 +2897,144 @@ fn build_branches_tree(gb_repository: &Repository) -> Result<git::Oid> {
         .context("failed to add branch file to index")?;
     }
 
-    let tree_oid = index
-        .write_tree_to(&gb_repository.git_repository)
+    let tree_oid = store
+        .write_index(&mut index)
         .context("failed to write index to tree")?;
 
     Ok(tree_oid)
 }
 
-fn build_session_tree(gb_repository: &Repository) -> Result<git::Oid> {
+// the reflog of the project's HEAD, as logs/HEAD. a symlinked reflog is read through, a project
+// without one has no logs tree.
+fn build_logs_tree(
+    project_repository: &project_repository::Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<Option<git::Oid>> {
+    let reflog_path = project_repository
+        .git_repository
+        .path()
+        .join("logs")
+        .join("HEAD");
+    let reflog = match std::fs::read(&reflog_path) {
+        Result::Ok(reflog) => reflog,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", reflog_path.display()))
+        }
+    };
+    let reflog = match options.reflog_max_entries {
+        Some(max_entries) => last_lines(&reflog, max_entries),
+        None => &reflog,
+    };
+    let blob = store.write_blob(reflog).context("failed to write reflog")?;
+    let tree = store
+        .write_tree(&[("HEAD", blob, git::FileMode::Blob)])
+        .context("failed to write logs tree")?;
+    Ok(Some(tree))
+}
+
+// the last count lines of the content, whole lines only. a last line without a newline, one that
+// is still written to, counts as a line.
+fn last_lines(content: &[u8], count: usize) -> &[u8] {
+    let body = content.strip_suffix(b"\n").unwrap_or(content);
+    let mut lines = 0;
+    for (i, byte) in body.iter().enumerate().rev() {
+        if *byte == b'\n' {
+            lines += 1;
+            if lines == count {
+                return &content[i + 1..];
+            }
+        }
+    }
+    content
+}
+
+// a crash while the meta of the current session was written can leave it unparseable, for example
+// with a truncated timestamp. committed like that, the session could never be read back from the
+// history. the meta is written again from the session that is flushed instead.
+fn repair_session_meta(
+    gb_repository: &Repository,
+    session: &sessions::Session,
+    session_writer: &sessions::Writer,
+) -> Result<()> {
+    let reader = reader::Reader::open(&gb_repository.root())
+        .context("failed to open current session reader")?;
+    let error = match sessions::Session::try_from(&reader) {
+        Result::Ok(_) => return Ok(()),
+        Err(error) => error,
+    };
+    match reader.read("session/meta/id") {
+        // nothing was written yet, all of the meta is written with the session
+        Err(reader::Error::NotFound) => return Ok(()),
+        // the meta of another session is left alone, writing to it fails
+        Result::Ok(reader::Content::UTF8(id))
+            if id.parse::<SessionId>().map_or(false, |id| id != session.id) =>
+        {
+            return Ok(())
+        }
+        _ => {}
+    }
+
+    tracing::warn!(
+        project_id = %gb_repository.project.id,
+        session_id = %session.id,
+        ?error,
+        "session meta is corrupted, writing it again"
+    );
+    session_writer
+        .rewrite(session)
+        .context("failed to repair session meta")
+}
+
+// rebuilds the meta of the current session from what can still be read of it. fields that can't
+// be read are reset, and an id that can't be read is replaced, so that the deltas and files of the
+// session are still committed.
+fn salvage_session_meta(gb_repository: &Repository, reader: &reader::Reader) -> sessions::Session {
+    let read_string = |path: &str| match reader.read(path) {
+        Result::Ok(reader::Content::UTF8(content)) => Some(content.trim().to_string()),
+        _ => None,
+    };
+    let now_ms = gb_repository
+        .now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let last_timestamp_ms = read_string("session/meta/last")
+        .and_then(|last| last.parse().ok())
+        .unwrap_or(now_ms);
+    sessions::Session {
+        id: read_string("session/meta/id")
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_else(SessionId::generate),
+        hash: None,
+        meta: sessions::Meta {
+            version: read_string("session/meta/version")
+                .and_then(|version| version.parse().ok())
+                .unwrap_or(sessions::META_VERSION),
+            start_timestamp_ms: read_string("session/meta/start")
+                .and_then(|start| start.parse().ok())
+                .unwrap_or(last_timestamp_ms),
+            last_timestamp_ms,
+            branch: read_string("session/meta/branch").filter(|branch| !branch.is_empty()),
+            commit: read_string("session/meta/commit").filter(|commit| !commit.is_empty()),
+            detached: read_string("session/meta/detached").as_deref() == Some("true"),
+            stashes: read_string("session/meta/stashes")
+                .and_then(|stashes| serde_json::from_str(&stashes).ok())
+                .unwrap_or_default(),
+            sparse_checkout: read_string("session/meta/sparseCheckout")
+                .and_then(|sparse_checkout| serde_json::from_str(&sparse_checkout).ok()),
+            // metadata is kept on disk, rewriting the meta doesn't touch it
+            metadata: BTreeMap::new(),
+        },
+    }
+}
+
+fn build_session_tree(
+    gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
+) -> Result<git::Oid> {
     let mut index = git::Index::new()?;
 
     // add all files in the working directory to the in-memory index, skipping for matching entries in the repo index
@@ -856,30 +3044,95 @@ fn build_session_tree(gb_repository: &Repository) -> Result<git::Oid> {
     )
     .context("failed to list session files")?
     {
-        add_file_to_index(
-            gb_repository,
-            &mut index,
-            &file_path,
-            &gb_repository.session_path().join(&file_path),
-        )
-        .with_context(|| format!("failed to add session file: {}", file_path.display()))?;
+        let abs_file_path = gb_repository.session_path().join(&file_path);
+
+        // session files are written by the session writer while we are reading them here.
+        // a file that disappeared since listing is fine to skip, but a file we can't read
+        // means that something else is holding it.
+        match File::open(&abs_file_path) {
+            Result::Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                tracing::trace!(
+                    target: logs::WATCHER,
+                    project_id = %gb_repository.project.id,
+                    path = %abs_file_path.display(),
+                    "session file removed while flushing, skipping"
+                );
+                continue;
+            }
+            Err(error) => {
+                tracing::warn!(
+                    project_id = %gb_repository.project.id,
+                    path = %abs_file_path.display(),
+                    %error,
+                    "failed to open session file"
+                );
+                return Err(Error::SessionLocked(abs_file_path).into());
+            }
+        }
+
+        add_file_to_index(options, store, &mut index, &file_path, &abs_file_path)
+            .with_context(|| format!("failed to add session file: {}", file_path.display()))?;
     }
 
-    let tree_oid = index
-        .write_tree_to(&gb_repository.git_repository)
+    let tree_oid = store
+        .write_index(&mut index)
         .context("failed to write index to tree")?;
 
     Ok(tree_oid)
 }
 
+// git only distinguishes regular files, executables and symlinks, the rest of the permission bits
+// reported by the filesystem is ignored
+fn git_file_mode(metadata: &std::fs::Metadata) -> u32 {
+    if metadata.is_symlink() {
+        return 0o120_000;
+    }
+    #[cfg(target_family = "unix")]
+    if metadata.mode() & 0o111 != 0 {
+        return 0o100_755;
+    }
+    0o100_644
+}
+
+// files above the threshold prefer to be stored as lfs objects, files above the git limit must be
+fn is_lfs_file(size: u64, lfs_threshold: u64) -> bool {
+    size > lfs_threshold || size > project_repository::GIT_MAX_BLOB_SIZE
+}
+
+// returns the path under which a file is stored in session trees. paths are always relative and
+// separated with slashes, so that sessions can be read on another machine, no matter where the
+// project is located there or which platform it runs on.
+fn index_path(rel_file_path: &path::Path) -> Result<String> {
+    let mut components = vec![];
+    for component in rel_file_path.components() {
+        match component {
+            path::Component::Normal(component) => components.push(
+                component
+                    .to_str()
+                    .with_context(|| format!("{} is not utf8", rel_file_path.display()))?,
+            ),
+            path::Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(
+                    "{} is not relative to the working directory",
+                    rel_file_path.display()
+                ))
+            }
+        }
+    }
+    Ok(components.join("/"))
+}
+
 // this is a helper function for build_gb_tree that takes paths under .git/gb/session and adds them to the in-memory index
 fn add_file_to_index(
-    gb_repository: &Repository,
+    options: &CaptureOptions,
+    store: &dyn SessionStore,
     index: &mut git::Index,
     rel_file_path: &std::path::Path,
     abs_file_path: &std::path::Path,
 ) -> Result<()> {
-    let blob = gb_repository.git_repository.blob_path(abs_file_path)?;
+    let blob = options.write_blob_path(store, abs_file_path)?;
     let metadata = abs_file_path.metadata()?;
     let modified_time = FileTime::from_last_modification_time(&metadata);
     let create_time = FileTime::from_creation_time(&metadata).unwrap_or(modified_time);
@@ -899,7 +3152,7 @@ fn add_file_to_index(
             file_size: metadata.len() as u32,
             flags: 10, // normal flags for normal file (for the curious: https://git-scm.com/docs/index-format)
             flags_extended: 0, // no extended flags
-            path: rel_file_path.to_str().unwrap().into(),
+            path: index_path(rel_file_path)?.into(),
             id: blob,
         })
         .with_context(|| format!("Failed to add file to index: {}", abs_file_path.display()))?;
@@ -907,51 +3160,155 @@ fn add_file_to_index(
     Ok(())
 }
 
+fn local_offset_minutes(seconds: i64) -> Option<i32> {
+    chrono::Local
+        .timestamp_opt(seconds, 0)
+        .single()
+        .map(|time| time.offset().local_minus_utc().div_euclid(60))
+}
+
+// parses gitbutler.commitTimezone, returns None for the local timezone
+fn parse_commit_timezone(timezone: &str) -> Result<Option<i32>> {
+    let invalid = || {
+        anyhow!("invalid gitbutler.commitTimezone {timezone}, expected local, utc or an offset like +0200")
+    };
+    match timezone.to_lowercase().as_str() {
+        "local" => return Ok(None),
+        "utc" => return Ok(Some(0)),
+        _ => {}
+    }
+    let (sign, digits) = if let Some(digits) = timezone.strip_prefix('+') {
+        (1, digits)
+    } else if let Some(digits) = timezone.strip_prefix('-') {
+        (-1, digits)
+    } else {
+        return Err(invalid());
+    };
+    let digits = digits
+        .chars()
+        .map(|digit| digit.to_digit(10))
+        .collect::<Option<Vec<u32>>>()
+        .ok_or_else(invalid)?;
+    let [hours_tens, hours_ones, minutes_tens, minutes_ones] = digits.as_slice() else {
+        return Err(invalid());
+    };
+    let hours = hours_tens * 10 + hours_ones;
+    let minutes = minutes_tens * 10 + minutes_ones;
+    if hours > 14 || minutes >= 60 {
+        return Err(invalid());
+    }
+    let offset_minutes = i32::try_from(hours * 60 + minutes).map_err(|_| invalid())?;
+    Ok(Some(sign * offset_minutes))
+}
+
 // write a new commit object to the repo
 // this is called once we have a tree of deltas, metadata and current wd snapshot
 // and either creates or updates the refs/heads/current ref
+// commit is timestamped with the given time, or the current time if it's not set
+// the timezone is the given offset in minutes, or the local one at the time of the commit
 fn write_gb_commit(
     tree_id: git::Oid,
     gb_repository: &Repository,
     user: Option<&users::User>,
+    message: &str,
+    timestamp_ms: Option<u128>,
+    timezone_offset_minutes: Option<i32>,
+    anchor_commit: Option<git::Oid>,
+    store: &dyn SessionStore,
 ) -> Result<git::Oid> {
-    let comitter = git::Signature::now("gitbutler", "gitbutler@localhost")?;
+    let now_seconds = i64::try_from(
+        gb_repository
+            .now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    )
+    .context("current time is out of range")?;
+    let seconds = match timestamp_ms {
+        Some(timestamp_ms) => {
+            i64::try_from(timestamp_ms / 1000).context("timestamp is out of range")?
+        }
+        None => now_seconds,
+    };
+    // the offset of now is not the one of an older timestamp when daylight saving time changed
+    // in between
+    let offset_minutes = timezone_offset_minutes
+        .or_else(|| local_offset_minutes(seconds))
+        .or_else(|| local_offset_minutes(now_seconds))
+        .unwrap_or_default();
+    let time = git2::Time::new(seconds, offset_minutes);
+    let comitter = git::Signature::new("gitbutler", "gitbutler@localhost", &time)?;
     let author = match user {
         None => comitter.clone(),
-        Some(user) => git::Signature::try_from(user)?,
+        Some(user) => git::Signature::try_from(user)?.at(&time)?,
     };
 
     let current_refname: git::Refname = "refs/heads/current".parse().unwrap();
 
-    match gb_repository
-        .git_repository
-        .find_reference(&current_refname)
-    {
-        Result::Ok(reference) => {
-            let last_commit = reference.peel_to_commit()?;
-            let new_commit = gb_repository.git_repository.commit(
-                Some(&current_refname),
-                &author,                                                   // author
-                &comitter,                                                 // committer
-                "gitbutler check",                                         // commit message
-                &gb_repository.git_repository.find_tree(tree_id).unwrap(), // tree
-                &[&last_commit],                                           // parents
-            )?;
-            Ok(new_commit)
-        }
-        Err(git::Error::NotFound(_)) => {
-            let new_commit = gb_repository.git_repository.commit(
-                Some(&current_refname),
-                &author,                                                   // author
-                &comitter,                                                 // committer
-                "gitbutler check",                                         // commit message
-                &gb_repository.git_repository.find_tree(tree_id).unwrap(), // tree
-                &[],                                                       // parents
-            )?;
-            Ok(new_commit)
+    let parents = match find_current_commit(gb_repository)? {
+        // the first parent is always the previous session, the chain of sessions is walked
+        // through first parents. the bootstrap commit is never anchored, so that the chain
+        // doesn't continue into the project's history.
+        Some(commit) => match anchor_commit {
+            Some(anchor_commit)
+                if gb_repository
+                    .git_repository
+                    .find_commit(anchor_commit)
+                    .is_ok() =>
+            {
+                vec![commit.id(), anchor_commit]
+            }
+            Some(anchor_commit) => {
+                tracing::warn!(
+                    project_id = %gb_repository.project.id,
+                    %anchor_commit,
+                    "head commit is not reachable from the gitbutler repository, not anchoring session"
+                );
+                vec![commit.id()]
+            }
+            None => vec![commit.id()],
+        },
+        None => {
+            // the first commit of a chain is never listed as a session. only the first flush of
+            // a new repository is that commit, sessions that start another chain, after the
+            // current ref was lost or for a new branch history, go on top of a bootstrap commit.
+            let has_history = gb_repository
+                .git_repository
+                .references()
+                .context("failed to list references")?
+                .next()
+                .is_some();
+            // a dangling ref can't be updated by a commit without parents, it is dropped instead
+            match gb_repository
+                .git_repository
+                .find_reference(&current_refname)
+            {
+                Result::Ok(mut reference) => reference
+                    .delete()
+                    .context("failed to delete dangling current ref")?,
+                Err(git::Error::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+            if has_history {
+                let empty_tree = store.write_tree(&[])?;
+                let bootstrap = store
+                    .write_commit(None, &comitter, &comitter, "bootstrap", empty_tree, &[])
+                    .context("failed to write bootstrap commit")?;
+                vec![bootstrap]
+            } else {
+                vec![]
+            }
         }
-        Err(e) => Err(e.into()),
-    }
+    };
+
+    store.write_commit(
+        Some(&current_refname),
+        &author,
+        &comitter,
+        message,
+        tree_id,
+        &parents,
+    )
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -967,7 +3324,14 @@ mod test {
     use anyhow::Result;
     use pretty_assertions::assert_eq;
 
-    use crate::test_utils::{Case, Suite};
+    use crate::{
+        project_repository::{DEFAULT_LFS_THRESHOLD, GIT_MAX_BLOB_SIZE},
+        test_utils::{self, Case, Suite},
+    };
+
+    use super::{
+        accessible_path, index_path, is_lfs_file, stream_blob, CaptureOptions, MAX_PATH_LEN,
+    };
 
     #[test]
     fn test_alternates_file_being_set() -> Result<()> {
@@ -994,4 +3358,134 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_alternates_file_without_trailing_newline() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            gb_repository,
+            project_repository,
+            ..
+        } = suite.new_case();
+
+        // written by hand, or by a tool that doesn't end the file with a newline
+        let other_objects_path = test_utils::temp_dir();
+        let alternates_path = gb_repository
+            .git_repository
+            .path()
+            .join("objects/info/alternates");
+        std::fs::write(&alternates_path, other_objects_path.to_str().unwrap())?;
+        drop(gb_repository);
+
+        super::Repository::open(&suite.local_app_data, &project_repository, None)?;
+
+        assert_eq!(
+            std::fs::read_to_string(&alternates_path)?,
+            format!(
+                "{}\n{}/.git/objects\n",
+                other_objects_path.to_str().unwrap(),
+                project_repository.path().to_str().unwrap()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_blob() -> Result<()> {
+        let Case {
+            gb_repository,
+            project_repository,
+            ..
+        } = Suite::default().new_case();
+
+        let file_path = project_repository.path().join("large.bin");
+        std::fs::write(&file_path, vec![1_u8; 1024 * 1024])?;
+        let metadata = std::fs::symlink_metadata(&file_path)?;
+
+        let streamed = stream_blob(gb_repository.git_repository(), &file_path, &metadata)?;
+        assert_eq!(
+            streamed,
+            Some(gb_repository.git_repository.blob_path(&file_path)?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_blob_path_once_per_capture() -> Result<()> {
+        let Case {
+            gb_repository,
+            project_repository,
+            ..
+        } = Suite::default().new_case();
+
+        let file_path = project_repository.path().join("file.txt");
+        std::fs::write(&file_path, "hello")?;
+
+        let options = CaptureOptions::try_from(&project_repository)?;
+        let blob = options.write_blob_path(gb_repository.git_repository(), &file_path)?;
+        assert_eq!(blob, gb_repository.git_repository.blob_path(&file_path)?);
+
+        // the file is not read again during the same capture
+        std::fs::write(&file_path, "world")?;
+        assert_eq!(
+            options.write_blob_path(gb_repository.git_repository(), &file_path)?,
+            blob
+        );
+
+        let options = CaptureOptions::try_from(&project_repository)?;
+        assert_ne!(
+            options.write_blob_path(gb_repository.git_repository(), &file_path)?,
+            blob
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accessible_path() {
+        let short = std::path::Path::new("/project/src/main.rs");
+        assert_eq!(accessible_path(short, false), Some(short.to_path_buf()));
+
+        let long = std::path::Path::new("/project").join("d/".repeat(MAX_PATH_LEN / 2));
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(accessible_path(&long, true), None);
+        assert_eq!(accessible_path(&long, false), None);
+    }
+
+    #[test]
+    fn test_index_path() -> Result<()> {
+        assert_eq!(
+            index_path(std::path::Path::new("dir/file.txt"))?,
+            "dir/file.txt"
+        );
+        assert_eq!(index_path(std::path::Path::new("./file.txt"))?, "file.txt");
+        assert!(index_path(std::path::Path::new("/etc/passwd")).is_err());
+        assert!(index_path(std::path::Path::new("../file.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_lfs_file() {
+        assert!(!is_lfs_file(DEFAULT_LFS_THRESHOLD, DEFAULT_LFS_THRESHOLD));
+        assert!(is_lfs_file(
+            DEFAULT_LFS_THRESHOLD + 1,
+            DEFAULT_LFS_THRESHOLD
+        ));
+
+        // the git limit applies even if the threshold is higher
+        assert!(!is_lfs_file(GIT_MAX_BLOB_SIZE, u64::MAX));
+        assert!(is_lfs_file(GIT_MAX_BLOB_SIZE + 1, u64::MAX));
+    }
+
+    #[test]
+    fn test_last_lines() {
+        assert_eq!(last_lines(b"a\nb\nc\n", 2), b"b\nc\n");
+        assert_eq!(last_lines(b"a\nb\nc\n", 3), b"a\nb\nc\n");
+        assert_eq!(last_lines(b"a\nb\nc\n", 10), b"a\nb\nc\n");
+        // the line that is still written to is the last one
+        assert_eq!(last_lines(b"a\nb\nc", 2), b"b\nc");
+        assert_eq!(last_lines(b"", 1), b"");
+    }
 }
@@ -0,0 +1,190 @@
+// runs gitbutler.statusCommand in the project when a session is captured, so that the session
+// records what the command printed at the time, like `cargo test` summaries or `git status -s`.
+//
+// a command that fails or takes too long doesn't fail the capture, what it printed until then is
+// recorded with how it ended.
+
+use std::{
+    io::{self, Read},
+    path,
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread, time,
+};
+
+use crate::sessions::StatusOutput;
+
+// commands are stopped after this long, unless gitbutler.statusCommandTimeout says otherwise
+pub const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+// only the beginning of the output is kept, the rest is read and dropped
+const OUTPUT_LIMIT: usize = 1024 * 1024;
+// processes that the command started in the background can keep the output open after it
+// exited, what they printed until then is kept
+const OUTPUT_GRACE: time::Duration = time::Duration::from_secs(1);
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
+
+pub fn run(command: &str, dir: &path::Path, timeout: time::Duration) -> StatusOutput {
+    let mut output = StatusOutput {
+        command: command.to_string(),
+        exit_code: None,
+        timed_out: false,
+        stdout: String::new(),
+        stderr: String::new(),
+    };
+
+    let mut child = match shell(command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(error) => {
+            output.stderr = format!("failed to start status command: {error}");
+            return output;
+        }
+    };
+    let stdout = child.stdout.take().map(read_output);
+    let stderr = child.stderr.take().map(read_output);
+
+    let deadline = time::Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                output.exit_code = status.code();
+                break;
+            }
+            Ok(None) if time::Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                output.timed_out = true;
+                if let Err(error) = kill(&mut child).and_then(|()| child.wait().map(|_| ())) {
+                    tracing::warn!(command, ?error, "failed to stop status command");
+                }
+                break;
+            }
+            Err(error) => {
+                output.stderr = format!("failed to wait for status command: {error}");
+                return output;
+            }
+        }
+    }
+
+    let grace_deadline = time::Instant::now() + OUTPUT_GRACE;
+    if let Some(stdout) = stdout {
+        output.stdout = stdout.collect(grace_deadline);
+    }
+    if let Some(stderr) = stderr {
+        output.stderr = stderr.collect(grace_deadline);
+    }
+    output
+}
+
+#[cfg(target_os = "windows")]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+// the command runs in a process group of its own, so that what it started is stopped with it
+#[cfg(not(target_os = "windows"))]
+fn shell(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command).process_group(0);
+    shell
+}
+
+#[cfg(target_os = "windows")]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    let pgid = i32::try_from(child.id())
+        .map(nix::unistd::Pid::from_raw)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL).map_err(io::Error::from)
+}
+
+// output of the command, read on a thread of its own. a command that fills a pipe while nothing
+// reads it would never exit.
+struct Output {
+    content: Arc<Mutex<Vec<u8>>>,
+    reader: thread::JoinHandle<()>,
+}
+
+impl Output {
+    // returns what was read until the pipe was closed, or until the deadline
+    fn collect(self, deadline: time::Instant) -> String {
+        while !self.reader.is_finished() && time::Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        let content = self.content.lock().map_or_else(
+            |error| error.into_inner().clone(),
+            |content| content.clone(),
+        );
+        String::from_utf8_lossy(&content).into_owned()
+    }
+}
+
+fn read_output<R: Read + Send + 'static>(mut pipe: R) -> Output {
+    let content = Arc::new(Mutex::new(vec![]));
+    let reader = thread::spawn({
+        let content = Arc::clone(&content);
+        move || {
+            let mut buffer = [0_u8; 8192];
+            loop {
+                match pipe.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        let Ok(mut content) = content.lock() else {
+                            break;
+                        };
+                        let kept = len.min(OUTPUT_LIMIT.saturating_sub(content.len()));
+                        content.extend_from_slice(&buffer[..kept]);
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => {
+                        tracing::debug!(?error, "failed to read status command output");
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Output { content, reader }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn test_run() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file.txt"), "").unwrap();
+
+        let output = run("ls", dir.path(), DEFAULT_TIMEOUT);
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout, "file.txt\n");
+        assert!(!output.timed_out);
+
+        let output = run("echo failed >&2; exit 3", dir.path(), DEFAULT_TIMEOUT);
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stderr, "failed\n");
+
+        let output = run(
+            "echo started; sleep 10",
+            dir.path(),
+            time::Duration::from_millis(200),
+        );
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.stdout, "started\n");
+    }
+}
@@ -0,0 +1,121 @@
+use std::path;
+
+use anyhow::{Context, Result};
+
+use crate::git;
+
+// where objects of flushed sessions are written to.
+//
+// the gitbutler repository must be able to read objects back once they are written, so a custom
+// store is expected to make them available to it, for example through an odb backend.
+pub trait SessionStore {
+    fn write_blob(&self, data: &[u8]) -> Result<git::Oid>;
+
+    fn write_blob_path(&self, path: &path::Path) -> Result<git::Oid>;
+
+    // writes the file without reading it into memory first
+    fn write_blob_stream(&self, path: &path::Path) -> Result<git::Oid>;
+
+    // writes a tree from the in-memory index
+    fn write_index(&self, index: &mut git::Index) -> Result<git::Oid>;
+
+    fn write_tree(&self, entries: &[(&str, git::Oid, git::FileMode)]) -> Result<git::Oid>;
+
+    fn write_commit(
+        &self,
+        update_ref: Option<&git::Refname>,
+        author: &git::Signature<'_>,
+        committer: &git::Signature<'_>,
+        message: &str,
+        tree: git::Oid,
+        parents: &[git::Oid],
+    ) -> Result<git::Oid>;
+}
+
+// default store, writes objects into the repository's object database
+impl SessionStore for git::Repository {
+    fn write_blob(&self, data: &[u8]) -> Result<git::Oid> {
+        self.blob(data).context("failed to write blob")
+    }
+
+    fn write_blob_path(&self, path: &path::Path) -> Result<git::Oid> {
+        self.blob_path(path)
+            .with_context(|| format!("failed to write blob from {}", path.display()))
+    }
+
+    fn write_blob_stream(&self, path: &path::Path) -> Result<git::Oid> {
+        self.blob_stream(path)
+            .with_context(|| format!("failed to stream blob from {}", path.display()))
+    }
+
+    fn write_index(&self, index: &mut git::Index) -> Result<git::Oid> {
+        index
+            .write_tree_to(self)
+            .context("failed to write index to tree")
+    }
+
+    fn write_tree(&self, entries: &[(&str, git::Oid, git::FileMode)]) -> Result<git::Oid> {
+        let mut tree_builder = self.treebuilder(None);
+        for (name, oid, mode) in entries {
+            tree_builder.upsert(name, *oid, *mode);
+        }
+        tree_builder.write().context("failed to write tree")
+    }
+
+    fn write_commit(
+        &self,
+        update_ref: Option<&git::Refname>,
+        author: &git::Signature<'_>,
+        committer: &git::Signature<'_>,
+        message: &str,
+        tree: git::Oid,
+        parents: &[git::Oid],
+    ) -> Result<git::Oid> {
+        let tree = self.find_tree(tree).context("failed to find tree")?;
+        let parents = parents
+            .iter()
+            .map(|parent| self.find_commit(*parent))
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to find parent commit")?;
+        self.commit(
+            update_ref,
+            author,
+            committer,
+            message,
+            &tree,
+            &parents.iter().collect::<Vec<_>>(),
+        )
+        .context("failed to write commit")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils;
+
+    use super::*;
+
+    #[test]
+    fn test_write_commit() -> Result<()> {
+        let repository = test_utils::empty_bare_repository();
+        let store: &dyn SessionStore = &repository;
+
+        let blob = store.write_blob(b"hello")?;
+        let tree = store.write_tree(&[("file.txt", blob, git::FileMode::Blob)])?;
+        let signature = git::Signature::now("test", "test@email.com")?;
+        let first = store.write_commit(None, &signature, &signature, "first", tree, &[])?;
+        let second = store.write_commit(None, &signature, &signature, "second", tree, &[first])?;
+
+        let commit = repository.find_commit(second)?;
+        assert_eq!(commit.parent(0)?.id(), first);
+        assert_eq!(commit.tree_id(), tree);
+        assert_eq!(
+            repository
+                .find_blob(commit.tree()?.get_path(path::Path::new("file.txt"))?.id())?
+                .content(),
+            b"hello"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+// trailers of session commits, so that the history can be parsed with `git interpret-trailers`.
+//
+// configured with gitbutler.commitTrailer, one per value. a value is either the name of a builtin
+// trailer (session-id, changed-files) or a `Key: value` trailer that is added as it is.
+
+use std::{path, sync::Arc};
+
+use anyhow::{anyhow, Result};
+
+use crate::{projects::ProjectId, sessions};
+
+// subject of every session commit
+pub const SUBJECT: &str = "gitbutler check";
+
+// what providers get to look at when a session is committed
+pub struct TrailerContext<'a> {
+    pub project_id: &'a ProjectId,
+    pub session: &'a sessions::Session,
+    // files of the working directory that changed since the previous session
+    pub changed_files: &'a [path::PathBuf],
+}
+
+// returns trailers to add to a session commit, as key and value. a failing provider is logged,
+// the session is committed without its trailers.
+pub type TrailerProvider =
+    Arc<dyn Fn(&TrailerContext) -> Result<Vec<(String, String)>> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trailer {
+    // Session-Id: the id of the committed session
+    SessionId,
+    // Changed-Files: how many files of the working directory changed since the previous session
+    ChangedFiles,
+    Static(String, String),
+}
+
+impl Trailer {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.split_once(':') {
+            Some((key, value)) if is_valid_key(key.trim()) => Ok(Trailer::Static(
+                key.trim().to_string(),
+                value.trim().to_string(),
+            )),
+            Some(_) => Err(anyhow!("invalid trailer key in {value}")),
+            None => match value.trim().to_lowercase().as_str() {
+                "session-id" => Ok(Trailer::SessionId),
+                "changed-files" => Ok(Trailer::ChangedFiles),
+                _ => Err(anyhow!("unknown trailer {value}")),
+            },
+        }
+    }
+}
+
+// keys are what git recognizes as trailer tokens
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+pub fn resolve(trailers: &[Trailer], context: &TrailerContext) -> Vec<(String, String)> {
+    trailers
+        .iter()
+        .map(|trailer| match trailer {
+            Trailer::SessionId => ("Session-Id".to_string(), context.session.id.to_string()),
+            Trailer::ChangedFiles => (
+                "Changed-Files".to_string(),
+                context.changed_files.len().to_string(),
+            ),
+            Trailer::Static(key, value) => (key.clone(), value.clone()),
+        })
+        .collect()
+}
+
+pub fn provide(providers: &[TrailerProvider], context: &TrailerContext) -> Vec<(String, String)> {
+    let mut trailers = vec![];
+    for provider in providers {
+        match provider(context) {
+            Ok(provided) => {
+                for (key, value) in provided {
+                    if is_valid_key(&key) {
+                        trailers.push((key, value));
+                    } else {
+                        tracing::warn!(
+                            project_id = %context.project_id,
+                            key,
+                            "trailer provider returned an invalid key, trailer dropped"
+                        );
+                    }
+                }
+            }
+            Err(error) => {
+                tracing::error!(
+                    project_id = %context.project_id,
+                    session_id = %context.session.id,
+                    ?error,
+                    "trailer provider failed"
+                );
+            }
+        }
+    }
+    trailers
+}
+
+// the subject alone without trailers, otherwise the trailers in their own paragraph. values are
+// kept on one line.
+pub fn message(trailers: &[(String, String)]) -> String {
+    if trailers.is_empty() {
+        return SUBJECT.to_string();
+    }
+    let trailers = trailers
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{key}: {}",
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("{SUBJECT}\n\n{trailers}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Trailer::parse("session-id").unwrap(), Trailer::SessionId);
+        assert_eq!(
+            Trailer::parse("Changed-Files").unwrap(),
+            Trailer::ChangedFiles
+        );
+        assert_eq!(
+            Trailer::parse("Reviewed-By: someone <someone@example.com>").unwrap(),
+            Trailer::Static(
+                "Reviewed-By".to_string(),
+                "someone <someone@example.com>".to_string()
+            )
+        );
+        assert!(Trailer::parse("not a key: value").is_err());
+        assert!(Trailer::parse("unknown").is_err());
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(message(&[]), "gitbutler check");
+        assert_eq!(
+            message(&[
+                ("Session-Id".to_string(), "1".to_string()),
+                ("Note".to_string(), "two\nlines".to_string()),
+            ]),
+            "gitbutler check\n\nSession-Id: 1\nNote: two lines\n"
+        );
+    }
+}
@@ -46,6 +46,30 @@ impl Config {
             Err(e) => Err(e),
         }
     }
+
+    // returns every value of a key that can be set more than once, in the order they are set
+    pub fn get_multivar(&self, key: &str) -> Result<Vec<String>> {
+        let entries = match self.config.multivar(key, None).map_err(Into::into) {
+            Ok(entries) => entries,
+            Err(Error::NotFound(_)) => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut values = vec![];
+        for entry in &entries {
+            if let Some(value) = entry?.value() {
+                values.push(value.to_string());
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn get_i64(&self, key: &str) -> Result<Option<i64>> {
+        match self.config.get_i64(key).map_err(Into::into) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -78,6 +102,19 @@ mod tests {
         assert_eq!(config.get_string("test.key").unwrap(), None);
     }
 
+    #[test]
+    pub fn test_get_multivar() {
+        let repo = test_utils::test_repository();
+        let mut config = repo.config().unwrap();
+        assert!(config.get_multivar("test.key").unwrap().is_empty());
+        config.set_multivar("test.key", "^$", "one").unwrap();
+        config.set_multivar("test.key", "^$", "two").unwrap();
+        assert_eq!(
+            config.get_multivar("test.key").unwrap(),
+            vec!["one".to_string(), "two".to_string()]
+        );
+    }
+
     #[test]
     pub fn test_get_bool_none() {
         let repo = test_utils::test_repository();
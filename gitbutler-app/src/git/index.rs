@@ -100,6 +100,10 @@ impl Index {
     pub fn get_path(&self, path: &path::Path, stage: i32) -> Option<IndexEntry> {
         self.index.get_path(path, stage).map(Into::into)
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = IndexEntry> + '_ {
+        self.index.iter().map(Into::into)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +58,10 @@ impl<'repo> Reference<'repo> {
         self.reference.delete().map_err(Into::into)
     }
 
+    pub fn is_branch(&self) -> bool {
+        self.reference.is_branch()
+    }
+
     pub fn is_remote(&self) -> bool {
         self.reference.is_remote()
     }
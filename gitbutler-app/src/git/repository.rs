@@ -50,16 +50,54 @@ impl Repository {
         Ok(Repository(inner))
     }
 
+    // alternates are absolute paths. a path that isn't listed yet is appended. alternates that
+    // don't exist anymore, for example because the repository they point to was moved, are
+    // removed, so that a relocated project replaces its old path.
     pub fn add_disk_alternate(&self, path: &str) -> Result<()> {
         let alternates_path = self.0.path().join("objects/info/alternates");
-        if !alternates_path.exists() {
-            std::fs::write(alternates_path, format!("{path}\n"))?;
+        let alternates = match std::fs::read_to_string(&alternates_path) {
+            Ok(alternates) => alternates,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        // relative alternates are relative to the objects directory
+        let objects_path = self.0.path().join("objects");
+        let mut kept = alternates
+            .lines()
+            .filter(|alternate| {
+                *alternate == path
+                    || (!alternate.is_empty() && objects_path.join(alternate).exists())
+            })
+            .collect::<Vec<_>>();
+        if !kept.contains(&path) {
+            kept.push(path);
+        }
+
+        let mut updated = kept.join("\n");
+        updated.push('\n');
+        if updated != alternates {
+            std::fs::write(alternates_path, updated)?;
             self.0.odb().and_then(|odb| odb.refresh())?;
         }
 
         Ok(())
     }
 
+    // same as add_disk_alternate, for this handle only. nothing is written to the repository.
+    pub fn add_odb_alternate(&self, path: &str) -> Result<()> {
+        self.0.odb()?.add_disk_alternate(path)?;
+        Ok(())
+    }
+
+    // objects written through this handle from now on are kept in memory, they never reach the
+    // disk and are gone when the handle is dropped
+    pub fn keep_new_objects_in_memory(&self) -> Result<()> {
+        // preferred over the loose backend when writing
+        self.0.odb()?.add_new_mempack_backend(1000)?;
+        Ok(())
+    }
+
     pub fn add_submodule(&self, url: &Url, path: &path::Path) -> Result<Submodule<'_>> {
         self.0
             .submodule(&url.to_string(), path, false)
@@ -228,6 +266,14 @@ impl Repository {
         self.0.blob_path(path).map(Into::into).map_err(Into::into)
     }
 
+    // writes the file into the object database chunk by chunk, without reading it into memory first
+    pub fn blob_stream(&self, path: &path::Path) -> Result<Oid> {
+        let mut file = std::fs::File::open(path)?;
+        let mut writer = self.0.blob_writer(None)?;
+        std::io::copy(&mut file, &mut writer)?;
+        writer.commit().map(Into::into).map_err(Into::into)
+    }
+
     pub fn cherry_pick(&self, base: &Commit, target: &Commit) -> Result<Index> {
         self.0
             .cherrypick_commit(target.into(), base.into(), 0, None)
@@ -235,6 +281,22 @@ impl Repository {
             .map_err(Into::into)
     }
 
+    // returns the ids and messages of stash entries, most recent first
+    pub fn stashes(&self) -> Result<Vec<(Oid, String)>> {
+        // iterating stashes needs a mutable repository, so it's done on a separate handle
+        let mut repository = git2::Repository::open(self.0.path())?;
+        let mut stashes = vec![];
+        repository.stash_foreach(|_index, message, oid| {
+            stashes.push(((*oid).into(), message.to_string()));
+            true
+        })?;
+        Ok(stashes)
+    }
+
+    pub fn contains_object(&self, id: Oid) -> Result<bool> {
+        Ok(self.0.odb()?.exists(id.into()))
+    }
+
     pub fn blob(&self, data: &[u8]) -> Result<Oid> {
         self.0.blob(data).map(Into::into).map_err(Into::into)
     }
@@ -302,6 +364,10 @@ impl Repository {
         TreeBuilder::new(self, tree)
     }
 
+    pub fn state(&self) -> git2::RepositoryState {
+        self.0.state()
+    }
+
     pub fn path(&self) -> &path::Path {
         self.0.path()
     }
@@ -310,6 +376,11 @@ impl Repository {
         self.0.workdir()
     }
 
+    // the git directory that is shared by all worktrees, where objects and refs are
+    pub fn commondir(&self) -> &path::Path {
+        self.0.commondir()
+    }
+
     pub fn branch_upstream_name(&self, branch_name: &str) -> Result<String> {
         self.0
             .branch_upstream_name(branch_name)
@@ -57,6 +57,27 @@ impl Signature<'_> {
             .map_err(Into::into)
     }
 
+    pub fn new(name: &str, email: &str, time: &git2::Time) -> Result<Self, super::Error> {
+        git2::Signature::new(name, email, time)
+            .map(Into::into)
+            .map_err(Into::into)
+    }
+
+    // returns the same signature, but made at the given time
+    pub fn at(&self, time: &git2::Time) -> Result<Signature<'static>, super::Error> {
+        git2::Signature::new(
+            &String::from_utf8_lossy(self.signature.name_bytes()),
+            &String::from_utf8_lossy(self.signature.email_bytes()),
+            time,
+        )
+        .map(Into::into)
+        .map_err(Into::into)
+    }
+
+    pub fn when(&self) -> git2::Time {
+        self.signature.when()
+    }
+
     pub fn name(&self) -> Option<&str> {
         self.signature.name()
     }
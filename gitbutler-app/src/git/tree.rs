@@ -85,7 +85,7 @@ impl<'repo> TreeEntry<'repo> {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum FileMode {
     Blob,
     BlobExecutable,
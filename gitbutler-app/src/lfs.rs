@@ -0,0 +1,813 @@
+// git lfs pointer files: https://github.com/git-lfs/git-lfs/blob/main/docs/spec.md
+//
+// this is the single place that knows about the pointer format, both when we write pointers
+// for large files into the wd tree and when we read them back.
+
+mod chunking;
+mod encryption;
+mod eviction;
+
+use std::{collections::HashSet, fmt, path, str};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{gb_repository, git, project_repository};
+
+pub use encryption::EncryptionKey;
+pub(crate) use eviction::evict_locked;
+pub use eviction::{evict, Eviction};
+
+const VERSION: &str = "https://git-lfs.github.com/spec/v1";
+const OID_PREFIX: &str = "sha256:";
+// objects encrypted at rest are marked in the pointer with this key and scheme
+const ENCRYPTION_KEY: &str = "encryption";
+const ENCRYPTION_SCHEME: &str = "aes-256-gcm";
+// objects stored as a manifest of chunks are marked in the pointer with this key and scheme
+const CHUNKING_KEY: &str = "chunking";
+const CHUNKING_SCHEME: &str = "gitbutler-cdc-v1";
+// pointer files are always smaller than 1024 bytes
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LfsPointer {
+    // lowercase hex sha256 of the file content
+    pub oid: String,
+    // size of the file content in bytes
+    pub size: u64,
+    // the object file is encrypted, the oid and size are of the plain content
+    pub encrypted: bool,
+    // the object file is a manifest of chunks, the oid and size are of the whole content
+    pub chunked: bool,
+}
+
+impl LfsPointer {
+    pub fn new<S: Into<String>>(oid: S, size: u64) -> Self {
+        Self {
+            oid: oid.into(),
+            size,
+            encrypted: false,
+            chunked: false,
+        }
+    }
+
+    pub fn new_encrypted<S: Into<String>>(oid: S, size: u64) -> Self {
+        Self {
+            encrypted: true,
+            ..Self::new(oid, size)
+        }
+    }
+
+    pub fn new_chunked<S: Into<String>>(oid: S, size: u64) -> Self {
+        Self {
+            chunked: true,
+            ..Self::new(oid, size)
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+}
+
+impl fmt::Display for LfsPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version {}", VERSION)?;
+        // keys are sorted alphabetically
+        if self.chunked {
+            writeln!(f, "{} {}", CHUNKING_KEY, CHUNKING_SCHEME)?;
+        }
+        if self.encrypted {
+            writeln!(f, "{} {}", ENCRYPTION_KEY, ENCRYPTION_SCHEME)?;
+        }
+        writeln!(f, "oid {}{}", OID_PREFIX, self.oid)?;
+        writeln!(f, "size {}", self.size)
+    }
+}
+
+pub fn is_pointer(content: &[u8]) -> bool {
+    parse_pointer(content).is_some()
+}
+
+pub fn parse_pointer(content: &[u8]) -> Option<LfsPointer> {
+    if content.len() > MAX_POINTER_SIZE {
+        return None;
+    }
+    let content = str::from_utf8(content).ok()?;
+    // every line, including the last one, is terminated with \n
+    let content = content.strip_suffix('\n')?;
+
+    let mut lines = content.split('\n');
+    if lines.next()?.strip_prefix("version ")? != VERSION {
+        return None;
+    }
+
+    let mut oid = None;
+    let mut size = None;
+    let mut encrypted = false;
+    let mut chunked = false;
+    for line in lines {
+        let (key, value) = line.split_once(' ')?;
+        match key {
+            "oid" => oid = Some(value.strip_prefix(OID_PREFIX)?),
+            "size" => size = Some(value.parse::<u64>().ok()?),
+            // an object encrypted with an unknown scheme can't be read
+            ENCRYPTION_KEY if value == ENCRYPTION_SCHEME => encrypted = true,
+            ENCRYPTION_KEY => return None,
+            CHUNKING_KEY if value == CHUNKING_SCHEME => chunked = true,
+            CHUNKING_KEY => return None,
+            // extension keys are allowed by the spec, but we don't use them
+            _ => {}
+        }
+    }
+
+    let oid = oid?;
+    if !is_valid_oid(oid) {
+        return None;
+    }
+
+    Some(LfsPointer {
+        encrypted,
+        chunked,
+        ..LfsPointer::new(oid, size?)
+    })
+}
+
+// reads the key large files are encrypted with, from gitbutler.lfsEncryptionKey. encryption is
+// off without a key.
+pub fn encryption_key(config: &project_repository::Config) -> Result<Option<EncryptionKey>> {
+    config
+        .lfs_encryption_key()
+        .context("failed to read gitbutler.lfsEncryptionKey")?
+        .map(|key| {
+            key.parse()
+                .context("invalid gitbutler.lfsEncryptionKey, expected 64 hex characters")
+        })
+        .transpose()
+}
+
+// writes the content of a large file into the lfs store, encrypted if a key is given. returns the
+// pointer to it.
+//
+// encryption needs the whole file in memory, plain files are copied.
+pub(crate) fn write_object(
+    objects_dir: &path::Path,
+    file_path: &path::Path,
+    oid: &str,
+    size: u64,
+    key: Option<&EncryptionKey>,
+) -> Result<LfsPointer> {
+    std::fs::create_dir_all(objects_dir)
+        .with_context(|| format!("failed to create {}", objects_dir.display()))?;
+    let object_path = objects_dir.join(oid);
+
+    match key {
+        Some(key) => {
+            let content = std::fs::read(file_path)
+                .with_context(|| format!("failed to read {}", file_path.display()))?;
+            let encrypted = encryption::encrypt(key, oid, &content)?;
+            std::fs::write(&object_path, encrypted)
+                .with_context(|| format!("failed to write {}", object_path.display()))?;
+            Ok(LfsPointer::new_encrypted(oid, size))
+        }
+        None => {
+            std::fs::copy(file_path, &object_path)
+                .with_context(|| format!("failed to copy {}", file_path.display()))?;
+            Ok(LfsPointer::new(oid, size))
+        }
+    }
+}
+
+// writes the content of a large file into the lfs store as chunks, so that chunks that didn't
+// change are shared with other versions of the file. returns the pointer to it.
+//
+// chunks are never encrypted, encrypted files are written with write_object.
+pub(crate) fn write_chunked_object(
+    objects_dir: &path::Path,
+    file_path: &path::Path,
+    oid: &str,
+    size: u64,
+) -> Result<LfsPointer> {
+    std::fs::create_dir_all(objects_dir)
+        .with_context(|| format!("failed to create {}", objects_dir.display()))?;
+    let object_path = objects_dir.join(oid);
+    // any form of an existing object is readable, there is nothing to gain from replacing it
+    if object_path.exists() {
+        return Ok(LfsPointer::new_chunked(oid, size));
+    }
+
+    let manifest = chunking::write_chunks(objects_dir, file_path)?;
+    // a file that is a single chunk is already stored under its oid, as that chunk
+    if !object_path.exists() {
+        let tmp_path = object_path.with_extension("tmp");
+        std::fs::write(&tmp_path, manifest)
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &object_path)
+            .with_context(|| format!("failed to rename {}", tmp_path.display()))?;
+    }
+    Ok(LfsPointer::new_chunked(oid, size))
+}
+
+// returns the content of a large file, decrypting it if needed. the content is checked against
+// the oid, so a corrupted object is never returned.
+//
+// an object is stored the way it was last written, which is not necessarily how older pointers
+// to it describe it, so the content decides whether it's decrypted or put together from chunks.
+pub(crate) fn read_object(
+    objects_dir: &path::Path,
+    pointer: &LfsPointer,
+    key: Option<&EncryptionKey>,
+) -> Result<Vec<u8>> {
+    let object_path = objects_dir.join(&pointer.oid);
+    let content = std::fs::read(&object_path)
+        .with_context(|| format!("failed to read {}", object_path.display()))?;
+    if is_content_of(&content, &pointer.oid) {
+        return Ok(content);
+    }
+    if let Some(chunks) = chunking::parse_manifest(&content) {
+        let content = chunking::read_chunks(objects_dir, &chunks)?;
+        if !is_content_of(&content, &pointer.oid) {
+            return Err(anyhow!("lfs object {} is corrupted", pointer.oid));
+        }
+        return Ok(content);
+    }
+
+    let Some(key) = key else {
+        return if pointer.encrypted {
+            Err(anyhow!(
+                "lfs object {} is encrypted, but there is no encryption key",
+                pointer.oid
+            ))
+        } else {
+            Err(anyhow!("lfs object {} is corrupted", pointer.oid))
+        };
+    };
+    let content = encryption::decrypt(key, &pointer.oid, &content)?;
+    if !is_content_of(&content, &pointer.oid) {
+        return Err(anyhow!("lfs object {} is corrupted", pointer.oid));
+    }
+    Ok(content)
+}
+
+fn is_content_of(content: &[u8], oid: &str) -> bool {
+    format!("{:x}", Sha256::digest(content)) == oid
+}
+
+// large files are stored in the gitbutler repository under lfs/objects/<oid>, unless
+// gitbutler.lfsObjectsDir points elsewhere
+pub(crate) fn default_objects_dir(git_repository: &git::Repository) -> path::PathBuf {
+    git_repository.path().join("lfs/objects")
+}
+
+// fails if large files can't be written into the directory, so that a misconfigured store is
+// noticed before the first large file is captured
+pub(crate) fn check_objects_dir(objects_dir: &path::Path) -> Result<()> {
+    std::fs::create_dir_all(objects_dir)
+        .with_context(|| format!("failed to create {}", objects_dir.display()))?;
+    let probe_path = objects_dir.join(".write-check");
+    std::fs::write(&probe_path, "").with_context(|| {
+        format!(
+            "lfs objects directory {} is not writable",
+            objects_dir.display()
+        )
+    })?;
+    std::fs::remove_file(&probe_path)
+        .with_context(|| format!("failed to remove {}", probe_path.display()))
+}
+
+// removes large files that are not referenced from any session from the lfs store. returns
+// removed oids.
+//
+// the repository lock is held while collecting references, so objects of a session that is being
+// flushed at the same time can't be removed.
+pub fn gc(repository: &gb_repository::Repository) -> Result<Vec<String>> {
+    let _lock = repository.lock();
+    let removed = gc_locked(repository.git_repository(), repository.lfs_objects_dir())?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        removed = removed.len(),
+        "removed unreferenced lfs objects"
+    );
+
+    Ok(removed)
+}
+
+// same as gc, for callers that already hold the repository lock
+pub(crate) fn gc_locked(
+    git_repository: &git::Repository,
+    objects_dir: &path::Path,
+) -> Result<Vec<String>> {
+    let referenced =
+        referenced_oids(git_repository).context("failed to collect referenced lfs objects")?;
+    remove_unreferenced_objects(objects_dir, &referenced)
+        .context("failed to remove unreferenced lfs objects")
+}
+
+// objects of the lfs store that are not referenced from any session
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Unreferenced {
+    pub oids: Vec<String>,
+    // total size of the objects, what removing them reclaims
+    pub bytes: u64,
+}
+
+// returns what gc would remove, without removing anything
+pub fn gc_dry_run(repository: &gb_repository::Repository) -> Result<Unreferenced> {
+    let _lock = repository.lock();
+    let referenced = referenced_oids(repository.git_repository())
+        .context("failed to collect referenced lfs objects")?;
+    unreferenced_objects(repository.lfs_objects_dir(), &referenced)
+        .context("failed to list unreferenced lfs objects")
+}
+
+// returns oids of all lfs objects that are pointed to from any commit reachable from any branch,
+// tag or parked branch history, or from any queued pending session
+pub(crate) fn referenced_oids(git_repository: &git::Repository) -> Result<HashSet<String>> {
+    Ok(referenced_pointers(git_repository)?
+        .into_iter()
+        .map(|pointer| pointer.oid)
+        .collect())
+}
+
+// same as referenced_oids, with the pointers themselves
+pub(crate) fn referenced_pointers(git_repository: &git::Repository) -> Result<HashSet<LfsPointer>> {
+    let mut seen = HashSet::new();
+    let mut pointers = HashSet::new();
+    for commit_id in refs_revwalk(git_repository, None)? {
+        let commit = git_repository.find_commit(commit_id?.into())?;
+        collect_pointers(git_repository, &commit.tree()?, &mut seen, &mut pointers)?;
+    }
+    collect_queued_pointers(git_repository, &mut seen, &mut pointers)?;
+    Ok(pointers)
+}
+
+// returns oids of the lfs objects that would still be referenced if the history of the branch
+// was replaced by the kept commits on top of a commit with the base tree, like pruning does it
+pub(crate) fn referenced_oids_after_prune(
+    git_repository: &git::Repository,
+    refname: &str,
+    kept: &[git::Oid],
+    base_tree: git::Oid,
+) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pointers = HashSet::new();
+    for commit_id in refs_revwalk(git_repository, Some(refname))? {
+        let commit = git_repository.find_commit(commit_id?.into())?;
+        collect_pointers(git_repository, &commit.tree()?, &mut seen, &mut pointers)?;
+    }
+    for commit_id in kept {
+        let commit = git_repository.find_commit(*commit_id)?;
+        collect_pointers(git_repository, &commit.tree()?, &mut seen, &mut pointers)?;
+    }
+    collect_pointers(
+        git_repository,
+        &git_repository.find_tree(base_tree)?,
+        &mut seen,
+        &mut pointers,
+    )?;
+    collect_queued_pointers(git_repository, &mut seen, &mut pointers)?;
+    Ok(pointers.into_iter().map(|pointer| pointer.oid).collect())
+}
+
+// adds the pointers in the trees of queued pending sessions to pointers
+fn collect_queued_pointers(
+    git_repository: &git::Repository,
+    seen: &mut HashSet<git::Oid>,
+    pointers: &mut HashSet<LfsPointer>,
+) -> Result<()> {
+    for tree_id in
+        gb_repository::queued_trees(git_repository).context("failed to read pending sessions")?
+    {
+        let tree = git_repository
+            .find_tree(tree_id)
+            .with_context(|| format!("failed to find tree {tree_id} of a pending session"))?;
+        collect_pointers(git_repository, &tree, seen, pointers)?;
+    }
+    Ok(())
+}
+
+// walks the commits of all branches, tags and parked branch histories, except for the branch
+// with the given refname, newest first
+fn refs_revwalk<'repo>(
+    git_repository: &'repo git::Repository,
+    except: Option<&str>,
+) -> Result<git2::Revwalk<'repo>> {
+    let mut revwalk = git_repository
+        .revwalk()
+        .context("failed to create revwalk")?;
+    // sorting resets the walk, it's set before anything is pushed. sessions are often committed
+    // within the same second, so children are kept before their parents as well.
+    revwalk
+        .set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)
+        .context("failed to sort revwalk")?;
+    for branch in git_repository.branches(None)? {
+        let (branch, _) = branch.context("failed to get branch")?;
+        if except.is_some() && branch.refname() == except {
+            continue;
+        }
+        revwalk
+            .push(branch.peel_to_commit()?.id().into())
+            .with_context(|| format!("failed to push branch {:?}", branch.name()))?;
+    }
+    revwalk
+        .push_glob("refs/tags/*")
+        .context("failed to push tags")?;
+    // histories of other branches are parked here while their branch is not checked out
+    revwalk
+        .push_glob("refs/gitbutler/branches/*")
+        .context("failed to push branch histories")?;
+    // second parents of anchored sessions are project commits, they don't reference lfs objects
+    revwalk
+        .simplify_first_parent()
+        .context("failed to simplify revwalk")?;
+    Ok(revwalk)
+}
+
+// adds the pointers in the tree to pointers. trees and blobs in seen are skipped, they were
+// already visited from another commit.
+fn collect_pointers(
+    git_repository: &git::Repository,
+    tree: &git::Tree,
+    seen: &mut HashSet<git::Oid>,
+    pointers: &mut HashSet<LfsPointer>,
+) -> Result<()> {
+    let mut walk_error = None;
+    tree.walk(|_, entry| {
+        if !seen.insert(entry.id()) {
+            return git::TreeWalkResult::Skip;
+        }
+        if entry.kind() != Some(git2::ObjectType::Blob) {
+            return git::TreeWalkResult::Continue;
+        }
+        match git_repository.find_blob(entry.id()) {
+            Ok(blob) => {
+                if let Some(pointer) = parse_pointer(blob.content()) {
+                    pointers.insert(pointer);
+                }
+                git::TreeWalkResult::Continue
+            }
+            Err(error) => {
+                walk_error = Some(error);
+                git::TreeWalkResult::Stop
+            }
+        }
+    })?;
+    if let Some(error) = walk_error {
+        return Err(error).context("failed to read blob");
+    }
+    Ok(())
+}
+
+// returns the objects of the store that are not referenced. chunks are only referenced from
+// the manifests of referenced objects.
+pub(crate) fn unreferenced_objects(
+    objects_dir: &path::Path,
+    referenced: &HashSet<String>,
+) -> Result<Unreferenced> {
+    let mut unreferenced = Unreferenced::default();
+    if !objects_dir.exists() {
+        return Ok(unreferenced);
+    }
+
+    let mut chunks = HashSet::new();
+    for oid in referenced {
+        if let Some(manifest) = chunking::read_manifest(&objects_dir.join(oid))? {
+            chunks.extend(manifest.into_iter().map(|chunk| chunk.oid));
+        }
+    }
+
+    for entry in std::fs::read_dir(objects_dir)
+        .with_context(|| format!("failed to read {}", objects_dir.display()))?
+    {
+        let entry = entry?;
+        let Some(oid) = entry.file_name().to_str().map(ToString::to_string) else {
+            continue;
+        };
+        if referenced.contains(&oid) || chunks.contains(&oid) {
+            continue;
+        }
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("failed to read metadata of {}", entry.path().display()))?;
+        unreferenced.bytes += metadata.len();
+        unreferenced.oids.push(oid);
+    }
+    unreferenced.oids.sort();
+
+    Ok(unreferenced)
+}
+
+pub(crate) fn remove_unreferenced_objects(
+    objects_dir: &path::Path,
+    referenced: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let unreferenced = unreferenced_objects(objects_dir, referenced)?;
+    for oid in &unreferenced.oids {
+        let object_path = objects_dir.join(oid);
+        std::fs::remove_file(&object_path)
+            .with_context(|| format!("failed to remove {}", object_path.display()))?;
+    }
+    Ok(unreferenced.oids)
+}
+
+fn is_valid_oid(oid: &str) -> bool {
+    oid.len() == 64
+        && oid
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{self, Case, SessionBuilder, Suite};
+
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    #[test]
+    fn test_round_trip() {
+        let pointer = LfsPointer::new(OID, 12345);
+        assert_eq!(parse_pointer(&pointer.to_bytes()), Some(pointer));
+    }
+
+    #[test]
+    fn test_format() {
+        let pointer = LfsPointer::new(OID, 12345);
+        assert_eq!(
+            pointer.to_string(),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n",
+                OID
+            )
+        );
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let pointer = LfsPointer::new_encrypted(OID, 12345);
+        assert_eq!(
+            pointer.to_string(),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\nencryption aes-256-gcm\noid sha256:{}\nsize 12345\n",
+                OID
+            )
+        );
+        assert_eq!(parse_pointer(&pointer.to_bytes()), Some(pointer));
+
+        let unknown = format!(
+            "version https://git-lfs.github.com/spec/v1\nencryption rot13\noid sha256:{}\nsize 1\n",
+            OID
+        );
+        assert!(parse_pointer(unknown.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_chunked_round_trip() {
+        let pointer = LfsPointer::new_chunked(OID, 12345);
+        assert_eq!(
+            pointer.to_string(),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\nchunking gitbutler-cdc-v1\noid sha256:{}\nsize 12345\n",
+                OID
+            )
+        );
+        assert_eq!(parse_pointer(&pointer.to_bytes()), Some(pointer));
+    }
+
+    #[test]
+    fn test_is_pointer() {
+        assert!(is_pointer(&LfsPointer::new(OID, 1).to_bytes()));
+        assert!(!is_pointer(b"hello world\n"));
+        assert!(!is_pointer(b""));
+    }
+
+    #[test]
+    fn test_uppercase_oid_is_not_a_pointer() {
+        let pointer = LfsPointer::new(OID.to_uppercase(), 1);
+        assert!(parse_pointer(&pointer.to_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_missing_trailing_newline() {
+        let pointer = LfsPointer::new(OID, 1).to_string();
+        assert!(parse_pointer(pointer.trim_end().as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_missing_size() {
+        let content = format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\n",
+            OID
+        );
+        assert!(parse_pointer(content.as_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_extension_keys_are_ignored() {
+        let content = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{}\noid sha256:{}\nsize 7\n",
+            OID, OID
+        );
+        assert_eq!(
+            parse_pointer(content.as_bytes()),
+            Some(LfsPointer::new(OID, 7))
+        );
+    }
+
+    #[test]
+    fn test_remove_unreferenced_objects() -> Result<()> {
+        let repository = test_utils::empty_bare_repository();
+        let unreferenced = OID.replace('4', "5");
+
+        let pointer_blob = repository.blob(&LfsPointer::new(OID, 1).to_bytes())?;
+        let mut tree_builder = repository.treebuilder(None);
+        tree_builder.upsert("wd/large.bin", pointer_blob, git::FileMode::Blob);
+        let tree_id = tree_builder.write()?;
+        let signature = git::Signature::now("test", "test@email.com")?;
+        repository.commit(
+            Some(&"refs/heads/current".parse().unwrap()),
+            &signature,
+            &signature,
+            "test",
+            &repository.find_tree(tree_id)?,
+            &[],
+        )?;
+
+        let objects_dir = default_objects_dir(&repository);
+        std::fs::create_dir_all(&objects_dir)?;
+        std::fs::write(objects_dir.join(OID), "referenced")?;
+        std::fs::write(objects_dir.join(&unreferenced), "unreferenced")?;
+
+        let referenced = referenced_oids(&repository)?;
+        assert_eq!(referenced, HashSet::from([OID.to_string()]));
+
+        let removed = remove_unreferenced_objects(&objects_dir, &referenced)?;
+        assert_eq!(removed, vec![unreferenced.clone()]);
+        assert!(objects_dir.join(OID).exists());
+        assert!(!objects_dir.join(unreferenced).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let repository = gb_repository.git_repository();
+        let tagged = OID.replace('4', "6");
+        let parked = OID.replace('4', "7");
+        let unreferenced = OID.replace('4', "5");
+
+        SessionBuilder::new(&gb_repository)
+            .wd_file("large.bin", &LfsPointer::new(OID, 1).to_string())
+            .build()?;
+
+        // commits that are only reachable from a tag, and from the parked history of a branch
+        for (oid, refname) in [
+            (&tagged, "refs/tags/tagged"),
+            (&parked, "refs/gitbutler/branches/feature"),
+        ] {
+            let pointer_blob = repository.blob(&LfsPointer::new(oid, 1).to_bytes())?;
+            let mut tree_builder = repository.treebuilder(None);
+            tree_builder.upsert("wd/large.bin", pointer_blob, git::FileMode::Blob);
+            let tree_id = tree_builder.write()?;
+            let signature = git::Signature::now("test", "test@email.com")?;
+            repository.commit(
+                Some(&refname.parse().unwrap()),
+                &signature,
+                &signature,
+                "test",
+                &repository.find_tree(tree_id)?,
+                &[],
+            )?;
+        }
+
+        let objects_dir = gb_repository.lfs_objects_dir();
+        std::fs::create_dir_all(objects_dir)?;
+        for oid in [OID, tagged.as_str(), parked.as_str(), unreferenced.as_str()] {
+            std::fs::write(objects_dir.join(oid), oid)?;
+        }
+
+        // nothing is removed by a dry run
+        assert_eq!(
+            gc_dry_run(&gb_repository)?,
+            Unreferenced {
+                oids: vec![unreferenced.clone()],
+                bytes: 64,
+            }
+        );
+        assert!(objects_dir.join(&unreferenced).exists());
+
+        assert_eq!(gc(&gb_repository)?, vec![unreferenced.clone()]);
+        assert!(objects_dir.join(OID).exists());
+        assert!(objects_dir.join(&tagged).exists());
+        assert!(objects_dir.join(&parked).exists());
+        assert!(!objects_dir.join(&unreferenced).exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let old = OID.replace('4', "6");
+        let unreferenced = OID.replace('4', "5");
+
+        SessionBuilder::new(&gb_repository)
+            .wd_file("large.bin", &LfsPointer::new(&old, 1).to_string())
+            .build()?;
+        SessionBuilder::new(&gb_repository)
+            .wd_file("large.bin", &LfsPointer::new(OID, 1).to_string())
+            .build()?;
+
+        let objects_dir = gb_repository.lfs_objects_dir();
+        std::fs::create_dir_all(objects_dir)?;
+        for oid in [OID, old.as_str(), unreferenced.as_str()] {
+            std::fs::write(objects_dir.join(oid), oid)?;
+        }
+
+        // nothing is evicted while the store is within its limit
+        assert_eq!(
+            evict(&gb_repository, 192, 1)?,
+            Eviction {
+                store_bytes: 192,
+                ..Eviction::default()
+            }
+        );
+
+        // unreferenced objects go first, referenced ones are kept
+        assert_eq!(
+            evict(&gb_repository, 100, 1)?,
+            Eviction {
+                store_bytes: 192,
+                evicted: vec![unreferenced.clone()],
+                evicted_bytes: 64,
+                kept: vec![old.clone()],
+                kept_bytes: 64,
+            }
+        );
+        assert!(!objects_dir.join(&unreferenced).exists());
+        assert!(objects_dir.join(&old).exists());
+        assert!(objects_dir.join(OID).exists());
+
+        // objects of the newest sessions are never candidates
+        assert_eq!(
+            evict(&gb_repository, 100, 2)?,
+            Eviction {
+                store_bytes: 128,
+                ..Eviction::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_sessions_keep_objects() -> Result<()> {
+        let Case { gb_repository, .. } = Suite::default().new_case();
+        let repository = gb_repository.git_repository();
+        let queued = OID.replace('4', "6");
+
+        SessionBuilder::new(&gb_repository)
+            .wd_file("large.bin", &LfsPointer::new(OID, 1).to_string())
+            .build()?;
+
+        // a session that was captured, but not committed yet
+        let pointer_blob = repository.blob(&LfsPointer::new(&queued, 1).to_bytes())?;
+        let mut tree_builder = repository.treebuilder(None);
+        tree_builder.upsert("wd/large.bin", pointer_blob, git::FileMode::Blob);
+        let tree_id = tree_builder.write()?;
+        std::fs::write(
+            repository.path().join("pending-sessions"),
+            serde_json::to_vec(&serde_json::json!([{
+                "sessionId": crate::sessions::SessionId::generate(),
+                "treeId": tree_id,
+            }]))?,
+        )?;
+
+        let objects_dir = gb_repository.lfs_objects_dir();
+        std::fs::create_dir_all(objects_dir)?;
+        for oid in [OID, queued.as_str()] {
+            std::fs::write(objects_dir.join(oid), oid)?;
+        }
+
+        assert!(gc(&gb_repository)?.is_empty());
+        assert!(objects_dir.join(&queued).exists());
+
+        // the pending session is the newest one
+        assert_eq!(
+            evict(&gb_repository, 100, 1)?,
+            Eviction {
+                store_bytes: 128,
+                kept: vec![OID.to_string()],
+                kept_bytes: 64,
+                ..Eviction::default()
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+// content defined chunking of large files.
+//
+// a chunked file is stored as a manifest that lists its chunks, and every chunk is stored as an
+// object of its own, by the sha256 of its content. chunk boundaries depend on the content only,
+// so a small change to a big file changes one or two chunks and the rest are shared with the
+// previous version.
+//
+// boundaries are found with a gear rolling hash, like fastcdc does.
+
+use std::{
+    io::{self, Read},
+    path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+
+use super::is_valid_oid;
+
+// first line of every manifest, so that a manifest can't be confused with file content
+const MANIFEST_HEADER: &str = "gitbutler-chunks v1\n";
+const MIN_CHUNK_SIZE: usize = 256 * 1024;
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+// a boundary is where the hash has this many low bits unset, which makes chunks about 1 MiB
+// on average
+const BOUNDARY_MASK: u64 = (1 << 20) - 1;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+// random, but fixed forever. chunks of the same content must always be cut at the same places.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6769_7462_7574_6c72;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    // lowercase hex sha256 of the chunk content
+    pub oid: String,
+    pub size: u64,
+}
+
+// splits the content into chunks, calling on_chunk with each of them in order
+fn split<R: Read>(mut reader: R, mut on_chunk: impl FnMut(&[u8]) -> Result<()>) -> Result<()> {
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut chunk = Vec::with_capacity(MIN_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error.into()),
+        };
+        for byte in &buffer[..read] {
+            chunk.push(*byte);
+            hash = (hash << 1).wrapping_add(GEAR[usize::from(*byte)]);
+            if chunk.len() >= MAX_CHUNK_SIZE
+                || (chunk.len() >= MIN_CHUNK_SIZE && hash & BOUNDARY_MASK == 0)
+            {
+                on_chunk(&chunk)?;
+                chunk.clear();
+                hash = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        on_chunk(&chunk)?;
+    }
+    Ok(())
+}
+
+// stores the file as chunks in the objects directory and returns the manifest. chunks that are
+// already stored are not written again.
+pub(crate) fn write_chunks(objects_dir: &path::Path, file_path: &path::Path) -> Result<String> {
+    let file = std::fs::File::open(file_path)
+        .with_context(|| format!("failed to open {}", file_path.display()))?;
+
+    let mut manifest = MANIFEST_HEADER.to_string();
+    split(file, |chunk| {
+        let oid = format!("{:x}", Sha256::digest(chunk));
+        let chunk_path = objects_dir.join(&oid);
+        if !chunk_path.exists() {
+            // a chunk that exists is complete, a crash must not leave a partial one behind
+            let tmp_path = chunk_path.with_extension("tmp");
+            std::fs::write(&tmp_path, chunk)
+                .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+            std::fs::rename(&tmp_path, &chunk_path)
+                .with_context(|| format!("failed to rename {}", tmp_path.display()))?;
+        }
+        manifest.push_str(&format!("{} {}\n", oid, chunk.len()));
+        Ok(())
+    })
+    .with_context(|| format!("failed to chunk {}", file_path.display()))?;
+
+    Ok(manifest)
+}
+
+// returns the chunks listed in the manifest, or None if the content is not a manifest
+pub(crate) fn parse_manifest(content: &[u8]) -> Option<Vec<Chunk>> {
+    let content = std::str::from_utf8(content).ok()?;
+    let content = content.strip_prefix(MANIFEST_HEADER)?;
+    content
+        .lines()
+        .map(|line| {
+            let (oid, size) = line.split_once(' ')?;
+            if !is_valid_oid(oid) {
+                return None;
+            }
+            Some(Chunk {
+                oid: oid.to_string(),
+                size: size.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+// reads the content of a chunked file back from its manifest, checking every chunk
+pub(crate) fn read_chunks(objects_dir: &path::Path, chunks: &[Chunk]) -> Result<Vec<u8>> {
+    let size = chunks.iter().map(|chunk| chunk.size).sum::<u64>();
+    let mut content = Vec::with_capacity(usize::try_from(size).unwrap_or_default());
+    for chunk in chunks {
+        let chunk_path = objects_dir.join(&chunk.oid);
+        let chunk_content = std::fs::read(&chunk_path)
+            .with_context(|| format!("failed to read {}", chunk_path.display()))?;
+        if format!("{:x}", Sha256::digest(&chunk_content)) != chunk.oid {
+            return Err(anyhow!("lfs chunk {} is corrupted", chunk.oid));
+        }
+        content.extend_from_slice(&chunk_content);
+    }
+    Ok(content)
+}
+
+// returns the chunks of the object if it's a manifest. only the header is read of objects that
+// are not, they might be huge.
+pub(crate) fn read_manifest(object_path: &path::Path) -> Result<Option<Vec<Chunk>>> {
+    let mut file = match std::fs::File::open(object_path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to open {}", object_path.display()))
+        }
+    };
+    let mut header = vec![0; MANIFEST_HEADER.len()];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    if header != MANIFEST_HEADER.as_bytes() {
+        return Ok(None);
+    }
+    let mut content = header;
+    file.read_to_end(&mut content)?;
+    Ok(parse_manifest(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // deterministic content that doesn't repeat, so that it has boundaries
+    fn content(size: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    fn chunk_oids(content: &[u8]) -> Result<Vec<String>> {
+        let mut oids = vec![];
+        split(content, |chunk| {
+            oids.push(format!("{:x}", Sha256::digest(chunk)));
+            Ok(())
+        })?;
+        Ok(oids)
+    }
+
+    #[test]
+    fn test_split() -> Result<()> {
+        let content = content(8 * 1024 * 1024, 1);
+        let mut chunks = vec![];
+        split(content.as_slice(), |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })?;
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= MAX_CHUNK_SIZE));
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| chunk.len() >= MIN_CHUNK_SIZE));
+        assert_eq!(chunks.concat(), content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_change_keeps_most_chunks() -> Result<()> {
+        let original = content(8 * 1024 * 1024, 2);
+        let mut changed = original.clone();
+        changed.splice(4_000_000..4_000_000, b"inserted".iter().copied());
+
+        let original_oids = chunk_oids(&original)?;
+        let changed_oids = chunk_oids(&changed)?;
+        let shared = changed_oids
+            .iter()
+            .filter(|oid| original_oids.contains(oid))
+            .count();
+        assert!(shared >= original_oids.len() - 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_and_read_chunks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file_path = dir.path().join("large.bin");
+        let content = content(3 * 1024 * 1024, 3);
+        std::fs::write(&file_path, &content)?;
+        let objects_dir = dir.path().join("objects");
+        std::fs::create_dir_all(&objects_dir)?;
+
+        let manifest = write_chunks(&objects_dir, &file_path)?;
+        let chunks = parse_manifest(manifest.as_bytes()).unwrap();
+        assert_eq!(read_chunks(&objects_dir, &chunks)?, content);
+
+        std::fs::write(objects_dir.join("manifest"), &manifest)?;
+        assert_eq!(read_manifest(&objects_dir.join("manifest"))?, Some(chunks));
+        assert_eq!(read_manifest(&file_path)?, None);
+        assert!(parse_manifest(&content).is_none());
+        Ok(())
+    }
+}
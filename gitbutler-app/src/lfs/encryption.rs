@@ -0,0 +1,120 @@
+use std::{fmt, str};
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, Result};
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+// aes-256-gcm key for large files stored at rest. the key never ends up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; KEY_SIZE]);
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl str::FromStr for EncryptionKey {
+    type Err = anyhow::Error;
+
+    // keys are 64 hex characters
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.len() != KEY_SIZE * 2 || !value.is_ascii() {
+            return Err(anyhow!("expected {} hex characters", KEY_SIZE * 2));
+        }
+        let mut key = [0; KEY_SIZE];
+        for (byte, hex) in key.iter_mut().zip(value.as_bytes().chunks(2)) {
+            let hex = str::from_utf8(hex)?;
+            *byte = u8::from_str_radix(hex, 16)
+                .map_err(|_| anyhow!("expected {} hex characters", KEY_SIZE * 2))?;
+        }
+        Ok(Self(key))
+    }
+}
+
+// encrypts the content of a large file. the lfs oid is authenticated together with the content,
+// so an object can't be swapped for another one encrypted with the same key.
+//
+// the output is the random nonce followed by the ciphertext and the tag.
+pub fn encrypt(key: &EncryptionKey, oid: &str, content: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let nonce: [u8; NONCE_SIZE] = rand::random();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: content,
+                aad: oid.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("failed to encrypt lfs object {}", oid))?;
+
+    let mut encrypted = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+// fails if the object was encrypted with another key, for another oid, or was modified
+pub fn decrypt(key: &EncryptionKey, oid: &str, encrypted: &[u8]) -> Result<Vec<u8>> {
+    if encrypted.len() < NONCE_SIZE {
+        return Err(anyhow!("lfs object {} is truncated", oid));
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_SIZE);
+    let cipher = Aes256Gcm::new(&key.0.into());
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: oid.as_bytes(),
+            },
+        )
+        .map_err(|_| anyhow!("failed to decrypt lfs object {}, it is corrupted", oid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn key() -> EncryptionKey {
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let encrypted = encrypt(&key(), OID, b"large file")?;
+        assert_ne!(&encrypted[NONCE_SIZE..], b"large file");
+        assert_eq!(decrypt(&key(), OID, &encrypted)?, b"large file");
+        Ok(())
+    }
+
+    #[test]
+    fn test_corruption_is_detected() -> Result<()> {
+        let mut encrypted = encrypt(&key(), OID, b"large file")?;
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+        assert!(decrypt(&key(), OID, &encrypted).is_err());
+
+        let encrypted = encrypt(&key(), OID, b"large file")?;
+        assert!(decrypt(&key(), &OID.replace('4', "5"), &encrypted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert!("00".parse::<EncryptionKey>().is_err());
+        assert!("zz".repeat(32).parse::<EncryptionKey>().is_err());
+        assert_eq!(format!("{:?}", key()), "EncryptionKey(..)");
+    }
+}
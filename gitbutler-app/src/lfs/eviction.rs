@@ -0,0 +1,197 @@
+// keeps the lfs store below gitbutler.maxLfsStoreBytes. objects are evicted least recently
+// referenced first, objects referenced by the newest gitbutler.lfsKeepSessions sessions never
+// are.
+//
+// an evicted object that is still referenced is gone for good until objects are uploaded to a
+// remote lfs store, see the TODO of add_wd_path. until then only unreferenced objects are removed,
+// the referenced ones that would be evicted are reported and kept.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    path,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{gb_repository, git};
+
+use super::{chunking, collect_pointers, refs_revwalk};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Eviction {
+    // size of the store before anything was evicted
+    pub store_bytes: u64,
+    // removed objects, least recently referenced first
+    pub evicted: Vec<String>,
+    pub evicted_bytes: u64,
+    // objects that would have been evicted, but are kept because they are still referenced
+    pub kept: Vec<String>,
+    pub kept_bytes: u64,
+}
+
+// evicts objects from the lfs store until it's no bigger than max_bytes, nothing is evicted if
+// it's not bigger already
+pub fn evict(
+    repository: &gb_repository::Repository,
+    max_bytes: u64,
+    keep_sessions: usize,
+) -> Result<Eviction> {
+    let _lock = repository.lock();
+    evict_locked(
+        repository.git_repository(),
+        repository.lfs_objects_dir(),
+        max_bytes,
+        keep_sessions,
+    )
+}
+
+// same as evict, for callers that already hold the repository lock
+pub(crate) fn evict_locked(
+    git_repository: &git::Repository,
+    objects_dir: &path::Path,
+    max_bytes: u64,
+    keep_sessions: usize,
+) -> Result<Eviction> {
+    let objects = store_objects(objects_dir)?;
+    let mut eviction = Eviction {
+        store_bytes: objects.iter().map(|(_, size)| size).sum(),
+        ..Eviction::default()
+    };
+    if eviction.store_bytes <= max_bytes {
+        return Ok(eviction);
+    }
+
+    let ages = reference_ages(git_repository, objects_dir)
+        .context("failed to collect referenced lfs objects")?;
+    let mut candidates = objects
+        .into_iter()
+        .filter_map(|(oid, size)| match ages.get(&oid) {
+            Some(age) if *age < keep_sessions => None,
+            age => Some((age.copied(), oid, size)),
+        })
+        .collect::<Vec<_>>();
+    // unreferenced objects go first, then the ones that were referenced the longest time ago
+    candidates.sort_by_key(|(age, oid, _)| (Reverse(age.unwrap_or(usize::MAX)), oid.clone()));
+
+    let mut remaining = eviction.store_bytes;
+    for (age, oid, size) in candidates {
+        if remaining <= max_bytes {
+            break;
+        }
+        remaining -= size;
+        if age.is_some() {
+            eviction.kept_bytes += size;
+            eviction.kept.push(oid);
+            continue;
+        }
+        let object_path = objects_dir.join(&oid);
+        std::fs::remove_file(&object_path)
+            .with_context(|| format!("failed to remove {}", object_path.display()))?;
+        eviction.evicted_bytes += size;
+        eviction.evicted.push(oid);
+    }
+
+    if !eviction.evicted.is_empty() {
+        tracing::info!(
+            store_bytes = eviction.store_bytes,
+            max_bytes,
+            evicted = ?eviction.evicted,
+            evicted_bytes = eviction.evicted_bytes,
+            "evicted lfs objects"
+        );
+    }
+    if !eviction.kept.is_empty() {
+        tracing::warn!(
+            kept = ?eviction.kept,
+            kept_bytes = eviction.kept_bytes,
+            "lfs store is over its limit, referenced objects are kept until they can be uploaded"
+        );
+    }
+
+    Ok(eviction)
+}
+
+// objects of the store with their sizes, chunks included
+fn store_objects(objects_dir: &path::Path) -> Result<Vec<(String, u64)>> {
+    if !objects_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut objects = vec![];
+    for entry in std::fs::read_dir(objects_dir)
+        .with_context(|| format!("failed to read {}", objects_dir.display()))?
+    {
+        let entry = entry?;
+        let Some(oid) = entry.file_name().to_str().map(ToString::to_string) else {
+            continue;
+        };
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("failed to read metadata of {}", entry.path().display()))?;
+        if metadata.is_file() {
+            objects.push((oid, metadata.len()));
+        }
+    }
+    Ok(objects)
+}
+
+// returns for every referenced object how many sessions newer than the newest one that
+// references it there are. queued pending sessions are newer than any commit. chunks are as old
+// as the newest object they are part of.
+fn reference_ages(
+    git_repository: &git::Repository,
+    objects_dir: &path::Path,
+) -> Result<HashMap<String, usize>> {
+    // trees that were seen in a newer session already have the age of that session
+    let mut seen = HashSet::new();
+    let mut ages = HashMap::new();
+    let queued =
+        gb_repository::queued_trees(git_repository).context("failed to read pending sessions")?;
+    for (age, tree_id) in queued.iter().rev().enumerate() {
+        let tree = git_repository.find_tree(*tree_id)?;
+        add_reference_ages(
+            git_repository,
+            objects_dir,
+            &tree,
+            age,
+            &mut seen,
+            &mut ages,
+        )?;
+    }
+    for (age, commit_id) in refs_revwalk(git_repository, None)?.enumerate() {
+        let commit = git_repository.find_commit(commit_id?.into())?;
+        add_reference_ages(
+            git_repository,
+            objects_dir,
+            &commit.tree()?,
+            queued.len() + age,
+            &mut seen,
+            &mut ages,
+        )?;
+    }
+    Ok(ages)
+}
+
+// gives the objects referenced from the tree that don't have an age yet the age
+fn add_reference_ages(
+    git_repository: &git::Repository,
+    objects_dir: &path::Path,
+    tree: &git::Tree,
+    age: usize,
+    seen: &mut HashSet<git::Oid>,
+    ages: &mut HashMap<String, usize>,
+) -> Result<()> {
+    let mut pointers = HashSet::new();
+    collect_pointers(git_repository, tree, seen, &mut pointers)?;
+    for pointer in pointers {
+        if let Some(manifest) = chunking::read_manifest(&objects_dir.join(&pointer.oid))? {
+            for chunk in manifest {
+                ages.entry(chunk.oid).or_insert(age);
+            }
+        }
+        ages.entry(pointer.oid).or_insert(age);
+    }
+    Ok(())
+}
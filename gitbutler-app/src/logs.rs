@@ -3,10 +3,14 @@ use std::{fs, net::Ipv4Addr, time::Duration};
 use tauri::{AppHandle, Manager};
 use tracing::{metadata::LevelFilter, subscriber::set_global_default};
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
-use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, Layer};
+use tracing_subscriber::{filter::Targets, fmt::format::FmtSpan, layer::SubscriberExt, Layer};
 
 use crate::sentry;
 
+// target of per-file logs of the watcher and of capturing sessions, so that they can be filtered
+// separately from the rest, for example with LOG_LEVEL=info,watcher=trace
+pub const WATCHER: &str = "watcher";
+
 pub fn init(app_handle: &AppHandle) {
     let logs_dir = app_handle
         .path_resolver()
@@ -29,11 +33,12 @@ pub fn init(app_handle: &AppHandle) {
         .with_target(false)
         .compact();
 
+    // either a level for everything, or a list of directives like info,watcher=trace
     let log_level_filter = std::env::var("LOG_LEVEL")
         .unwrap_or("info".to_string())
         .to_lowercase()
-        .parse()
-        .unwrap_or(LevelFilter::INFO);
+        .parse::<Targets>()
+        .unwrap_or_else(|_| Targets::new().with_default(LevelFilter::INFO));
 
     let subscriber = tracing_subscriber::registry()
         .with(
@@ -50,7 +55,7 @@ pub fn init(app_handle: &AppHandle) {
             tracing_subscriber::fmt::layer()
                 .event_format(format_for_humans.clone())
                 .with_span_events(FmtSpan::CLOSE)
-                .with_filter(log_level_filter),
+                .with_filter(log_level_filter.clone()),
         )
         .with(sentry::tracing_layer())
         .with(
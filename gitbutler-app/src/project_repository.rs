@@ -1,8 +1,14 @@
 mod config;
 pub mod conflicts;
+mod quiet_hours;
 mod repository;
+mod settings;
 
 pub use config::Config;
+pub use quiet_hours::QuietHours;
 pub use repository::{LogUntil, OpenError, RemoteError, Repository};
+pub use settings::{
+    is_inside_workdir, resolve_lfs_threshold, Settings, DEFAULT_LFS_THRESHOLD, GIT_MAX_BLOB_SIZE,
+};
 
 pub mod signatures;
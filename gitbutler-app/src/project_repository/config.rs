@@ -1,5 +1,10 @@
+use std::time;
+
 use crate::git;
 
+// files modified within this window are not captured yet, see Config::settle_window
+const DEFAULT_SETTLE_WINDOW: time::Duration = time::Duration::from_secs(2);
+
 pub struct Config<'a> {
     git_repository: &'a git::Repository,
 }
@@ -33,6 +38,416 @@ impl Config<'_> {
         Ok(no_comitter == "1")
     }
 
+    pub fn stream_large_files(&self) -> Result<bool, git::Error> {
+        let stream_large_files = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.streamLargeFiles")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(stream_large_files)
+    }
+
+    // sessions that didn't change anything since the last one are discarded by the watcher
+    // instead of committed
+    pub fn skip_unchanged_sessions(&self) -> Result<bool, git::Error> {
+        let skip_unchanged_sessions = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.skipUnchangedSessions")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(skip_unchanged_sessions)
+    }
+
+    pub fn capture_index(&self) -> Result<bool, git::Error> {
+        let capture_index = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.captureIndex")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(capture_index)
+    }
+
+    // the reflog of the project's HEAD is captured with every session, under the logs subtree
+    pub fn capture_reflog(&self) -> Result<bool, git::Error> {
+        let capture_reflog = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.captureReflog")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(capture_reflog)
+    }
+
+    // only this many of the newest reflog entries are captured. all of them are when it's not
+    // set, or set to 0.
+    pub fn reflog_max_entries(&self) -> Result<Option<usize>, git::Error> {
+        let reflog_max_entries = self
+            .git_repository
+            .config()?
+            .get_i64("gitbutler.reflogMaxEntries")
+            .unwrap_or(None)
+            .and_then(|entries| usize::try_from(entries).ok())
+            .filter(|entries| *entries > 0);
+        Ok(reflog_max_entries)
+    }
+
+    // gitbutler commits are timestamped with the last activity in the session, unless
+    // gitbutler.commitTimestamp is set to "now"
+    pub fn session_commit_timestamp(&self) -> Result<bool, git::Error> {
+        let commit_timestamp = self
+            .git_repository
+            .config()?
+            .get_string("gitbutler.commitTimestamp")
+            .unwrap_or(Some("session".to_string()))
+            .unwrap_or("session".to_string());
+        Ok(commit_timestamp != "now")
+    }
+
+    // gitbutler commits are made in the local timezone, unless gitbutler.commitTimezone is set
+    // to utc or an offset like +0200
+    pub fn commit_timezone(&self) -> Result<Option<String>, git::Error> {
+        let commit_timezone = self
+            .git_repository
+            .config()?
+            .get_string("gitbutler.commitTimezone")
+            .unwrap_or(None);
+        Ok(commit_timezone)
+    }
+
+    // session commits are pushed with this author and committer, as name and email, when
+    // gitbutler.pushAuthorName or gitbutler.pushAuthorEmail is set. the other one defaults to
+    // gitbutler. local history keeps the identity it was committed with.
+    pub fn push_author(&self) -> Result<Option<(String, String)>, git::Error> {
+        let config = self.git_repository.config()?;
+        let name = config
+            .get_string("gitbutler.pushAuthorName")
+            .unwrap_or(None);
+        let email = config
+            .get_string("gitbutler.pushAuthorEmail")
+            .unwrap_or(None);
+        if name.is_none() && email.is_none() {
+            return Ok(None);
+        }
+        Ok(Some((
+            name.unwrap_or_else(|| "gitbutler".to_string()),
+            email.unwrap_or_else(|| "gitbutler@localhost".to_string()),
+        )))
+    }
+
+    pub fn ignore_case(&self) -> Result<bool, git::Error> {
+        let ignore_case = self
+            .git_repository
+            .config()?
+            .get_bool("core.ignorecase")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(ignore_case)
+    }
+
+    pub fn sparse_checkout(&self) -> Result<bool, git::Error> {
+        let sparse_checkout = self
+            .git_repository
+            .config()?
+            .get_bool("core.sparseCheckout")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(sparse_checkout)
+    }
+
+    pub fn sparse_checkout_cone(&self) -> Result<bool, git::Error> {
+        let sparse_checkout_cone = self
+            .git_repository
+            .config()?
+            .get_bool("core.sparseCheckoutCone")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(sparse_checkout_cone)
+    }
+
+    // line endings of text files are normalized in the object database, core.autocrlf is either
+    // a boolean or input
+    pub fn autocrlf(&self) -> Result<bool, git::Error> {
+        let autocrlf = self
+            .git_repository
+            .config()?
+            .get_string("core.autocrlf")
+            .unwrap_or(None)
+            .unwrap_or_default();
+        Ok(matches!(
+            autocrlf.to_lowercase().as_str(),
+            "true" | "yes" | "on" | "1" | "input"
+        ))
+    }
+
+    // paths longer than the usual windows limit are supported, like git for windows does it
+    pub fn long_paths(&self) -> Result<bool, git::Error> {
+        let long_paths = self
+            .git_repository
+            .config()?
+            .get_bool("core.longpaths")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(long_paths)
+    }
+
+    pub fn max_poll_interval(&self) -> Result<Option<time::Duration>, git::Error> {
+        let max_poll_interval = self
+            .git_repository
+            .config()?
+            .get_i64("gitbutler.maxPollInterval")
+            .unwrap_or(None)
+            .and_then(|seconds| u64::try_from(seconds).ok())
+            .map(time::Duration::from_secs);
+        Ok(max_poll_interval)
+    }
+
+    // files modified more recently than this are considered to be still written to. it's
+    // DEFAULT_SETTLE_WINDOW when not set, and 0 turns it off.
+    pub fn settle_window(&self) -> Result<Option<time::Duration>, git::Error> {
+        let settle_window = match self
+            .git_repository
+            .config()?
+            .get_i64("gitbutler.settleWindowMs")
+            .unwrap_or(None)
+        {
+            Some(millis) => u64::try_from(millis)
+                .ok()
+                .filter(|millis| *millis > 0)
+                .map(time::Duration::from_millis),
+            None => Some(DEFAULT_SETTLE_WINDOW),
+        };
+        Ok(settle_window)
+    }
+
+    // seconds without activity after which the current session is flushed
+    pub fn idle_timeout(&self) -> Result<Option<i64>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_i64("gitbutler.idleTimeout")
+    }
+
+    // files bigger than this many bytes are stored as lfs objects
+    pub fn lfs_threshold(&self) -> Result<Option<i64>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_i64("gitbutler.lfsThreshold")
+    }
+
+    // once the lfs store is bigger than this many bytes, the least recently referenced objects are
+    // evicted from it
+    pub fn max_lfs_store_bytes(&self) -> Result<Option<i64>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_i64("gitbutler.maxLfsStoreBytes")
+    }
+
+    // lfs objects referenced by this many of the newest sessions are never evicted
+    pub fn lfs_keep_sessions(&self) -> Result<Option<i64>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_i64("gitbutler.lfsKeepSessions")
+    }
+
+    // files marked assume-unchanged or skip-worktree in the index are not captured
+    pub fn respect_index_flags(&self) -> Result<Option<bool>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_bool("gitbutler.respectIndexFlags")
+    }
+
+    // comma separated extensions of files to compute deltas for
+    pub fn delta_extensions(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.deltaExtensions")
+    }
+
+    // comma separated directories that are captured even if they are ignored
+    pub fn force_capture_dirs(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.forceCaptureDirs")
+    }
+
+    // directory to store large files in, instead of the gitbutler repository
+    // comma separated globs of files that are never stored as lfs objects
+    pub fn inline_paths(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.inlinePaths")
+    }
+
+    // directory of the gitbutler repository that the current session is written to, relative to
+    // the repository
+    pub fn session_root(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.sessionRoot")
+    }
+
+    // shell command that is run in the project when a session is captured, what it prints is
+    // recorded with the session
+    pub fn status_command(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.statusCommand")
+    }
+
+    // seconds after which the status command is stopped
+    pub fn status_command_timeout(&self) -> Result<Option<time::Duration>, git::Error> {
+        let status_command_timeout = self
+            .git_repository
+            .config()?
+            .get_i64("gitbutler.statusCommandTimeout")
+            .unwrap_or(None)
+            .and_then(|seconds| u64::try_from(seconds).ok())
+            .map(time::Duration::from_secs);
+        Ok(status_command_timeout)
+    }
+
+    pub fn lfs_objects_dir(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.lfsObjectsDir")
+    }
+
+    // large files are stored as content defined chunks, so that versions of a file share the
+    // chunks that didn't change. not used for encrypted files.
+    pub fn lfs_chunking(&self) -> Result<bool, git::Error> {
+        let lfs_chunking = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.lfsChunking")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(lfs_chunking)
+    }
+
+    // hex encoded key to encrypt large files with
+    pub fn lfs_encryption_key(&self) -> Result<Option<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_string("gitbutler.lfsEncryptionKey")
+    }
+
+    // keep a persistent cache of blob ids of project files, so that they are not hashed again
+    // after a restart
+    pub fn index_cache(&self) -> Result<bool, git::Error> {
+        let index_cache = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.indexCache")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(index_cache)
+    }
+
+    // committed sessions are also appended to an append-only, hash chained log
+    pub fn audit_log(&self) -> Result<bool, git::Error> {
+        let audit_log = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.auditLog")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(audit_log)
+    }
+
+    // session commits get the project's head commit as a second parent, so that sessions can be
+    // navigated relative to the project's own history
+    pub fn anchor_sessions(&self) -> Result<bool, git::Error> {
+        let anchor_sessions = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.anchorSessions")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(anchor_sessions)
+    }
+
+    // every branch of the project gets its own history of sessions, sessions are appended to the
+    // history of the branch that is checked out when they are flushed
+    pub fn branch_sessions(&self) -> Result<bool, git::Error> {
+        let branch_sessions = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.branchSessions")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(branch_sessions)
+    }
+
+    // directories without files are recorded with the session, so that they can be restored.
+    // git doesn't store them in trees.
+    pub fn capture_empty_dirs(&self) -> Result<bool, git::Error> {
+        let capture_empty_dirs = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.captureEmptyDirs")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(capture_empty_dirs)
+    }
+
+    // ignored directories at the root of the project are recorded with the session, with their
+    // size but without their contents
+    pub fn record_ignored_dirs(&self) -> Result<bool, git::Error> {
+        let record_ignored_dirs = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.recordIgnoredDirs")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(record_ignored_dirs)
+    }
+
+    // extended attributes of captured files, acls included, are recorded with the session
+    pub fn capture_xattrs(&self) -> Result<bool, git::Error> {
+        let capture_xattrs = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.captureXattrs")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(capture_xattrs)
+    }
+
+    // contents of newly captured files are checked for secrets, files that seem to have some are
+    // reported. capture is not affected.
+    pub fn scan_secrets(&self) -> Result<bool, git::Error> {
+        let scan_secrets = self
+            .git_repository
+            .config()?
+            .get_bool("gitbutler.scanSecrets")
+            .unwrap_or(Some(false))
+            .unwrap_or(false);
+        Ok(scan_secrets)
+    }
+
+    // regular expressions of secrets to look for, on top of the builtin ones
+    pub fn secret_patterns(&self) -> Result<Vec<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_multivar("gitbutler.secretPattern")
+    }
+
+    // trailers added to session commits, builtin ones by name or `Key: value`
+    pub fn commit_trailers(&self) -> Result<Vec<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_multivar("gitbutler.commitTrailer")
+    }
+
+    // windows of the day like 22:00-07:00 during which nothing is captured in the background
+    pub fn quiet_hours(&self) -> Result<Vec<String>, git::Error> {
+        self.git_repository
+            .config()?
+            .get_multivar("gitbutler.quietHours")
+    }
+
     pub fn user_name(&self) -> Result<Option<String>, git::Error> {
         self.git_repository.config()?.get_string("user.name")
     }
@@ -0,0 +1,148 @@
+// windows of the day during which nothing is captured in the background, for example overnight
+// or during presentations. windows are in local time and might wrap around midnight.
+
+use std::{fmt, str, time};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveTime, Timelike};
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl str::FromStr for QuietHours {
+    type Err = anyhow::Error;
+
+    // parses windows like 22:00-07:30
+    fn from_str(value: &str) -> Result<Self> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| anyhow!("invalid quiet hours {value}, expected HH:MM-HH:MM"))?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .with_context(|| format!("invalid quiet hours {value}, expected HH:MM-HH:MM"))
+        };
+        let quiet_hours = Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+        if quiet_hours.start == quiet_hours.end {
+            return Err(anyhow!(
+                "invalid quiet hours {value}, expected the start and the end to differ"
+            ));
+        }
+        Ok(quiet_hours)
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+impl QuietHours {
+    // returns how long the window lasts from now on, or None if now is outside of it
+    fn remaining(&self, now: NaiveTime) -> Option<time::Duration> {
+        let now = now.num_seconds_from_midnight();
+        let start = self.start.num_seconds_from_midnight();
+        let end = self.end.num_seconds_from_midnight();
+        let since_start = (now + SECONDS_PER_DAY - start) % SECONDS_PER_DAY;
+        let length = (end + SECONDS_PER_DAY - start) % SECONDS_PER_DAY;
+        (since_start < length).then(|| time::Duration::from_secs(u64::from(length - since_start)))
+    }
+}
+
+// returns how long it is until none of the windows is open, or None if none of them is open now.
+// windows that overlap or follow each other are one long window.
+pub fn quiet_remaining(windows: &[QuietHours], now: NaiveTime) -> Option<time::Duration> {
+    let mut remaining = time::Duration::ZERO;
+    let mut at = now;
+    // every window can extend the quiet time at most once
+    for _ in 0..windows.len() {
+        let Some(longest) = windows
+            .iter()
+            .filter_map(|window| window.remaining(at))
+            .max()
+        else {
+            break;
+        };
+        remaining += longest;
+        at = now + chrono::Duration::from_std(remaining).ok()?;
+        if remaining.as_secs() >= u64::from(SECONDS_PER_DAY) {
+            break;
+        }
+    }
+    (!remaining.is_zero()).then_some(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn minutes(minutes: u64) -> Option<time::Duration> {
+        Some(time::Duration::from_secs(minutes * 60))
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let quiet_hours = " 22:00 - 07:30 ".parse::<QuietHours>()?;
+        assert_eq!(quiet_hours.to_string(), "22:00-07:30");
+
+        for (value, error) in [
+            ("22:00", "invalid quiet hours 22:00, expected HH:MM-HH:MM"),
+            ("22-07", "invalid quiet hours 22-07, expected HH:MM-HH:MM"),
+            (
+                "25:00-07:00",
+                "invalid quiet hours 25:00-07:00, expected HH:MM-HH:MM",
+            ),
+            (
+                "09:00-09:00",
+                "invalid quiet hours 09:00-09:00, expected the start and the end to differ",
+            ),
+        ] {
+            assert_eq!(
+                value.parse::<QuietHours>().unwrap_err().to_string(),
+                error,
+                "{value}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_quiet_remaining() -> Result<()> {
+        let overnight = "22:00-07:00".parse::<QuietHours>()?;
+        let lunch = "12:00-13:00".parse::<QuietHours>()?;
+        let windows = [overnight, lunch];
+
+        assert_eq!(quiet_remaining(&windows, at("21:59")), None);
+        assert_eq!(quiet_remaining(&windows, at("22:00")), minutes(9 * 60));
+        assert_eq!(quiet_remaining(&windows, at("06:30")), minutes(30));
+        assert_eq!(quiet_remaining(&windows, at("07:00")), None);
+        assert_eq!(quiet_remaining(&windows, at("12:15")), minutes(45));
+        assert_eq!(quiet_remaining(&[], at("12:15")), None);
+
+        // windows that follow each other are one
+        let afternoon = "13:00-14:00".parse::<QuietHours>()?;
+        assert_eq!(
+            quiet_remaining(&[afternoon, lunch], at("12:30")),
+            minutes(90)
+        );
+
+        Ok(())
+    }
+}
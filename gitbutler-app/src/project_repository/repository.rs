@@ -66,6 +66,10 @@ impl Repository {
         super::Config::from(&self.git_repository)
     }
 
+    pub fn settings(&self) -> anyhow::Result<super::Settings> {
+        super::Settings::resolve(&self.project, &self.config())
+    }
+
     pub fn git_signatures<'a>(
         &self,
         user: Option<&users::User>,
@@ -97,8 +101,25 @@ impl Repository {
         Ok(ignored)
     }
 
+    // the working directory of the project. the git directory is not always its .git, for
+    // example in submodules and in repositories with a separate git dir.
     pub fn root(&self) -> &std::path::Path {
-        self.git_repository.path().parent().unwrap()
+        self.git_repository
+            .workdir()
+            .unwrap_or_else(|| self.git_repository.path())
+    }
+
+    // paths relative to the root that belong to git and are never captured: .git, which might be
+    // a gitlink file, and the git directory if it's somewhere else inside the working directory
+    pub fn git_paths(&self) -> Vec<&path::Path> {
+        let dotgit = path::Path::new(".git");
+        let mut git_paths = vec![dotgit];
+        if let Ok(git_dir) = self.git_repository.path().strip_prefix(self.root()) {
+            if !git_dir.as_os_str().is_empty() && git_dir != dotgit {
+                git_paths.push(git_dir);
+            }
+        }
+        git_paths
     }
 
     pub fn git_remote_branches(&self) -> Result<Vec<git::RemoteRefname>> {
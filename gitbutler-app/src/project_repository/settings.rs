@@ -0,0 +1,422 @@
+use std::{path, time};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{git, projects};
+
+use super::{quiet_hours, Config, QuietHours};
+
+pub const DEFAULT_IDLE_TIMEOUT: time::Duration = time::Duration::new(5 * 60, 0);
+// files bigger than this are stored as lfs objects by default. well below GIT_MAX_BLOB_SIZE,
+// because git gets slow with big blobs long before it can't store them.
+pub const DEFAULT_LFS_THRESHOLD: u64 = 100_000_000;
+// sizes in the git index are 32 bit, so git can't store bigger blobs. bigger files are always
+// stored as lfs objects, no matter how high the lfs threshold is set.
+pub const GIT_MAX_BLOB_SIZE: u64 = u32::MAX as u64;
+// lfs objects referenced by the newest sessions are kept when the store is over its limit
+pub const DEFAULT_LFS_KEEP_SESSIONS: usize = 10;
+pub const DEFAULT_DELTA_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cs", "css", "go", "h", "hpp", "html", "java", "js", "json", "jsx", "kt",
+    "lua", "md", "php", "py", "rb", "rs", "scss", "sh", "sql", "svelte", "swift", "toml", "ts",
+    "tsx", "txt", "vue", "xml", "yaml", "yml",
+];
+
+// watcher settings of a project.
+//
+// every setting is taken from the project first, then from the [gitbutler] section of the
+// repository's git config, so that it can be shared with the repository, and then falls back to
+// the default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    // the current session is flushed after this long without activity
+    pub idle_timeout: time::Duration,
+    // files bigger than this many bytes are stored as lfs objects
+    pub lfs_threshold: u64,
+    // files marked assume-unchanged or skip-worktree in the index are not captured
+    pub respect_index_flags: bool,
+    // deltas are only computed for text files with one of these extensions, lowercase and
+    // without the leading dot
+    pub delta_extensions: Vec<String>,
+    // directories relative to the project root that are captured even if they are ignored
+    pub force_capture_dirs: Vec<path::PathBuf>,
+    // large files are stored under this directory instead of the gitbutler repository. relative
+    // paths are resolved against the project root.
+    pub lfs_objects_dir: Option<path::PathBuf>,
+    // the current session is not flushed in the background during these windows
+    pub quiet_hours: Vec<QuietHours>,
+    // globs of files that are stored as blobs no matter how big they are, unless git can't store
+    // them
+    pub inline_paths: Vec<String>,
+    // the lfs store is kept below this many bytes, when it's set
+    pub max_lfs_store_bytes: Option<u64>,
+    // objects referenced by this many of the newest sessions are never evicted from the lfs store
+    pub lfs_keep_sessions: usize,
+}
+
+impl Settings {
+    pub fn resolve(project: &projects::Project, config: &Config) -> Result<Self> {
+        let idle_timeout = match project.idle_timeout_secs {
+            Some(seconds) => time::Duration::from_secs(seconds),
+            None => non_negative(
+                "gitbutler.idleTimeout",
+                "a number of seconds",
+                config.idle_timeout(),
+            )?
+            .map_or(DEFAULT_IDLE_TIMEOUT, time::Duration::from_secs),
+        };
+
+        let lfs_threshold = match project.lfs_threshold {
+            Some(bytes) => bytes,
+            None => resolve_lfs_threshold(config)?,
+        };
+
+        let respect_index_flags = match project.respect_index_flags {
+            Some(respect_index_flags) => respect_index_flags,
+            None => config
+                .respect_index_flags()
+                .context("failed to read gitbutler.respectIndexFlags, expected a boolean")?
+                .unwrap_or(false),
+        };
+
+        let delta_extensions = match &project.delta_extensions {
+            Some(delta_extensions) => normalize_extensions(delta_extensions),
+            None => match config.delta_extensions().context(
+                "failed to read gitbutler.deltaExtensions, expected a list of extensions",
+            )? {
+                Some(delta_extensions) => normalize_extensions(delta_extensions.split(',')),
+                None => normalize_extensions(DEFAULT_DELTA_EXTENSIONS),
+            },
+        };
+
+        let force_capture_dirs = match &project.force_capture_dirs {
+            Some(force_capture_dirs) => force_capture_dirs.clone(),
+            None => config
+                .force_capture_dirs()
+                .context(
+                    "failed to read gitbutler.forceCaptureDirs, expected a list of directories",
+                )?
+                .map(|dirs| {
+                    dirs.split(',')
+                        .map(str::trim)
+                        .filter(|dir| !dir.is_empty())
+                        .map(path::PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        if let Some(dir) = force_capture_dirs
+            .iter()
+            .find(|dir| !is_inside_workdir(dir))
+        {
+            return Err(anyhow!(
+                "invalid force capture directory {}, expected a directory inside the project",
+                dir.display()
+            ));
+        }
+
+        let quiet_hours = match &project.quiet_hours {
+            Some(quiet_hours) => quiet_hours.clone(),
+            None => config.quiet_hours().context(
+                "failed to read gitbutler.quietHours, expected windows like 22:00-07:00",
+            )?,
+        }
+        .iter()
+        .map(|window| window.parse())
+        .collect::<Result<Vec<QuietHours>>>()?;
+
+        let inline_paths = match &project.inline_paths {
+            Some(inline_paths) => inline_paths.clone(),
+            None => config
+                .inline_paths()
+                .context("failed to read gitbutler.inlinePaths, expected a list of globs")?
+                .map(|globs| {
+                    globs
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|glob| !glob.is_empty())
+                        .map(ToString::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+
+        let max_lfs_store_bytes = match project.max_lfs_store_bytes {
+            Some(bytes) => Some(bytes),
+            None => non_negative(
+                "gitbutler.maxLfsStoreBytes",
+                "a number of bytes",
+                config.max_lfs_store_bytes(),
+            )?,
+        };
+
+        let lfs_keep_sessions = match project.lfs_keep_sessions {
+            Some(sessions) => Some(sessions),
+            None => non_negative(
+                "gitbutler.lfsKeepSessions",
+                "a number of sessions",
+                config.lfs_keep_sessions(),
+            )?,
+        }
+        .map(usize::try_from)
+        .transpose()
+        .context("invalid lfs keep sessions, expected a number of sessions")?
+        .unwrap_or(DEFAULT_LFS_KEEP_SESSIONS);
+
+        Ok(Self {
+            idle_timeout,
+            lfs_threshold,
+            respect_index_flags,
+            delta_extensions,
+            force_capture_dirs,
+            lfs_objects_dir: Self::resolve_lfs_objects_dir(project, config)?,
+            quiet_hours,
+            inline_paths,
+            max_lfs_store_bytes,
+            lfs_keep_sessions,
+        })
+    }
+
+    // the gitbutler repository needs the lfs store even when other settings are invalid, so it
+    // can be resolved on its own
+    pub fn resolve_lfs_objects_dir(
+        project: &projects::Project,
+        config: &Config,
+    ) -> Result<Option<path::PathBuf>> {
+        let lfs_objects_dir = match &project.lfs_objects_dir {
+            Some(lfs_objects_dir) => Some(lfs_objects_dir.clone()),
+            None => config
+                .lfs_objects_dir()
+                .context("failed to read gitbutler.lfsObjectsDir, expected a directory")?
+                .map(|dir| dir.trim().to_string())
+                .filter(|dir| !dir.is_empty())
+                .map(path::PathBuf::from),
+        };
+        Ok(lfs_objects_dir.map(|dir| project.path.join(dir)))
+    }
+
+    // returns how long it is until the quiet hours are over, or None if it's not quiet now
+    pub fn quiet_remaining(&self, now: &time::SystemTime) -> Option<time::Duration> {
+        let local = chrono::DateTime::<chrono::Local>::from(*now);
+        quiet_hours::quiet_remaining(&self.quiet_hours, local.time())
+    }
+
+    pub fn computes_deltas(&self, path: &path::Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| {
+                self.delta_extensions
+                    .iter()
+                    .any(|delta_extension| delta_extension.eq_ignore_ascii_case(extension))
+            })
+    }
+}
+
+// returns true if the path is relative and doesn't leave the directory it's relative to
+pub fn is_inside_workdir(path: &path::Path) -> bool {
+    !path.as_os_str().is_empty()
+        && path.components().all(|component| {
+            matches!(
+                component,
+                path::Component::Normal(_) | path::Component::CurDir
+            )
+        })
+}
+
+fn normalize_extensions<I, S>(extensions: I) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    extensions
+        .into_iter()
+        .map(|extension| {
+            extension
+                .as_ref()
+                .trim()
+                .trim_start_matches('.')
+                .to_lowercase()
+        })
+        .filter(|extension| !extension.is_empty())
+        .collect()
+}
+
+// lfs threshold from the repository's git config alone, for repositories that are not projects
+// yet
+pub fn resolve_lfs_threshold(config: &Config) -> Result<u64> {
+    Ok(non_negative(
+        "gitbutler.lfsThreshold",
+        "a number of bytes",
+        config.lfs_threshold(),
+    )?
+    .unwrap_or(DEFAULT_LFS_THRESHOLD))
+}
+
+fn non_negative(
+    key: &str,
+    expected: &str,
+    value: Result<Option<i64>, git::Error>,
+) -> Result<Option<u64>> {
+    let Some(value) =
+        value.with_context(|| format!("failed to read {key}, expected {expected}"))?
+    else {
+        return Ok(None);
+    };
+    u64::try_from(value)
+        .map(Some)
+        .with_context(|| format!("invalid {key} {value}, expected {expected}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{Case, Suite};
+
+    use super::*;
+
+    #[test]
+    fn test_defaults() -> Result<()> {
+        let Case {
+            project_repository, ..
+        } = Suite::default().new_case();
+
+        assert_eq!(
+            project_repository.settings()?,
+            Settings {
+                idle_timeout: DEFAULT_IDLE_TIMEOUT,
+                lfs_threshold: DEFAULT_LFS_THRESHOLD,
+                respect_index_flags: false,
+                delta_extensions: normalize_extensions(DEFAULT_DELTA_EXTENSIONS),
+                force_capture_dirs: vec![],
+                lfs_objects_dir: None,
+                quiet_hours: vec![],
+                inline_paths: vec![],
+                max_lfs_store_bytes: None,
+                lfs_keep_sessions: DEFAULT_LFS_KEEP_SESSIONS,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_precedence() -> Result<()> {
+        let Case {
+            mut project_repository,
+            project,
+            ..
+        } = Suite::default().new_case();
+
+        let mut config = project_repository.git_repository.config()?;
+        config.set_str("gitbutler.idleTimeout", "60")?;
+        config.set_str("gitbutler.lfsThreshold", "1k")?;
+        config.set_bool("gitbutler.respectIndexFlags", true)?;
+        config.set_str("gitbutler.deltaExtensions", "rs, .TS")?;
+        config.set_str("gitbutler.forceCaptureDirs", ".vscode")?;
+        config.set_str("gitbutler.lfsObjectsDir", "../lfs")?;
+        config.set_multivar("gitbutler.quietHours", "^$", "22:00-07:00")?;
+        config.set_str("gitbutler.inlinePaths", "*.psd, design/**")?;
+        config.set_str("gitbutler.maxLfsStoreBytes", "1g")?;
+        config.set_str("gitbutler.lfsKeepSessions", "5")?;
+
+        assert_eq!(
+            project_repository.settings()?,
+            Settings {
+                idle_timeout: time::Duration::from_secs(60),
+                lfs_threshold: 1024,
+                respect_index_flags: true,
+                delta_extensions: vec!["rs".to_string(), "ts".to_string()],
+                force_capture_dirs: vec![path::PathBuf::from(".vscode")],
+                lfs_objects_dir: Some(project.path.join("../lfs")),
+                quiet_hours: vec!["22:00-07:00".parse()?],
+                inline_paths: vec!["*.psd".to_string(), "design/**".to_string()],
+                max_lfs_store_bytes: Some(1024 * 1024 * 1024),
+                lfs_keep_sessions: 5,
+            }
+        );
+
+        project_repository.set_project(&projects::Project {
+            idle_timeout_secs: Some(10),
+            respect_index_flags: Some(false),
+            delta_extensions: Some(vec!["md".to_string()]),
+            lfs_objects_dir: Some(path::PathBuf::from("/mnt/lfs")),
+            quiet_hours: Some(vec![]),
+            inline_paths: Some(vec!["*.sketch".to_string()]),
+            max_lfs_store_bytes: Some(1024),
+            ..project
+        });
+
+        assert_eq!(
+            project_repository.settings()?,
+            Settings {
+                idle_timeout: time::Duration::from_secs(10),
+                lfs_threshold: 1024,
+                respect_index_flags: false,
+                delta_extensions: vec!["md".to_string()],
+                force_capture_dirs: vec![path::PathBuf::from(".vscode")],
+                lfs_objects_dir: Some(path::PathBuf::from("/mnt/lfs")),
+                quiet_hours: vec![],
+                inline_paths: vec!["*.sketch".to_string()],
+                max_lfs_store_bytes: Some(1024),
+                lfs_keep_sessions: 5,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_computes_deltas() -> Result<()> {
+        let Case {
+            project_repository, ..
+        } = Suite::default().new_case();
+
+        let settings = project_repository.settings()?;
+        assert!(settings.computes_deltas(path::Path::new("src/main.rs")));
+        assert!(settings.computes_deltas(path::Path::new("README.MD")));
+        assert!(!settings.computes_deltas(path::Path::new("dist/app.map")));
+        assert!(!settings.computes_deltas(path::Path::new("Makefile")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_value() -> Result<()> {
+        let Case {
+            project_repository, ..
+        } = Suite::default().new_case();
+
+        let mut config = project_repository.git_repository.config()?;
+        config.set_str("gitbutler.idleTimeout", "soon")?;
+        let error = project_repository.settings().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "failed to read gitbutler.idleTimeout, expected a number of seconds"
+        );
+
+        config.set_str("gitbutler.idleTimeout", "-1")?;
+        let error = project_repository.settings().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid gitbutler.idleTimeout -1, expected a number of seconds"
+        );
+
+        config.set_str("gitbutler.idleTimeout", "60")?;
+        config.set_str("gitbutler.forceCaptureDirs", "../secrets")?;
+        let error = project_repository.settings().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid force capture directory ../secrets, expected a directory inside the project"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_inside_workdir() {
+        assert!(is_inside_workdir(path::Path::new(".vscode")));
+        assert!(is_inside_workdir(path::Path::new("./config/local")));
+        assert!(!is_inside_workdir(path::Path::new("")));
+        assert!(!is_inside_workdir(path::Path::new("../other")));
+        assert!(!is_inside_workdir(path::Path::new("config/../../other")));
+        assert!(!is_inside_workdir(path::Path::new("/etc")));
+    }
+}
@@ -5,7 +5,7 @@ use tracing::instrument;
 
 use crate::{
     error::{Code, Error},
-    projects,
+    projects, watcher,
 };
 
 use super::controller::{self, Controller};
@@ -25,6 +25,12 @@ impl From<controller::UpdateError> for Error {
                 code: Code::Projects,
                 message: format!("'{}' is not a file", path.display()),
             },
+            controller::UpdateError::Validation(
+                controller::UpdateValidationError::OutsideOfProject(path),
+            ) => Error::UserError {
+                code: Code::Projects,
+                message: format!("'{}' is outside of the project", path.display()),
+            },
             controller::UpdateError::NotFound => Error::UserError {
                 code: Code::Projects,
                 message: "Project not found".into(),
@@ -71,6 +77,19 @@ impl From<controller::AddError> for Error {
                 message: "Path not found".to_string(),
             },
             controller::AddError::User(error) => error.into(),
+            controller::AddError::Watch(watcher::WatchError::ReadOnlyRepository(path)) => {
+                Error::UserError {
+                    code: Code::Projects,
+                    message: format!(
+                        "Sessions can't be captured, {} is read-only",
+                        path.display()
+                    ),
+                }
+            }
+            controller::AddError::Watch(watcher::WatchError::Other(error)) => {
+                tracing::error!(?error, "failed to watch project");
+                Error::Unknown
+            }
             controller::AddError::Other(error) => {
                 tracing::error!(?error, "failed to add project");
                 Error::Unknown
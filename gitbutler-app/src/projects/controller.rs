@@ -92,8 +92,14 @@ impl Controller {
             .add(&project)
             .context("failed to add project to storage")?;
 
+        // a project that can't be watched isn't added, it would be listed without capturing anything
         if let Some(watchers) = &self.watchers {
-            watchers.watch(&project)?;
+            if let Err(error) = watchers.watch(&project) {
+                if let Err(error) = self.projects_storage.purge(&project.id) {
+                    tracing::error!(project_id = %project.id, ?error, "failed to remove project from storage");
+                }
+                return Err(error.into());
+            }
         }
 
         Ok(project)
@@ -120,6 +126,17 @@ impl Controller {
             }
         }
 
+        if let Some(dir) = project
+            .force_capture_dirs
+            .iter()
+            .flatten()
+            .find(|dir| !project_repository::is_inside_workdir(dir))
+        {
+            return Err(UpdateError::Validation(
+                UpdateValidationError::OutsideOfProject(dir.clone()),
+            ));
+        }
+
         let updated = self
             .projects_storage
             .update(project)
@@ -241,6 +258,8 @@ pub enum UpdateValidationError {
     KeyNotFound(path::PathBuf),
     #[error("{0} is not a file")]
     KeyNotFile(path::PathBuf),
+    #[error("{0} is outside of the project")]
+    OutsideOfProject(path::PathBuf),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -258,5 +277,7 @@ pub enum AddError {
     #[error(transparent)]
     OpenProjectRepository(#[from] project_repository::OpenError),
     #[error(transparent)]
+    Watch(#[from] watcher::WatchError),
+    #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
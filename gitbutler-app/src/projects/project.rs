@@ -78,6 +78,36 @@ pub struct Project {
     pub project_data_last_fetch: Option<FetchResult>,
     #[serde(default)]
     pub omit_certificate_check: Option<bool>,
+    /// overrides gitbutler.idleTimeout from the repository's git config
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+    /// overrides gitbutler.lfsThreshold from the repository's git config
+    #[serde(default)]
+    pub lfs_threshold: Option<u64>,
+    /// overrides gitbutler.respectIndexFlags from the repository's git config
+    #[serde(default)]
+    pub respect_index_flags: Option<bool>,
+    /// overrides gitbutler.deltaExtensions from the repository's git config
+    #[serde(default)]
+    pub delta_extensions: Option<Vec<String>>,
+    /// overrides gitbutler.forceCaptureDirs from the repository's git config
+    #[serde(default)]
+    pub force_capture_dirs: Option<Vec<path::PathBuf>>,
+    /// overrides gitbutler.lfsObjectsDir from the repository's git config
+    #[serde(default)]
+    pub lfs_objects_dir: Option<path::PathBuf>,
+    /// overrides gitbutler.quietHours from the repository's git config
+    #[serde(default)]
+    pub quiet_hours: Option<Vec<String>>,
+    /// overrides gitbutler.inlinePaths from the repository's git config
+    #[serde(default)]
+    pub inline_paths: Option<Vec<String>>,
+    /// overrides gitbutler.maxLfsStoreBytes from the repository's git config
+    #[serde(default)]
+    pub max_lfs_store_bytes: Option<u64>,
+    /// overrides gitbutler.lfsKeepSessions from the repository's git config
+    #[serde(default)]
+    pub lfs_keep_sessions: Option<u64>,
 }
 
 impl AsRef<Project> for Project {
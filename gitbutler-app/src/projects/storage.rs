@@ -1,3 +1,5 @@
+use std::path;
+
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Manager};
 
@@ -47,6 +49,16 @@ pub struct UpdateRequest {
     pub gitbutler_code_push_state: Option<project::CodePushState>,
     pub project_data_last_fetched: Option<project::FetchResult>,
     pub omit_certificate_check: Option<bool>,
+    pub idle_timeout_secs: Option<u64>,
+    pub lfs_threshold: Option<u64>,
+    pub respect_index_flags: Option<bool>,
+    pub delta_extensions: Option<Vec<String>>,
+    pub force_capture_dirs: Option<Vec<path::PathBuf>>,
+    pub lfs_objects_dir: Option<path::PathBuf>,
+    pub quiet_hours: Option<Vec<String>>,
+    pub inline_paths: Option<Vec<String>>,
+    pub max_lfs_store_bytes: Option<u64>,
+    pub lfs_keep_sessions: Option<u64>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -139,6 +151,46 @@ impl Storage {
             project.omit_certificate_check = Some(omit_certificate_check);
         }
 
+        if let Some(idle_timeout_secs) = update_request.idle_timeout_secs {
+            project.idle_timeout_secs = Some(idle_timeout_secs);
+        }
+
+        if let Some(lfs_threshold) = update_request.lfs_threshold {
+            project.lfs_threshold = Some(lfs_threshold);
+        }
+
+        if let Some(respect_index_flags) = update_request.respect_index_flags {
+            project.respect_index_flags = Some(respect_index_flags);
+        }
+
+        if let Some(delta_extensions) = &update_request.delta_extensions {
+            project.delta_extensions = Some(delta_extensions.clone());
+        }
+
+        if let Some(force_capture_dirs) = &update_request.force_capture_dirs {
+            project.force_capture_dirs = Some(force_capture_dirs.clone());
+        }
+
+        if let Some(lfs_objects_dir) = &update_request.lfs_objects_dir {
+            project.lfs_objects_dir = Some(lfs_objects_dir.clone());
+        }
+
+        if let Some(quiet_hours) = &update_request.quiet_hours {
+            project.quiet_hours = Some(quiet_hours.clone());
+        }
+
+        if let Some(inline_paths) = &update_request.inline_paths {
+            project.inline_paths = Some(inline_paths.clone());
+        }
+
+        if let Some(max_lfs_store_bytes) = update_request.max_lfs_store_bytes {
+            project.max_lfs_store_bytes = Some(max_lfs_store_bytes);
+        }
+
+        if let Some(lfs_keep_sessions) = update_request.lfs_keep_sessions {
+            project.lfs_keep_sessions = Some(lfs_keep_sessions);
+        }
+
         self.storage
             .write(PROJECTS_FILE, &serde_json::to_string_pretty(&projects)?)?;
 
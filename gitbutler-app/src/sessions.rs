@@ -1,18 +1,73 @@
+mod audit;
+mod changes;
+mod conflicted_files;
 mod controller;
 mod database;
+mod diff;
+mod export;
+mod files;
+mod history;
+mod ignored_dirs;
 mod iterator;
+mod latest;
+mod lifecycle;
+mod live;
+mod materialize;
+mod preview;
+mod progress;
+mod prune;
 mod reader;
+mod recover;
+mod refs;
+mod restore;
+mod reuse;
 mod session;
+mod squash;
+mod stats;
+mod status_output;
+mod thumbnail;
+mod worktree;
 mod writer;
+mod xattrs;
 
 pub mod commands;
 
 #[cfg(test)]
 mod tests;
 
+pub(crate) use audit::append as append_audit_log;
+pub use audit::{verify_audit_log, AuditEntry, AuditLogError};
+pub use changes::change_counts;
+pub use conflicted_files::conflicted_files;
+pub(crate) use conflicted_files::from_commit as conflicted_files_from_commit;
 pub use controller::Controller;
 pub use database::Database;
+pub use diff::{diff_sessions, DiffScope, FileDiff};
+pub use export::export_bundle;
+pub use files::{list_files, FileEntry};
+pub use ignored_dirs::{ignored_dirs, IgnoredDir};
 pub use iterator::SessionsIterator;
+pub use latest::latest;
+pub use lifecycle::{start, touch, StartError};
+pub use live::diff_live;
+pub use materialize::{materialize, MaterializeError, MaterializeOptions};
+pub use preview::{checkout_preview, end_preview, PreviewError, PreviewToken};
+pub(crate) use progress::Reporter;
+pub use progress::{Progress, ProgressCallback};
+pub use prune::{prune_sessions_by_count, prune_sessions_by_count_dry_run, PruneReport};
 pub use reader::SessionReader as Reader;
-pub use session::{Meta, Session, SessionError, SessionId};
+pub use recover::recover;
+pub use refs::{list_refs, GbRef, GbRefKind};
+pub use restore::{restore_empty_dirs, restore_file, restore_xattrs, RestoreFileError};
+pub use reuse::{blob_reuse, BlobReuse};
+pub use session::{
+    Meta, Session, SessionError, SessionId, SparseCheckout, StashRef, MAX_SESSION_AGE, META_VERSION,
+};
+pub use squash::{squash, SquashError};
+pub use stats::{storage_stats, StorageStats};
+pub use status_output::{status_output, StatusOutput};
+pub use thumbnail::thumbnail;
+pub use worktree::{restore_worktree, RestoreWorktreeError};
 pub use writer::SessionWriter as Writer;
+pub(crate) use xattrs::from_commit as xattrs_from_commit;
+pub use xattrs::{xattrs, FileXattrs, Xattrs};
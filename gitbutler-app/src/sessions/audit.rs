@@ -0,0 +1,123 @@
+// with gitbutler.auditLog set, every committed session is also appended to an append-only log in
+// the gitbutler repository, one json line per session. every line has the hash of the line before
+// it, so that lines that were changed or removed can be told apart from the history being
+// squashed or pruned, which rewrites commits without touching the log.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{gb_repository, git};
+
+use super::SessionId;
+
+const AUDIT_LOG_PATH: &str = "audit.log";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub session_id: SessionId,
+    // commit time of the session
+    pub timestamp_ms: u128,
+    pub commit: git::Oid,
+    pub tree: git::Oid,
+    // previous session in the history at the time, none for the first one
+    pub parent: Option<git::Oid>,
+    // sha256 of the line before this one, none for the first line
+    pub previous: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditLogError {
+    // lines are numbered from 1
+    #[error("line {0} of the audit log is not an entry")]
+    Malformed(usize),
+    #[error("line {0} of the audit log doesn't follow the line before it")]
+    BrokenChain(usize),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// appends the committed session to the audit log. the repository lock must be held, so that
+// nothing is appended in between reading the last line and writing the new one.
+pub(crate) fn append(
+    git_repository: &git::Repository,
+    session_id: &SessionId,
+    commit_oid: git::Oid,
+) -> Result<()> {
+    let commit = git_repository
+        .find_commit(commit_oid)
+        .context("failed to find session commit")?;
+    let parent = if commit.parent_count() > 0 {
+        Some(commit.parent(0)?.id())
+    } else {
+        None
+    };
+    let path = audit_log_path(git_repository);
+    let previous = read_lines(&path)?.last().map(String::as_str).map(hash);
+    let entry = AuditEntry {
+        session_id: *session_id,
+        timestamp_ms: u128::try_from(commit.time().seconds())
+            .context("commit time is out of range")?
+            * 1000,
+        commit: commit_oid,
+        tree: commit.tree_id(),
+        parent,
+        previous,
+    };
+    let mut line = serde_json::to_string(&entry).context("failed to serialize audit entry")?;
+    line.push('\n');
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    file.write_all(line.as_bytes())
+        .and_then(|()| file.sync_data())
+        .with_context(|| format!("failed to append to {}", path.display()))
+}
+
+// checks that every line of the audit log follows the one before it, returns the entries oldest
+// first. a missing log is empty.
+pub fn verify_audit_log(
+    repository: &gb_repository::Repository,
+) -> Result<Vec<AuditEntry>, AuditLogError> {
+    let _lock = repository.lock();
+
+    let lines = read_lines(&audit_log_path(repository.git_repository()))?;
+    let mut entries = vec![];
+    let mut previous: Option<String> = None;
+    for (index, line) in lines.iter().enumerate() {
+        let entry: AuditEntry =
+            serde_json::from_str(line).map_err(|_| AuditLogError::Malformed(index + 1))?;
+        if entry.previous != previous {
+            return Err(AuditLogError::BrokenChain(index + 1));
+        }
+        previous = Some(hash(line));
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn audit_log_path(git_repository: &git::Repository) -> path::PathBuf {
+    git_repository.path().join(AUDIT_LOG_PATH)
+}
+
+fn read_lines(path: &path::Path) -> Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().map(ToString::to_string).collect()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(error).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+fn hash(line: &str) -> String {
+    format!("{:x}", Sha256::digest(line.as_bytes()))
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, path};
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git};
+
+use super::{history, SessionId};
+
+// counts files that changed in every flushed session compared to the session before it, newest
+// first.
+//
+// commits never change, so the counts are cached by commit id next to the history.
+pub fn change_counts(repository: &gb_repository::Repository) -> Result<Vec<(SessionId, usize)>> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let cache_path = git_repository.path().join("change-counts");
+    let mut cache = load_cache(&cache_path);
+    let chain = history::chain(git_repository).context("failed to read session history")?;
+
+    let mut counts = vec![];
+    let mut chain_cache = HashMap::new();
+    for (position, (commit, session)) in chain.iter().enumerate() {
+        let Some(session) = session else {
+            continue;
+        };
+        let count = match cache.get(&commit.id()) {
+            Some(count) => *count,
+            None => {
+                let parent = chain.get(position + 1).map(|(parent, _)| parent);
+                count_changes(git_repository, parent, commit)
+                    .with_context(|| format!("failed to count changes of session {}", session.id))?
+            }
+        };
+        chain_cache.insert(commit.id(), count);
+        counts.push((session.id, count));
+    }
+
+    // entries of commits that were squashed or pruned away are dropped
+    if chain_cache != cache && !repository.is_read_only() {
+        cache = chain_cache;
+        if let Err(error) = save_cache(&cache_path, &cache) {
+            tracing::warn!(?error, "failed to save change counts");
+        }
+    }
+
+    Ok(counts)
+}
+
+fn count_changes(
+    git_repository: &git::Repository,
+    parent: Option<&git::Commit>,
+    commit: &git::Commit,
+) -> Result<usize> {
+    let repository = <&git2::Repository>::from(git_repository);
+    let wd_tree = |commit: &git::Commit| -> Result<Option<git2::Tree>> {
+        match commit.tree()?.get_path(path::Path::new("wd")) {
+            Ok(entry) => Ok(Some(repository.find_tree(entry.id().into())?)),
+            Err(git::Error::NotFound(_)) => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    };
+
+    let old_tree = match parent {
+        Some(parent) => wd_tree(parent)?,
+        None => None,
+    };
+    let new_tree = wd_tree(commit)?;
+    let diff = repository
+        .diff_tree_to_tree(old_tree.as_ref(), new_tree.as_ref(), None)
+        .context("failed to diff wd trees")?;
+    // counting deltas doesn't compute line stats or generate patches
+    Ok(diff.deltas().len())
+}
+
+// a missing or unreadable cache is empty, counts are computed again
+fn load_cache(path: &path::Path) -> HashMap<git::Oid, usize> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+        Err(error) => {
+            tracing::warn!(path = %path.display(), ?error, "failed to read change counts");
+            return HashMap::new();
+        }
+    };
+    serde_json::from_slice(&content).unwrap_or_else(|error| {
+        tracing::warn!(path = %path.display(), ?error, "failed to parse change counts");
+        HashMap::new()
+    })
+}
+
+fn save_cache(path: &path::Path, cache: &HashMap<git::Oid, usize>) -> Result<()> {
+    let content = serde_json::to_vec(cache).context("failed to serialize change counts")?;
+    // write to a temporary file first, so that a crash doesn't leave a truncated cache behind
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, content)
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to rename {}", tmp_path.display()))
+}
@@ -0,0 +1,41 @@
+use std::path;
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git};
+
+use super::{history, writer::CONFLICTED_FILES_PATH, SessionError, SessionId};
+
+// returns the files of the project that had merge conflict markers in them when the session was
+// captured, relative to the project root
+pub fn conflicted_files(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Vec<path::PathBuf>, SessionError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+    Ok(from_commit(repository.git_repository(), &commit)?)
+}
+
+pub(crate) fn from_commit(
+    git_repository: &git::Repository,
+    commit: &git::Commit,
+) -> Result<Vec<path::PathBuf>> {
+    match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(path::Path::new(CONFLICTED_FILES_PATH))
+    {
+        Ok(entry) => {
+            let blob = git_repository
+                .find_blob(entry.id())
+                .context("failed to find blob")?;
+            let conflicted_files = serde_json::from_slice(blob.content())
+                .context("failed to parse conflicted files")?;
+            Ok(conflicted_files)
+        }
+        Err(git::Error::NotFound(_)) => Ok(vec![]),
+        Err(error) => Err(error.into()),
+    }
+}
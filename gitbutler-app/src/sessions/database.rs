@@ -41,6 +41,19 @@ impl Database {
                     ":commit": session.meta.commit,
                     ":start_timestamp_ms": session.meta.start_timestamp_ms.to_string(),
                     ":last_timestamp_ms": session.meta.last_timestamp_ms.to_string(),
+                    ":metadata": serde_json::to_string(&session.meta.metadata)
+                        .context("Failed to serialize metadata")?,
+                    ":detached": session.meta.detached,
+                    ":stashes": serde_json::to_string(&session.meta.stashes)
+                        .context("Failed to serialize stashes")?,
+                    ":version": session.meta.version,
+                    ":sparse_checkout": session
+                        .meta
+                        .sparse_checkout
+                        .as_ref()
+                        .map(serde_json::to_string)
+                        .transpose()
+                        .context("Failed to serialize sparse checkout")?,
                 })
                 .context("Failed to execute insert statement")?;
             }
@@ -137,8 +150,31 @@ fn parse_row(row: &rusqlite::Row) -> Result<session::Session> {
             .map(|hash| hash.parse().context("Failed to parse hash"))
             .transpose()?,
         meta: session::Meta {
+            // rows cached before the version was stored are version 1, like their sessions
+            version: row
+                .get::<usize, Option<u32>>(10)
+                .context("Failed to get version")?
+                .unwrap_or(1),
             branch: row.get(3).context("Failed to get branch")?,
             commit: row.get(4).context("Failed to get commit")?,
+            detached: row
+                .get::<usize, Option<bool>>(8)
+                .context("Failed to get detached")?
+                .unwrap_or_default(),
+            stashes: row
+                .get::<usize, Option<String>>(9)
+                .context("Failed to get stashes")?
+                .map(|stashes| serde_json::from_str(&stashes).context("Failed to parse stashes"))
+                .transpose()?
+                .unwrap_or_default(),
+            sparse_checkout: row
+                .get::<usize, Option<String>>(11)
+                .context("Failed to get sparse_checkout")?
+                .map(|sparse_checkout| {
+                    serde_json::from_str(&sparse_checkout)
+                        .context("Failed to parse sparse_checkout")
+                })
+                .transpose()?,
             start_timestamp_ms: row
                 .get::<usize, String>(5)
                 .context("Failed to get start_timestamp_ms")?
@@ -149,6 +185,12 @@ fn parse_row(row: &rusqlite::Row) -> Result<session::Session> {
                 .context("Failed to get last_timestamp_ms")?
                 .parse()
                 .context("Failed to parse last_timestamp_ms")?,
+            metadata: row
+                .get::<usize, Option<String>>(7)
+                .context("Failed to get metadata")?
+                .map(|metadata| serde_json::from_str(&metadata).context("Failed to parse metadata"))
+                .transpose()?
+                .unwrap_or_default(),
         },
     })
 }
@@ -157,7 +199,7 @@ fn list_by_project_id_stmt<'conn>(
     tx: &'conn rusqlite::Transaction,
 ) -> Result<rusqlite::CachedStatement<'conn>> {
     Ok(tx.prepare_cached(
-        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms` FROM `sessions` WHERE `project_id` = :project_id ORDER BY `start_timestamp_ms` DESC",
+        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms`, `metadata`, `detached`, `stashes`, `version`, `sparse_checkout` FROM `sessions` WHERE `project_id` = :project_id ORDER BY `start_timestamp_ms` DESC",
     )?)
 }
 
@@ -165,7 +207,7 @@ fn get_by_project_id_id_stmt<'conn>(
     tx: &'conn rusqlite::Transaction,
 ) -> Result<rusqlite::CachedStatement<'conn>> {
     Ok(tx.prepare_cached(
-        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms` FROM `sessions` WHERE `project_id` = :project_id AND `id` = :id",
+        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms`, `metadata`, `detached`, `stashes`, `version`, `sparse_checkout` FROM `sessions` WHERE `project_id` = :project_id AND `id` = :id",
     )?)
 }
 
@@ -173,7 +215,7 @@ fn get_by_id_stmt<'conn>(
     tx: &'conn rusqlite::Transaction,
 ) -> Result<rusqlite::CachedStatement<'conn>> {
     Ok(tx.prepare_cached(
-        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms` FROM `sessions` WHERE `id` = :id",
+        "SELECT `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms`, `metadata`, `detached`, `stashes`, `version`, `sparse_checkout` FROM `sessions` WHERE `id` = :id",
     )?)
 }
 
@@ -182,22 +224,29 @@ fn insert_stmt<'conn>(
 ) -> Result<rusqlite::CachedStatement<'conn>> {
     Ok(tx.prepare_cached(
         "INSERT INTO 'sessions' (
-            `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms`
+            `id`, `project_id`, `hash`, `branch`, `commit`, `start_timestamp_ms`, `last_timestamp_ms`, `metadata`, `detached`, `stashes`, `version`, `sparse_checkout`
         ) VALUES (
-            :id, :project_id, :hash, :branch, :commit, :start_timestamp_ms, :last_timestamp_ms
+            :id, :project_id, :hash, :branch, :commit, :start_timestamp_ms, :last_timestamp_ms, :metadata, :detached, :stashes, :version, :sparse_checkout
         ) ON CONFLICT(`id`) DO UPDATE SET
             `project_id` = :project_id,
             `hash` = :hash,
             `branch` = :branch,
             `commit` = :commit,
             `start_timestamp_ms` = :start_timestamp_ms,
-            `last_timestamp_ms` = :last_timestamp_ms
+            `last_timestamp_ms` = :last_timestamp_ms,
+            `metadata` = :metadata,
+            `detached` = :detached,
+            `stashes` = :stashes,
+            `version` = :version,
+            `sparse_checkout` = :sparse_checkout
         ",
     )?)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use crate::test_utils;
 
     use super::*;
@@ -214,20 +263,36 @@ mod tests {
             id: SessionId::generate(),
             hash: None,
             meta: session::Meta {
+                version: session::META_VERSION,
                 branch: None,
-                commit: None,
+                commit: Some("commit1".to_string()),
+                detached: true,
+                stashes: vec![],
+                sparse_checkout: None,
                 start_timestamp_ms: 1,
                 last_timestamp_ms: 2,
+                metadata: BTreeMap::new(),
             },
         };
         let session2 = session::Session {
             id: SessionId::generate(),
             hash: Some("08f23df1b9c2dec3d0c826a3ae745f9b821a1a26".parse().unwrap()),
             meta: session::Meta {
+                version: session::META_VERSION,
                 branch: Some("branch2".to_string()),
                 commit: Some("commit2".to_string()),
+                detached: false,
+                stashes: vec![session::StashRef {
+                    oid: "4d7a214614ab2935c943f9e0ff69d22eadbb8f32".parse().unwrap(),
+                    message: "WIP on branch2: commit2".to_string(),
+                }],
+                sparse_checkout: Some(session::SparseCheckout {
+                    cone: true,
+                    patterns: vec!["/*".to_string(), "!/*/".to_string(), "/src/".to_string()],
+                }),
                 start_timestamp_ms: 3,
                 last_timestamp_ms: 4,
+                metadata: BTreeMap::from([("task".to_string(), serde_json::json!(42))]),
             },
         };
         let sessions = vec![&session1, &session2];
@@ -255,20 +320,30 @@ mod tests {
             id: SessionId::generate(),
             hash: None,
             meta: session::Meta {
+                version: session::META_VERSION,
                 branch: None,
                 commit: None,
+                detached: false,
+                stashes: vec![],
+                sparse_checkout: None,
                 start_timestamp_ms: 1,
                 last_timestamp_ms: 2,
+                metadata: BTreeMap::new(),
             },
         };
         let session_updated = session::Session {
             id: session.id,
             hash: Some("08f23df1b9c2dec3d0c826a3ae745f9b821a1a26".parse().unwrap()),
             meta: session::Meta {
+                version: session::META_VERSION,
                 branch: Some("branch2".to_string()),
                 commit: Some("commit2".to_string()),
+                detached: false,
+                stashes: vec![],
+                sparse_checkout: None,
                 start_timestamp_ms: 3,
                 last_timestamp_ms: 4,
+                metadata: BTreeMap::from([("task".to_string(), serde_json::json!(42))]),
             },
         };
         database.insert(&project_id, &[&session])?;
@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, fmt, path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{gb_repository, git, lfs};
+
+use super::{history, SessionError, SessionId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum FileDiff {
+    // a unified diff of the file content
+    #[serde(rename_all = "camelCase")]
+    Text {
+        change_type: git::diff::ChangeType,
+        patch: String,
+    },
+    // content that can't be diffed line by line, like binary files and large files that are
+    // stored as lfs objects. only sizes are compared, none is a file that doesn't exist.
+    #[serde(rename_all = "camelCase")]
+    Binary {
+        change_type: git::diff::ChangeType,
+        old_size: Option<u64>,
+        new_size: Option<u64>,
+    },
+}
+
+// binary diffs are summarized like "binary changed, 120.0 MB -> 135.0 MB"
+impl fmt::Display for FileDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileDiff::Text { patch, .. } => write!(f, "{patch}"),
+            FileDiff::Binary {
+                old_size: None,
+                new_size: Some(new_size),
+                ..
+            } => write!(f, "binary added, {}", human_size(*new_size)),
+            FileDiff::Binary {
+                old_size: Some(old_size),
+                new_size: None,
+                ..
+            } => write!(f, "binary deleted, {}", human_size(*old_size)),
+            FileDiff::Binary {
+                old_size, new_size, ..
+            } => write!(
+                f,
+                "binary changed, {} -> {}",
+                human_size(old_size.unwrap_or_default()),
+                human_size(new_size.unwrap_or_default())
+            ),
+        }
+    }
+}
+
+// which part of the session trees is diffed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiffScope {
+    // the working directory, paths are relative to the project root
+    #[default]
+    Wd,
+    // the session meta and deltas, paths are relative to the session tree, like session/meta/id
+    Session,
+    // the whole session tree, including logs and branches, with paths relative to it too
+    All,
+}
+
+// returns the files that changed between two flushed sessions, in the given scope.
+//
+// large files are stored as lfs pointers, diffing the pointer text would not say anything
+// useful. they are compared by the oid and size of their content instead, and a pointer that was
+// only rewritten, for example when encryption was turned on, is not a change.
+pub fn diff_sessions(
+    repository: &gb_repository::Repository,
+    old_session_id: &SessionId,
+    new_session_id: &SessionId,
+    scope: DiffScope,
+) -> Result<BTreeMap<path::PathBuf, FileDiff>, SessionError> {
+    let git_repository = repository.git_repository();
+    let old_tree = scope_tree(git_repository, old_session_id, scope)?;
+    let new_tree = scope_tree(git_repository, new_session_id, scope)?;
+
+    let repository = <&git2::Repository>::from(git_repository);
+    let mut diff_opts = git2::DiffOptions::new();
+    diff_opts.ignore_submodules(true);
+    if scope == DiffScope::Session {
+        diff_opts.pathspec("session");
+    }
+    let diff = repository
+        .diff_tree_to_tree(old_tree.as_ref(), new_tree.as_ref(), Some(&mut diff_opts))
+        .context("failed to diff session trees")?;
+
+    let mut diffs = BTreeMap::new();
+    for delta in diff.deltas() {
+        let Some(file_path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+            continue;
+        };
+        let old_blob = find_blob(repository, &delta.old_file())?;
+        let new_blob = find_blob(repository, &delta.new_file())?;
+        if let Some(file_diff) = diff_blobs(
+            delta.status().into(),
+            file_path,
+            old_blob.as_ref(),
+            new_blob.as_ref(),
+        )
+        .with_context(|| format!("failed to diff {}", file_path.display()))?
+        {
+            diffs.insert(file_path.to_path_buf(), file_diff);
+        }
+    }
+
+    Ok(diffs)
+}
+
+// the session scope is diffed on the whole tree, limited to the session subtree with a pathspec,
+// so that its paths keep their prefix
+fn scope_tree<'repo>(
+    git_repository: &'repo git::Repository,
+    session_id: &SessionId,
+    scope: DiffScope,
+) -> Result<Option<git2::Tree<'repo>>, SessionError> {
+    let commit = history::find_session_commit(git_repository, session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+    let tree = commit.tree().context("failed to get session tree")?;
+    let tree_id = match scope {
+        DiffScope::Session | DiffScope::All => tree.id(),
+        DiffScope::Wd => match tree.get_path(path::Path::new("wd")) {
+            Ok(entry) => entry.id(),
+            Err(git::Error::NotFound(_)) => return Ok(None),
+            Err(error) => return Err(anyhow::Error::from(error).into()),
+        },
+    };
+    let tree = <&git2::Repository>::from(git_repository)
+        .find_tree(tree_id.into())
+        .context("failed to find tree")?;
+    Ok(Some(tree))
+}
+
+fn find_blob<'repo>(
+    repository: &'repo git2::Repository,
+    file: &git2::DiffFile,
+) -> Result<Option<git2::Blob<'repo>>> {
+    if file.id().is_zero() {
+        return Ok(None);
+    }
+    repository
+        .find_blob(file.id())
+        .map(Some)
+        .context("failed to find blob")
+}
+
+// returns None if the content of the file didn't change
+fn diff_blobs(
+    change_type: git::diff::ChangeType,
+    file_path: &path::Path,
+    old_blob: Option<&git2::Blob>,
+    new_blob: Option<&git2::Blob>,
+) -> Result<Option<FileDiff>> {
+    let old_pointer = old_blob.and_then(|blob| lfs::parse_pointer(blob.content()));
+    let new_pointer = new_blob.and_then(|blob| lfs::parse_pointer(blob.content()));
+    if old_pointer.is_some() || new_pointer.is_some() {
+        let size = |blob: Option<&git2::Blob>, pointer: Option<&lfs::LfsPointer>| {
+            blob.map(|blob| pointer.map_or(blob.size() as u64, |pointer| pointer.size))
+        };
+        let unchanged = matches!(
+            (&old_pointer, &new_pointer),
+            (Some(old_pointer), Some(new_pointer)) if old_pointer.oid == new_pointer.oid
+        );
+        return Ok((!unchanged).then(|| FileDiff::Binary {
+            change_type,
+            old_size: size(old_blob, old_pointer.as_ref()),
+            new_size: size(new_blob, new_pointer.as_ref()),
+        }));
+    }
+
+    let mut patch =
+        git2::Patch::from_blobs(old_blob, Some(file_path), new_blob, Some(file_path), None)
+            .context("failed to create patch")?;
+    if patch.delta().flags().is_binary() {
+        return Ok(Some(FileDiff::Binary {
+            change_type,
+            old_size: old_blob.map(|blob| blob.size() as u64),
+            new_size: new_blob.map(|blob| blob.size() as u64),
+        }));
+    }
+    let patch = patch.to_buf().context("failed to print patch")?;
+    Ok(Some(FileDiff::Text {
+        change_type,
+        patch: String::from_utf8_lossy(&patch).into_owned(),
+    }))
+}
+
+// sizes in decimal units, like file managers show them
+fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["kB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(999), "999 B");
+        assert_eq!(human_size(1_500), "1.5 kB");
+        assert_eq!(human_size(120_000_000), "120.0 MB");
+        assert_eq!(human_size(4_200_000_000_000_000), "4200.0 TB");
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{gb_repository, git, lfs, project_repository};
+
+use super::{
+    history,
+    progress::{ProgressCallback, Reporter},
+};
+
+const BUNDLE_SIGNATURE: &str = "# v2 git bundle\n";
+// large files referenced from sessions are exported under this ref, as a tree of blobs named by
+// their lfs oid
+const LFS_REFNAME: &str = "refs/gitbutler/lfs";
+// objects created for the export are kept in memory, so they never end up in the repository.
+// the priority makes them preferred over the loose backend when writing.
+const MEMPACK_PRIORITY: i32 = 1000;
+
+// writes the whole session history as a git bundle, so that it can be stored anywhere as a single
+// file and restored with `git clone` or `git fetch` from it.
+//
+// large files live outside of the object database, so they are added to the bundle under
+// refs/gitbutler/lfs. encrypted ones are decrypted with the project's key.
+//
+// progress reports the large files that are added and the objects that are packed, with the
+// bytes of the bundle that are written.
+pub fn export_bundle<W: io::Write>(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    writer: &mut W,
+    progress: Option<ProgressCallback>,
+) -> Result<()> {
+    let mut reporter = Reporter::new(progress);
+    let encryption_key = lfs::encryption_key(&project_repository.config())?;
+
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let current = match git_repository.find_reference(&history::current_refname()) {
+        Ok(reference) => reference.peel_to_commit()?.id(),
+        Err(git::Error::NotFound(_)) => return Err(anyhow!("there are no sessions to export")),
+        Err(error) => return Err(error.into()),
+    };
+
+    // a separate handle, so that in memory objects are not visible to anyone else
+    let export_handle = git::Repository::from(
+        git2::Repository::open(git_repository.path())
+            .context("failed to open repository for export")?,
+    );
+    let export_repository = <&git2::Repository>::from(&export_handle);
+    let export_odb = export_repository.odb()?;
+    let _mempack = export_odb
+        .add_new_mempack_backend(MEMPACK_PRIORITY)
+        .context("failed to add mempack backend")?;
+
+    // anchored sessions carry the project head as an extra parent, the export is rewritten to
+    // the first parent chain so that the project history is not packed with them
+    let current = gb_repository::first_parent_history(&export_handle, current)
+        .context("failed to rewrite sessions for export")?;
+
+    let mut refs = vec![(
+        git2::Oid::from(current),
+        history::current_refname().to_string(),
+    )];
+    if let Some(lfs_commit) = write_lfs_commit(
+        repository,
+        export_repository,
+        encryption_key.as_ref(),
+        &mut reporter,
+    )? {
+        refs.push((lfs_commit, LFS_REFNAME.to_string()));
+    }
+
+    let mut header = BUNDLE_SIGNATURE.to_string();
+    for (oid, refname) in &refs {
+        header.push_str(&format!("{} {}\n", oid, refname));
+    }
+    header.push('\n');
+    writer
+        .write_all(header.as_bytes())
+        .context("failed to write bundle header")?;
+    reporter.advance(0, header.len() as u64);
+
+    let mut pack_builder = export_repository.packbuilder()?;
+    let mut revwalk = export_repository.revwalk()?;
+    for (oid, _) in &refs {
+        revwalk.push(*oid)?;
+    }
+    pack_builder
+        .insert_walk(&mut revwalk)
+        .context("failed to collect objects")?;
+    reporter.add_total(pack_builder.object_count());
+
+    let mut write_error = None;
+    pack_builder.foreach(|chunk| match writer.write_all(chunk) {
+        Ok(()) => {
+            reporter.advance(0, chunk.len() as u64);
+            true
+        }
+        Err(error) => {
+            write_error = Some(error);
+            false
+        }
+    })?;
+    if let Some(error) = write_error {
+        return Err(error).context("failed to write bundle pack");
+    }
+    // objects are not counted while the pack is written, it's all or nothing
+    reporter.advance(pack_builder.object_count(), 0);
+    reporter.finish();
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        objects = pack_builder.object_count(),
+        "exported sessions bundle"
+    );
+
+    Ok(())
+}
+
+// writes a commit with all referenced large files into the export repository. returns None if
+// there are none.
+fn write_lfs_commit(
+    repository: &gb_repository::Repository,
+    export_repository: &git2::Repository,
+    encryption_key: Option<&lfs::EncryptionKey>,
+    reporter: &mut Reporter,
+) -> Result<Option<git2::Oid>> {
+    let mut pointers = lfs::referenced_pointers(repository.git_repository())
+        .context("failed to collect referenced lfs objects")?
+        .into_iter()
+        .collect::<Vec<_>>();
+    pointers.sort_by(|a, b| a.oid.cmp(&b.oid));
+    reporter.add_total(pointers.len());
+
+    let objects_dir = repository.lfs_objects_dir();
+    let mut tree_builder = export_repository.treebuilder(None)?;
+    for pointer in pointers {
+        if !objects_dir.join(&pointer.oid).exists() {
+            tracing::warn!(oid = %pointer.oid, "lfs object is missing, skipping");
+            reporter.advance(1, 0);
+            continue;
+        }
+        // the same object might be referenced both as encrypted and as plain
+        if tree_builder.get(&pointer.oid)?.is_some() {
+            reporter.advance(1, 0);
+            continue;
+        }
+        let content = lfs::read_object(objects_dir, &pointer, encryption_key)?;
+        let blob = export_repository.blob(&content)?;
+        tree_builder.insert(&pointer.oid, blob, git2::FileMode::Blob.into())?;
+        // the bytes are the ones of the bundle, large files are counted when they are packed
+        reporter.advance(1, 0);
+    }
+    if tree_builder.is_empty() {
+        return Ok(None);
+    }
+
+    let tree = export_repository.find_tree(tree_builder.write()?)?;
+    let signature = git2::Signature::now("gitbutler", "gitbutler@localhost")?;
+    let commit =
+        export_repository.commit(None, &signature, &signature, "lfs objects", &tree, &[])?;
+    Ok(Some(commit))
+}
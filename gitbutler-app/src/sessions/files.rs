@@ -0,0 +1,109 @@
+use std::path;
+
+use anyhow::Context;
+
+use crate::{gb_repository, git, lfs};
+
+use super::{history, SessionError, SessionId};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileEntry {
+    pub mode: git::FileMode,
+    // size of the file content in bytes. for large files it's the size of the lfs object, not
+    // of the pointer
+    pub size: u64,
+    // the file is stored as an lfs pointer
+    pub lfs: bool,
+}
+
+// lists files of a flushed session without reading their content. only blobs that are small
+// enough to be lfs pointers are read.
+pub fn list_files(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Vec<(path::PathBuf, FileEntry)>, SessionError> {
+    let git_repository = repository.git_repository();
+    let commit = history::find_session_commit(git_repository, session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+
+    let tree = commit.tree().context("failed to get session tree")?;
+    let wd_tree = match tree.get_path(path::Path::new("wd")) {
+        Ok(entry) => git_repository
+            .find_tree(entry.id())
+            .context("failed to find wd tree")?,
+        Err(git::Error::NotFound(_)) => return Ok(vec![]),
+        Err(error) => return Err(anyhow::Error::from(error).into()),
+    };
+
+    let odb = <&git2::Repository>::from(git_repository)
+        .odb()
+        .context("failed to open object database")?;
+
+    let mut files = vec![];
+    let mut walk_error = None;
+    let walk_result = wd_tree.walk(|root, entry| {
+        if entry.kind() != Some(git2::ObjectType::Blob) {
+            return git::TreeWalkResult::Continue;
+        }
+        match file_entry(git_repository, &odb, entry) {
+            Ok(file_entry) => {
+                let path = path::Path::new(root).join(entry.name().unwrap_or_default());
+                files.push((path, file_entry));
+                git::TreeWalkResult::Continue
+            }
+            Err(error) => {
+                walk_error = Some(error);
+                git::TreeWalkResult::Stop
+            }
+        }
+    });
+    // stopping the walk makes it fail, so the error of the callback is checked first
+    if let Some(error) = walk_error {
+        return Err(error.into());
+    }
+    walk_result.context("failed to walk wd tree")?;
+
+    Ok(files)
+}
+
+fn file_entry(
+    git_repository: &git::Repository,
+    odb: &git2::Odb,
+    entry: &git::TreeEntry,
+) -> anyhow::Result<FileEntry> {
+    let mode = if entry.filemode() == i32::from(git2::FileMode::BlobExecutable) {
+        git::FileMode::BlobExecutable
+    } else if entry.filemode() == i32::from(git2::FileMode::Link) {
+        git::FileMode::Link
+    } else {
+        git::FileMode::Blob
+    };
+
+    let (size, _) = odb
+        .read_header(entry.id().into())
+        .context("failed to read blob header")?;
+    if mode == git::FileMode::Link || size > lfs::MAX_POINTER_SIZE {
+        return Ok(FileEntry {
+            mode,
+            size: size as u64,
+            lfs: false,
+        });
+    }
+
+    let blob = git_repository
+        .find_blob(entry.id())
+        .context("failed to find blob")?;
+    Ok(match lfs::parse_pointer(blob.content()) {
+        Some(pointer) => FileEntry {
+            mode,
+            size: pointer.size,
+            lfs: true,
+        },
+        None => FileEntry {
+            mode,
+            size: size as u64,
+            lfs: false,
+        },
+    })
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+
+use crate::{git, reader};
+
+use super::{Session, SessionError, SessionId};
+
+// helpers for rewriting the chain of session commits that refs/heads/current points to.
+
+pub(crate) fn current_refname() -> git::Refname {
+    "refs/heads/current".parse().unwrap()
+}
+
+// returns first parent chain of refs/heads/current, newest first, together with the session
+// stored in each commit. The bootstrap commit doesn't have a session.
+pub(crate) fn chain(
+    git_repository: &git::Repository,
+) -> Result<Vec<(git::Commit<'_>, Option<Session>)>> {
+    let reference = match git_repository.find_reference(&current_refname()) {
+        Ok(reference) => reference,
+        Err(git::Error::NotFound(_)) => return Ok(vec![]),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut chain = vec![];
+    let mut next = Some(reference.peel_to_commit()?);
+    while let Some(commit) = next {
+        let session = session_from_commit(git_repository, &commit)?;
+        next = if commit.parent_count() > 0 {
+            Some(commit.parent(0)?)
+        } else {
+            None
+        };
+        chain.push((commit, session));
+    }
+
+    Ok(chain)
+}
+
+// returns the sessions that tags point to, rewrites of the history don't drop them. sessions are
+// matched by id, their commits change when the history is rewritten while tags keep pointing to
+// the old ones.
+pub(crate) fn tagged_sessions(git_repository: &git::Repository) -> Result<HashSet<SessionId>> {
+    let mut tagged = HashSet::new();
+    for reference in git_repository
+        .references_glob("refs/tags/*")
+        .context("failed to list tags")?
+    {
+        let reference = reference.context("failed to read tag")?;
+        // tags of commits that are gone don't point anywhere
+        let Ok(commit) = reference.peel_to_commit() else {
+            continue;
+        };
+        if let Some(session) = session_from_commit(git_repository, &commit)? {
+            tagged.insert(session.id);
+        }
+    }
+    Ok(tagged)
+}
+
+// returns the commit of a flushed session
+pub(crate) fn find_session_commit<'repo>(
+    git_repository: &'repo git::Repository,
+    session_id: &SessionId,
+) -> Result<Option<git::Commit<'repo>>> {
+    Ok(chain(git_repository)?
+        .into_iter()
+        .find(|(_, session)| session.as_ref().map(|session| &session.id) == Some(session_id))
+        .map(|(commit, _)| commit))
+}
+
+pub(crate) fn session_from_commit(
+    git_repository: &git::Repository,
+    commit: &git::Commit<'_>,
+) -> Result<Option<Session>> {
+    if commit.parent_count() == 0 {
+        return Ok(None);
+    }
+    let commit_reader = reader::Reader::from_commit(git_repository, commit)?;
+    match Session::try_from(&commit_reader) {
+        Ok(session) => Ok(Some(session)),
+        Err(SessionError::NoSession) => Ok(None),
+        Err(SessionError::Other(error)) => Err(error),
+    }
+}
+
+// recreates given commits (oldest first) on top of the base, keeping their authors, committers and
+// messages. Optionally, the tree of a commit can be replaced.
+// returns the id of the newest written commit, or the base id if there was nothing to write.
+pub(crate) fn replay<'repo>(
+    git_repository: &'repo git::Repository,
+    base: Option<git::Commit<'repo>>,
+    commits: &[(&git::Commit<'repo>, Option<git::Oid>)],
+) -> Result<Option<git::Oid>> {
+    let mut parent = base;
+    for (commit, tree_id) in commits {
+        let tree = git_repository
+            .find_tree(tree_id.unwrap_or_else(|| commit.tree_id()))
+            .context("failed to find tree")?;
+        // extra parents, like the project head of anchored sessions, are kept as they are
+        let extra_parents = commit.parents()?.into_iter().skip(1).collect::<Vec<_>>();
+        let parents = parent
+            .iter()
+            .chain(extra_parents.iter())
+            .collect::<Vec<_>>();
+        let new_commit_oid = git_repository
+            .commit(
+                None,
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
+                &tree,
+                &parents,
+            )
+            .context("failed to write commit")?;
+        parent = Some(git_repository.find_commit(new_commit_oid)?);
+    }
+    Ok(parent.map(|commit| commit.id()))
+}
+
+// points refs/heads/current to the given commit
+pub(crate) fn update_current(
+    git_repository: &git::Repository,
+    commit_oid: git::Oid,
+    log_message: &str,
+) -> Result<()> {
+    git_repository
+        .reference(&current_refname(), commit_oid, true, log_message)
+        .context("failed to update current reference")?;
+    Ok(())
+}
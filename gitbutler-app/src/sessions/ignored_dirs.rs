@@ -0,0 +1,46 @@
+use std::path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{gb_repository, git};
+
+use super::{history, writer::IGNORED_DIRS_PATH, SessionError, SessionId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IgnoredDir {
+    // relative to the project root
+    pub path: path::PathBuf,
+    // total size of the files in the directory, in bytes
+    pub size: u64,
+}
+
+// returns the ignored directories that were recorded with the session, with
+// gitbutler.recordIgnoredDirs set. none are recorded otherwise.
+pub fn ignored_dirs(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Vec<IgnoredDir>, SessionError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+
+    match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(path::Path::new(IGNORED_DIRS_PATH))
+    {
+        Ok(entry) => {
+            let blob = repository
+                .git_repository()
+                .find_blob(entry.id())
+                .context("failed to find blob")?;
+            let ignored_dirs = serde_json::from_slice(blob.content())
+                .context("failed to parse ignored directories")?;
+            Ok(ignored_dirs)
+        }
+        Err(git::Error::NotFound(_)) => Ok(vec![]),
+        Err(error) => Err(anyhow::Error::from(error).into()),
+    }
+}
@@ -17,6 +17,9 @@ impl<'iterator> SessionsIterator<'iterator> {
 
         iter.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)
             .context("failed to set sorting")?;
+        // anchored sessions have the project's head as the second parent
+        iter.simplify_first_parent()
+            .context("failed to simplify revwalk")?;
 
         let branches = git_repository.branches(None)?;
         for branch in branches {
@@ -0,0 +1,24 @@
+use anyhow::Result;
+
+use crate::{gb_repository, git};
+
+use super::{history, Session};
+
+// returns the most recently flushed session, reading only the commit refs/heads/current points
+// to. returns None if no session was flushed yet.
+pub fn latest(repository: &gb_repository::Repository) -> Result<Option<Session>> {
+    let git_repository = repository.git_repository();
+    let reference = match git_repository.find_reference(&history::current_refname()) {
+        Ok(reference) => reference,
+        Err(git::Error::NotFound(_)) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let commit = match reference.peel_to_commit() {
+        Ok(commit) => commit,
+        // the ref points to a commit that was garbage collected, a new chain is started on the
+        // next flush
+        Err(git::Error::NotFound(_)) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    history::session_from_commit(git_repository, &commit)
+}
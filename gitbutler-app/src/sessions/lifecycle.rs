@@ -0,0 +1,74 @@
+use anyhow::Context;
+
+use crate::{gb_repository, reader};
+
+use super::{Meta, Session, SessionError, SessionId, Writer};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StartError {
+    #[error("session {0} is already started")]
+    AlreadyStarted(SessionId),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// starts a new current session with the given meta. it's an error to start a session while
+// another one is current, it has to be flushed first.
+//
+// last timestamp of the meta is ignored, the session is active as of now.
+pub fn start(repository: &gb_repository::Repository, meta: Meta) -> Result<Session, StartError> {
+    let lock = repository.lock();
+
+    if let Some(current_session) = read_current(repository)? {
+        return Err(StartError::AlreadyStarted(current_session.id));
+    }
+
+    let session = Session {
+        id: SessionId::generate(),
+        hash: None,
+        meta,
+    };
+    Writer::new(repository)
+        .context("failed to create session writer")?
+        .write(&session)
+        .context("failed to write session")?;
+    let session = read_current(repository)?.context("session was not written")?;
+
+    drop(lock);
+    repository
+        .copy_branches()
+        .context("failed to unpack branches")?;
+
+    tracing::debug!(
+        project_id = %repository.get_project_id(),
+        session_id = %session.id,
+        "started session"
+    );
+
+    Ok(session)
+}
+
+// marks the current session as active now. returns the updated session, or None if there is no
+// current session.
+pub fn touch(repository: &gb_repository::Repository) -> anyhow::Result<Option<Session>> {
+    let _lock = repository.lock();
+
+    let Some(current_session) = read_current(repository)? else {
+        return Ok(None);
+    };
+    Writer::new(repository)
+        .context("failed to create session writer")?
+        .write(&current_session)
+        .context("failed to write session")?;
+
+    read_current(repository)
+}
+
+fn read_current(repository: &gb_repository::Repository) -> anyhow::Result<Option<Session>> {
+    let reader = reader::Reader::open(&repository.root()).context("failed to open reader")?;
+    match Session::try_from(&reader) {
+        Ok(session) => Ok(Some(session)),
+        Err(SessionError::NoSession) => Ok(None),
+        Err(SessionError::Other(error)) => Err(error).context("failed to read current session"),
+    }
+}
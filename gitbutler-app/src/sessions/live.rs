@@ -0,0 +1,51 @@
+use std::{collections::HashMap, path};
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git, project_repository};
+
+use super::history;
+
+// returns files that changed in the working directory since the last flushed session
+pub fn diff_live(
+    gb_repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+) -> Result<HashMap<path::PathBuf, git::diff::ChangeType>> {
+    let git_repository = gb_repository.git_repository();
+
+    let live_tree_id = gb_repository
+        .build_live_wd_tree(project_repository)
+        .context("failed to build working directory tree")?;
+    let live_tree = git_repository
+        .find_tree(live_tree_id)
+        .context("failed to find working directory tree")?;
+
+    let last_tree = match git_repository.find_reference(&history::current_refname()) {
+        Ok(reference) => {
+            let commit = reference.peel_to_commit()?;
+            match commit.tree()?.get_path(path::Path::new("wd")) {
+                Ok(entry) => Some(git_repository.find_tree(entry.id())?),
+                Err(git::Error::NotFound(_)) => None,
+                Err(error) => return Err(error).context("failed to get wd tree"),
+            }
+        }
+        Err(git::Error::NotFound(_)) => None,
+        Err(error) => return Err(error).context("failed to find current reference"),
+    };
+
+    let mut diff_opts = git2::DiffOptions::new();
+    diff_opts.ignore_submodules(true);
+    let diff = git_repository
+        .diff_tree_to_tree(last_tree.as_ref(), Some(&live_tree), Some(&mut diff_opts))
+        .context("failed to diff working directory trees")?;
+
+    let mut changes = HashMap::new();
+    for delta in diff.deltas() {
+        let Some(file_path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+            continue;
+        };
+        changes.insert(file_path.to_path_buf(), delta.status().into());
+    }
+
+    Ok(changes)
+}
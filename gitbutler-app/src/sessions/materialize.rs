@@ -0,0 +1,186 @@
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{gb_repository, git, lfs, project_repository};
+
+use super::{history, SessionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MaterializeError {
+    #[error("session {0} not found")]
+    SessionNotFound(SessionId),
+    #[error("{0} is not a valid branch name")]
+    InvalidBranchName(String),
+    #[error("branch {0} does not point to HEAD, committing would drop its commits")]
+    BranchNotAtHead(String),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterializeOptions {
+    // defaults to a message that names the session
+    pub message: Option<String>,
+    // default to user.name and user.email of the project, like git commit does
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+}
+
+// turns the working directory of a session into a normal commit of the project, parented on the
+// current HEAD, and points the branch at it. the branch is created if it doesn't exist, an
+// existing one must point to HEAD so that no commits are lost. returns the id of the commit.
+//
+// objects are copied into the project, large files with their content from the lfs store. the
+// working directory and the index are not touched, even if the branch is checked out.
+pub fn materialize(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+    branch: &str,
+    options: &MaterializeOptions,
+) -> Result<git::Oid, MaterializeError> {
+    let refname = format!("refs/heads/{branch}");
+    if !git2::Reference::is_valid_name(&refname) {
+        return Err(MaterializeError::InvalidBranchName(branch.to_string()));
+    }
+
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(MaterializeError::SessionNotFound(*session_id))?;
+    let wd_tree = commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_name("wd")
+        .map(|entry| entry.id())
+        .context("session has no wd tree")?;
+
+    let project = <&git2::Repository>::from(&project_repository.git_repository);
+    let head = match project.head() {
+        Ok(head) => Some(
+            head.peel_to_commit()
+                .context("failed to read HEAD commit")?,
+        ),
+        // an unborn branch, the commit is the first one
+        Err(error) if error.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(error) => {
+            return Err(anyhow::Error::from(error)
+                .context("failed to read HEAD")
+                .into())
+        }
+    };
+    match project.find_reference(&refname) {
+        Ok(reference) => {
+            if reference.target() != head.as_ref().map(git2::Commit::id) {
+                return Err(MaterializeError::BranchNotAtHead(branch.to_string()));
+            }
+        }
+        Err(error) if error.code() == git2::ErrorCode::NotFound => {}
+        Err(error) => {
+            return Err(anyhow::Error::from(error)
+                .context(format!("failed to read {refname}"))
+                .into())
+        }
+    }
+
+    let encryption_key = lfs::encryption_key(&project_repository.config())?;
+    let tree = copy_tree(repository, project, wd_tree.into(), encryption_key.as_ref())?;
+    let tree = project.find_tree(tree).context("failed to find tree")?;
+
+    let committer = project
+        .signature()
+        .context("failed to read user.name and user.email")?;
+    let author = match (&options.author_name, &options.author_email) {
+        (None, None) => committer.clone(),
+        (name, email) => git2::Signature::now(
+            name.as_deref()
+                .unwrap_or_else(|| committer.name().unwrap_or_default()),
+            email
+                .as_deref()
+                .unwrap_or_else(|| committer.email().unwrap_or_default()),
+        )
+        .context("invalid author")?,
+    };
+    let message = options
+        .message
+        .clone()
+        .unwrap_or_else(|| format!("gitbutler session {session_id}"));
+
+    let parents = head.iter().collect::<Vec<_>>();
+    let oid = project
+        .commit(
+            Some(&refname),
+            &author,
+            &committer,
+            &message,
+            &tree,
+            &parents,
+        )
+        .context("failed to commit")?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        %session_id,
+        branch,
+        %oid,
+        "materialized session"
+    );
+
+    Ok(oid.into())
+}
+
+// writes the tree and everything in it into the project, returns the id of the written tree
+pub(super) fn copy_tree(
+    repository: &gb_repository::Repository,
+    project: &git2::Repository,
+    tree_id: git2::Oid,
+    encryption_key: Option<&lfs::EncryptionKey>,
+) -> anyhow::Result<git2::Oid> {
+    let source = <&git2::Repository>::from(repository.git_repository());
+    let tree = source.find_tree(tree_id).context("failed to find tree")?;
+    let mut builder = project
+        .treebuilder(None)
+        .context("failed to create tree builder")?;
+    for entry in &tree {
+        let name = entry.name().context("tree entry name is not utf-8")?;
+        let id = match entry.kind() {
+            Some(git2::ObjectType::Tree) => {
+                copy_tree(repository, project, entry.id(), encryption_key)?
+            }
+            Some(git2::ObjectType::Blob) => {
+                copy_blob(repository, project, entry.id(), encryption_key)
+                    .with_context(|| format!("failed to copy {name}"))?
+            }
+            // submodules point to commits of other repositories, there is nothing to copy
+            _ => entry.id(),
+        };
+        builder
+            .insert(name, id, entry.filemode())
+            .with_context(|| format!("failed to insert {name}"))?;
+    }
+    builder.write().context("failed to write tree")
+}
+
+fn copy_blob(
+    repository: &gb_repository::Repository,
+    project: &git2::Repository,
+    blob_id: git2::Oid,
+    encryption_key: Option<&lfs::EncryptionKey>,
+) -> anyhow::Result<git2::Oid> {
+    let source = <&git2::Repository>::from(repository.git_repository());
+    let blob = source.find_blob(blob_id).context("failed to find blob")?;
+    if let Some(pointer) = lfs::parse_pointer(blob.content()) {
+        let content = lfs::read_object(repository.lfs_objects_dir(), &pointer, encryption_key)?;
+        return project.blob(&content).context("failed to write blob");
+    }
+    // the project's objects are an alternate of the gitbutler repository, so most blobs are
+    // already there
+    if project
+        .odb()
+        .context("failed to open object database")?
+        .exists(blob_id)
+    {
+        return Ok(blob_id);
+    }
+    project.blob(blob.content()).context("failed to write blob")
+}
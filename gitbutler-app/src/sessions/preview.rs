@@ -0,0 +1,250 @@
+use std::{collections::HashMap, path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{gb_repository, git, project_repository, users};
+
+use super::{
+    history,
+    progress::{ProgressCallback, Reporter},
+    restore, SessionId, SparseCheckout,
+};
+
+// the exact working directory a preview started from is kept in the stashed session under this
+// subtree. the wd tree of a session only has the files that were tracked by deltas.
+const STASH_SUBTREE: &str = "preview-stash";
+
+// returned by checkout_preview, end_preview needs it to put the working directory back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewToken {
+    // session that is checked out
+    pub session_id: SessionId,
+    // session the working directory was captured as before the preview started
+    pub stashed_session_id: SessionId,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PreviewError {
+    #[error("session {0} not found")]
+    SessionNotFound(SessionId),
+    #[error("session {0} was not stashed by a preview")]
+    NotStashed(SessionId),
+    #[error("failed to capture the working directory, it was not changed")]
+    NotCaptured(#[source] anyhow::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// checks out the working directory of a session into the project, for example to scrub through
+// the timeline. the working directory is captured as a session first, and it's not touched if
+// that fails. end_preview puts it back.
+//
+// changes made to the project while previewing are overwritten by end_preview. files that the
+// session's sparse checkout excluded were not captured, they are kept rather than removed.
+//
+// progress reports the files that are written and removed.
+pub fn checkout_preview(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+    user: Option<&users::User>,
+    progress: Option<ProgressCallback>,
+) -> Result<PreviewToken, PreviewError> {
+    let (target_tree, target_sparse_checkout) = find_wd_tree(repository, session_id)?;
+
+    let live_tree = repository
+        .build_live_wd_tree(project_repository)
+        .map_err(PreviewError::NotCaptured)?;
+    let stashed_session = repository
+        .get_or_create_current_session()
+        .and_then(|session| {
+            repository.flush_session_with_subtrees(
+                project_repository,
+                &session,
+                user,
+                &[(STASH_SUBTREE, live_tree)],
+            )
+        })
+        .map_err(PreviewError::NotCaptured)?;
+
+    checkout_wd_tree(
+        repository,
+        project_repository,
+        live_tree,
+        target_tree,
+        target_sparse_checkout.as_ref(),
+        &mut Reporter::new(progress),
+    )
+    .context("failed to check out session")?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        %session_id,
+        stashed_session_id = %stashed_session.id,
+        "started preview"
+    );
+
+    Ok(PreviewToken {
+        session_id: *session_id,
+        stashed_session_id: stashed_session.id,
+    })
+}
+
+// puts the working directory back to what it was when the preview started
+pub fn end_preview(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    token: &PreviewToken,
+    progress: Option<ProgressCallback>,
+) -> Result<(), PreviewError> {
+    let git_repository = repository.git_repository();
+    let stashed_commit = history::find_session_commit(git_repository, &token.stashed_session_id)
+        .context("failed to read session history")?
+        .ok_or(PreviewError::SessionNotFound(token.stashed_session_id))?;
+    let stashed_tree = stashed_commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_name(STASH_SUBTREE)
+        .map(|entry| entry.id())
+        .ok_or(PreviewError::NotStashed(token.stashed_session_id))?;
+    let stashed_sparse_checkout = history::session_from_commit(git_repository, &stashed_commit)
+        .context("failed to read stashed session")?
+        .and_then(|session| session.meta.sparse_checkout);
+
+    // the project might have been changed during the preview, so it's compared as it is now
+    let live_tree = repository
+        .build_live_wd_tree(project_repository)
+        .context("failed to read working directory")?;
+    checkout_wd_tree(
+        repository,
+        project_repository,
+        live_tree,
+        stashed_tree,
+        stashed_sparse_checkout.as_ref(),
+        &mut Reporter::new(progress),
+    )
+    .context("failed to restore working directory")?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        session_id = %token.session_id,
+        stashed_session_id = %token.stashed_session_id,
+        "ended preview"
+    );
+
+    Ok(())
+}
+
+fn find_wd_tree(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<(git::Oid, Option<SparseCheckout>), PreviewError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(PreviewError::SessionNotFound(*session_id))?;
+    let wd_tree = commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_name("wd")
+        .map(|entry| entry.id())
+        .context("session has no wd tree")?;
+    let sparse_checkout = history::session_from_commit(repository.git_repository(), &commit)
+        .context("failed to read session")?
+        .and_then(|session| session.meta.sparse_checkout);
+    Ok((wd_tree, sparse_checkout))
+}
+
+// makes the project's working directory match the `to` tree, given that it currently matches
+// the `from` tree. files that are the same in both are left alone, and so are files that the
+// sparse checkout of the `to` tree excluded.
+fn checkout_wd_tree(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    from: git::Oid,
+    to: git::Oid,
+    to_sparse_checkout: Option<&SparseCheckout>,
+    reporter: &mut Reporter,
+) -> anyhow::Result<()> {
+    let from = blobs(repository.git_repository(), from)?;
+    let to = blobs(repository.git_repository(), to)?;
+    let project_path = &project_repository.project().path;
+
+    // removed first, so that a file can replace a directory and the other way around
+    let is_excluded = |file_path: &path::Path| {
+        to_sparse_checkout.map_or(false, |sparse_checkout| {
+            !sparse_checkout.includes(file_path)
+        })
+    };
+    let removed = from
+        .keys()
+        .filter(|file_path| !to.contains_key(*file_path) && !is_excluded(file_path))
+        .collect::<Vec<_>>();
+    let written = to
+        .iter()
+        .filter(|(file_path, entry)| from.get(*file_path) != Some(*entry))
+        .collect::<Vec<_>>();
+    reporter.add_total(removed.len() + written.len());
+
+    for file_path in removed {
+        let abs_path = project_path.join(file_path);
+        match std::fs::remove_file(&abs_path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("failed to remove {}", abs_path.display()))
+            }
+        }
+        // directories that are left empty go too, they didn't exist in the target
+        for dir in abs_path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| *dir != project_path.as_path())
+        {
+            if std::fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+        reporter.advance(1, 0);
+    }
+
+    for (file_path, (id, filemode)) in written {
+        let bytes = restore::write_blob(
+            repository,
+            project_repository,
+            *id,
+            *filemode,
+            &project_path.join(file_path),
+        )?;
+        reporter.advance(1, bytes);
+    }
+    reporter.finish();
+
+    Ok(())
+}
+
+// returns ids and modes of all files of a tree, by their path
+fn blobs(
+    git_repository: &git::Repository,
+    tree_id: git::Oid,
+) -> anyhow::Result<HashMap<path::PathBuf, (git::Oid, i32)>> {
+    let mut blobs = HashMap::new();
+    git_repository
+        .find_tree(tree_id)
+        .context("failed to find tree")?
+        .walk(|root, entry| {
+            if entry.kind() == Some(git2::ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    blobs.insert(
+                        path::Path::new(root).join(name),
+                        (entry.id(), entry.filemode()),
+                    );
+                }
+            }
+            git::TreeWalkResult::Continue
+        })
+        .context("failed to walk tree")?;
+    Ok(blobs)
+}
@@ -0,0 +1,102 @@
+use std::time;
+
+use serde::Serialize;
+
+// callbacks are called at most this often, and once more when the operation is done
+const REPORT_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Progress {
+    // files written or removed by a restore, objects written by an export, sessions indexed by
+    // the watcher
+    pub done: usize,
+    // what there is to do, it grows while an export counts its objects
+    pub total: usize,
+    // bytes written so far
+    pub bytes: u64,
+}
+
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(Progress);
+
+// counts progress and reports it to the callback, if there is one
+pub(crate) struct Reporter<'a> {
+    callback: Option<ProgressCallback<'a>>,
+    progress: Progress,
+    last_report: Option<time::Instant>,
+}
+
+impl<'a> Reporter<'a> {
+    pub(crate) fn new(callback: Option<ProgressCallback<'a>>) -> Self {
+        Self {
+            callback,
+            progress: Progress::default(),
+            last_report: None,
+        }
+    }
+
+    pub(crate) fn add_total(&mut self, total: usize) {
+        self.progress.total += total;
+        self.report(false);
+    }
+
+    pub(crate) fn advance(&mut self, done: usize, bytes: u64) {
+        self.progress.done += done;
+        self.progress.bytes += bytes;
+        self.report(false);
+    }
+
+    pub(crate) fn finish(&mut self) {
+        self.report(true);
+    }
+
+    fn report(&mut self, force: bool) {
+        let Some(callback) = self.callback.as_mut() else {
+            return;
+        };
+        let now = time::Instant::now();
+        if !force
+            && self
+                .last_report
+                .map_or(false, |last_report| now - last_report < REPORT_INTERVAL)
+        {
+            return;
+        }
+        self.last_report = Some(now);
+        callback(self.progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reporter_throttles() {
+        let mut reports = vec![];
+        let mut callback = |progress: Progress| reports.push(progress);
+        let mut reporter = Reporter::new(Some(&mut callback));
+        reporter.add_total(3);
+        reporter.advance(1, 10);
+        reporter.advance(1, 10);
+        reporter.advance(1, 10);
+        reporter.finish();
+
+        // the first report goes out, the ones right after it don't
+        assert_eq!(
+            reports,
+            vec![
+                Progress {
+                    done: 0,
+                    total: 3,
+                    bytes: 0,
+                },
+                Progress {
+                    done: 3,
+                    total: 3,
+                    bytes: 30,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{gb_repository, git, lfs};
+
+use super::{history, Session, SessionId};
+
+// what pruning removes, or would remove in a dry run
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneReport {
+    // sessions that are dropped from the history, newest first
+    pub sessions: Vec<SessionId>,
+    // commits of the dropped sessions. the kept ones are rewritten, they are not listed.
+    pub commits: Vec<git::Oid>,
+    // lfs objects that are no longer referenced
+    pub lfs_objects: Vec<String>,
+    // total size of the lfs objects
+    pub lfs_bytes: u64,
+}
+
+type Chain<'repo> = [(git::Commit<'repo>, Option<Session>)];
+
+// how the history is cut to keep a number of sessions
+struct Plan<'a, 'repo> {
+    bootstrap_commit: &'a git::Commit<'repo>,
+    // newest first, like the chain
+    kept: Vec<&'a (git::Commit<'repo>, Option<Session>)>,
+    dropped: Vec<&'a (git::Commit<'repo>, Option<Session>)>,
+    // tree of the new bootstrap commit
+    base_tree: git::Oid,
+}
+
+// returns None if there are no more than `keep` sessions that are not tagged. tagged sessions
+// are always kept and don't count against `keep`.
+fn plan<'a, 'repo>(
+    chain: &'a Chain<'repo>,
+    keep: usize,
+    tagged: &HashSet<SessionId>,
+) -> Result<Option<Plan<'a, 'repo>>> {
+    let is_tagged = |session: &Option<Session>| {
+        session
+            .as_ref()
+            .map_or(false, |session| tagged.contains(&session.id))
+    };
+    let is_untagged_session = |session: &Option<Session>| session.is_some() && !is_tagged(session);
+    let untagged_count = chain
+        .iter()
+        .filter(|(_, session)| is_untagged_session(session))
+        .count();
+    if untagged_count <= keep {
+        return Ok(None);
+    }
+
+    // chain is ordered newest first and ends with the bootstrap commit
+    let (bootstrap_commit, _) = chain.last().context("history is empty")?;
+    let cut = chain
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, session))| is_untagged_session(session))
+        .nth(keep)
+        .map_or(chain.len() - 1, |(position, _)| position);
+    let (tagged_older, dropped): (Vec<_>, Vec<_>) = chain[cut..chain.len() - 1]
+        .iter()
+        .partition(|(_, session)| is_tagged(session));
+    let kept = chain[..cut].iter().chain(tagged_older).collect::<Vec<_>>();
+
+    // the commit before the oldest kept session becomes the new bootstrap commit, so that the
+    // session still has its previous state to be compared to. bootstrap commits are never listed
+    // as sessions.
+    let base_tree = match kept.last() {
+        Some((oldest_kept, _)) => chain
+            .iter()
+            .position(|(commit, _)| commit.id() == oldest_kept.id())
+            .and_then(|position| chain.get(position + 1))
+            .map_or_else(
+                || bootstrap_commit.tree_id(),
+                |(commit, _)| commit.tree_id(),
+            ),
+        None => dropped.first().map_or_else(
+            || bootstrap_commit.tree_id(),
+            |(commit, _)| commit.tree_id(),
+        ),
+    };
+
+    Ok(Some(Plan {
+        bootstrap_commit,
+        kept,
+        dropped,
+        base_tree,
+    }))
+}
+
+// keeps only the most recent `keep` sessions in the history, and the tagged ones, dropping older
+// ones together with lfs objects that are no longer referenced. returns the number of pruned
+// sessions.
+pub fn prune_sessions_by_count(
+    repository: &gb_repository::Repository,
+    keep: usize,
+) -> Result<usize> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let chain = history::chain(git_repository).context("failed to read sessions history")?;
+    let tagged = history::tagged_sessions(git_repository)?;
+    let Some(plan) = plan(&chain, keep, &tagged)? else {
+        return Ok(0);
+    };
+
+    let base_oid = history::replay(
+        git_repository,
+        None,
+        &[(plan.bootstrap_commit, Some(plan.base_tree))],
+    )
+    .context("failed to write bootstrap commit")?
+    .expect("bootstrap commit is written");
+    let base = git_repository.find_commit(base_oid)?;
+
+    let commits = plan
+        .kept
+        .iter()
+        .rev()
+        .map(|(commit, _)| (commit, None))
+        .collect::<Vec<_>>();
+    let head_oid = history::replay(git_repository, Some(base), &commits)
+        .context("failed to replay sessions")?
+        .unwrap_or(base_oid);
+
+    history::update_current(
+        git_repository,
+        head_oid,
+        &format!("prune sessions, keep {}", keep),
+    )?;
+
+    let pruned = plan
+        .dropped
+        .iter()
+        .filter(|(_, session)| session.is_some())
+        .count();
+
+    let removed = lfs::gc_locked(git_repository, repository.lfs_objects_dir())?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        pruned,
+        removed_lfs_objects = removed.len(),
+        "pruned sessions"
+    );
+
+    Ok(pruned)
+}
+
+// returns what prune_sessions_by_count would remove, without changing anything
+pub fn prune_sessions_by_count_dry_run(
+    repository: &gb_repository::Repository,
+    keep: usize,
+) -> Result<PruneReport> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let chain = history::chain(git_repository).context("failed to read sessions history")?;
+    let tagged = history::tagged_sessions(git_repository)?;
+    let Some(plan) = plan(&chain, keep, &tagged)? else {
+        return Ok(PruneReport::default());
+    };
+
+    let kept = plan
+        .kept
+        .iter()
+        .map(|(commit, _)| commit.id())
+        .collect::<Vec<_>>();
+    let referenced = lfs::referenced_oids_after_prune(
+        git_repository,
+        &history::current_refname().to_string(),
+        &kept,
+        plan.base_tree,
+    )
+    .context("failed to collect referenced lfs objects")?;
+    let unreferenced = lfs::unreferenced_objects(repository.lfs_objects_dir(), &referenced)
+        .context("failed to list unreferenced lfs objects")?;
+
+    Ok(PruneReport {
+        sessions: plan
+            .dropped
+            .iter()
+            .filter_map(|(_, session)| session.as_ref().map(|session| session.id))
+            .collect(),
+        commits: plan.dropped.iter().map(|(commit, _)| commit.id()).collect(),
+        lfs_objects: unreferenced.oids,
+        lfs_bytes: unreferenced.bytes,
+    })
+}
@@ -0,0 +1,162 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git, reader};
+
+use super::{history, Session, SessionError, SessionId};
+
+// rebuilds refs/heads/current from the session commits that are still in the object database,
+// for when the ref was lost or clobbered. sessions are ordered by when they started. returns the
+// number of sessions that were not in the history before.
+//
+// a session that was rewritten, for example by squash, is recovered from its newest commit.
+// sessions that were dropped from the history but not garbage collected come back.
+pub fn recover(repository: &gb_repository::Repository) -> Result<usize> {
+    if repository.is_read_only() {
+        return Err(gb_repository::Error::ReadOnly.into());
+    }
+
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let known = history::chain(git_repository)
+        .unwrap_or_else(|error| {
+            // a clobbered ref might point to anything, it's rebuilt anyway
+            tracing::warn!(
+                project_id = %repository.get_project_id(),
+                ?error,
+                "failed to read sessions history"
+            );
+            vec![]
+        })
+        .into_iter()
+        .filter_map(|(_, session)| session.map(|session| session.id))
+        .collect::<HashSet<_>>();
+
+    let mut bootstrap: Option<git::Commit> = None;
+    let mut sessions: HashMap<SessionId, (git::Commit, Session)> = HashMap::new();
+    for commit in session_commits(git_repository)? {
+        if commit.parent_count() == 0 {
+            // the oldest one is the bootstrap commit of the lost history
+            if bootstrap.as_ref().map_or(true, |bootstrap| {
+                commit.time().seconds() < bootstrap.time().seconds()
+            }) {
+                bootstrap = Some(commit);
+            }
+            continue;
+        }
+        let Some(session) = read_session(git_repository, &commit)? else {
+            continue;
+        };
+        match sessions.get(&session.id) {
+            Some((found, _))
+                if found.committer().when().seconds() >= commit.committer().when().seconds() => {}
+            _ => {
+                sessions.insert(session.id, (commit, session));
+            }
+        }
+    }
+
+    let mut sessions = sessions.into_values().collect::<Vec<_>>();
+    sessions.sort_by_key(|(_, session)| {
+        (
+            session.meta.start_timestamp_ms,
+            session.meta.last_timestamp_ms,
+        )
+    });
+    let Some((oldest_commit, _)) = sessions.first() else {
+        return Ok(0);
+    };
+
+    // sessions are compared to the commit before them, so without a bootstrap commit one is
+    // made from the oldest session
+    let bootstrap = match bootstrap {
+        Some(bootstrap) => bootstrap,
+        None => {
+            let bootstrap_oid = git_repository
+                .commit(
+                    None,
+                    &oldest_commit.author(),
+                    &oldest_commit.committer(),
+                    "gitbutler check",
+                    &oldest_commit.tree()?,
+                    &[],
+                )
+                .context("failed to write bootstrap commit")?;
+            git_repository.find_commit(bootstrap_oid)?
+        }
+    };
+    let bootstrap_oid = bootstrap.id();
+
+    let commits = sessions
+        .iter()
+        .map(|(commit, _)| (commit, None))
+        .collect::<Vec<_>>();
+    let head_oid = history::replay(git_repository, Some(bootstrap), &commits)
+        .context("failed to replay sessions")?
+        .unwrap_or(bootstrap_oid);
+    history::update_current(git_repository, head_oid, "recover sessions")?;
+
+    let recovered = sessions
+        .iter()
+        .filter(|(_, session)| !known.contains(&session.id))
+        .count();
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        sessions = sessions.len(),
+        recovered,
+        %head_oid,
+        "recovered sessions"
+    );
+
+    Ok(recovered)
+}
+
+// returns every commit in the object database that looks like it was written by a flush
+fn session_commits(git_repository: &git::Repository) -> Result<Vec<git::Commit<'_>>> {
+    let odb = <&git2::Repository>::from(git_repository)
+        .odb()
+        .context("failed to open object database")?;
+
+    // headers are read after listing, the object database is busy while it's being listed
+    let mut oids = vec![];
+    odb.foreach(|oid| {
+        oids.push(*oid);
+        true
+    })
+    .context("failed to list objects")?;
+
+    let mut commit_oids = vec![];
+    for oid in oids {
+        let (_, kind) = odb
+            .read_header(oid)
+            .context("failed to read object header")?;
+        if kind == git2::ObjectType::Commit {
+            commit_oids.push(git::Oid::from(oid));
+        }
+    }
+
+    let mut commits = vec![];
+    for oid in commit_oids {
+        let commit = git_repository.find_commit(oid)?;
+        if commit.message() != Some("gitbutler check") {
+            continue;
+        }
+        let tree = commit.tree()?;
+        if tree.get_name("session").is_some() && tree.get_name("wd").is_some() {
+            commits.push(commit);
+        }
+    }
+    Ok(commits)
+}
+
+fn read_session(git_repository: &git::Repository, commit: &git::Commit) -> Result<Option<Session>> {
+    let commit_reader = reader::Reader::from_commit(git_repository, commit)?;
+    match Session::try_from(&commit_reader) {
+        Ok(session) => Ok(Some(session)),
+        Err(SessionError::NoSession) => Ok(None),
+        Err(SessionError::Other(error)) => Err(error),
+    }
+}
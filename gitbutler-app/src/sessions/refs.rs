@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{gb_repository, git};
+
+use super::history;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GbRefKind {
+    // refs/heads/current, the history sessions are flushed to
+    Current,
+    // refs under refs/gitbutler/, like the histories of other branches
+    Gitbutler,
+    Tag,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GbRef {
+    pub name: String,
+    pub kind: GbRefKind,
+    // the commit the ref points to, peeled for annotated tags
+    pub target: git::Oid,
+    // sessions in the first parent chain of the target, when they are counted
+    pub sessions: Option<usize>,
+}
+
+// lists the refs of the gitbutler repository, ordered by kind and name. refs that don't point to
+// a commit, for example to one that was garbage collected, are skipped.
+//
+// only refs are read, unless `count_sessions` is set. counting walks the history of every ref.
+pub fn list_refs(
+    repository: &gb_repository::Repository,
+    count_sessions: bool,
+) -> Result<Vec<GbRef>> {
+    let git_repository = <&git2::Repository>::from(repository.git_repository());
+    let current_refname = history::current_refname().to_string();
+
+    let mut refs = vec![];
+    for reference in git_repository.references().context("failed to list refs")? {
+        let reference = reference.context("failed to read ref")?;
+        let Some(name) = reference.name() else {
+            continue;
+        };
+        let kind = if name == current_refname {
+            GbRefKind::Current
+        } else if name.starts_with("refs/gitbutler/") {
+            GbRefKind::Gitbutler
+        } else if name.starts_with("refs/tags/") {
+            GbRefKind::Tag
+        } else {
+            continue;
+        };
+        let Ok(commit) = reference.peel_to_commit() else {
+            continue;
+        };
+        let sessions = count_sessions
+            .then(|| count(git_repository, commit.id()))
+            .transpose()
+            .with_context(|| format!("failed to count sessions of {name}"))?;
+        refs.push(GbRef {
+            name: name.to_string(),
+            kind,
+            target: commit.id().into(),
+            sessions,
+        });
+    }
+
+    refs.sort_by(|a, b| (a.kind, &a.name).cmp(&(b.kind, &b.name)));
+    Ok(refs)
+}
+
+// sessions are all commits of the first parent chain, except for the bootstrap commit
+fn count(git_repository: &git2::Repository, oid: git2::Oid) -> Result<usize> {
+    let mut revwalk = git_repository.revwalk()?;
+    revwalk.push(oid)?;
+    revwalk.simplify_first_parent()?;
+    let mut count = 0;
+    for oid in revwalk {
+        if git_repository.find_commit(oid?)?.parent_count() > 0 {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,220 @@
+use std::path;
+
+use anyhow::Context;
+
+use crate::{gb_repository, git, lfs, project_repository};
+
+use super::{history, writer::EMPTY_DIRS_PATH, xattrs, SessionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreFileError {
+    #[error("session {0} not found")]
+    SessionNotFound(SessionId),
+    #[error("{} not found in session", .0.display())]
+    PathNotFound(path::PathBuf),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// writes a single file, as it was in the given session, to dest. large files are read from the
+// lfs store, executables and symlinks are restored as such.
+pub fn restore_file(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+    file_path: &path::Path,
+    dest: &path::Path,
+) -> Result<(), RestoreFileError> {
+    let git_repository = repository.git_repository();
+    let commit = history::find_session_commit(git_repository, session_id)
+        .context("failed to read session history")?
+        .ok_or(RestoreFileError::SessionNotFound(*session_id))?;
+
+    let entry = match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(&path::Path::new("wd").join(file_path))
+    {
+        Ok(entry) if entry.kind() == Some(git2::ObjectType::Blob) => entry,
+        Ok(_) | Err(git::Error::NotFound(_)) => {
+            return Err(RestoreFileError::PathNotFound(file_path.to_path_buf()))
+        }
+        Err(error) => return Err(anyhow::Error::from(error).into()),
+    };
+
+    write_blob(
+        repository,
+        project_repository,
+        entry.id(),
+        entry.filemode(),
+        dest,
+    )?;
+    Ok(())
+}
+
+// creates the directories that were empty when the session was captured, with
+// gitbutler.captureEmptyDirs set, and that don't exist in the project now. returns the
+// directories that were created, relative to the project root.
+pub fn restore_empty_dirs(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Vec<path::PathBuf>, RestoreFileError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(RestoreFileError::SessionNotFound(*session_id))?;
+
+    let empty_dirs = match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(path::Path::new(EMPTY_DIRS_PATH))
+    {
+        Ok(entry) => {
+            let blob = repository
+                .git_repository()
+                .find_blob(entry.id())
+                .context("failed to find blob")?;
+            serde_json::from_slice::<Vec<path::PathBuf>>(blob.content())
+                .context("failed to parse empty directories")?
+        }
+        // nothing was recorded
+        Err(git::Error::NotFound(_)) => return Ok(vec![]),
+        Err(error) => return Err(anyhow::Error::from(error).into()),
+    };
+
+    let mut created = vec![];
+    for dir in empty_dirs {
+        if !project_repository::is_inside_workdir(&dir) {
+            return Err(anyhow::anyhow!("invalid empty directory {}", dir.display()).into());
+        }
+        let abs_path = project_repository.root().join(&dir);
+        if abs_path.exists() {
+            continue;
+        }
+        std::fs::create_dir_all(&abs_path)
+            .with_context(|| format!("failed to create {}", abs_path.display()))?;
+        created.push(dir);
+    }
+    Ok(created)
+}
+
+// sets the extended attributes that were recorded with the session, with gitbutler.captureXattrs
+// set, on the files of the project that still exist. attributes that are there but were not
+// recorded are left alone. returns the files that got all of their attributes back, relative to
+// the project root.
+//
+// some attributes can only be set with privileges, like the ones in the trusted namespace. those
+// that can't be set are logged and skipped.
+pub fn restore_xattrs(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Vec<path::PathBuf>, RestoreFileError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(RestoreFileError::SessionNotFound(*session_id))?;
+
+    let mut restored = vec![];
+    for (file_path, file_xattrs) in xattrs::from_commit(repository.git_repository(), &commit)? {
+        if !project_repository::is_inside_workdir(&file_path) {
+            return Err(anyhow::anyhow!("invalid xattrs path {}", file_path.display()).into());
+        }
+        let abs_path = project_repository.root().join(&file_path);
+        if abs_path.symlink_metadata().is_err() {
+            continue;
+        }
+        let mut all_set = true;
+        for (name, value) in &file_xattrs {
+            if let Err(error) = xattr::set(&abs_path, name, value) {
+                tracing::warn!(
+                    path = %abs_path.display(),
+                    name,
+                    %error,
+                    "failed to restore xattr"
+                );
+                all_set = false;
+            }
+        }
+        if all_set {
+            restored.push(file_path);
+        }
+    }
+    Ok(restored)
+}
+
+// writes a blob of a session tree to dest, with the given git file mode. returns the number of
+// bytes that were written.
+pub(super) fn write_blob(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    id: git::Oid,
+    filemode: i32,
+    dest: &path::Path,
+) -> anyhow::Result<u64> {
+    let blob = repository
+        .git_repository()
+        .find_blob(id)
+        .context("failed to find blob")?;
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    if filemode == i32::from(git2::FileMode::Link) {
+        let target = std::str::from_utf8(blob.content()).context("invalid symlink target")?;
+        write_symlink(path::Path::new(target), dest)?;
+        return Ok(blob.size() as u64);
+    }
+
+    let content = match lfs::parse_pointer(blob.content()) {
+        Some(pointer) => {
+            let encryption_key = lfs::encryption_key(&project_repository.config())?;
+            lfs::read_object(
+                repository.lfs_objects_dir(),
+                &pointer,
+                encryption_key.as_ref(),
+            )?
+        }
+        None => blob.content().to_vec(),
+    };
+    // writing through a symlink would change the file it points to instead
+    if std::fs::symlink_metadata(dest).map_or(false, |metadata| metadata.is_symlink()) {
+        std::fs::remove_file(dest)
+            .with_context(|| format!("failed to remove {}", dest.display()))?;
+    }
+    std::fs::write(dest, &content)
+        .with_context(|| format!("failed to write {}", dest.display()))?;
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = if filemode == i32::from(git2::FileMode::BlobExecutable) {
+            0o755
+        } else {
+            0o644
+        };
+        std::fs::set_permissions(dest, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions of {}", dest.display()))?;
+    }
+
+    Ok(content.len() as u64)
+}
+
+fn write_symlink(target: &path::Path, dest: &path::Path) -> anyhow::Result<()> {
+    // an existing file at dest is replaced, like it is when restoring a regular file
+    match std::fs::symlink_metadata(dest) {
+        Ok(_) => std::fs::remove_file(dest)
+            .with_context(|| format!("failed to remove {}", dest.display()))?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error.into()),
+    }
+    #[cfg(target_family = "unix")]
+    std::os::unix::fs::symlink(target, dest)
+        .with_context(|| format!("failed to create symlink {}", dest.display()))?;
+    #[cfg(target_os = "windows")]
+    std::os::windows::fs::symlink_file(target, dest)
+        .with_context(|| format!("failed to create symlink {}", dest.display()))?;
+    Ok(())
+}
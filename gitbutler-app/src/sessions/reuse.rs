@@ -0,0 +1,148 @@
+use std::{collections::HashMap, path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{gb_repository, git};
+
+use super::{history, SessionId};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobReuse {
+    // distinct blobs of the session that the session before it didn't have
+    pub new_blobs: usize,
+    // total size of those blobs, what the session added to the history
+    pub new_bytes: u64,
+    // distinct blobs of the session that the session before it had too, they are stored once
+    pub reused_blobs: usize,
+    pub reused_bytes: u64,
+}
+
+// tells how much new content every flushed session brought in compared to the session before it,
+// newest first. a blob is counted once per session, no matter how many files have it.
+//
+// like change counts, the results are cached by commit id next to the history.
+pub fn blob_reuse(repository: &gb_repository::Repository) -> Result<Vec<(SessionId, BlobReuse)>> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let odb = <&git2::Repository>::from(git_repository)
+        .odb()
+        .context("failed to open object database")?;
+    let cache_path = git_repository.path().join("blob-reuse");
+    let mut cache = load_cache(&cache_path);
+    let chain = history::chain(git_repository).context("failed to read session history")?;
+
+    let mut reuse = vec![];
+    let mut chain_cache = HashMap::new();
+    for (position, (commit, session)) in chain.iter().enumerate() {
+        let Some(session) = session else {
+            continue;
+        };
+        let session_reuse = match cache.get(&commit.id()) {
+            Some(session_reuse) => *session_reuse,
+            None => {
+                let parent = chain.get(position + 1).map(|(parent, _)| parent);
+                compute(&odb, parent, commit).with_context(|| {
+                    format!("failed to compute blob reuse of session {}", session.id)
+                })?
+            }
+        };
+        chain_cache.insert(commit.id(), session_reuse);
+        reuse.push((session.id, session_reuse));
+    }
+
+    // entries of commits that were squashed or pruned away are dropped
+    if chain_cache != cache && !repository.is_read_only() {
+        cache = chain_cache;
+        if let Err(error) = save_cache(&cache_path, &cache) {
+            tracing::warn!(?error, "failed to save blob reuse");
+        }
+    }
+
+    Ok(reuse)
+}
+
+fn compute(
+    odb: &git2::Odb,
+    parent: Option<&git::Commit>,
+    commit: &git::Commit,
+) -> Result<BlobReuse> {
+    let parent_blobs = match parent {
+        Some(parent) => blob_sizes(odb, parent, &HashMap::new())?,
+        None => HashMap::new(),
+    };
+
+    let mut reuse = BlobReuse::default();
+    for (id, size) in blob_sizes(odb, commit, &parent_blobs)? {
+        if parent_blobs.contains_key(&id) {
+            reuse.reused_blobs += 1;
+            reuse.reused_bytes += size;
+        } else {
+            reuse.new_blobs += 1;
+            reuse.new_bytes += size;
+        }
+    }
+    Ok(reuse)
+}
+
+// returns the sizes of all blobs in the tree of the commit. sizes that are known already are not
+// read again.
+fn blob_sizes(
+    odb: &git2::Odb,
+    commit: &git::Commit,
+    known: &HashMap<git::Oid, u64>,
+) -> Result<HashMap<git::Oid, u64>> {
+    let mut sizes = HashMap::new();
+    let mut walk_error = None;
+    commit.tree()?.walk(|_, entry| {
+        if entry.kind() != Some(git2::ObjectType::Blob) || sizes.contains_key(&entry.id()) {
+            return git::TreeWalkResult::Continue;
+        }
+        if let Some(size) = known.get(&entry.id()) {
+            sizes.insert(entry.id(), *size);
+            return git::TreeWalkResult::Continue;
+        }
+        match odb.read_header(entry.id().into()) {
+            Ok((size, _)) => {
+                sizes.insert(entry.id(), size as u64);
+                git::TreeWalkResult::Continue
+            }
+            Err(error) => {
+                walk_error = Some(error);
+                git::TreeWalkResult::Stop
+            }
+        }
+    })?;
+    if let Some(error) = walk_error {
+        return Err(error).context("failed to read blob header");
+    }
+    Ok(sizes)
+}
+
+// a missing or unreadable cache is empty, reuse is computed again
+fn load_cache(path: &path::Path) -> HashMap<git::Oid, BlobReuse> {
+    let content = match std::fs::read(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return HashMap::new(),
+        Err(error) => {
+            tracing::warn!(path = %path.display(), ?error, "failed to read blob reuse");
+            return HashMap::new();
+        }
+    };
+    serde_json::from_slice(&content).unwrap_or_else(|error| {
+        tracing::warn!(path = %path.display(), ?error, "failed to parse blob reuse");
+        HashMap::new()
+    })
+}
+
+fn save_cache(path: &path::Path, cache: &HashMap<git::Oid, BlobReuse>) -> Result<()> {
+    let content = serde_json::to_vec(cache).context("failed to serialize blob reuse")?;
+    // write to a temporary file first, so that a crash doesn't leave a truncated cache behind
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, content)
+        .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to rename {}", tmp_path.display()))
+}
@@ -1,22 +1,118 @@
-use std::path;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path, time,
+};
 
 use anyhow::{Context, Result};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{git, id::Id, reader};
 
+// version of the session meta layout that is written. every meta value is a file of its own, so
+// new values are added without a bump: readers ignore files they don't know about and default the
+// ones that are missing. the version is bumped when the meaning of an existing value changes.
+//
+// sessions recorded before the version was introduced are version 1.
+pub const META_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Meta {
+    // version of the layout the meta was recorded with
+    pub version: u32,
     // timestamp of when the session was created
     pub start_timestamp_ms: u128,
     // timestamp of when the session was last active
     pub last_timestamp_ms: u128,
-    // session branch name
+    // session branch name, not set when the head was detached
     pub branch: Option<String>,
     // session commit hash
     pub commit: Option<String>,
+    // head was detached when the session started, commit is what it pointed to
+    pub detached: bool,
+    // stash entries of the project when the session was captured, most recent first
+    pub stashes: Vec<StashRef>,
+    // sparse checkout of the project when the session was captured, not set when all files are
+    // checked out
+    pub sparse_checkout: Option<SparseCheckout>,
+    // arbitrary key/value pairs attached to the session by integrations
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StashRef {
+    // id of the stash commit
+    pub oid: git::Oid,
+    // fields that newer versions add are ignored, and the ones they drop are defaulted
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SparseCheckout {
+    // core.sparseCheckoutCone was set, the patterns are directories
+    #[serde(default)]
+    pub cone: bool,
+    // lines of info/sparse-checkout, without comments and blank lines
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl SparseCheckout {
+    // returns true if the file is checked out with these patterns. only cone patterns are
+    // understood, with other patterns any file might be excluded and none is included.
+    //
+    // in cone mode, files at the root are always included. files directly in a parent directory
+    // of a cone, written as `/dir/` followed by `!/dir/*/`, are included, as is everything under
+    // a directory that is not followed by such a negation.
+    pub fn includes(&self, file_path: &path::Path) -> bool {
+        if !self.cone {
+            return false;
+        }
+        let Some(parent) = file_path.parent() else {
+            return true;
+        };
+        if parent.as_os_str().is_empty() {
+            return true;
+        }
+
+        let mut dirs = HashSet::new();
+        let mut parents = HashSet::new();
+        for pattern in &self.patterns {
+            if let Some(dir) = pattern
+                .strip_prefix("!/")
+                .and_then(|pattern| pattern.strip_suffix("/*/"))
+            {
+                parents.insert(dir);
+            } else if let Some(dir) = pattern
+                .strip_prefix('/')
+                .and_then(|pattern| pattern.strip_suffix('/'))
+            {
+                dirs.insert(dir);
+            }
+        }
+
+        // patterns always use forward slashes
+        let key = |dir: &path::Path| {
+            dir.components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        if dirs.contains(key(parent).as_str()) {
+            return true;
+        }
+        parent
+            .ancestors()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| {
+                let dir = key(dir);
+                dirs.contains(dir.as_str()) && !parents.contains(dir.as_str())
+            })
+    }
 }
 
 pub type SessionId = Id<Session>;
@@ -24,12 +120,28 @@ pub type SessionId = Id<Session>;
 #[derive(Debug, Clone, Serialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Session {
+    // recorded in session/meta/id when the session is created. unlike the hash, it stays the same
+    // when the history is rewritten by squashing or pruning, so references to sessions are kept
+    // by id.
     pub id: SessionId,
-    // if hash is not set, the session is not saved aka current
+    // commit of the session in the current history. if hash is not set, the session is not saved
+    // aka current
     pub hash: Option<git::Oid>,
     pub meta: Meta,
 }
 
+// sessions are flushed once they are this old, even while they are still active
+pub const MAX_SESSION_AGE: time::Duration = time::Duration::new(60 * 60, 0);
+
+impl Session {
+    // like idle sessions, a session is too old from the moment it turns MAX_SESSION_AGE old
+    pub fn is_too_old(&self, now: time::SystemTime) -> Result<bool> {
+        let start = time::UNIX_EPOCH
+            + time::Duration::from_millis(self.meta.start_timestamp_ms.try_into()?);
+        Ok(start + MAX_SESSION_AGE <= now)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum SessionError {
     #[error("session does not exist")]
@@ -49,6 +161,10 @@ impl TryFrom<&reader::Reader<'_>> for Session {
                 path::Path::new("session/meta/last"),
                 path::Path::new("session/meta/branch"),
                 path::Path::new("session/meta/commit"),
+                path::Path::new("session/meta/detached"),
+                path::Path::new("session/meta/stashes"),
+                path::Path::new("session/meta/version"),
+                path::Path::new("session/meta/sparseCheckout"),
             ])
             .context("failed to batch read")?;
 
@@ -57,6 +173,10 @@ impl TryFrom<&reader::Reader<'_>> for Session {
         let last_timestamp_ms = &results[2];
         let branch = &results[3];
         let commit = &results[4];
+        let detached = &results[5];
+        let stashes = &results[6];
+        let version = &results[7];
+        let sparse_checkout = &results[8];
 
         let id = id.clone().map_err(|error| match error {
             reader::Error::NotFound => SessionError::NoSession,
@@ -112,15 +232,102 @@ impl TryFrom<&reader::Reader<'_>> for Session {
         }
         .context("failed to parse session commit as string")?;
 
+        // sessions recorded before detached was introduced don't have it
+        let detached = match detached.clone() {
+            Ok(detached) => {
+                let detached: bool = detached
+                    .try_into()
+                    .context("failed to parse session detached as boolean")?;
+                Ok(detached)
+            }
+            Err(reader::Error::NotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+        .context("failed to parse session detached as boolean")?;
+
+        // sessions recorded before stashes were introduced don't have them
+        let stashes: Vec<StashRef> = match stashes.clone() {
+            Ok(reader::Content::UTF8(stashes)) => {
+                serde_json::from_str(&stashes).context("failed to parse session stashes")?
+            }
+            Ok(_) => return Err(anyhow::anyhow!("session stashes are not utf8").into()),
+            Err(reader::Error::NotFound) => vec![],
+            Err(error) => return Err(SessionError::Other(error.into())),
+        };
+
+        // sessions recorded before sparse checkouts were introduced don't have them
+        let sparse_checkout: Option<SparseCheckout> = match sparse_checkout.clone() {
+            Ok(reader::Content::UTF8(sparse_checkout)) => Some(
+                serde_json::from_str(&sparse_checkout)
+                    .context("failed to parse session sparse checkout")?,
+            ),
+            Ok(_) => return Err(anyhow::anyhow!("session sparse checkout is not utf8").into()),
+            Err(reader::Error::NotFound) => None,
+            Err(error) => return Err(SessionError::Other(error.into())),
+        };
+
+        let version = match version.clone() {
+            Ok(version) => {
+                let version: u64 = version
+                    .try_into()
+                    .context("failed to parse session meta version as number")?;
+                u32::try_from(version).context("session meta version is out of range")?
+            }
+            Err(reader::Error::NotFound) => 1,
+            Err(error) => return Err(SessionError::Other(error.into())),
+        };
+        if version > META_VERSION {
+            // read as far as it's understood, values that were added since are ignored
+            tracing::debug!(%id, version, "session meta is newer than this version");
+        }
+
+        let metadata = read_metadata(reader)?;
+
         Ok(Self {
             id,
             hash: reader.commit_id(),
             meta: Meta {
+                version,
                 start_timestamp_ms,
                 last_timestamp_ms,
                 branch,
                 commit,
+                detached,
+                stashes,
+                sparse_checkout,
+                metadata,
             },
         })
     }
 }
+
+// every metadata key is stored in its own file under session/meta/metadata, with a json value as
+// the content. this way, integrations can write their keys without coordinating with each other.
+fn read_metadata(reader: &reader::Reader) -> Result<BTreeMap<String, serde_json::Value>> {
+    let metadata_dir = path::Path::new("session/meta/metadata");
+    let keys = reader
+        .list_files(metadata_dir)
+        .context("failed to list session metadata")?;
+
+    let mut metadata = BTreeMap::new();
+    for key in keys {
+        let Some(key_str) = key.to_str() else {
+            continue;
+        };
+        let value = match reader.read(metadata_dir.join(&key)) {
+            Ok(reader::Content::UTF8(value)) => value,
+            Ok(_) | Err(reader::Error::NotFound) => continue,
+            Err(error) => return Err(error).context("failed to read session metadata"),
+        };
+        match serde_json::from_str(&value) {
+            Ok(value) => {
+                metadata.insert(key_str.to_string(), value);
+            }
+            Err(error) => {
+                tracing::warn!(key = key_str, ?error, "ignoring invalid session metadata");
+            }
+        }
+    }
+
+    Ok(metadata)
+}
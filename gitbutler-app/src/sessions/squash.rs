@@ -0,0 +1,123 @@
+use anyhow::Context;
+
+use crate::{gb_repository, git};
+
+use super::{history, Meta, Session, SessionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SquashError {
+    #[error("session {0} not found")]
+    SessionNotFound(SessionId),
+    #[error("session {from} is newer than session {to}")]
+    InvalidRange { from: SessionId, to: SessionId },
+    // squashing would drop the tagged session from the history
+    #[error("session {0} is tagged")]
+    TaggedSession(SessionId),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// squashes all sessions between from and to (both inclusive) into one session.
+//
+// the resulting session keeps the state of the newest session in the range and its meta spans
+// the whole range. Sessions newer than the range are recreated on top of the squashed one.
+// ranges with a tagged session in them are not squashed.
+pub fn squash(
+    repository: &gb_repository::Repository,
+    from: &SessionId,
+    to: &SessionId,
+) -> Result<Session, SquashError> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let chain = history::chain(git_repository).context("failed to read sessions history")?;
+
+    let position = |id: &SessionId| {
+        chain
+            .iter()
+            .position(|(_, session)| session.as_ref().map(|session| &session.id) == Some(id))
+            .ok_or(SquashError::SessionNotFound(*id))
+    };
+
+    // chain is ordered newest first
+    let from_position = position(from)?;
+    let to_position = position(to)?;
+    if from_position < to_position {
+        return Err(SquashError::InvalidRange {
+            from: *from,
+            to: *to,
+        });
+    }
+
+    let (from_commit, from_session) = &chain[from_position];
+    let (to_commit, to_session) = &chain[to_position];
+    let from_session = from_session.as_ref().unwrap();
+    let to_session = to_session.as_ref().unwrap();
+
+    if from_position == to_position {
+        return Ok(to_session.clone());
+    }
+
+    let tagged = history::tagged_sessions(git_repository)?;
+    if let Some(tagged_session) = chain[to_position..=from_position]
+        .iter()
+        .filter_map(|(_, session)| session.as_ref())
+        .find(|session| tagged.contains(&session.id))
+    {
+        return Err(SquashError::TaggedSession(tagged_session.id));
+    }
+
+    let squashed_tree = {
+        let to_tree = to_commit.tree().context("failed to get tree")?;
+        let start_blob = git_repository
+            .blob(from_session.meta.start_timestamp_ms.to_string().as_bytes())
+            .context("failed to write start timestamp")?;
+        let mut tree_builder = git_repository.treebuilder(Some(&to_tree));
+        tree_builder.upsert("session/meta/start", start_blob, git::FileMode::Blob);
+        tree_builder.write().context("failed to write tree")?
+    };
+
+    let base = from_commit.parent(0).context("failed to get parent")?;
+
+    let mut commits = vec![(to_commit, Some(squashed_tree))];
+    commits.extend(
+        chain[..to_position]
+            .iter()
+            .rev()
+            .map(|(commit, _)| (commit, None)),
+    );
+
+    let head_oid = history::replay(git_repository, Some(base), &commits)
+        .context("failed to replay sessions")?
+        .expect("at least one commit is replayed");
+
+    // find the id of the squashed commit in the new history
+    let mut squashed_commit = git_repository
+        .find_commit(head_oid)
+        .context("failed to find commit")?;
+    for _ in 0..to_position {
+        squashed_commit = squashed_commit.parent(0).context("failed to get parent")?;
+    }
+
+    history::update_current(
+        git_repository,
+        head_oid,
+        &format!("squash sessions {}..{}", from, to),
+    )?;
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        %from,
+        %to,
+        "squashed sessions"
+    );
+
+    Ok(Session {
+        id: to_session.id,
+        hash: Some(squashed_commit.id()),
+        meta: Meta {
+            start_timestamp_ms: from_session.meta.start_timestamp_ms,
+            ..to_session.meta.clone()
+        },
+    })
+}
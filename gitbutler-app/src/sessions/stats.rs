@@ -0,0 +1,88 @@
+use std::{collections::HashSet, path};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{gb_repository, git};
+
+use super::history;
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageStats {
+    // number of flushed sessions
+    pub session_count: usize,
+    // number of distinct blobs reachable from the session history
+    pub blob_count: usize,
+    // total size of those blobs, every blob is counted once no matter how many sessions have it
+    pub blob_bytes: u64,
+    // total size of large files in the lfs store
+    pub lfs_bytes: u64,
+}
+
+// reports how much space the session history takes
+pub fn storage_stats(repository: &gb_repository::Repository) -> Result<StorageStats> {
+    let _lock = repository.lock();
+
+    let git_repository = repository.git_repository();
+    let odb = <&git2::Repository>::from(git_repository)
+        .odb()
+        .context("failed to open object database")?;
+
+    let mut stats = StorageStats::default();
+    let mut seen = HashSet::new();
+    for (commit, session) in
+        history::chain(git_repository).context("failed to read session history")?
+    {
+        if session.is_some() {
+            stats.session_count += 1;
+        }
+
+        let mut walk_error = None;
+        commit.tree()?.walk(|_, entry| {
+            if !seen.insert(entry.id()) {
+                // shared with a session that was already visited
+                return git::TreeWalkResult::Skip;
+            }
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git::TreeWalkResult::Continue;
+            }
+            match odb.read_header(entry.id().into()) {
+                Ok((size, _)) => {
+                    stats.blob_count += 1;
+                    stats.blob_bytes += size as u64;
+                    git::TreeWalkResult::Continue
+                }
+                Err(error) => {
+                    walk_error = Some(error);
+                    git::TreeWalkResult::Stop
+                }
+            }
+        })?;
+        if let Some(error) = walk_error {
+            return Err(error).context("failed to read blob header");
+        }
+    }
+
+    stats.lfs_bytes =
+        lfs_bytes(repository.lfs_objects_dir()).context("failed to read lfs objects")?;
+
+    Ok(stats)
+}
+
+fn lfs_bytes(objects_dir: &path::Path) -> Result<u64> {
+    if !objects_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut bytes = 0;
+    for entry in std::fs::read_dir(objects_dir)
+        .with_context(|| format!("failed to read {}", objects_dir.display()))?
+    {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            bytes += metadata.len();
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,48 @@
+use std::path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{gb_repository, git};
+
+use super::{history, writer::STATUS_OUTPUT_PATH, SessionError, SessionId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusOutput {
+    pub command: String,
+    // none when the command didn't exit by itself, because it timed out or failed to start
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    // also tells why the command failed to start or to be waited for
+    pub stderr: String,
+}
+
+// returns what gitbutler.statusCommand printed when the session was captured, none when no
+// status command was set
+pub fn status_output(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Option<StatusOutput>, SessionError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+    match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(path::Path::new(STATUS_OUTPUT_PATH))
+    {
+        Ok(entry) => {
+            let blob = repository
+                .git_repository()
+                .find_blob(entry.id())
+                .context("failed to find blob")?;
+            let status_output =
+                serde_json::from_slice(blob.content()).context("failed to parse status output")?;
+            Ok(Some(status_output))
+        }
+        Err(git::Error::NotFound(_)) => Ok(None),
+        Err(error) => Err(anyhow::Error::from(error).into()),
+    }
+}
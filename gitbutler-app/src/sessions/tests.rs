@@ -1,8 +1,14 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path,
+};
+
 use anyhow::Result;
 
 use crate::{
+    deltas, git, lfs, reader,
     sessions::{self, session::SessionId},
-    test_utils::{Case, Suite},
+    test_utils::{self, Case, SessionBuilder, Suite},
 };
 
 use super::Writer;
@@ -15,10 +21,15 @@ fn test_should_not_write_session_with_hash() {
         id: SessionId::generate(),
         hash: Some("08f23df1b9c2dec3d0c826a3ae745f9b821a1a26".parse().unwrap()),
         meta: sessions::Meta {
+            version: sessions::META_VERSION,
             start_timestamp_ms: 0,
             last_timestamp_ms: 1,
             branch: Some("branch".to_string()),
             commit: Some("commit".to_string()),
+            detached: false,
+            stashes: vec![],
+            sparse_checkout: None,
+            metadata: BTreeMap::new(),
         },
     };
 
@@ -36,10 +47,15 @@ fn test_should_write_full_session() -> Result<()> {
         id: SessionId::generate(),
         hash: None,
         meta: sessions::Meta {
+            version: sessions::META_VERSION,
             start_timestamp_ms: 0,
             last_timestamp_ms: 1,
             branch: Some("branch".to_string()),
             commit: Some("commit".to_string()),
+            detached: false,
+            stashes: vec![],
+            sparse_checkout: None,
+            metadata: BTreeMap::new(),
         },
     };
 
@@ -69,6 +85,37 @@ fn test_should_write_full_session() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_should_write_session_version() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    // sessions of an older version keep it when their meta is written again
+    let session = sessions::Session {
+        id: SessionId::generate(),
+        hash: None,
+        meta: sessions::Meta {
+            version: 1,
+            start_timestamp_ms: 0,
+            last_timestamp_ms: 1,
+            branch: None,
+            commit: None,
+            detached: false,
+            stashes: vec![],
+            sparse_checkout: None,
+            metadata: BTreeMap::new(),
+        },
+    };
+
+    Writer::new(&gb_repository)?.rewrite(&session)?;
+
+    assert_eq!(
+        std::fs::read_to_string(gb_repository.session_path().join("meta/version"))?,
+        "1"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_should_write_partial_session() -> Result<()> {
     let Case { gb_repository, .. } = Suite::default().new_case();
@@ -77,10 +124,15 @@ fn test_should_write_partial_session() -> Result<()> {
         id: SessionId::generate(),
         hash: None,
         meta: sessions::Meta {
+            version: sessions::META_VERSION,
             start_timestamp_ms: 0,
             last_timestamp_ms: 1,
             branch: None,
             commit: None,
+            detached: false,
+            stashes: vec![],
+            sparse_checkout: None,
+            metadata: BTreeMap::new(),
         },
     };
 
@@ -103,3 +155,1830 @@ fn test_should_write_partial_session() -> Result<()> {
 
     Ok(())
 }
+
+// returns flushed sessions as they are stored in the history, oldest first
+fn flush_sessions(case: &Case, count: usize) -> Result<Vec<sessions::Session>> {
+    for _ in 0..count {
+        case.gb_repository.get_or_create_current_session()?;
+        case.gb_repository.flush(&case.project_repository, None)?;
+    }
+    let mut sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    sessions.reverse();
+    Ok(sessions)
+}
+
+#[test]
+fn test_squash() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 4)?;
+
+    let squashed = sessions::squash(&case.gb_repository, &flushed[1].id, &flushed[2].id)?;
+    assert_eq!(squashed.id, flushed[2].id);
+    assert_eq!(
+        squashed.meta.start_timestamp_ms,
+        flushed[1].meta.start_timestamp_ms
+    );
+    assert_eq!(
+        squashed.meta.last_timestamp_ms,
+        flushed[2].meta.last_timestamp_ms
+    );
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        sessions
+            .iter()
+            .map(|session| session.id)
+            .collect::<Vec<_>>(),
+        vec![flushed[3].id, flushed[2].id, flushed[0].id]
+    );
+    assert_eq!(sessions[0].meta, flushed[3].meta);
+    assert_eq!(sessions[1], squashed);
+    assert_eq!(sessions[2], flushed[0]);
+
+    Ok(())
+}
+
+#[test]
+fn test_squash_tagged_session() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 4)?;
+    let git_repository = case.gb_repository.git_repository();
+    git_repository.reference(
+        &"refs/tags/keep".parse()?,
+        flushed[1].hash.unwrap(),
+        false,
+        "tag session",
+    )?;
+
+    assert!(matches!(
+        sessions::squash(&case.gb_repository, &flushed[0].id, &flushed[2].id),
+        Err(sessions::SquashError::TaggedSession(id)) if id == flushed[1].id
+    ));
+    assert_eq!(
+        case.gb_repository
+            .get_sessions_iterator()?
+            .map(|session| session.map(|session| session.id))
+            .collect::<Result<Vec<_>>>()?,
+        vec![flushed[3].id, flushed[2].id, flushed[1].id, flushed[0].id]
+    );
+
+    // ranges next to the tagged session can be squashed
+    sessions::squash(&case.gb_repository, &flushed[2].id, &flushed[3].id)?;
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_squash_single_session() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 2)?;
+
+    let squashed = sessions::squash(&case.gb_repository, &flushed[0].id, &flushed[0].id)?;
+    assert_eq!(squashed, flushed[0]);
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_squash_invalid_range() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 2)?;
+
+    assert!(matches!(
+        sessions::squash(&case.gb_repository, &flushed[1].id, &flushed[0].id),
+        Err(sessions::SquashError::InvalidRange { .. })
+    ));
+    assert!(matches!(
+        sessions::squash(&case.gb_repository, &SessionId::generate(), &flushed[0].id),
+        Err(sessions::SquashError::SessionNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_sessions_by_count() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 4)?;
+
+    assert_eq!(
+        sessions::prune_sessions_by_count(&case.gb_repository, 2)?,
+        2
+    );
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].id, flushed[3].id);
+    assert_eq!(sessions[0].meta, flushed[3].meta);
+    assert_eq!(sessions[1].id, flushed[2].id);
+    assert_eq!(sessions[1].meta, flushed[2].meta);
+
+    // pruning again is a no-op
+    assert_eq!(
+        sessions::prune_sessions_by_count(&case.gb_repository, 2)?,
+        0
+    );
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_dry_run() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let oids = ["1", "2", "3"].map(|digit| digit.repeat(64));
+    let mut built = vec![];
+    for oid in [&oids[0], &oids[1], &oids[2], &oids[2]] {
+        built.push(
+            SessionBuilder::new(&gb_repository)
+                .wd_file("large.bin", &lfs::LfsPointer::new(oid, 5).to_string())
+                .build()?,
+        );
+    }
+    let objects_dir = gb_repository.lfs_objects_dir();
+    std::fs::create_dir_all(objects_dir)?;
+    for oid in &oids {
+        std::fs::write(objects_dir.join(oid), "large")?;
+    }
+
+    // the second session stays as the base of the kept ones, only the first object goes
+    let report = sessions::prune_sessions_by_count_dry_run(&gb_repository, 2)?;
+    assert_eq!(report.sessions, vec![built[1].id, built[0].id]);
+    assert_eq!(
+        report.commits,
+        vec![built[1].hash.unwrap(), built[0].hash.unwrap()]
+    );
+    assert_eq!(report.lfs_objects, vec![oids[0].clone()]);
+    assert_eq!(report.lfs_bytes, 5);
+
+    // nothing changed
+    assert_eq!(gb_repository.get_sessions_iterator()?.count(), 4);
+    assert!(objects_dir.join(&oids[0]).exists());
+
+    assert_eq!(sessions::prune_sessions_by_count(&gb_repository, 2)?, 2);
+    assert!(!objects_dir.join(&oids[0]).exists());
+    assert!(objects_dir.join(&oids[1]).exists());
+
+    assert_eq!(
+        sessions::prune_sessions_by_count_dry_run(&gb_repository, 2)?,
+        sessions::PruneReport::default()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_tagged_sessions() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 4)?;
+    case.gb_repository.git_repository().reference(
+        &"refs/tags/keep".parse()?,
+        flushed[0].hash.unwrap(),
+        false,
+        "tag session",
+    )?;
+
+    // the tagged session is kept on top of the budget
+    let report = sessions::prune_sessions_by_count_dry_run(&case.gb_repository, 1)?;
+    assert_eq!(report.sessions, vec![flushed[2].id, flushed[1].id]);
+
+    assert_eq!(
+        sessions::prune_sessions_by_count(&case.gb_repository, 1)?,
+        2
+    );
+    assert_eq!(
+        case.gb_repository
+            .get_sessions_iterator()?
+            .map(|session| session.map(|session| session.id))
+            .collect::<Result<Vec<_>>>()?,
+        vec![flushed[3].id, flushed[0].id]
+    );
+
+    // and it doesn't count against it
+    assert_eq!(
+        sessions::prune_sessions_by_count(&case.gb_repository, 1)?,
+        0
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_prune_all_sessions() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    flush_sessions(&case, 2)?;
+
+    assert_eq!(
+        sessions::prune_sessions_by_count(&case.gb_repository, 0)?,
+        2
+    );
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_recover() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let flushed = flush_sessions(&case, 3)?;
+
+    // nothing was lost
+    assert_eq!(sessions::recover(&case.gb_repository)?, 0);
+
+    case.gb_repository
+        .git_repository()
+        .find_reference(&"refs/heads/current".parse().unwrap())?
+        .delete()?;
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 0);
+
+    assert_eq!(sessions::recover(&case.gb_repository)?, 3);
+    let mut recovered = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    recovered.reverse();
+    assert_eq!(
+        recovered
+            .iter()
+            .map(|session| (session.id, session.meta.clone()))
+            .collect::<Vec<_>>(),
+        flushed
+            .iter()
+            .map(|session| (session.id, session.meta.clone()))
+            .collect::<Vec<_>>()
+    );
+
+    // sessions are flushed on top of the recovered history
+    case.gb_repository.get_or_create_current_session()?;
+    case.gb_repository.flush(&case.project_repository, None)?;
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 4);
+
+    Ok(())
+}
+
+#[test]
+fn test_session_builder() -> Result<()> {
+    let suite = Suite::default();
+    let Case { gb_repository, .. } = suite.new_case();
+
+    let delta = deltas::Delta {
+        operations: vec![deltas::Operation::Insert((0, "hello".to_string()))],
+        timestamp_ms: 1,
+    };
+    let first = SessionBuilder::new(&gb_repository)
+        .timestamps(1, 2)
+        .branch("refs/heads/master")
+        .wd_file("file.txt", "hello")
+        .deltas("file.txt", vec![delta.clone()])
+        .build()?;
+    let second = SessionBuilder::new(&gb_repository)
+        .timestamps(3, 4)
+        .wd_file("other.txt", "world")
+        .build()?;
+
+    let sessions = gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions, vec![second.clone(), first.clone()]);
+
+    let first_reader = sessions::Reader::open(&gb_repository, &first)?;
+    let first_deltas = deltas::Reader::new(&first_reader).read(None)?;
+    assert_eq!(first_deltas.len(), 1);
+    assert_eq!(first_deltas[&path::PathBuf::from("file.txt")], vec![delta]);
+
+    // wd of the second session is built on top of the first one
+    let second_commit = gb_repository
+        .git_repository()
+        .find_commit(second.hash.unwrap())?;
+    let second_tree = reader::Reader::from_commit(gb_repository.git_repository(), &second_commit)?;
+    assert_eq!(
+        second_tree.read("wd/file.txt")?,
+        reader::Content::UTF8("hello".to_string())
+    );
+    assert_eq!(
+        second_tree.read("wd/other.txt")?,
+        reader::Content::UTF8("world".to_string())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_session_metadata() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let session = case.gb_repository.get_or_create_current_session()?;
+    assert!(session.meta.metadata.is_empty());
+
+    let writer = Writer::new(&case.gb_repository)?;
+    writer.write_metadata("taskId", &serde_json::json!("GB-123"))?;
+    assert!(writer
+        .write_metadata("../escape", &serde_json::json!(1))
+        .is_err());
+
+    // integrations can also write directly into the session directory
+    let metadata_dir = case.gb_repository.session_path().join("meta/metadata");
+    std::fs::write(metadata_dir.join("activeFile"), r#"{"path":"src/main.rs"}"#)?;
+    std::fs::write(metadata_dir.join("broken"), "not json")?;
+
+    let flushed = case
+        .gb_repository
+        .flush(&case.project_repository, None)?
+        .unwrap();
+    assert_eq!(flushed.id, session.id);
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(
+        sessions[0].meta.metadata,
+        BTreeMap::from([
+            (
+                "activeFile".to_string(),
+                serde_json::json!({ "path": "src/main.rs" })
+            ),
+            ("taskId".to_string(), serde_json::json!("GB-123")),
+        ])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_live() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case_with_files(HashMap::from([
+        (path::PathBuf::from("modified.txt"), "before"),
+        (path::PathBuf::from("deleted.txt"), "deleted"),
+        (path::PathBuf::from("unchanged.txt"), "unchanged"),
+    ]));
+    flush_sessions(&case, 1)?;
+
+    assert!(sessions::diff_live(&case.gb_repository, &case.project_repository)?.is_empty());
+
+    let root = case.project_repository.root();
+    std::fs::write(root.join("modified.txt"), "after")?;
+    std::fs::remove_file(root.join("deleted.txt"))?;
+    std::fs::write(root.join("added.txt"), "added")?;
+
+    let changes = sessions::diff_live(&case.gb_repository, &case.project_repository)?;
+    assert_eq!(
+        changes,
+        HashMap::from([
+            (
+                path::PathBuf::from("modified.txt"),
+                git::diff::ChangeType::Modified
+            ),
+            (
+                path::PathBuf::from("deleted.txt"),
+                git::diff::ChangeType::Deleted
+            ),
+            (
+                path::PathBuf::from("added.txt"),
+                git::diff::ChangeType::Added
+            ),
+        ])
+    );
+
+    // nothing was committed
+    assert_eq!(case.gb_repository.get_sessions_iterator()?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_session_detached_head() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case();
+
+    let session = case.gb_repository.get_or_create_current_session()?;
+    assert_eq!(session.meta.branch, Some("refs/heads/master".to_string()));
+    assert!(!session.meta.detached);
+    case.gb_repository.flush(&case.project_repository, None)?;
+
+    let head = case.project_repository.get_head()?.peel_to_commit()?.id();
+    case.project_repository
+        .git_repository
+        .set_head_detached(head)?;
+
+    let session = case.gb_repository.get_or_create_current_session()?;
+    assert_eq!(session.meta.branch, None);
+    assert_eq!(session.meta.commit, Some(head.to_string()));
+    assert!(session.meta.detached);
+
+    let flushed = case
+        .gb_repository
+        .flush(&case.project_repository, None)?
+        .unwrap();
+    assert_eq!(flushed.id, session.id);
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].meta.branch, None);
+    assert!(sessions[0].meta.detached);
+    assert!(!sessions[1].meta.detached);
+
+    Ok(())
+}
+
+#[test]
+fn test_export_bundle() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    // sha256 of "large"
+    let oid = "d35c416a85b807e9b5384915d6ebb4a9f7352713efd89857b45a242f473728a9";
+    let session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "hello")
+        .wd_file("large.bin", &lfs::LfsPointer::new(oid, 5).to_string())
+        .build()?;
+    let lfs_objects_dir = gb_repository.git_repository().path().join("lfs/objects");
+    std::fs::create_dir_all(&lfs_objects_dir)?;
+    std::fs::write(lfs_objects_dir.join(oid), "large")?;
+
+    let mut bundle = vec![];
+    sessions::export_bundle(&gb_repository, &project_repository, &mut bundle, None)?;
+
+    let header_end = bundle
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .unwrap();
+    let header = std::str::from_utf8(&bundle[..header_end])?;
+    let mut lines = header.lines();
+    assert_eq!(lines.next(), Some("# v2 git bundle"));
+    let refs = lines
+        .map(|line| line.split_once(' ').unwrap())
+        .map(|(oid, refname)| (refname.to_string(), oid.parse::<git::Oid>().unwrap()))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(refs["refs/heads/current"], session.hash.unwrap());
+
+    // unpack the bundle into an empty repository
+    let restored = test_utils::empty_bare_repository();
+    let odb = <&git2::Repository>::from(&restored).odb()?;
+    let mut pack_writer = odb.packwriter()?;
+    std::io::Write::write_all(&mut pack_writer, &bundle[header_end + 2..])?;
+    pack_writer.commit()?;
+
+    let current = restored.find_commit(refs["refs/heads/current"])?;
+    let current = reader::Reader::from_commit(&restored, &current)?;
+    assert_eq!(
+        current.read("wd/file.txt")?,
+        reader::Content::UTF8("hello".to_string())
+    );
+
+    let lfs_commit = restored.find_commit(refs["refs/gitbutler/lfs"])?;
+    let lfs_tree = lfs_commit.tree()?;
+    let lfs_blob = restored.find_blob(lfs_tree.get_name(oid).unwrap().id())?;
+    assert_eq!(lfs_blob.content(), b"large");
+
+    Ok(())
+}
+
+#[test]
+fn test_export_bundle_anchored_sessions() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    project_repository
+        .git_repository
+        .config()?
+        .set_bool("gitbutler.anchorSessions", true)?;
+    let head = project_repository
+        .git_repository
+        .head()?
+        .peel_to_commit()?
+        .id();
+    let session = gb_repository.get_or_create_current_session()?;
+    gb_repository.flush_session(&project_repository, &session, None)?;
+    let session = gb_repository.get_or_create_current_session()?;
+    let session = gb_repository.flush_session(&project_repository, &session, None)?;
+
+    let mut bundle = vec![];
+    sessions::export_bundle(&gb_repository, &project_repository, &mut bundle, None)?;
+    let header_end = bundle
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .unwrap();
+
+    let restored = test_utils::empty_bare_repository();
+    let odb = <&git2::Repository>::from(&restored).odb()?;
+    let mut pack_writer = odb.packwriter()?;
+    std::io::Write::write_all(&mut pack_writer, &bundle[header_end + 2..])?;
+    pack_writer.commit()?;
+
+    // the project history is left out
+    assert!(restored.find_commit(head).is_err());
+    let commit = gb_repository
+        .git_repository()
+        .find_commit(session.hash.unwrap())?;
+    let header = std::str::from_utf8(&bundle[..header_end])?;
+    let current = header
+        .lines()
+        .find_map(|line| line.strip_suffix(" refs/heads/current"))
+        .unwrap()
+        .parse::<git::Oid>()?;
+    let current = restored.find_commit(current)?;
+    assert_eq!(current.parent_count(), 1);
+    assert_eq!(current.tree_id(), commit.tree_id());
+
+    Ok(())
+}
+
+#[test]
+fn test_export_bundle_decrypts_lfs_objects() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default().new_case();
+
+    let mut config = project_repository.git_repository.config()?;
+    config.set_str("gitbutler.lfsThreshold", "4")?;
+    config.set_str(
+        "gitbutler.lfsEncryptionKey",
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+    )?;
+    std::fs::write(project.path.join("large.bin"), "large")?;
+
+    let session = gb_repository.get_or_create_current_session()?;
+    let session = gb_repository.flush_session(&project_repository, &session, None)?;
+
+    // the object is encrypted at rest, and marked as such in the pointer
+    let oid = "d35c416a85b807e9b5384915d6ebb4a9f7352713efd89857b45a242f473728a9";
+    let commit = gb_repository
+        .git_repository()
+        .find_commit(session.hash.unwrap())?;
+    let commit_reader = reader::Reader::from_commit(gb_repository.git_repository(), &commit)?;
+    assert_eq!(
+        commit_reader.read("wd/large.bin")?,
+        reader::Content::UTF8(lfs::LfsPointer::new_encrypted(oid, 5).to_string())
+    );
+    let object = std::fs::read(
+        gb_repository
+            .git_repository()
+            .path()
+            .join("lfs/objects")
+            .join(oid),
+    )?;
+    assert!(!object.windows(5).any(|window| window == b"large"));
+
+    let mut bundle = vec![];
+    let mut last_progress = None;
+    sessions::export_bundle(
+        &gb_repository,
+        &project_repository,
+        &mut bundle,
+        Some(&mut |progress: sessions::Progress| {
+            last_progress = Some(progress);
+        }),
+    )?;
+    let last_progress = last_progress.unwrap();
+    assert_eq!(last_progress.done, last_progress.total);
+    assert_eq!(last_progress.bytes, bundle.len() as u64);
+
+    let header_end = bundle
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .unwrap();
+    let header = std::str::from_utf8(&bundle[..header_end])?;
+    let lfs_commit = header
+        .lines()
+        .find_map(|line| line.strip_suffix(" refs/gitbutler/lfs"))
+        .unwrap()
+        .parse::<git::Oid>()?;
+
+    let restored = test_utils::empty_bare_repository();
+    let odb = <&git2::Repository>::from(&restored).odb()?;
+    let mut pack_writer = odb.packwriter()?;
+    std::io::Write::write_all(&mut pack_writer, &bundle[header_end + 2..])?;
+    pack_writer.commit()?;
+
+    let lfs_tree = restored.find_commit(lfs_commit)?.tree()?;
+    let lfs_blob = restored.find_blob(lfs_tree.get_name(oid).unwrap().id())?;
+    assert_eq!(lfs_blob.content(), b"large");
+
+    Ok(())
+}
+
+#[test]
+fn test_start_and_touch() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    assert!(sessions::touch(&gb_repository)?.is_none());
+
+    let session = sessions::start(
+        &gb_repository,
+        sessions::Meta {
+            version: sessions::META_VERSION,
+            start_timestamp_ms: 1,
+            last_timestamp_ms: 1,
+            branch: Some("refs/heads/master".to_string()),
+            commit: None,
+            detached: false,
+            stashes: vec![],
+            sparse_checkout: None,
+            metadata: BTreeMap::from([("taskId".to_string(), serde_json::json!("GB-123"))]),
+        },
+    )?;
+    assert_eq!(session.meta.start_timestamp_ms, 1);
+    assert!(session.meta.last_timestamp_ms > 1);
+    assert_eq!(gb_repository.get_current_session()?, Some(session.clone()));
+
+    assert!(matches!(
+        sessions::start(&gb_repository, session.meta.clone()),
+        Err(sessions::StartError::AlreadyStarted(id)) if id == session.id
+    ));
+
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let touched = sessions::touch(&gb_repository)?.unwrap();
+    assert_eq!(touched.id, session.id);
+    assert!(touched.meta.last_timestamp_ms > session.meta.last_timestamp_ms);
+
+    let flushed = gb_repository.flush(&project_repository, None)?.unwrap();
+    assert_eq!(flushed.id, session.id);
+    assert_eq!(
+        flushed.meta.metadata,
+        BTreeMap::from([("taskId".to_string(), serde_json::json!("GB-123"))])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_session_stashes() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "one")]));
+
+    let session = case.gb_repository.get_or_create_current_session()?;
+    assert!(session.meta.stashes.is_empty());
+
+    std::fs::write(case.project.path.join("file.txt"), "two")?;
+    let mut project_git_repository = git2::Repository::open(&case.project.path)?;
+    let signature = git2::Signature::now("test", "test@example.com")?;
+    let stash = project_git_repository.stash_save(&signature, "work in progress", None)?;
+
+    case.gb_repository.flush(&case.project_repository, None)?;
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        sessions[0].meta.stashes,
+        vec![sessions::StashRef {
+            oid: stash.into(),
+            message: "On master: work in progress".to_string(),
+        }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_session_sparse_checkout() -> Result<()> {
+    let suite = Suite::default();
+    let case = suite.new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "one")]));
+
+    let session = case.gb_repository.get_or_create_current_session()?;
+    assert_eq!(session.meta.sparse_checkout, None);
+
+    let mut config = case.project_repository.git_repository.config()?;
+    config.set_bool("core.sparseCheckout", true)?;
+    config.set_bool("core.sparseCheckoutCone", true)?;
+    let info_path = case.project.path.join(".git/info");
+    std::fs::create_dir_all(&info_path)?;
+    std::fs::write(
+        info_path.join("sparse-checkout"),
+        "# cone\n/*\n!/*/\n/src/\n!/src/*/\n/src/app/\n",
+    )?;
+    std::fs::write(case.project.path.join("file.txt"), "two")?;
+
+    case.gb_repository.flush(&case.project_repository, None)?;
+
+    let sessions = case
+        .gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    let sparse_checkout = sessions[0].meta.sparse_checkout.clone().unwrap();
+    assert_eq!(
+        sparse_checkout,
+        sessions::SparseCheckout {
+            cone: true,
+            patterns: ["/*", "!/*/", "/src/", "!/src/*/", "/src/app/"]
+                .map(ToString::to_string)
+                .to_vec(),
+        }
+    );
+
+    for (file_path, included) in [
+        ("file.txt", true),
+        ("src/main.rs", true),
+        ("src/lib/mod.rs", false),
+        ("src/app/main.rs", true),
+        ("src/app/deep/main.rs", true),
+        ("docs/index.md", false),
+    ] {
+        assert_eq!(
+            sparse_checkout.includes(path::Path::new(file_path)),
+            included,
+            "{file_path}"
+        );
+    }
+
+    // without cone mode, patterns are not understood and nothing is known to be included
+    let sparse_checkout = sessions::SparseCheckout {
+        cone: false,
+        ..sparse_checkout
+    };
+    assert!(!sparse_checkout.includes(path::Path::new("src/main.rs")));
+
+    Ok(())
+}
+
+#[test]
+fn test_meta_versions() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    let session = gb_repository.get_or_create_current_session()?;
+    assert_eq!(session.meta.version, sessions::META_VERSION);
+    let meta_dir = gb_repository.root().join("session/meta");
+
+    // recorded before the version was introduced
+    std::fs::remove_file(meta_dir.join("version"))?;
+    let old = gb_repository.get_current_session()?.unwrap();
+    assert_eq!(old.meta.version, 1);
+    assert_eq!(old.meta.start_timestamp_ms, session.meta.start_timestamp_ms);
+    assert!(old.meta.stashes.is_empty());
+
+    // recorded by a newer version, with values this one doesn't know about
+    std::fs::write(meta_dir.join("version"), "3")?;
+    std::fs::write(meta_dir.join("hostname"), "laptop")?;
+    std::fs::write(
+        meta_dir.join("stashes"),
+        r#"[{"oid":"0123456789abcdef0123456789abcdef01234567","author":"test"}]"#,
+    )?;
+    let new = gb_repository.get_current_session()?.unwrap();
+    assert_eq!(new.meta.version, 3);
+    assert_eq!(new.meta.start_timestamp_ms, session.meta.start_timestamp_ms);
+    assert_eq!(
+        new.meta.stashes,
+        vec![sessions::StashRef {
+            oid: "0123456789abcdef0123456789abcdef01234567".parse()?,
+            message: String::new(),
+        }]
+    );
+
+    // and it's kept as is when flushed
+    let flushed = gb_repository.flush(&project_repository, None)?.unwrap();
+    let sessions = gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions[0].id, flushed.id);
+    assert_eq!(sessions[0].meta.version, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_session_thumbnail() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    let writer = Writer::new(&gb_repository)?;
+    assert!(writer.write_thumbnail(b"image").is_err());
+
+    let without = gb_repository.get_or_create_current_session()?;
+    let without = gb_repository.flush_session(&project_repository, &without, None)?;
+    assert_eq!(sessions::thumbnail(&gb_repository, &without.id)?, None);
+
+    let with = gb_repository.get_or_create_current_session()?;
+    let content = b"\x89PNG\r\n\x1a\nimage";
+    writer.write_thumbnail(content)?;
+    let with = gb_repository.flush_session(&project_repository, &with, None)?;
+
+    let oid = sessions::thumbnail(&gb_repository, &with.id)?.unwrap();
+    let blob = gb_repository.git_repository().find_blob(oid)?;
+    assert_eq!(blob.content(), content);
+
+    // the session is otherwise read as usual
+    let sessions = gb_repository
+        .get_sessions_iterator()?
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(sessions[0].id, with.id);
+
+    Ok(())
+}
+
+#[test]
+fn test_storage_stats() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let content = "a".repeat(10_000);
+    SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", &content)
+        .build()?;
+    let first = sessions::storage_stats(&gb_repository)?;
+    assert_eq!(first.session_count, 1);
+    assert!(first.blob_bytes >= 10_000);
+    assert_eq!(first.lfs_bytes, 0);
+
+    // the same file in another session is counted once
+    SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", &content)
+        .build()?;
+    let lfs_objects_dir = gb_repository.git_repository().path().join("lfs/objects");
+    std::fs::create_dir_all(&lfs_objects_dir)?;
+    std::fs::write(lfs_objects_dir.join("object"), "large")?;
+
+    let second = sessions::storage_stats(&gb_repository)?;
+    assert_eq!(second.session_count, 2);
+    assert!(second.blob_count > first.blob_count);
+    assert!(second.blob_bytes < first.blob_bytes + 10_000);
+    assert_eq!(second.lfs_bytes, 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_list_files() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    // sha256 of "large"
+    let oid = "d35c416a85b807e9b5384915d6ebb4a9f7352713efd89857b45a242f473728a9";
+    let session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "hello")
+        .wd_file(
+            "dir/large.bin",
+            &lfs::LfsPointer::new(oid, 5_000_000).to_string(),
+        )
+        .build()?;
+
+    let mut files = sessions::list_files(&gb_repository, &session.id)?;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        files,
+        vec![
+            (
+                path::PathBuf::from("dir/large.bin"),
+                sessions::FileEntry {
+                    mode: git::FileMode::Blob,
+                    size: 5_000_000,
+                    lfs: true,
+                }
+            ),
+            (
+                path::PathBuf::from("file.txt"),
+                sessions::FileEntry {
+                    mode: git::FileMode::Blob,
+                    size: 5,
+                    lfs: false,
+                }
+            ),
+        ]
+    );
+
+    assert!(matches!(
+        sessions::list_files(&gb_repository, &SessionId::generate()),
+        Err(sessions::SessionError::NoSession)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_latest() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    // only the bootstrap commit
+    assert_eq!(sessions::latest(&gb_repository)?, None);
+
+    SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "first")
+        .build()?;
+    let second = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "second")
+        .build()?;
+
+    let latest = sessions::latest(&gb_repository)?.unwrap();
+    assert_eq!(latest.id, second.id);
+    assert_eq!(latest.hash, second.hash);
+
+    Ok(())
+}
+
+#[test]
+fn test_change_counts() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let first = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "first")
+        .build()?;
+    let second = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "second")
+        .wd_file("other.txt", "other")
+        .build()?;
+    let third = SessionBuilder::new(&gb_repository).build()?;
+
+    let counts = sessions::change_counts(&gb_repository)?;
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts[0], (third.id, 0));
+    assert_eq!(counts[1], (second.id, 2));
+    assert_eq!(counts[2].0, first.id);
+
+    // counts are cached by commit
+    assert!(gb_repository
+        .git_repository()
+        .path()
+        .join("change-counts")
+        .exists());
+    assert_eq!(sessions::change_counts(&gb_repository)?, counts);
+
+    Ok(())
+}
+
+#[test]
+fn test_blob_reuse() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let first = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "first")
+        .wd_file("unchanged.txt", "0123456789")
+        .build()?;
+    let second = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "second")
+        .build()?;
+
+    let reuse = sessions::blob_reuse(&gb_repository)?;
+    assert_eq!(reuse.len(), 2);
+    assert_eq!(reuse[0].0, second.id);
+    assert_eq!(reuse[1].0, first.id);
+
+    // the new id and the changed file, the unchanged file and the timestamps are the same as before
+    assert_eq!(reuse[0].1.new_blobs, 2);
+    assert_eq!(
+        reuse[0].1.new_bytes,
+        (second.id.to_string().len() + "second".len()) as u64
+    );
+    assert!(reuse[0].1.reused_bytes >= ("0123456789".len() + "0".len()) as u64);
+    assert!(reuse[1].1.new_bytes >= ("first".len() + "0123456789".len()) as u64);
+
+    // and cached by commit
+    assert!(gb_repository
+        .git_repository()
+        .path()
+        .join("blob-reuse")
+        .exists());
+    assert_eq!(sessions::blob_reuse(&gb_repository)?, reuse);
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_file() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+
+    // sha256 of "large"
+    let oid = "d35c416a85b807e9b5384915d6ebb4a9f7352713efd89857b45a242f473728a9";
+    let session = SessionBuilder::new(&gb_repository)
+        .wd_file("dir/file.txt", "hello")
+        .wd_file("large.bin", &lfs::LfsPointer::new(oid, 5).to_string())
+        .build()?;
+    let lfs_objects_dir = gb_repository.git_repository().path().join("lfs/objects");
+    std::fs::create_dir_all(&lfs_objects_dir)?;
+    std::fs::write(lfs_objects_dir.join(oid), "large")?;
+
+    let dest_dir = test_utils::temp_dir();
+    sessions::restore_file(
+        &gb_repository,
+        &project_repository,
+        &session.id,
+        path::Path::new("dir/file.txt"),
+        &dest_dir.join("restored/file.txt"),
+    )?;
+    assert_eq!(
+        std::fs::read_to_string(dest_dir.join("restored/file.txt"))?,
+        "hello"
+    );
+
+    sessions::restore_file(
+        &gb_repository,
+        &project_repository,
+        &session.id,
+        path::Path::new("large.bin"),
+        &dest_dir.join("large.bin"),
+    )?;
+    assert_eq!(std::fs::read(dest_dir.join("large.bin"))?, b"large");
+
+    assert!(matches!(
+        sessions::restore_file(
+            &gb_repository,
+            &project_repository,
+            &session.id,
+            path::Path::new("missing.txt"),
+            &dest_dir.join("missing.txt"),
+        ),
+        Err(sessions::RestoreFileError::PathNotFound(path)) if path == path::Path::new("missing.txt")
+    ));
+    assert!(matches!(
+        sessions::restore_file(
+            &gb_repository,
+            &project_repository,
+            &SessionId::generate(),
+            path::Path::new("dir/file.txt"),
+            &dest_dir.join("file.txt"),
+        ),
+        Err(sessions::RestoreFileError::SessionNotFound(_))
+    ));
+    // directories are not files
+    assert!(matches!(
+        sessions::restore_file(
+            &gb_repository,
+            &project_repository,
+            &session.id,
+            path::Path::new("dir"),
+            &dest_dir.join("dir"),
+        ),
+        Err(sessions::RestoreFileError::PathNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_empty_dirs() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default().new_case();
+
+    let project_path = path::Path::new(&project.path);
+    std::fs::create_dir_all(project_path.join("logs"))?;
+    std::fs::create_dir_all(project_path.join("tmp/cache"))?;
+
+    // not recorded by default
+    let session = gb_repository.get_or_create_current_session()?;
+    let unrecorded = gb_repository.flush_session(&project_repository, &session, None)?;
+
+    project_repository
+        .git_repository
+        .config()?
+        .set_bool("gitbutler.captureEmptyDirs", true)?;
+    let session = gb_repository.get_or_create_current_session()?;
+    let recorded = gb_repository.flush_session(&project_repository, &session, None)?;
+
+    std::fs::remove_dir_all(project_path.join("logs"))?;
+    std::fs::remove_dir_all(project_path.join("tmp"))?;
+
+    assert!(
+        sessions::restore_empty_dirs(&gb_repository, &project_repository, &unrecorded.id)?
+            .is_empty()
+    );
+    assert_eq!(
+        sessions::restore_empty_dirs(&gb_repository, &project_repository, &recorded.id)?,
+        vec![
+            path::PathBuf::from("logs"),
+            path::PathBuf::from("tmp/cache")
+        ]
+    );
+    assert!(project_path.join("logs").is_dir());
+    assert!(project_path.join("tmp/cache").is_dir());
+
+    // directories that exist are left alone
+    assert!(
+        sessions::restore_empty_dirs(&gb_repository, &project_repository, &recorded.id)?.is_empty()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_preview() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default()
+        .new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "hello")]));
+
+    let session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "old")
+        .build()?;
+    std::fs::create_dir_all(project.path.join("dir"))?;
+    std::fs::write(project.path.join("dir/new.txt"), "new")?;
+
+    let mut last_progress = None;
+    let token = sessions::checkout_preview(
+        &gb_repository,
+        &project_repository,
+        &session.id,
+        None,
+        Some(&mut |progress: sessions::Progress| {
+            last_progress = Some(progress);
+        }),
+    )?;
+    assert_eq!(token.session_id, session.id);
+    // dir/new.txt is removed and file.txt is written
+    let last_progress = last_progress.unwrap();
+    assert_eq!(last_progress.done, last_progress.total);
+    assert_eq!(last_progress.bytes, 3);
+    assert_eq!(
+        std::fs::read_to_string(project.path.join("file.txt"))?,
+        "old"
+    );
+    assert!(!project.path.join("dir").exists());
+
+    // the working directory was captured before it was replaced
+    let stashed = sessions::latest(&gb_repository)?.unwrap();
+    assert_eq!(stashed.id, token.stashed_session_id);
+
+    sessions::end_preview(&gb_repository, &project_repository, &token, None)?;
+    assert_eq!(
+        std::fs::read_to_string(project.path.join("file.txt"))?,
+        "hello"
+    );
+    assert_eq!(
+        std::fs::read_to_string(project.path.join("dir/new.txt"))?,
+        "new"
+    );
+
+    assert!(matches!(
+        sessions::checkout_preview(
+            &gb_repository,
+            &project_repository,
+            &SessionId::generate(),
+            None,
+            None
+        ),
+        Err(sessions::PreviewError::SessionNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_chunked_lfs_objects() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default().new_case();
+
+    let mut config = project_repository.git_repository.config()?;
+    config.set_str("gitbutler.lfsThreshold", "4")?;
+    config.set_bool("gitbutler.lfsChunking", true)?;
+    std::fs::write(project.path.join("large.bin"), "large")?;
+
+    let session = gb_repository.get_or_create_current_session()?;
+    let session = gb_repository.flush_session(&project_repository, &session, None)?;
+
+    // sha256 of "large"
+    let oid = "d35c416a85b807e9b5384915d6ebb4a9f7352713efd89857b45a242f473728a9";
+    let commit = gb_repository
+        .git_repository()
+        .find_commit(session.hash.unwrap())?;
+    let commit_reader = reader::Reader::from_commit(gb_repository.git_repository(), &commit)?;
+    assert_eq!(
+        commit_reader.read("wd/large.bin")?,
+        reader::Content::UTF8(lfs::LfsPointer::new_chunked(oid, 5).to_string())
+    );
+
+    let dest_dir = test_utils::temp_dir();
+    sessions::restore_file(
+        &gb_repository,
+        &project_repository,
+        &session.id,
+        path::Path::new("large.bin"),
+        &dest_dir.join("large.bin"),
+    )?;
+    assert_eq!(std::fs::read(dest_dir.join("large.bin"))?, b"large");
+
+    // the chunk is only referenced from the manifest
+    assert!(lfs::gc(&gb_repository)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_sessions() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let oid = |c: &str| c.repeat(64);
+    let old_session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "hello\n")
+        .wd_file(
+            "large.bin",
+            &lfs::LfsPointer::new(oid("a"), 120_000_000).to_string(),
+        )
+        .wd_file("same.bin", &lfs::LfsPointer::new(oid("b"), 10).to_string())
+        .build()?;
+    let new_session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "world\n")
+        .wd_file(
+            "large.bin",
+            &lfs::LfsPointer::new(oid("c"), 135_000_000).to_string(),
+        )
+        // only the pointer was rewritten, the content is the same
+        .wd_file(
+            "same.bin",
+            &lfs::LfsPointer::new_encrypted(oid("b"), 10).to_string(),
+        )
+        .wd_file(
+            "new.bin",
+            &lfs::LfsPointer::new(oid("d"), 2_000).to_string(),
+        )
+        .build()?;
+
+    let diffs = sessions::diff_sessions(
+        &gb_repository,
+        &old_session.id,
+        &new_session.id,
+        sessions::DiffScope::default(),
+    )?;
+    assert_eq!(
+        diffs.keys().collect::<Vec<_>>(),
+        vec![
+            path::Path::new("file.txt"),
+            path::Path::new("large.bin"),
+            path::Path::new("new.bin")
+        ]
+    );
+
+    assert!(matches!(
+        &diffs[path::Path::new("file.txt")],
+        sessions::FileDiff::Text {
+            change_type: git::diff::ChangeType::Modified,
+            patch,
+        } if patch.contains("-hello\n+world\n")
+    ));
+
+    assert_eq!(
+        diffs[path::Path::new("large.bin")],
+        sessions::FileDiff::Binary {
+            change_type: git::diff::ChangeType::Modified,
+            old_size: Some(120_000_000),
+            new_size: Some(135_000_000),
+        }
+    );
+    assert_eq!(
+        diffs[path::Path::new("large.bin")].to_string(),
+        "binary changed, 120.0 MB -> 135.0 MB"
+    );
+    assert_eq!(
+        diffs[path::Path::new("new.bin")].to_string(),
+        "binary added, 2.0 kB"
+    );
+
+    assert!(matches!(
+        sessions::diff_sessions(
+            &gb_repository,
+            &old_session.id,
+            &SessionId::generate(),
+            sessions::DiffScope::Wd,
+        ),
+        Err(sessions::SessionError::NoSession)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_sessions_scope() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let old_session = SessionBuilder::new(&gb_repository)
+        .timestamps(1, 2)
+        .wd_file("file.txt", "hello\n")
+        .build()?;
+    let new_session = SessionBuilder::new(&gb_repository)
+        .timestamps(1, 3)
+        .wd_file("file.txt", "world\n")
+        .build()?;
+    let diff = |scope| -> Result<Vec<path::PathBuf>> {
+        Ok(
+            sessions::diff_sessions(&gb_repository, &old_session.id, &new_session.id, scope)?
+                .into_keys()
+                .collect(),
+        )
+    };
+
+    assert_eq!(
+        diff(sessions::DiffScope::Wd)?,
+        vec![path::PathBuf::from("file.txt")]
+    );
+    let session = diff(sessions::DiffScope::Session)?;
+    assert!(session.contains(&path::PathBuf::from("session/meta/last")));
+    assert!(session.iter().all(|path| path.starts_with("session")));
+    let all = diff(sessions::DiffScope::All)?;
+    assert!(all.contains(&path::PathBuf::from("wd/file.txt")));
+    assert!(all.contains(&path::PathBuf::from("session/meta/last")));
+
+    Ok(())
+}
+
+#[test]
+fn test_materialize() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default()
+        .new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "hello")]));
+    let mut config = project_repository.git_repository.config()?;
+    config.set_str("user.name", "test")?;
+    config.set_str("user.email", "test@example.com")?;
+
+    let session = SessionBuilder::new(&gb_repository)
+        .wd_file("file.txt", "snapshot")
+        .wd_file("dir/new.txt", "new")
+        .build()?;
+
+    let options = sessions::MaterializeOptions {
+        message: Some("promote snapshot".to_string()),
+        author_name: Some("author".to_string()),
+        ..Default::default()
+    };
+    let oid = sessions::materialize(
+        &gb_repository,
+        &project_repository,
+        &session.id,
+        "snapshot",
+        &options,
+    )?;
+
+    let repository = git2::Repository::open(&project.path)?;
+    let commit = repository.find_commit(oid.into())?;
+    assert_eq!(
+        repository.refname_to_id("refs/heads/snapshot")?,
+        commit.id()
+    );
+    assert_eq!(commit.parent_id(0)?, repository.head()?.target().unwrap());
+    assert_eq!(commit.message(), Some("promote snapshot"));
+    assert_eq!(commit.author().name(), Some("author"));
+    assert_eq!(commit.author().email(), Some("test@example.com"));
+    assert_eq!(commit.committer().name(), Some("test"));
+    let tree = commit.tree()?;
+    let content = |file_path: &str| -> Result<String> {
+        let blob = repository.find_blob(tree.get_path(path::Path::new(file_path))?.id())?;
+        Ok(String::from_utf8(blob.content().to_vec())?)
+    };
+    assert_eq!(content("file.txt")?, "snapshot");
+    assert_eq!(content("dir/new.txt")?, "new");
+    // the working directory is left alone
+    assert_eq!(
+        std::fs::read_to_string(project.path.join("file.txt"))?,
+        "hello"
+    );
+
+    // the branch moved past HEAD, committing again would drop the commit
+    assert!(matches!(
+        sessions::materialize(
+            &gb_repository,
+            &project_repository,
+            &session.id,
+            "snapshot",
+            &options
+        ),
+        Err(sessions::MaterializeError::BranchNotAtHead(_))
+    ));
+    assert!(matches!(
+        sessions::materialize(
+            &gb_repository,
+            &project_repository,
+            &session.id,
+            "bad..name",
+            &options
+        ),
+        Err(sessions::MaterializeError::InvalidBranchName(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_restore_worktree() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default()
+        .new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "hello")]));
+    let repository = git2::Repository::open(&project.path)?;
+    let head = repository.head()?.target().unwrap();
+
+    let session = SessionBuilder::new(&gb_repository)
+        .commit(&head.to_string())
+        .wd_file("file.txt", "snapshot")
+        .wd_file("dir/new.txt", "new")
+        .build()?;
+
+    let path = test_utils::temp_dir().join("restored");
+    let oid = sessions::restore_worktree(&gb_repository, &project_repository, &session.id, &path)?;
+
+    // a detached worktree of the project, on top of the commit the session was captured on
+    let worktree = git2::Repository::open(&path)?;
+    assert!(worktree.is_worktree());
+    assert!(worktree.head_detached()?);
+    assert_eq!(worktree.head()?.target(), Some(oid.into()));
+    assert_eq!(repository.find_commit(oid.into())?.parent_id(0)?, head);
+    assert_eq!(std::fs::read_to_string(path.join("file.txt"))?, "snapshot");
+    assert_eq!(std::fs::read_to_string(path.join("dir/new.txt"))?, "new");
+    assert!(worktree.statuses(None)?.is_empty());
+
+    // no branches are left behind and the project is left alone
+    assert_eq!(
+        repository.branches(Some(git2::BranchType::Local))?.count(),
+        1
+    );
+    assert_eq!(
+        std::fs::read_to_string(project.path.join("file.txt"))?,
+        "hello"
+    );
+
+    assert!(matches!(
+        sessions::restore_worktree(&gb_repository, &project_repository, &session.id, &path),
+        Err(sessions::RestoreWorktreeError::PathExists(_))
+    ));
+    assert!(matches!(
+        sessions::restore_worktree(
+            &gb_repository,
+            &project_repository,
+            &SessionId::generate(),
+            &test_utils::temp_dir().join("missing")
+        ),
+        Err(sessions::RestoreWorktreeError::SessionNotFound(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_ignored_dirs() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default().new_case();
+
+    let project_path = path::Path::new(&project.path);
+    std::fs::write(project_path.join(".gitignore"), "target/\nnode_modules/\n")?;
+    std::fs::create_dir_all(project_path.join("target/debug"))?;
+    std::fs::write(project_path.join("target/debug/app"), "0123456789")?;
+    std::fs::write(project_path.join("target/.rustc_info.json"), "{}")?;
+    std::fs::create_dir_all(project_path.join("node_modules"))?;
+    std::fs::create_dir_all(project_path.join("src"))?;
+    std::fs::write(project_path.join("src/main.rs"), "fn main() {}")?;
+
+    // not recorded by default
+    let session = gb_repository.get_or_create_current_session()?;
+    let unrecorded = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert!(sessions::ignored_dirs(&gb_repository, &unrecorded.id)?.is_empty());
+
+    project_repository
+        .git_repository
+        .config()?
+        .set_bool("gitbutler.recordIgnoredDirs", true)?;
+    let session = gb_repository.get_or_create_current_session()?;
+    let recorded = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(
+        sessions::ignored_dirs(&gb_repository, &recorded.id)?,
+        vec![
+            sessions::IgnoredDir {
+                path: path::PathBuf::from("node_modules"),
+                size: 0,
+            },
+            sessions::IgnoredDir {
+                path: path::PathBuf::from("target"),
+                size: 12,
+            },
+        ]
+    );
+
+    // contents are not captured
+    let tree = gb_repository
+        .git_repository()
+        .find_commit(recorded.hash.unwrap())?
+        .tree()?;
+    assert!(tree.get_path(path::Path::new("wd/target")).is_err());
+
+    assert!(matches!(
+        sessions::ignored_dirs(&gb_repository, &SessionId::generate()),
+        Err(sessions::SessionError::NoSession)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_xattrs() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default()
+        .new_case_with_files(HashMap::from([(path::PathBuf::from("test.txt"), "hello")]));
+
+    let file_path = path::Path::new(&project.path).join("test.txt");
+    // not every file system has user attributes
+    if xattr::set(&file_path, "user.gitbutler", b"value").is_err() {
+        return Ok(());
+    }
+    let recorded = |session: &sessions::Session| -> Result<Option<Vec<u8>>> {
+        Ok(sessions::xattrs(&gb_repository, &session.id)?
+            .get(path::Path::new("test.txt"))
+            .and_then(|file_xattrs| file_xattrs.get("user.gitbutler"))
+            .cloned())
+    };
+
+    // not recorded by default
+    let session = gb_repository.get_or_create_current_session()?;
+    let unrecorded = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(recorded(&unrecorded)?, None);
+
+    project_repository
+        .git_repository
+        .config()?
+        .set_bool("gitbutler.captureXattrs", true)?;
+
+    // taken from the project file, the session copy has none
+    let session = gb_repository.get_or_create_current_session()?;
+    deltas::Writer::new(&gb_repository)?.write_wd_file(path::Path::new("test.txt"), "hello")?;
+    let first = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(recorded(&first)?, Some(b"value".to_vec()));
+
+    // and kept when the file is not captured again
+    let session = gb_repository.get_or_create_current_session()?;
+    let second = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(recorded(&second)?, Some(b"value".to_vec()));
+
+    xattr::remove(&file_path, "user.gitbutler")?;
+    assert!(
+        sessions::restore_xattrs(&gb_repository, &project_repository, &second.id)?
+            .contains(&path::PathBuf::from("test.txt"))
+    );
+    assert_eq!(
+        xattr::get(&file_path, "user.gitbutler")?,
+        Some(b"value".to_vec())
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_conflicted_files() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        project,
+        ..
+    } = Suite::default().new_case_with_files(HashMap::from([
+        (
+            path::PathBuf::from("conflicted.txt"),
+            "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> branch\n",
+        ),
+        (path::PathBuf::from("clean.txt"), "hello"),
+    ]));
+
+    let session = gb_repository.get_or_create_current_session()?;
+    deltas::Writer::new(&gb_repository)?.write_wd_file(path::Path::new("clean.txt"), "hello")?;
+    let first = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(
+        sessions::conflicted_files(&gb_repository, &first.id)?,
+        vec![path::PathBuf::from("conflicted.txt")]
+    );
+
+    // resolving the conflict clears it
+    std::fs::write(
+        path::Path::new(&project.path).join("conflicted.txt"),
+        "resolved\n",
+    )?;
+    let session = gb_repository.get_or_create_current_session()?;
+    deltas::Writer::new(&gb_repository)?
+        .write_wd_file(path::Path::new("conflicted.txt"), "resolved\n")?;
+    let second = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert!(sessions::conflicted_files(&gb_repository, &second.id)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn test_status_output() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default()
+        .new_case_with_files(HashMap::from([(path::PathBuf::from("file.txt"), "hello")]));
+
+    let session = gb_repository.get_or_create_current_session()?;
+    let flushed = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(sessions::status_output(&gb_repository, &flushed.id)?, None);
+
+    project_repository
+        .git_repository
+        .config()?
+        .set_str("gitbutler.statusCommand", "cat file.txt")?;
+    let session = gb_repository.get_or_create_current_session()?;
+    let flushed = gb_repository.flush_session(&project_repository, &session, None)?;
+    assert_eq!(
+        sessions::status_output(&gb_repository, &flushed.id)?,
+        Some(sessions::StatusOutput {
+            command: "cat file.txt".to_string(),
+            exit_code: Some(0),
+            timed_out: false,
+            stdout: "hello".to_string(),
+            stderr: String::new(),
+        })
+    );
+
+    // a command that fails or times out doesn't fail the flush
+    project_repository
+        .git_repository
+        .config()?
+        .set_str("gitbutler.statusCommand", "echo waiting; sleep 10")?;
+    project_repository
+        .git_repository
+        .config()?
+        .set_str("gitbutler.statusCommandTimeout", "0")?;
+    let session = gb_repository.get_or_create_current_session()?;
+    let flushed = gb_repository.flush_session(&project_repository, &session, None)?;
+    let status_output = sessions::status_output(&gb_repository, &flushed.id)?.unwrap();
+    assert!(status_output.timed_out);
+    assert_eq!(status_output.exit_code, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_audit_log() -> Result<()> {
+    let Case {
+        gb_repository,
+        project_repository,
+        ..
+    } = Suite::default().new_case();
+    project_repository
+        .git_repository
+        .config()?
+        .set_bool("gitbutler.auditLog", true)?;
+
+    let mut flushed = vec![];
+    for _ in 0..3 {
+        let session = gb_repository.get_or_create_current_session()?;
+        flushed.push(gb_repository.flush_session(&project_repository, &session, None)?);
+    }
+
+    let entries = sessions::verify_audit_log(&gb_repository)?;
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| (entry.session_id, Some(entry.commit)))
+            .collect::<Vec<_>>(),
+        flushed
+            .iter()
+            .map(|session| (session.id, session.hash))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(entries[0].previous, None);
+
+    // rewriting the history leaves the log alone
+    sessions::squash(&gb_repository, &flushed[0].id, &flushed[1].id)?;
+    assert_eq!(sessions::verify_audit_log(&gb_repository)?, entries);
+
+    let audit_log_path = gb_repository.git_repository().path().join("audit.log");
+    let content = std::fs::read_to_string(&audit_log_path)?;
+    std::fs::write(
+        &audit_log_path,
+        content.replacen(
+            &flushed[0].id.to_string(),
+            &SessionId::generate().to_string(),
+            1,
+        ),
+    )?;
+    assert!(matches!(
+        sessions::verify_audit_log(&gb_repository),
+        Err(sessions::AuditLogError::BrokenChain(2))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_list_refs() -> Result<()> {
+    let Case { gb_repository, .. } = Suite::default().new_case();
+
+    let first = SessionBuilder::new(&gb_repository).build()?;
+    let second = SessionBuilder::new(&gb_repository).build()?;
+
+    let repository = <&git2::Repository>::from(gb_repository.git_repository());
+    let first_commit = repository.find_commit(first.hash.unwrap().into())?;
+    repository.reference(
+        "refs/gitbutler/branches/feature",
+        first_commit.id(),
+        false,
+        "test",
+    )?;
+    repository.tag(
+        "v1",
+        first_commit.as_object(),
+        &git2::Signature::now("test", "test@example.com")?,
+        "tagged",
+        false,
+    )?;
+
+    let refs = sessions::list_refs(&gb_repository, false)?;
+    assert_eq!(
+        refs.iter()
+            .map(|gb_ref| (gb_ref.name.as_str(), gb_ref.kind, gb_ref.target))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "refs/heads/current",
+                sessions::GbRefKind::Current,
+                second.hash.unwrap()
+            ),
+            (
+                "refs/gitbutler/branches/feature",
+                sessions::GbRefKind::Gitbutler,
+                first.hash.unwrap()
+            ),
+            (
+                "refs/tags/v1",
+                sessions::GbRefKind::Tag,
+                first.hash.unwrap()
+            ),
+        ]
+    );
+    assert!(refs.iter().all(|gb_ref| gb_ref.sessions.is_none()));
+
+    let counts = sessions::list_refs(&gb_repository, true)?
+        .into_iter()
+        .map(|gb_ref| gb_ref.sessions)
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![Some(2), Some(1), Some(1)]);
+
+    Ok(())
+}
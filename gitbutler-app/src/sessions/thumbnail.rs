@@ -0,0 +1,24 @@
+use std::path;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{gb_repository, git};
+
+use super::{history, writer::THUMBNAIL_PATH, SessionId};
+
+// returns the id of the blob of the thumbnail attached to a flushed session, or None if it has
+// no thumbnail. the blob is in the gitbutler repository.
+pub fn thumbnail(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Option<git::Oid>> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or_else(|| anyhow!("session {} not found", session_id))?;
+    let tree = commit.tree().context("failed to get session tree")?;
+    match tree.get_path(path::Path::new(THUMBNAIL_PATH)) {
+        Ok(entry) => Ok(Some(entry.id())),
+        Err(git::Error::NotFound(_)) => Ok(None),
+        Err(error) => Err(error).context("failed to find thumbnail"),
+    }
+}
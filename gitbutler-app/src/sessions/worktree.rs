@@ -0,0 +1,176 @@
+use std::path;
+
+use anyhow::Context;
+
+use crate::{gb_repository, git, lfs, project_repository};
+
+use super::{history, materialize::copy_tree, SessionId};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreWorktreeError {
+    #[error("session {0} not found")]
+    SessionNotFound(SessionId),
+    #[error("{} already exists", .0.display())]
+    PathExists(path::PathBuf),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+// creates a linked worktree of the project at path, with the working directory of the session
+// checked out on a detached HEAD. the commit of the worktree has the commit the session was
+// captured on as its parent, when the project still has it, so that `git diff HEAD~` shows what
+// was not committed at the time. returns the id of that commit.
+//
+// no branch is created or moved. when anything fails, the worktree and the directory are removed
+// again.
+pub fn restore_worktree(
+    repository: &gb_repository::Repository,
+    project_repository: &project_repository::Repository,
+    session_id: &SessionId,
+    path: &path::Path,
+) -> Result<git::Oid, RestoreWorktreeError> {
+    if path.exists() {
+        return Err(RestoreWorktreeError::PathExists(path.to_path_buf()));
+    }
+
+    let (commit, session) = history::chain(repository.git_repository())
+        .context("failed to read session history")?
+        .into_iter()
+        .find_map(|(commit, session)| {
+            session
+                .filter(|session| session.id == *session_id)
+                .map(|session| (commit, session))
+        })
+        .ok_or(RestoreWorktreeError::SessionNotFound(*session_id))?;
+    let wd_tree = commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_name("wd")
+        .map(|entry| entry.id())
+        .context("session has no wd tree")?;
+
+    let project = <&git2::Repository>::from(&project_repository.git_repository);
+    let encryption_key = lfs::encryption_key(&project_repository.config())?;
+    let tree = copy_tree(repository, project, wd_tree.into(), encryption_key.as_ref())?;
+    let tree = project.find_tree(tree).context("failed to find tree")?;
+
+    // the commit might be gone, for example after a rebase and a gc
+    let parent = session
+        .meta
+        .commit
+        .as_deref()
+        .and_then(|commit| git2::Oid::from_str(commit).ok())
+        .and_then(|commit| project.find_commit(commit).ok());
+    let signature = project
+        .signature()
+        .or_else(|_| git2::Signature::now("gitbutler", "gitbutler@localhost"))
+        .context("failed to create signature")?;
+    let oid = project
+        .commit(
+            None,
+            &signature,
+            &signature,
+            &format!("gitbutler session {session_id}"),
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )
+        .context("failed to commit")?;
+
+    let name = worktree_name(project, session_id);
+    if let Err(error) = add_worktree(project, &name, path, oid) {
+        remove_worktree(project, &name, path);
+        return Err(error.into());
+    }
+
+    tracing::info!(
+        project_id = %repository.get_project_id(),
+        %session_id,
+        path = %path.display(),
+        %oid,
+        "restored session into a worktree"
+    );
+
+    Ok(oid.into())
+}
+
+// worktrees are named after the session, restoring the same session again gets a suffix
+fn worktree_name(project: &git2::Repository, session_id: &SessionId) -> String {
+    let is_taken = |name: &str| {
+        project.find_worktree(name).is_ok() || project.find_reference(&branch_refname(name)).is_ok()
+    };
+    let base = format!("gitbutler-{session_id}");
+    let mut name = base.clone();
+    let mut suffix = 1_usize;
+    while is_taken(&name) {
+        suffix += 1;
+        name = format!("{base}-{suffix}");
+    }
+    name
+}
+
+fn branch_refname(name: &str) -> String {
+    format!("refs/heads/{name}")
+}
+
+// libgit2 can only add worktrees on a branch. the worktree is added on a temporary one that is
+// deleted again once its HEAD is detached.
+fn add_worktree(
+    project: &git2::Repository,
+    name: &str,
+    path: &path::Path,
+    oid: git2::Oid,
+) -> anyhow::Result<()> {
+    let refname = branch_refname(name);
+    let mut reference = project
+        .reference(&refname, oid, false, "gitbutler: restore worktree")
+        .context("failed to create worktree branch")?;
+
+    let result = add_detached_worktree(project, name, path, &reference, oid);
+    reference
+        .delete()
+        .context("failed to delete worktree branch")?;
+    result
+}
+
+fn add_detached_worktree(
+    project: &git2::Repository,
+    name: &str,
+    path: &path::Path,
+    reference: &git2::Reference,
+    oid: git2::Oid,
+) -> anyhow::Result<()> {
+    let mut options = git2::WorktreeAddOptions::new();
+    options.reference(Some(reference));
+    let worktree = project
+        .worktree(name, path, Some(&options))
+        .context("failed to add worktree")?;
+    git2::Repository::open_from_worktree(&worktree)
+        .context("failed to open worktree")?
+        .set_head_detached(oid)
+        .context("failed to detach worktree HEAD")?;
+    Ok(())
+}
+
+// removes what was created of a worktree that failed to be added
+fn remove_worktree(project: &git2::Repository, name: &str, path: &path::Path) {
+    if let Ok(worktree) = project.find_worktree(name) {
+        if let Err(error) = worktree.prune(Some(
+            git2::WorktreePruneOptions::new()
+                .valid(true)
+                .locked(true)
+                .working_tree(true),
+        )) {
+            tracing::warn!(?error, name, "failed to prune worktree");
+        }
+    }
+    if let Ok(mut reference) = project.find_reference(&branch_refname(name)) {
+        if let Err(error) = reference.delete() {
+            tracing::warn!(?error, name, "failed to delete worktree branch");
+        }
+    }
+    if path.exists() {
+        if let Err(error) = std::fs::remove_dir_all(path) {
+            tracing::warn!(?error, path = %path.display(), "failed to remove worktree directory");
+        }
+    }
+}
@@ -1,10 +1,23 @@
-use std::time;
+use std::{path, time};
 
 use anyhow::{anyhow, Context, Result};
 
 use crate::{gb_repository, reader, writer};
 
-use super::Session;
+use super::{IgnoredDir, Session, SparseCheckout, StashRef, StatusOutput, Xattrs};
+
+// empty directories of the project are recorded here, git trees can't have them
+pub(super) const EMPTY_DIRS_PATH: &str = "session/meta/empty-dirs";
+// ignored directories of the project with their sizes, their contents are not captured
+pub(super) const IGNORED_DIRS_PATH: &str = "session/meta/ignored-dirs";
+// extended attributes of the captured files, git trees can't have them either
+pub(super) const XATTRS_PATH: &str = "session/meta/xattrs";
+// files that had merge conflict markers in them when the session was captured
+pub(super) const CONFLICTED_FILES_PATH: &str = "session/meta/conflicted-files";
+// what gitbutler.statusCommand printed when the session was captured
+pub(super) const STATUS_OUTPUT_PATH: &str = "session/meta/status-output";
+// an image an integration attached to the session, committed with the other session files
+pub(super) const THUMBNAIL_PATH: &str = "session/thumbnail.png";
 
 pub struct SessionWriter<'writer> {
     repository: &'writer gb_repository::Repository,
@@ -13,6 +26,12 @@ pub struct SessionWriter<'writer> {
 
 impl<'writer> SessionWriter<'writer> {
     pub fn new(repository: &'writer gb_repository::Repository) -> Result<Self, std::io::Error> {
+        if repository.is_read_only() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                gb_repository::Error::ReadOnly,
+            ));
+        }
         writer::DirWriter::open(repository.root())
             .map(|writer| SessionWriter { repository, writer })
     }
@@ -54,32 +73,49 @@ impl<'writer> SessionWriter<'writer> {
             ));
         }
 
-        let mut batch = vec![writer::BatchTask::Write(
-            "session/meta/last",
-            time::SystemTime::now()
-                .duration_since(time::SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-                .to_string(),
-        )];
-
         if current_session_id.is_some()
             && current_session_id.as_ref() == Some(&session.id.to_string())
         {
             self.writer
-                .batch(&batch)
+                .batch(&[self.last_timestamp()])
                 .context("failed to write last timestamp")?;
             return Ok(());
         }
 
-        batch.push(writer::BatchTask::Write(
-            "session/meta/id",
-            session.id.to_string(),
-        ));
-        batch.push(writer::BatchTask::Write(
-            "session/meta/start",
-            session.meta.start_timestamp_ms.to_string(),
-        ));
+        self.write_meta(session)
+    }
+
+    // writes all of the meta of the session again, no matter what is there. a crash while the
+    // meta was written can leave it unparseable, this is how it's repaired.
+    pub(crate) fn rewrite(&self, session: &Session) -> Result<()> {
+        if session.hash.is_some() {
+            return Err(anyhow!("can not open writer for a session with a hash"));
+        }
+        self.write_meta(session)
+    }
+
+    fn last_timestamp(&self) -> writer::BatchTask<&'static str, String> {
+        writer::BatchTask::Write(
+            "session/meta/last",
+            self.repository
+                .now()
+                .duration_since(time::SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
+                .to_string(),
+        )
+    }
+
+    fn write_meta(&self, session: &Session) -> Result<()> {
+        let mut batch = vec![
+            self.last_timestamp(),
+            writer::BatchTask::Write("session/meta/id", session.id.to_string()),
+            writer::BatchTask::Write(
+                "session/meta/start",
+                session.meta.start_timestamp_ms.to_string(),
+            ),
+            writer::BatchTask::Write("session/meta/version", session.meta.version.to_string()),
+        ];
 
         if let Some(branch) = session.meta.branch.as_ref() {
             batch.push(writer::BatchTask::Write(
@@ -99,10 +135,224 @@ impl<'writer> SessionWriter<'writer> {
             batch.push(writer::BatchTask::Remove("session/meta/commit"));
         }
 
+        if session.meta.detached {
+            batch.push(writer::BatchTask::Write(
+                "session/meta/detached",
+                "true".to_string(),
+            ));
+        } else {
+            batch.push(writer::BatchTask::Remove("session/meta/detached"));
+        }
+
+        if session.meta.stashes.is_empty() {
+            batch.push(writer::BatchTask::Remove("session/meta/stashes"));
+        } else {
+            batch.push(writer::BatchTask::Write(
+                "session/meta/stashes",
+                serde_json::to_string(&session.meta.stashes)
+                    .context("failed to serialize stashes")?,
+            ));
+        }
+
+        if let Some(sparse_checkout) = session.meta.sparse_checkout.as_ref() {
+            batch.push(writer::BatchTask::Write(
+                "session/meta/sparseCheckout",
+                serde_json::to_string(sparse_checkout)
+                    .context("failed to serialize sparse checkout")?,
+            ));
+        } else {
+            batch.push(writer::BatchTask::Remove("session/meta/sparseCheckout"));
+        }
+
         self.writer
             .batch(&batch)
             .context("failed to write session meta")?;
 
+        for (key, value) in &session.meta.metadata {
+            self.write_metadata(key, value)?;
+        }
+
+        Ok(())
+    }
+
+    // replaces the stash entries recorded for the current session
+    pub fn write_stashes(&self, stashes: &[StashRef]) -> Result<()> {
+        if stashes.is_empty() {
+            self.writer
+                .remove("session/meta/stashes")
+                .context("failed to remove stashes")?;
+        } else {
+            let stashes = serde_json::to_string(stashes).context("failed to serialize stashes")?;
+            self.writer
+                .write_string("session/meta/stashes", &stashes)
+                .context("failed to write stashes")?;
+        }
+        Ok(())
+    }
+
+    // replaces the sparse checkout recorded for the current session
+    pub fn write_sparse_checkout(&self, sparse_checkout: Option<&SparseCheckout>) -> Result<()> {
+        if let Some(sparse_checkout) = sparse_checkout {
+            let sparse_checkout = serde_json::to_string(sparse_checkout)
+                .context("failed to serialize sparse checkout")?;
+            self.writer
+                .write_string("session/meta/sparseCheckout", &sparse_checkout)
+                .context("failed to write sparse checkout")?;
+        } else {
+            self.writer
+                .remove("session/meta/sparseCheckout")
+                .context("failed to remove sparse checkout")?;
+        }
+        Ok(())
+    }
+
+    // replaces the empty directories recorded for the current session
+    pub fn write_empty_dirs(&self, empty_dirs: &[path::PathBuf]) -> Result<()> {
+        if empty_dirs.is_empty() {
+            self.writer
+                .remove(EMPTY_DIRS_PATH)
+                .context("failed to remove empty directories")?;
+        } else {
+            let empty_dirs = serde_json::to_string(empty_dirs)
+                .context("failed to serialize empty directories")?;
+            self.writer
+                .write_string(EMPTY_DIRS_PATH, &empty_dirs)
+                .context("failed to write empty directories")?;
+        }
+        Ok(())
+    }
+
+    // replaces the ignored directories recorded for the current session
+    pub fn write_ignored_dirs(&self, ignored_dirs: &[IgnoredDir]) -> Result<()> {
+        if ignored_dirs.is_empty() {
+            self.writer
+                .remove(IGNORED_DIRS_PATH)
+                .context("failed to remove ignored directories")?;
+        } else {
+            let ignored_dirs = serde_json::to_string(ignored_dirs)
+                .context("failed to serialize ignored directories")?;
+            self.writer
+                .write_string(IGNORED_DIRS_PATH, &ignored_dirs)
+                .context("failed to write ignored directories")?;
+        }
+        Ok(())
+    }
+
+    // replaces the extended attributes recorded for the current session
+    pub fn write_xattrs(&self, xattrs: &Xattrs) -> Result<()> {
+        if xattrs.is_empty() {
+            self.writer
+                .remove(XATTRS_PATH)
+                .context("failed to remove xattrs")?;
+        } else {
+            let xattrs = serde_json::to_string(xattrs).context("failed to serialize xattrs")?;
+            self.writer
+                .write_string(XATTRS_PATH, &xattrs)
+                .context("failed to write xattrs")?;
+        }
+        Ok(())
+    }
+
+    // replaces the conflicted files recorded for the current session
+    pub fn write_conflicted_files(&self, conflicted_files: &[path::PathBuf]) -> Result<()> {
+        if conflicted_files.is_empty() {
+            self.writer
+                .remove(CONFLICTED_FILES_PATH)
+                .context("failed to remove conflicted files")?;
+        } else {
+            let conflicted_files = serde_json::to_string(conflicted_files)
+                .context("failed to serialize conflicted files")?;
+            self.writer
+                .write_string(CONFLICTED_FILES_PATH, &conflicted_files)
+                .context("failed to write conflicted files")?;
+        }
+        Ok(())
+    }
+
+    // replaces the status command output recorded for the current session
+    pub fn write_status_output(&self, status_output: Option<&StatusOutput>) -> Result<()> {
+        match status_output {
+            Some(status_output) => {
+                let status_output = serde_json::to_string(status_output)
+                    .context("failed to serialize status output")?;
+                self.writer
+                    .write_string(STATUS_OUTPUT_PATH, &status_output)
+                    .context("failed to write status output")?;
+            }
+            None => {
+                self.writer
+                    .remove(STATUS_OUTPUT_PATH)
+                    .context("failed to remove status output")?;
+            }
+        }
+        Ok(())
+    }
+
+    // attaches a key/value pair to the current session. the value is committed together with the
+    // rest of the session meta when the session is flushed.
+    pub fn write_metadata(&self, key: &str, value: &serde_json::Value) -> Result<()> {
+        let path = metadata_path(key)?;
+
+        let reader = reader::Reader::open(&self.repository.root())
+            .context("failed to open current session reader")?;
+        if !reader.exists("session/meta/id")? {
+            return Err(anyhow!(
+                "{}: can not write metadata without a current session",
+                self.repository.get_project_id()
+            ));
+        }
+
+        let value = serde_json::to_string(value).context("failed to serialize metadata")?;
+        self.writer
+            .write_string(&path, &value)
+            .with_context(|| format!("failed to write metadata {}", key))?;
+
+        Ok(())
+    }
+
+    // attaches a thumbnail to the current session, for example a screenshot of a design tool.
+    // the content is stored as is, it is not checked to be an image.
+    pub fn write_thumbnail(&self, content: &[u8]) -> Result<()> {
+        let reader = reader::Reader::open(&self.repository.root())
+            .context("failed to open current session reader")?;
+        if !reader.exists("session/meta/id")? {
+            return Err(anyhow!(
+                "{}: can not write thumbnail without a current session",
+                self.repository.get_project_id()
+            ));
+        }
+
+        self.writer
+            .batch(&[writer::BatchTask::Write(THUMBNAIL_PATH, content)])
+            .context("failed to write thumbnail")?;
+
+        Ok(())
+    }
+
+    pub fn remove_thumbnail(&self) -> Result<()> {
+        self.writer
+            .remove(THUMBNAIL_PATH)
+            .context("failed to remove thumbnail")?;
         Ok(())
     }
+
+    pub fn remove_metadata(&self, key: &str) -> Result<()> {
+        let path = metadata_path(key)?;
+        self.writer
+            .remove(path)
+            .with_context(|| format!("failed to remove metadata {}", key))?;
+        Ok(())
+    }
+}
+
+// keys are used as file names, so they must not escape the metadata directory
+fn metadata_path(key: &str) -> Result<String> {
+    if key.is_empty()
+        || key == "."
+        || key == ".."
+        || key.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(anyhow!("invalid metadata key: {:?}", key));
+    }
+    Ok(format!("session/meta/metadata/{}", key))
 }
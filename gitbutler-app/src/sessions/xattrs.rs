@@ -0,0 +1,47 @@
+use std::{collections::BTreeMap, path};
+
+use anyhow::{Context, Result};
+
+use crate::{gb_repository, git};
+
+use super::{history, writer::XATTRS_PATH, SessionError, SessionId};
+
+// extended attributes of a file by name. acls are stored as the system.posix_acl_* attributes.
+pub type FileXattrs = BTreeMap<String, Vec<u8>>;
+
+// extended attributes of the captured files that have any, by path relative to the project root
+pub type Xattrs = BTreeMap<path::PathBuf, FileXattrs>;
+
+// returns the extended attributes that were recorded with the session, with
+// gitbutler.captureXattrs set. none are recorded otherwise.
+pub fn xattrs(
+    repository: &gb_repository::Repository,
+    session_id: &SessionId,
+) -> Result<Xattrs, SessionError> {
+    let commit = history::find_session_commit(repository.git_repository(), session_id)
+        .context("failed to read session history")?
+        .ok_or(SessionError::NoSession)?;
+    Ok(from_commit(repository.git_repository(), &commit)?)
+}
+
+pub(crate) fn from_commit(
+    git_repository: &git::Repository,
+    commit: &git::Commit,
+) -> Result<Xattrs> {
+    match commit
+        .tree()
+        .context("failed to get session tree")?
+        .get_path(path::Path::new(XATTRS_PATH))
+    {
+        Ok(entry) => {
+            let blob = git_repository
+                .find_blob(entry.id())
+                .context("failed to find blob")?;
+            let xattrs =
+                serde_json::from_slice(blob.content()).context("failed to parse xattrs")?;
+            Ok(xattrs)
+        }
+        Err(git::Error::NotFound(_)) => Ok(Xattrs::new()),
+        Err(error) => Err(error.into()),
+    }
+}
@@ -1,8 +1,15 @@
-use std::{collections::HashMap, fs, path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, path,
+};
 
 use tempfile::tempdir;
 
-use crate::{database, gb_repository, git, keys, project_repository, projects, storage, users};
+use crate::{
+    database, deltas, gb_repository, git, keys, project_repository, projects,
+    sessions::{self, SessionId},
+    storage, users,
+};
 
 pub struct Suite {
     pub local_app_data: path::PathBuf,
@@ -143,6 +150,19 @@ pub fn empty_bare_repository() -> git::Repository {
 }
 
 pub fn test_repository() -> git::Repository {
+    let repository = unconfigured_test_repository();
+    // test files are written right before they are flushed, they would never settle
+    repository
+        .config()
+        .expect("failed to open config")
+        .set_str("gitbutler.settleWindowMs", "0")
+        .expect("failed to disable settle window");
+    repository
+}
+
+// a repository with an initial commit, configured like a user's would be, without what other
+// tests rely on
+pub fn unconfigured_test_repository() -> git::Repository {
     let path = temp_dir();
     let repository = git::Repository::init(path).expect("failed to init repository");
     let mut index = repository.index().expect("failed to get index");
@@ -188,3 +208,147 @@ pub fn commit_all(repository: &git::Repository) -> git::Oid {
         .expect("failed to commit");
     commit_oid
 }
+
+// builds a flushed session commit with given contents on top of the gitbutler history, without
+// going through the watcher. wd files are added on top of the previous session's wd tree, the
+// same way flushing does.
+pub struct SessionBuilder<'a> {
+    gb_repository: &'a gb_repository::Repository,
+    id: SessionId,
+    start_timestamp_ms: u128,
+    last_timestamp_ms: u128,
+    branch: Option<String>,
+    commit: Option<String>,
+    metadata: BTreeMap<String, serde_json::Value>,
+    wd: HashMap<path::PathBuf, String>,
+    deltas: HashMap<path::PathBuf, Vec<deltas::Delta>>,
+}
+
+impl<'a> SessionBuilder<'a> {
+    pub fn new(gb_repository: &'a gb_repository::Repository) -> Self {
+        Self {
+            gb_repository,
+            id: SessionId::generate(),
+            start_timestamp_ms: 0,
+            last_timestamp_ms: 0,
+            branch: None,
+            commit: None,
+            metadata: BTreeMap::new(),
+            wd: HashMap::new(),
+            deltas: HashMap::new(),
+        }
+    }
+
+    pub fn timestamps(mut self, start_timestamp_ms: u128, last_timestamp_ms: u128) -> Self {
+        self.start_timestamp_ms = start_timestamp_ms;
+        self.last_timestamp_ms = last_timestamp_ms;
+        self
+    }
+
+    pub fn branch(mut self, branch: &str) -> Self {
+        self.branch = Some(branch.to_string());
+        self
+    }
+
+    pub fn commit(mut self, commit: &str) -> Self {
+        self.commit = Some(commit.to_string());
+        self
+    }
+
+    pub fn metadata(mut self, key: &str, value: serde_json::Value) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn wd_file<P: AsRef<path::Path>>(mut self, path: P, contents: &str) -> Self {
+        self.wd
+            .insert(path.as_ref().to_path_buf(), contents.to_string());
+        self
+    }
+
+    pub fn deltas<P: AsRef<path::Path>>(mut self, path: P, deltas: Vec<deltas::Delta>) -> Self {
+        self.deltas.insert(path.as_ref().to_path_buf(), deltas);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<sessions::Session> {
+        let git_repository = self.gb_repository.git_repository();
+        let refname: git::Refname = "refs/heads/current".parse().unwrap();
+
+        let head = git_repository.find_reference(&refname)?.peel_to_commit()?;
+        let head_tree = head.tree()?;
+
+        let mut session_tree_builder = git_repository.treebuilder(None);
+        let mut meta = vec![
+            ("id", self.id.to_string()),
+            ("version", sessions::META_VERSION.to_string()),
+            ("start", self.start_timestamp_ms.to_string()),
+            ("last", self.last_timestamp_ms.to_string()),
+        ];
+        if let Some(branch) = &self.branch {
+            meta.push(("branch", branch.clone()));
+        }
+        if let Some(commit) = &self.commit {
+            meta.push(("commit", commit.clone()));
+        }
+        for (name, value) in meta {
+            session_tree_builder.upsert(
+                path::Path::new("meta").join(name),
+                git_repository.blob(value.as_bytes())?,
+                git::FileMode::Blob,
+            );
+        }
+        for (key, value) in &self.metadata {
+            session_tree_builder.upsert(
+                path::Path::new("meta/metadata").join(key),
+                git_repository.blob(serde_json::to_string(value)?.as_bytes())?,
+                git::FileMode::Blob,
+            );
+        }
+        for (path, deltas) in &self.deltas {
+            session_tree_builder.upsert(
+                path::Path::new("deltas").join(path),
+                git_repository.blob(serde_json::to_string(deltas)?.as_bytes())?,
+                git::FileMode::Blob,
+            );
+        }
+        let session_tree_id = session_tree_builder.write()?;
+
+        let mut tree_builder = git_repository.treebuilder(Some(&head_tree));
+        tree_builder.upsert("session", session_tree_id, git::FileMode::Tree);
+        for (path, contents) in &self.wd {
+            tree_builder.upsert(
+                path::Path::new("wd").join(path),
+                git_repository.blob(contents.as_bytes())?,
+                git::FileMode::Blob,
+            );
+        }
+        let tree_id = tree_builder.write()?;
+
+        let signature = git::Signature::now("gitbutler", "gitbutler@localhost")?;
+        let commit_oid = git_repository.commit(
+            Some(&refname),
+            &signature,
+            &signature,
+            "gitbutler check",
+            &git_repository.find_tree(tree_id)?,
+            &[&head],
+        )?;
+
+        Ok(sessions::Session {
+            id: self.id,
+            hash: Some(commit_oid),
+            meta: sessions::Meta {
+                version: sessions::META_VERSION,
+                start_timestamp_ms: self.start_timestamp_ms,
+                last_timestamp_ms: self.last_timestamp_ms,
+                branch: self.branch,
+                commit: self.commit,
+                detached: false,
+                stashes: vec![],
+                sparse_checkout: None,
+                metadata: self.metadata,
+            },
+        })
+    }
+}
@@ -1,28 +1,41 @@
 mod dispatchers;
 mod events;
 mod handlers;
+mod workdir;
 
-use std::{collections::HashMap, path, sync::Arc, time};
+use std::{any, collections::HashMap, panic, path, sync::Arc, time};
 
-pub use events::Event;
+pub use events::{Event, SkipReason, WatchEvent};
+pub use handlers::{FlushStatus, SessionCommittedHook};
 
 use anyhow::{Context, Result};
 use tauri::{AppHandle, Manager};
 use tokio::{
     sync::{
-        mpsc::{unbounded_channel, UnboundedSender},
-        Mutex,
+        mpsc::{unbounded_channel, Receiver, UnboundedSender},
+        Mutex, Semaphore,
     },
     task,
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::projects::{self, ProjectId};
+use crate::{
+    events as app_events, gb_repository, lfs, logs, project_repository,
+    projects::{self, ProjectId},
+    users,
+};
+
+// how many events can be handled at the same time, across all watched projects. defaults to the
+// number of cpus.
+const WORKERS_ENV: &str = "GITBUTLER_WATCHER_WORKERS";
 
 #[derive(Clone)]
 pub struct Watchers {
     app_handle: AppHandle,
     watchers: Arc<Mutex<HashMap<ProjectId, Watcher>>>,
+    // handling an event blocks a thread, permits keep the number of those threads bounded no
+    // matter how many projects are watched
+    workers: Arc<Semaphore>,
 }
 
 impl TryFrom<&AppHandle> for Watchers {
@@ -44,11 +57,70 @@ impl Watchers {
         Self {
             app_handle,
             watchers: Arc::new(Mutex::new(HashMap::new())),
+            workers: Arc::new(Semaphore::new(worker_count())),
         }
     }
 
-    pub fn watch(&self, project: &projects::Project) -> Result<()> {
-        let watcher = Watcher::try_from(&self.app_handle)?;
+    // watches the project and emits what the watcher reports to the app
+    pub fn watch(&self, project: &projects::Project) -> Result<(), WatchError> {
+        let (tx, mut rx) = unbounded_channel::<WatchEvent>();
+        let sender = app_events::Sender::try_from(&self.app_handle)?;
+        task::Builder::new()
+            .name(&format!("{} watch events", project.id))
+            .spawn(async move {
+                // the channel is closed once the watcher is stopped
+                while let Some(event) = rx.recv().await {
+                    let Some(app_event) = event.app_event() else {
+                        continue;
+                    };
+                    if let Err(error) = sender.send(&app_event) {
+                        tracing::error!(
+                            project_id = %event.project_id(),
+                            ?error,
+                            "failed to emit event"
+                        );
+                    }
+                }
+            })
+            .context("failed to spawn watch events task")?;
+        self.watch_with_sender(project, tx)
+    }
+
+    // watches the project and sends what the watcher reports to tx, without emitting anything to
+    // the app. tx is dropped when the watcher stops.
+    //
+    // the watcher is still built from the app's state: its handlers, the projects and the user
+    // come from the app handle. only what it reports is decoupled from the app.
+    pub fn watch_with_sender(
+        &self,
+        project: &projects::Project,
+        tx: UnboundedSender<WatchEvent>,
+    ) -> Result<(), WatchError> {
+        // not fatal, if the project is gone, the watcher will stop by itself
+        if let Some(gb_repository) = self.open_gb_repository(project)? {
+            // nothing can be captured into a store that isn't writable, better to fail now than
+            // on every flush, or on the first large file
+            match gb_repository.check_objects_writable() {
+                Ok(()) => {}
+                Err(gb_repository::Error::ObjectsNotWritable(objects_dir)) => {
+                    return Err(WatchError::ReadOnlyRepository(objects_dir));
+                }
+                Err(error) => {
+                    return Err(anyhow::Error::from(error)
+                        .context("failed to check gitbutler repository")
+                        .into())
+                }
+            }
+            lfs::check_objects_dir(gb_repository.lfs_objects_dir())?;
+            // on a fresh project, first cycle must not fail on missing gitbutler directories
+            gb_repository
+                .ensure_scaffold()
+                .context("failed to prepare gitbutler repository")?;
+        } else {
+            tracing::warn!(project_id = %project.id, path = %project.path.display(), "project path not found");
+        }
+
+        let watcher = Watcher::new(&self.app_handle, Arc::clone(&self.workers), tx)?;
 
         let project_id = project.id;
         let project_path = project.path.clone();
@@ -74,11 +146,72 @@ impl Watchers {
                         }
                     }
                 }
-            })?;
+            })
+            .context("failed to spawn watcher")?;
+
+        Ok(())
+    }
+
+    // opens the gitbutler repository of the project. none if the project is gone.
+    fn open_gb_repository(
+        &self,
+        project: &projects::Project,
+    ) -> Result<Option<gb_repository::Repository>> {
+        if !project.path.exists() {
+            return Ok(None);
+        }
+        let local_data_dir = self
+            .app_handle
+            .path_resolver()
+            .app_data_dir()
+            .context("failed to get app data dir")?;
+        let user = users::Controller::try_from(&self.app_handle)?
+            .get_user()
+            .context("failed to get user")?;
+        let project_repository = project_repository::Repository::open(project)
+            .context("failed to open project repository")?;
+        match gb_repository::Repository::open(&local_data_dir, &project_repository, user.as_ref()) {
+            Ok(gb_repository) => Ok(Some(gb_repository)),
+            Err(gb_repository::Error::ProjectPathNotFound(_)) => Ok(None),
+            Err(error) => {
+                Err(anyhow::Error::from(error).context("failed to open gitbutler repository"))
+            }
+        }
+    }
+
+    // registers a hook that is called with every session the watchers commit, for any project.
+    //
+    // hooks run after the session is committed and the current session is removed, before the
+    // session is indexed or pushed. a failing hook is logged and doesn't affect the flush.
+    pub fn on_session_committed(&self, hook: SessionCommittedHook) -> Result<()> {
+        handlers::Handler::try_from(&self.app_handle)?.on_session_committed(hook);
+        Ok(())
+    }
 
+    // registers a provider of trailers for the commits of sessions the watchers flush, for any
+    // project. providers run when the session is committed, after the configured trailers are
+    // resolved.
+    pub fn add_trailer_provider(&self, provider: gb_repository::TrailerProvider) -> Result<()> {
+        handlers::Handler::try_from(&self.app_handle)?.add_trailer_provider(provider);
         Ok(())
     }
 
+    // reports whether the current session of the project would be flushed on the next tick,
+    // without flushing it
+    pub fn flush_status(&self, project_id: &ProjectId) -> Result<FlushStatus> {
+        handlers::Handler::try_from(&self.app_handle)?
+            .flush_status(project_id, &time::SystemTime::now())
+    }
+
+    // how often the last capture of the project could take files from the index cache instead
+    // of hashing them
+    pub fn capture_stats(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Option<gb_repository::CaptureStats>> {
+        Ok(handlers::Handler::try_from(&self.app_handle)?.capture_stats(project_id))
+    }
+
     pub async fn post(&self, event: Event) -> Result<()> {
         let watchers = self.watchers.lock().await;
         if let Some(watcher) = watchers.get(event.project_id()) {
@@ -101,14 +234,20 @@ struct Watcher {
     inner: Arc<WatcherInner>,
 }
 
-impl TryFrom<&AppHandle> for Watcher {
-    type Error = anyhow::Error;
+fn worker_count() -> usize {
+    std::env::var(WORKERS_ENV)
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or_else(num_cpus::get)
+}
 
-    fn try_from(value: &AppHandle) -> std::result::Result<Self, Self::Error> {
-        Ok(Self {
-            inner: Arc::new(WatcherInner::try_from(value)?),
-        })
-    }
+#[derive(Debug, thiserror::Error)]
+pub enum WatchError {
+    #[error("sessions can't be captured, {0} is read-only")]
+    ReadOnlyRepository(path::PathBuf),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -120,6 +259,16 @@ pub enum RunError {
 }
 
 impl Watcher {
+    fn new(
+        app_handle: &AppHandle,
+        workers: Arc<Semaphore>,
+        tx: UnboundedSender<WatchEvent>,
+    ) -> Result<Self> {
+        Ok(Self {
+            inner: Arc::new(WatcherInner::new(app_handle, workers, tx)?),
+        })
+    }
+
     pub fn stop(&self) {
         self.inner.stop();
     }
@@ -139,30 +288,37 @@ impl Watcher {
 
 struct WatcherInner {
     handler: handlers::Handler,
-    dispatcher: dispatchers::Dispatcher,
     cancellation_token: CancellationToken,
 
     proxy_tx: Arc<tokio::sync::Mutex<Option<UnboundedSender<Event>>>>,
+    workers: Arc<Semaphore>,
+    // whoever watches the project gets what the watcher reports through this
+    watch_tx: UnboundedSender<WatchEvent>,
 }
 
-impl TryFrom<&AppHandle> for WatcherInner {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &AppHandle) -> std::result::Result<Self, Self::Error> {
+impl WatcherInner {
+    fn new(
+        app_handle: &AppHandle,
+        workers: Arc<Semaphore>,
+        watch_tx: UnboundedSender<WatchEvent>,
+    ) -> Result<Self> {
         Ok(Self {
-            handler: handlers::Handler::try_from(value)?,
-            dispatcher: dispatchers::Dispatcher::new(),
+            handler: handlers::Handler::try_from(app_handle)?,
             cancellation_token: CancellationToken::new(),
             proxy_tx: Arc::new(tokio::sync::Mutex::new(None)),
+            workers,
+            watch_tx,
         })
     }
-}
 
-impl WatcherInner {
     pub fn stop(&self) {
         self.cancellation_token.cancel();
     }
 
+    fn report(&self, event: WatchEvent) {
+        report(&self.watch_tx, event);
+    }
+
     pub async fn post(&self, event: Event) -> Result<()> {
         let tx = self.proxy_tx.lock().await;
         if tx.is_some() {
@@ -181,37 +337,66 @@ impl WatcherInner {
         path: P,
         project_id: &ProjectId,
     ) -> Result<(), RunError> {
+        let path = path.as_ref();
         let (proxy_tx, mut proxy_rx) = unbounded_channel();
         self.proxy_tx.lock().await.replace(proxy_tx.clone());
 
-        let dispatcher = self.dispatcher.clone();
-        let mut dispatcher_rx = match dispatcher.run(project_id, path.as_ref()) {
-            Ok(dispatcher_rx) => Ok(dispatcher_rx),
-            Err(dispatchers::RunError::PathNotFound(path)) => Err(RunError::PathNotFound(path)),
-            Err(error) => Err(error).context("failed to run dispatcher")?,
-        }?;
+        let mut workdir = workdir::Workdir::new(path);
+        let (mut dispatcher, mut dispatcher_rx) = start_dispatcher(project_id, path)?;
 
         proxy_tx
             .send(Event::IndexAll(*project_id))
             .context("failed to send event")?;
 
         let handle_event = |event: &Event| -> Result<()> {
-            task::Builder::new()
-                .name(&format!("handle {}", event))
-                .spawn_blocking({
-                    let project_id = project_id.to_string();
-                    let handler = self.handler.clone();
-                    let tx = proxy_tx.clone();
-                    let event = event.clone();
-                    move || {
+            if let Some(watch_event) = WatchEvent::from_event(event) {
+                self.report(watch_event);
+            }
+            // sessions are indexed too, the rest is only reported
+            if matches!(
+                event,
+                Event::Emit(_) | Event::Skipped(_, _, _) | Event::SessionStarted(_, _)
+            ) {
+                return Ok(());
+            }
+
+            let handle = {
+                let watch_project_id = *project_id;
+                let project_id = project_id.to_string();
+                let handler = self.handler.clone();
+                let tx = proxy_tx.clone();
+                let watch_tx = self.watch_tx.clone();
+                let event = event.clone();
+                move || {
+                    let (panic_project_id, panic_event) = (project_id.clone(), event.clone());
+                    let panic_watch_tx = watch_tx.clone();
+                    // a panic must not stop the watcher. state that is kept between events is
+                    // either reloaded by the next event or reset when its lock turns out poisoned.
+                    let handled = panic::catch_unwind(panic::AssertUnwindSafe(|| {
                         futures::executor::block_on(async move {
-                            match handler.handle(&event, time::SystemTime::now()).await {
-                                Err(error) => tracing::error!(
-                                    project_id,
-                                    %event,
-                                    ?error,
-                                    "failed to handle event",
-                                ),
+                            let progress_tx = watch_tx.clone();
+                            let mut progress = |progress| {
+                                report(
+                                    &progress_tx,
+                                    WatchEvent::Progress(watch_project_id, progress),
+                                );
+                            };
+                            match handler
+                                .handle(&event, time::SystemTime::now(), Some(&mut progress))
+                                .await
+                            {
+                                Err(error) => {
+                                    tracing::error!(
+                                        project_id,
+                                        %event,
+                                        ?error,
+                                        "failed to handle event",
+                                    );
+                                    report(
+                                        &watch_tx,
+                                        WatchEvent::Error(watch_project_id, format!("{error:#}")),
+                                    );
+                                }
                                 Ok(events) => {
                                     for e in events {
                                         if let Err(error) = tx.send(e.clone()) {
@@ -222,7 +407,8 @@ impl WatcherInner {
                                                 "failed to post event",
                                             );
                                         } else {
-                                            tracing::debug!(
+                                            tracing::trace!(
+                                                target: logs::WATCHER,
                                                 project_id,
                                                 %event,
                                                 "sent response event",
@@ -231,7 +417,44 @@ impl WatcherInner {
                                     }
                                 }
                             }
-                        });
+                        })
+                    }));
+                    if let Err(payload) = handled {
+                        let panic = panic_message(payload.as_ref());
+                        tracing::error!(
+                            project_id = panic_project_id,
+                            event = %panic_event,
+                            panic,
+                            "panicked while handling event",
+                        );
+                        report(
+                            &panic_watch_tx,
+                            WatchEvent::Error(
+                                watch_project_id,
+                                format!("panicked while handling {panic_event}: {panic}"),
+                            ),
+                        );
+                    }
+                }
+            };
+
+            let name = format!("handle {}", event);
+            task::Builder::new()
+                .name(&format!("wait for worker to {}", name))
+                .spawn({
+                    let workers = Arc::clone(&self.workers);
+                    async move {
+                        // the semaphore is never closed
+                        let Ok(_permit) = workers.acquire_owned().await else {
+                            return;
+                        };
+                        let result = match task::Builder::new().name(&name).spawn_blocking(handle) {
+                            Ok(join_handle) => join_handle.await.map_err(anyhow::Error::from),
+                            Err(error) => Err(error.into()),
+                        };
+                        if let Err(error) = result {
+                            tracing::error!(?error, "failed to {}", name);
+                        }
                     }
                 })?;
             Ok(())
@@ -239,10 +462,29 @@ impl WatcherInner {
 
         loop {
             tokio::select! {
-                Some(event) = dispatcher_rx.recv() => handle_event(&event)?,
+                Some(event) = dispatcher_rx.recv() => match workdir.availability() {
+                    workdir::Availability::Available => handle_event(&event)?,
+                    workdir::Availability::Deleted => {
+                        dispatcher.stop();
+                        return Err(RunError::PathNotFound(path.to_path_buf()));
+                    }
+                    // every event would fail until the directory is back, capture is suspended
+                    // instead
+                    workdir::Availability::Unavailable => {
+                        dispatcher.stop();
+                        tracing::warn!(%project_id, path = %path.display(), "project directory is not available, watcher suspended");
+                        self.report(WatchEvent::Suspended(*project_id, path.to_path_buf()));
+                        let Some(resumed) = self.wait_for_workdir(project_id, &mut workdir).await? else {
+                            break;
+                        };
+                        (dispatcher, dispatcher_rx) = resumed;
+                        tracing::info!(%project_id, "project directory is available again, watcher resumed");
+                        self.report(WatchEvent::Resumed(*project_id));
+                    }
+                },
                 Some(event) = proxy_rx.recv() => handle_event(&event)?,
                 () = self.cancellation_token.cancelled() => {
-                    self.dispatcher.stop();
+                    dispatcher.stop();
                     break;
                 }
             }
@@ -250,4 +492,62 @@ impl WatcherInner {
 
         Ok(())
     }
+
+    // polls the workdir with backoff until capture can resume, and restarts the dispatcher.
+    // returns None if the watcher is stopped in the meantime.
+    async fn wait_for_workdir(
+        &self,
+        project_id: &ProjectId,
+        workdir: &mut workdir::Workdir,
+    ) -> Result<Option<(dispatchers::Dispatcher, Receiver<Event>)>, RunError> {
+        let mut backoff = workdir::Backoff::default();
+        loop {
+            tokio::select! {
+                () = self.cancellation_token.cancelled() => return Ok(None),
+                () = tokio::time::sleep(backoff.interval()) => {}
+            }
+            if workdir.availability() == workdir::Availability::Deleted {
+                return Err(RunError::PathNotFound(workdir.path().to_path_buf()));
+            }
+            if !workdir.is_ready() {
+                continue;
+            }
+            // the directory can go away again before the watcher is started
+            match start_dispatcher(project_id, workdir.path()) {
+                Ok(started) => return Ok(Some(started)),
+                Err(error) => {
+                    tracing::debug!(%project_id, ?error, "failed to resume watcher");
+                }
+            }
+        }
+    }
+}
+
+fn start_dispatcher(
+    project_id: &ProjectId,
+    path: &path::Path,
+) -> Result<(dispatchers::Dispatcher, Receiver<Event>), RunError> {
+    let dispatcher = dispatchers::Dispatcher::new();
+    match dispatcher.clone().run(project_id, path) {
+        Ok(dispatcher_rx) => Ok((dispatcher, dispatcher_rx)),
+        Err(dispatchers::RunError::PathNotFound(path)) => Err(RunError::PathNotFound(path)),
+        Err(error) => Err(anyhow::Error::from(error)
+            .context("failed to run dispatcher")
+            .into()),
+    }
+}
+
+// nobody might be listening anymore, that's fine
+fn report(watch_tx: &UnboundedSender<WatchEvent>, event: WatchEvent) {
+    if watch_tx.send(event).is_err() {
+        tracing::trace!(target: logs::WATCHER, "nobody watches, event dropped");
+    }
+}
+
+fn panic_message(payload: &(dyn any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
 }
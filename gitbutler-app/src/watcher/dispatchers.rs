@@ -11,10 +11,15 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::projects::ProjectId;
+use crate::{git, project_repository, projects::ProjectId};
 
 use super::events;
 
+const TICK_INTERVAL: time::Duration = time::Duration::from_secs(10);
+const MAX_TICK_INTERVAL: time::Duration = time::Duration::from_secs(5 * 60);
+// sessions are flushed at the latest an hour after they start, ticks must not be further apart
+const MAX_TICK_INTERVAL_LIMIT: time::Duration = time::Duration::from_secs(60 * 60);
+
 #[derive(Clone)]
 pub struct Dispatcher {
     tick_dispatcher: tick::Dispatcher,
@@ -42,6 +47,7 @@ impl Dispatcher {
     pub fn stop(&self) {
         self.tick_dispatcher.stop();
         self.file_change_dispatcher.stop();
+        self.cancellation_token.cancel();
     }
 
     pub fn run<P: AsRef<path::Path>>(
@@ -59,7 +65,8 @@ impl Dispatcher {
 
         let mut tick_rx = self
             .tick_dispatcher
-            .run(project_id, time::Duration::from_secs(10))
+            .clone()
+            .run(project_id, TICK_INTERVAL, max_tick_interval(path))
             .context("failed to run tick dispatcher")?;
 
         let (tx, rx) = channel(1);
@@ -78,6 +85,7 @@ impl Dispatcher {
                             }
                         }
                         Some(event) = file_change_rx.recv() => {
+                            self.tick_dispatcher.reset();
                             if let Err(error) = tx.send(event).await {
                                 tracing::error!(%project_id, ?error,"failed to send file change");
                             }
@@ -91,3 +99,18 @@ impl Dispatcher {
         Ok(rx)
     }
 }
+
+// how long can ticks be apart when project is idle, configured with gitbutler.maxPollInterval
+// (in seconds).
+fn max_tick_interval(project_path: &path::Path) -> time::Duration {
+    let max_poll_interval = git::Repository::open(project_path)
+        .and_then(|repository| project_repository::Config::from(&repository).max_poll_interval());
+    match max_poll_interval {
+        Ok(Some(max_poll_interval)) => max_poll_interval.min(MAX_TICK_INTERVAL_LIMIT),
+        Ok(None) => MAX_TICK_INTERVAL,
+        Err(error) => {
+            tracing::warn!(?error, "failed to read max poll interval, using default");
+            MAX_TICK_INTERVAL
+        }
+    }
+}
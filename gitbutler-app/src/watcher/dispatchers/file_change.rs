@@ -1,5 +1,5 @@
 use std::{
-    path,
+    fs, path,
     sync::{Arc, Mutex},
     time::Duration,
 };
@@ -56,27 +56,53 @@ impl Dispatcher {
             .with_max_elapsed_time(Some(std::time::Duration::from_secs(30)))
             .build();
 
-        backoff::retry(policy, || {
-            debouncer
-                .watcher()
-                .watch(path, notify::RecursiveMode::Recursive)
-                .map_err(|error| match error.kind {
-                    notify::ErrorKind::PathNotFound => {
-                        backoff::Error::permanent(RunError::PathNotFound(path.to_path_buf()))
-                    }
-                    notify::ErrorKind::Io(_) | notify::ErrorKind::InvalidConfig(_) => {
-                        backoff::Error::permanent(RunError::Other(error.into()))
-                    }
-                    _ => backoff::Error::transient(RunError::Other(error.into())),
-                })
-        })
-        .context("failed to start watcher")?;
-
         let repo = git::Repository::open(path).context(format!(
             "failed to open project repository: {}",
             path.display()
         ))?;
 
+        // the git directory is watched on its own when it's not inside of the project, for
+        // example in submodules or with a separate git dir
+        let mut watched_paths = vec![path.to_path_buf()];
+        if !repo.path().starts_with(path) {
+            watched_paths.push(repo.path().to_path_buf());
+        }
+
+        for watched_path in &watched_paths {
+            backoff::retry(policy.clone(), || {
+                debouncer
+                    .watcher()
+                    .watch(watched_path, notify::RecursiveMode::Recursive)
+                    .map_err(|error| match error.kind {
+                        notify::ErrorKind::PathNotFound => {
+                            backoff::Error::permanent(RunError::PathNotFound(watched_path.clone()))
+                        }
+                        notify::ErrorKind::Io(_) | notify::ErrorKind::InvalidConfig(_) => {
+                            backoff::Error::permanent(RunError::Other(error.into()))
+                        }
+                        _ => backoff::Error::transient(RunError::Other(error.into())),
+                    })
+            })
+            .context("failed to start watcher")?;
+        }
+
+        // changes to a symlinked logs/HEAD are made to the file it points to, which is watched on
+        // its own when it's somewhere else
+        let log_head = log_head_target(repo.path());
+        if let Some(log_head_dir) = log_head
+            .as_deref()
+            .and_then(path::Path::parent)
+            .filter(|dir| !watched_paths.iter().any(|watched| dir.starts_with(watched)))
+        {
+            debouncer
+                .watcher()
+                .watch(log_head_dir, notify::RecursiveMode::NonRecursive)
+                .context(format!(
+                    "failed to watch logs/HEAD target: {}",
+                    log_head_dir.display()
+                ))?;
+        }
+
         self.watcher.lock().unwrap().replace(debouncer);
 
         tracing::debug!(%project_id, "file watcher started");
@@ -94,8 +120,24 @@ impl Dispatcher {
                                 tracing::error!(?errors, "file watcher error");
                             }
                             Ok(events) => {
-                                let file_paths = events.into_iter().filter(|event| is_interesting_kind(event.kind)).flat_map(|event| event.paths.clone()).filter(|file| is_interesting_file(&repo, file));
-                                for file_path in file_paths {
+                                let file_paths = events.into_iter().filter_map(|event| change_kind(event.kind).map(|kind| (kind, event))).flat_map(|(kind, event)| event.paths.clone().into_iter().map(move |file_path| (file_path, kind))).filter(|(file, _)| is_interesting_file(&repo, log_head.as_deref(), file));
+                                for (file_path, kind) in file_paths {
+                                    if let Some(git_file_path) = git_file_path(repo.path(), log_head.as_deref(), &file_path) {
+                                        tracing::info!(
+                                            %project_id,
+                                            file_path = %git_file_path.display(),
+                                            "git file change",
+                                        );
+                                        let event = events::Event::GitFileChange(project_id, git_file_path);
+                                        if let Err(error) = block_on(tx.send(event)) {
+                                            tracing::error!(
+                                                %project_id,
+                                                ?error,
+                                                "failed to send file change event",
+                                            );
+                                        }
+                                        continue;
+                                    }
                                     match file_path.strip_prefix(&path) {
                                         Ok(relative_file_path) if relative_file_path.display().to_string().is_empty() => { /* noop */ }
                                         Ok(relative_file_path) => {
@@ -121,6 +163,7 @@ impl Dispatcher {
                                                 events::Event::ProjectFileChange(
                                                     project_id,
                                                     relative_file_path.to_path_buf(),
+                                                    resolve_kind(kind, &file_path),
                                                 )
                                             };
                                             if let Err(error) = block_on(tx.send(event)) {
@@ -148,18 +191,67 @@ impl Dispatcher {
     }
 }
 
-fn is_interesting_kind(kind: notify::EventKind) -> bool {
-    matches!(
-        kind,
-        notify::EventKind::Create(notify::event::CreateKind::File)
-            | notify::EventKind::Modify(notify::event::ModifyKind::Data(_))
-            | notify::EventKind::Modify(notify::event::ModifyKind::Name(_))
-            | notify::EventKind::Remove(notify::event::RemoveKind::File)
-    )
+// returns how a file was changed, or none if the event is not interesting
+fn change_kind(kind: notify::EventKind) -> Option<events::ChangeKind> {
+    match kind {
+        notify::EventKind::Create(notify::event::CreateKind::File) => {
+            Some(events::ChangeKind::Created)
+        }
+        notify::EventKind::Modify(
+            notify::event::ModifyKind::Data(_) | notify::event::ModifyKind::Name(_),
+        ) => Some(events::ChangeKind::Modified),
+        notify::EventKind::Remove(notify::event::RemoveKind::File) => {
+            Some(events::ChangeKind::Removed)
+        }
+        _ => None,
+    }
+}
+
+// a modified file might be gone already, for example the old path of a rename
+fn resolve_kind(kind: events::ChangeKind, file_path: &path::Path) -> events::ChangeKind {
+    match kind {
+        events::ChangeKind::Modified if !file_path.exists() => events::ChangeKind::Removed,
+        kind => kind,
+    }
+}
+
+// logs/HEAD can be a symlink, for example to keep reflogs on another disk. returns the file it
+// points to, if it is one.
+fn log_head_target(git_dir: &path::Path) -> Option<path::PathBuf> {
+    let log_head = git_dir.join("logs/HEAD");
+    let metadata = fs::symlink_metadata(&log_head).ok()?;
+    if metadata.file_type().is_symlink() {
+        fs::canonicalize(&log_head).ok()
+    } else {
+        None
+    }
 }
 
-fn is_interesting_file(git_repo: &git::Repository, file_path: &path::Path) -> bool {
-    if file_path.starts_with(git_repo.path()) {
+// returns the path of a changed file relative to the git directory, the file logs/HEAD points to
+// is reported as logs/HEAD
+fn git_file_path(
+    git_dir: &path::Path,
+    log_head: Option<&path::Path>,
+    file_path: &path::Path,
+) -> Option<path::PathBuf> {
+    if log_head == Some(file_path) {
+        Some(path::PathBuf::from("logs/HEAD"))
+    } else {
+        file_path
+            .strip_prefix(git_dir)
+            .ok()
+            .map(path::Path::to_path_buf)
+    }
+}
+
+fn is_interesting_file(
+    git_repo: &git::Repository,
+    log_head: Option<&path::Path>,
+    file_path: &path::Path,
+) -> bool {
+    if log_head == Some(file_path) {
+        true
+    } else if file_path.starts_with(git_repo.path()) {
         let check_file_path = file_path.strip_prefix(git_repo.path()).unwrap();
         check_file_path.ends_with("FETCH_HEAD")
             || check_file_path.eq(path::Path::new("logs/HEAD"))
@@ -170,3 +262,46 @@ fn is_interesting_file(git_repo: &git::Repository, file_path: &path::Path) -> bo
         !git_repo.is_path_ignored(file_path).unwrap_or(false)
     }
 }
+
+// symlinks are created the unix way
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use crate::test_utils;
+
+    use super::*;
+
+    #[test]
+    fn test_symlinked_log_head() -> Result<()> {
+        let repository = test_utils::test_repository();
+        let log_head = repository.path().join("logs/HEAD");
+        assert_eq!(log_head_target(repository.path()), None);
+
+        let target = test_utils::temp_dir().join("HEAD");
+        fs::write(&target, "")?;
+        if log_head.exists() {
+            fs::remove_file(&log_head)?;
+        }
+        fs::create_dir_all(log_head.parent().unwrap())?;
+        std::os::unix::fs::symlink(&target, &log_head)?;
+
+        let resolved = log_head_target(repository.path());
+        assert_eq!(resolved, Some(fs::canonicalize(&target)?));
+
+        let changed = resolved.as_deref().unwrap();
+        assert!(is_interesting_file(&repository, Some(changed), changed));
+        assert_eq!(
+            git_file_path(repository.path(), Some(changed), changed),
+            Some(path::PathBuf::from("logs/HEAD"))
+        );
+        assert_eq!(
+            git_file_path(
+                repository.path(),
+                Some(changed),
+                &repository.path().join("HEAD")
+            ),
+            Some(path::PathBuf::from("HEAD"))
+        );
+
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
-use std::time;
+use std::{sync::Arc, time};
 
 use anyhow::Context;
 use tokio::{
-    sync::mpsc::{channel, Receiver},
+    select,
+    sync::{
+        mpsc::{channel, Receiver},
+        Notify,
+    },
     task,
 };
 use tokio_util::sync::CancellationToken;
@@ -12,6 +16,7 @@ use crate::{projects::ProjectId, watcher::events};
 #[derive(Debug, Clone)]
 pub struct Dispatcher {
     cancellation_token: CancellationToken,
+    activity: Arc<Notify>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -24,6 +29,7 @@ impl Dispatcher {
     pub fn new() -> Self {
         Self {
             cancellation_token: CancellationToken::new(),
+            activity: Arc::new(Notify::new()),
         }
     }
 
@@ -31,13 +37,23 @@ impl Dispatcher {
         self.cancellation_token.cancel();
     }
 
+    // resets the tick interval back to the base interval
+    pub fn reset(&self) {
+        self.activity.notify_one();
+    }
+
+    // ticks every `interval` while there is activity. every tick without activity in between
+    // doubles the interval, up to `max_interval`.
+    //
+    // ticks keep coming even when idle, so that sessions still get flushed eventually.
     pub fn run(
         self,
         project_id: &ProjectId,
         interval: time::Duration,
+        max_interval: time::Duration,
     ) -> Result<Receiver<events::Event>, RunError> {
         let (tx, rx) = channel(1);
-        let mut ticker = tokio::time::interval(interval);
+        let max_interval = max_interval.max(interval);
 
         task::Builder::new()
             .name(&format!("{} ticker", project_id))
@@ -45,14 +61,26 @@ impl Dispatcher {
                 let project_id = *project_id;
                 async move {
                     tracing::debug!(%project_id, "ticker started");
+                    let mut current_interval = interval;
+                    let mut deadline = tokio::time::Instant::now() + current_interval;
                     loop {
-                        ticker.tick().await;
-                        if self.cancellation_token.is_cancelled() {
-                            break;
+                        select! {
+                            () = self.cancellation_token.cancelled() => {
+                                break;
+                            }
+                            () = self.activity.notified() => {
+                                // don't postpone the next tick, only make sure it happens soon
+                                current_interval = interval;
+                                deadline = deadline.min(tokio::time::Instant::now() + interval);
+                                continue;
+                            }
+                            () = tokio::time::sleep_until(deadline) => {}
                         }
                         if let Err(error) = tx.send(events::Event::Tick(project_id)).await {
                             tracing::error!(%project_id, ?error, "failed to send tick");
                         }
+                        current_interval = current_interval.saturating_mul(2).min(max_interval);
+                        deadline = tokio::time::Instant::now() + current_interval;
                     }
                     tracing::debug!(%project_id, "ticker stopped");
                 }
@@ -74,7 +102,11 @@ mod tests {
         let dispatcher = Dispatcher::new();
         let dispatcher2 = dispatcher.clone();
         let mut rx = dispatcher2
-            .run(&ProjectId::generate(), Duration::from_millis(10))
+            .run(
+                &ProjectId::generate(),
+                Duration::from_millis(10),
+                Duration::from_millis(10),
+            )
             .unwrap();
 
         tokio::spawn(async move {
@@ -91,4 +123,33 @@ mod tests {
 
         assert!(count >= 4_i32);
     }
+
+    #[tokio::test]
+    async fn test_ticker_backoff() {
+        let dispatcher = Dispatcher::new();
+        let dispatcher2 = dispatcher.clone();
+        let mut rx = dispatcher2
+            .run(
+                &ProjectId::generate(),
+                Duration::from_millis(10),
+                Duration::from_millis(80),
+            )
+            .unwrap();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            dispatcher.stop();
+        });
+
+        // ticks at 10, 30, 70, 150, 230 and 310ms. without backoff, there would be 30 of them.
+        let mut count = 0_i32;
+        while let Some(event) = rx.recv().await {
+            if let events::Event::Tick(_) = event {
+                count += 1_i32;
+            }
+        }
+
+        assert!(count >= 3_i32);
+        assert!(count < 10_i32);
+    }
 }
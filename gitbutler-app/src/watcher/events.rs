@@ -1,5 +1,7 @@
 use std::{fmt::Display, path};
 
+use serde::Serialize;
+
 use crate::{
     analytics, deltas, events,
     projects::ProjectId,
@@ -19,14 +21,19 @@ pub enum Event {
 
     GitFileChange(ProjectId, path::PathBuf),
 
-    ProjectFileChange(ProjectId, path::PathBuf),
+    ProjectFileChange(ProjectId, path::PathBuf, ChangeKind),
 
     Session(ProjectId, sessions::Session),
+    // a new current session was created for a change in the project
+    SessionStarted(ProjectId, sessions::Session),
     SessionFile((ProjectId, SessionId, path::PathBuf, Option<reader::Content>)),
     SessionDelta((ProjectId, SessionId, path::PathBuf, deltas::Delta)),
 
     IndexAll(ProjectId),
 
+    // the current session was not committed when it could have been
+    Skipped(ProjectId, SessionId, SkipReason),
+
     Emit(events::Event),
     Analytics(analytics::Event),
 
@@ -34,6 +41,58 @@ pub enum Event {
     CalculateDeltas(ProjectId, path::PathBuf),
 }
 
+// what the watcher of a project reports to whoever watches it, see Watchers::watch_with_sender
+#[derive(Debug, PartialEq, Clone)]
+pub enum WatchEvent {
+    // a session was committed to the history, here or on another device
+    SessionCommitted(ProjectId, sessions::Session),
+    // a new current session was started, on the first change after the last one was committed
+    SessionStarted(ProjectId, sessions::Session),
+    // the current session was written to, it is not committed yet
+    SessionUpdated(ProjectId, sessions::Session),
+    Skipped(ProjectId, SessionId, SkipReason),
+    // the project directory is not available, nothing is captured until it is back
+    Suspended(ProjectId, path::PathBuf),
+    Resumed(ProjectId),
+    // an event failed to be handled, or panicked while it was
+    Error(ProjectId, String),
+    // how far indexing the sessions of the project is, when the watcher is started
+    Progress(ProjectId, sessions::Progress),
+    // an event for the app's frontend, as it is emitted to it
+    App(events::Event),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+// why the current session was not committed when it could have been
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[serde(tag = "reason", rename_all = "camelCase")]
+pub enum SkipReason {
+    // nothing changed since the last session, the session was discarded
+    NoChanges,
+    // a git operation like a merge or a rebase is in progress in the project
+    OperationInProgress { operation: String },
+    // a project file was modified too recently, it might still be being written
+    FileSettling { path: path::PathBuf },
+    // a session file is still being written
+    SessionLocked { path: path::PathBuf },
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
 impl Event {
     pub fn project_id(&self) -> &ProjectId {
         match self {
@@ -45,14 +104,72 @@ impl Event {
             | Event::FetchProjectData(project_id)
             | Event::Flush(project_id, _)
             | Event::GitFileChange(project_id, _)
-            | Event::ProjectFileChange(project_id, _)
+            | Event::ProjectFileChange(project_id, _, _)
             | Event::Session(project_id, _)
+            | Event::SessionStarted(project_id, _)
             | Event::SessionFile((project_id, _, _, _))
             | Event::SessionDelta((project_id, _, _, _))
             | Event::CalculateVirtualBranches(project_id)
             | Event::CalculateDeltas(project_id, _)
             | Event::PushGitbutlerData(project_id)
-            | Event::PushProjectToGitbutler(project_id) => project_id,
+            | Event::PushProjectToGitbutler(project_id)
+            | Event::Skipped(project_id, _, _) => project_id,
+        }
+    }
+}
+
+impl WatchEvent {
+    // what an event of the watcher is reported as, if it is reported at all
+    pub(super) fn from_event(event: &Event) -> Option<Self> {
+        match event {
+            Event::Session(project_id, session) if session.hash.is_some() => {
+                Some(Self::SessionCommitted(*project_id, session.clone()))
+            }
+            Event::Session(project_id, session) => {
+                Some(Self::SessionUpdated(*project_id, session.clone()))
+            }
+            Event::SessionStarted(project_id, session) => {
+                Some(Self::SessionStarted(*project_id, session.clone()))
+            }
+            Event::Skipped(project_id, session_id, reason) => {
+                Some(Self::Skipped(*project_id, *session_id, reason.clone()))
+            }
+            Event::Emit(event) => Some(Self::App(event.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn project_id(&self) -> &ProjectId {
+        match self {
+            WatchEvent::App(event) => event.project_id(),
+            WatchEvent::SessionCommitted(project_id, _)
+            | WatchEvent::SessionStarted(project_id, _)
+            | WatchEvent::SessionUpdated(project_id, _)
+            | WatchEvent::Skipped(project_id, _, _)
+            | WatchEvent::Suspended(project_id, _)
+            | WatchEvent::Resumed(project_id)
+            | WatchEvent::Error(project_id, _)
+            | WatchEvent::Progress(project_id, _) => project_id,
+        }
+    }
+
+    // the event the app's frontend gets for this, if any. sessions are emitted once they are
+    // indexed, errors are only logged.
+    pub fn app_event(&self) -> Option<events::Event> {
+        match self {
+            WatchEvent::Skipped(project_id, session_id, reason) => Some(
+                events::Event::session_skipped(project_id, session_id, reason),
+            ),
+            WatchEvent::Suspended(project_id, path) => {
+                Some(events::Event::watcher_suspended(project_id, path))
+            }
+            WatchEvent::Resumed(project_id) => Some(events::Event::watcher_resumed(project_id)),
+            WatchEvent::App(event) => Some(event.clone()),
+            WatchEvent::SessionCommitted(_, _)
+            | WatchEvent::SessionStarted(_, _)
+            | WatchEvent::SessionUpdated(_, _)
+            | WatchEvent::Error(_, _)
+            | WatchEvent::Progress(_, _) => None,
         }
     }
 }
@@ -73,10 +190,19 @@ impl Display for Event {
             Event::GitFileChange(project_id, path) => {
                 write!(f, "GitFileChange({}, {})", project_id, path.display())
             }
-            Event::ProjectFileChange(project_id, path) => {
-                write!(f, "ProjectFileChange({}, {})", project_id, path.display())
+            Event::ProjectFileChange(project_id, path, kind) => {
+                write!(
+                    f,
+                    "ProjectFileChange({}, {}, {})",
+                    project_id,
+                    path.display(),
+                    kind.as_str()
+                )
             }
             Event::Session(pid, session) => write!(f, "Session({}, {})", pid, session.id),
+            Event::SessionStarted(pid, session) => {
+                write!(f, "SessionStarted({}, {})", pid, session.id)
+            }
             Event::SessionFile((pid, session_id, path, _)) => {
                 write!(f, "File({}, {}, {})", pid, session_id, path.display())
             }
@@ -97,6 +223,54 @@ impl Display for Event {
             Event::PushGitbutlerData(pid) => write!(f, "PushGitbutlerData({})", pid),
             Event::PushProjectToGitbutler(pid) => write!(f, "PushProjectToGitbutler({})", pid),
             Event::IndexAll(pid) => write!(f, "IndexAll({})", pid),
+            Event::Skipped(pid, session_id, _) => write!(f, "Skipped({}, {})", pid, session_id),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_events() {
+        let project_id = ProjectId::generate();
+        let session_id = SessionId::generate();
+
+        let skipped = WatchEvent::from_event(&Event::Skipped(
+            project_id,
+            session_id,
+            SkipReason::NoChanges,
+        ));
+        assert_eq!(
+            skipped,
+            Some(WatchEvent::Skipped(
+                project_id,
+                session_id,
+                SkipReason::NoChanges
+            ))
+        );
+        assert_eq!(
+            skipped.and_then(|event| event.app_event()),
+            Some(events::Event::session_skipped(
+                &project_id,
+                &session_id,
+                &SkipReason::NoChanges
+            ))
+        );
+
+        let emitted = events::Event::git_index(&project_id);
+        assert_eq!(
+            WatchEvent::from_event(&Event::Emit(emitted.clone()))
+                .and_then(|event| event.app_event()),
+            Some(emitted)
+        );
+
+        // internal events are not reported, errors are not emitted
+        assert_eq!(WatchEvent::from_event(&Event::Tick(project_id)), None);
+        assert_eq!(
+            WatchEvent::Error(project_id, "failed".to_string()).app_event(),
+            None
+        );
+    }
+}
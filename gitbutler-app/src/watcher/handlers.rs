@@ -6,6 +6,7 @@ mod fetch_project_data;
 mod flush_session;
 mod git_file_change;
 mod index_handler;
+mod project_file_change;
 mod push_gitbutler_data;
 mod push_project_to_gitbutler;
 mod tick_handler;
@@ -16,10 +17,13 @@ use anyhow::{Context, Result};
 use tauri::{AppHandle, Manager};
 use tracing::instrument;
 
-use crate::events as app_events;
+use crate::{events as app_events, gb_repository, projects::ProjectId, sessions};
 
 use super::events;
 
+pub use flush_session::SessionCommittedHook;
+pub use tick_handler::FlushStatus;
+
 #[derive(Clone)]
 pub struct Handler {
     git_file_change_handler: git_file_change::Handler,
@@ -33,8 +37,7 @@ pub struct Handler {
     push_project_to_gitbutler: push_project_to_gitbutler::Handler,
     calculate_vbranches_handler: caltulate_virtual_branches_handler::Handler,
     calculate_deltas_handler: calculate_deltas_handler::Handler,
-
-    events_sender: app_events::Sender,
+    project_file_change_handler: project_file_change::Handler,
 }
 
 impl TryFrom<&AppHandle> for Handler {
@@ -56,7 +59,6 @@ impl TryFrom<&AppHandle> for Handler {
                 push_project_to_gitbutler::Handler::try_from(value)?,
                 caltulate_virtual_branches_handler::Handler::try_from(value)?,
                 calculate_deltas_handler::Handler::try_from(value)?,
-                app_events::Sender::try_from(value)?,
             );
             value.manage(handler.clone());
             Ok(handler)
@@ -78,7 +80,6 @@ impl Handler {
         push_project_to_gitbutler: push_project_to_gitbutler::Handler,
         calculate_vbranches_handler: caltulate_virtual_branches_handler::Handler,
         calculate_deltas_handler: calculate_deltas_handler::Handler,
-        events_sender: app_events::Sender,
     ) -> Self {
         Self {
             git_file_change_handler,
@@ -92,21 +93,43 @@ impl Handler {
             push_project_to_gitbutler,
             calculate_vbranches_handler,
             calculate_deltas_handler,
-            events_sender,
+            project_file_change_handler: project_file_change::Handler::default(),
         }
     }
 
-    #[instrument(skip(self), fields(event = %event), level = "debug")]
+    pub fn on_session_committed(&self, hook: SessionCommittedHook) {
+        self.flush_session_handler.on_session_committed(hook);
+    }
+
+    pub fn add_trailer_provider(&self, provider: gb_repository::TrailerProvider) {
+        self.flush_session_handler.add_trailer_provider(provider);
+    }
+
+    pub fn capture_stats(&self, project_id: &ProjectId) -> Option<gb_repository::CaptureStats> {
+        self.flush_session_handler.capture_stats(project_id)
+    }
+
+    pub fn flush_status(
+        &self,
+        project_id: &ProjectId,
+        now: &time::SystemTime,
+    ) -> Result<FlushStatus> {
+        self.tick_handler.flush_status(project_id, now)
+    }
+
+    // progress is reported while the sessions of a project are indexed
+    #[instrument(skip(self, progress), fields(event = %event), level = "debug")]
     pub async fn handle(
         &self,
         event: &events::Event,
         now: time::SystemTime,
+        progress: Option<sessions::ProgressCallback<'_>>,
     ) -> Result<Vec<events::Event>> {
         match event {
-            events::Event::ProjectFileChange(project_id, path) => Ok(vec![
-                events::Event::CalculateDeltas(*project_id, path.clone()),
-                events::Event::CalculateVirtualBranches(*project_id),
-            ]),
+            events::Event::ProjectFileChange(project_id, path, kind) => self
+                .project_file_change_handler
+                .handle(project_id, path, *kind, now)
+                .context("failed to handle project file change event"),
 
             events::Event::GitFileChange(project_id, path) => self
                 .git_file_change_handler
@@ -182,12 +205,10 @@ impl Handler {
                     path.display()
                 )),
 
-            events::Event::Emit(event) => {
-                self.events_sender
-                    .send(event)
-                    .context("failed to send event")?;
-                Ok(vec![])
-            }
+            // reported by the watcher to whoever watches the project, there is nothing to handle
+            events::Event::Emit(_)
+            | events::Event::Skipped(_, _, _)
+            | events::Event::SessionStarted(_, _) => Ok(vec![]),
 
             events::Event::Analytics(event) => self
                 .analytics_handler
@@ -200,7 +221,7 @@ impl Handler {
                 .index_session(project_id, session)
                 .context("failed to index session"),
 
-            events::Event::IndexAll(project_id) => self.index_handler.reindex(project_id),
+            events::Event::IndexAll(project_id) => self.index_handler.reindex(project_id, progress),
         }
     }
 }
@@ -4,7 +4,7 @@ use anyhow::{Context, Result};
 use tauri::{AppHandle, Manager};
 
 use crate::{
-    deltas, gb_repository, project_repository,
+    deltas, gb_repository, logs, project_repository,
     projects::{self, ProjectId},
     reader, sessions, users,
 };
@@ -81,6 +81,51 @@ impl Handler {
         reader::Content::try_from(&full_path).map_err(Into::into)
     }
 
+    fn write_without_deltas(
+        gb_repository: &gb_repository::Repository,
+        project_id: &ProjectId,
+        current_session: &sessions::Session,
+        path: &path::Path,
+        text: &str,
+        latest_file_content: Option<reader::Content>,
+    ) -> Result<Vec<events::Event>> {
+        // the file might have been written to the session already
+        let session_file_path = gb_repository.session_wd_path().join(path);
+        let session_file_content = if session_file_path.exists() {
+            Some(
+                reader::Content::try_from(&session_file_path)
+                    .context("failed to read session file")?,
+            )
+        } else {
+            latest_file_content.clone()
+        };
+        if let Some(reader::Content::UTF8(session_text)) = &session_file_content {
+            if session_text == text {
+                tracing::trace!(
+                    target: logs::WATCHER,
+                    %project_id,
+                    path = %path.display(),
+                    "file didn't change, ignoring"
+                );
+                return Ok(vec![]);
+            }
+        }
+
+        deltas::Writer::new(gb_repository)
+            .context("failed to open deltas writer")?
+            .write_wd_file(path, text)?;
+
+        Ok(vec![
+            events::Event::SessionFile((
+                *project_id,
+                current_session.id,
+                path.to_path_buf(),
+                latest_file_content,
+            )),
+            events::Event::Session(*project_id, current_session.clone()),
+        ])
+    }
+
     pub fn handle<P: AsRef<std::path::Path>>(
         &self,
         path: P,
@@ -125,11 +170,42 @@ impl Handler {
             Err(err) => Err(err).context("failed to get file content")?,
         };
 
+        let started = gb_repository
+            .get_current_session()
+            .context("failed to get current session")?
+            .is_none();
         let current_session = gb_repository
             .get_or_create_current_session()
             .context("failed to get or create current session")?;
 
-        let current_session_reader = sessions::Reader::open(&gb_repository, &current_session)
+        let mut events = vec![];
+        if started {
+            events.push(events::Event::SessionStarted(
+                *project_id,
+                current_session.clone(),
+            ));
+        }
+        events.extend(Self::write_changes(
+            &gb_repository,
+            &project_repository,
+            project_id,
+            path,
+            &current_session,
+            current_wd_file_content,
+        )?);
+        Ok(events)
+    }
+
+    // writes the deltas and the session copy of the file, if it changed
+    fn write_changes(
+        gb_repository: &gb_repository::Repository,
+        project_repository: &project_repository::Repository,
+        project_id: &ProjectId,
+        path: &path::Path,
+        current_session: &sessions::Session,
+        current_wd_file_content: Option<reader::Content>,
+    ) -> Result<Vec<events::Event>> {
+        let current_session_reader = sessions::Reader::open(gb_repository, current_session)
             .context("failed to get session reader")?;
 
         let latest_file_content = match current_session_reader.file(path) {
@@ -138,6 +214,23 @@ impl Handler {
             Err(err) => Err(err).context("failed to get file content")?,
         };
 
+        // text files that are not allowlisted are captured as they are, without computing deltas
+        let settings = project_repository
+            .settings()
+            .context("failed to get project settings")?;
+        if let Some(reader::Content::UTF8(text)) = &current_wd_file_content {
+            if !settings.computes_deltas(path) {
+                return Self::write_without_deltas(
+                    gb_repository,
+                    project_id,
+                    current_session,
+                    path,
+                    text,
+                    latest_file_content,
+                );
+            }
+        }
+
         let deltas_reader = deltas::Reader::new(&current_session_reader);
         let current_deltas = deltas_reader
             .read_file(path)
@@ -156,7 +249,7 @@ impl Handler {
             let deltas = text_doc.get_deltas();
 
             let writer =
-                deltas::Writer::new(&gb_repository).context("failed to open deltas writer")?;
+                deltas::Writer::new(gb_repository).context("failed to open deltas writer")?;
             writer
                 .write(path, &deltas)
                 .context("failed to write deltas")?;
@@ -183,7 +276,12 @@ impl Handler {
                 )),
             ])
         } else {
-            tracing::debug!(%project_id, path = %path.display(), "no new deltas, ignoring");
+            tracing::trace!(
+                target: logs::WATCHER,
+                %project_id,
+                path = %path.display(),
+                "no new deltas, ignoring"
+            );
             Ok(vec![])
         }
     }
@@ -341,6 +439,33 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_register_reports_session_started_once() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            gb_repository,
+            project,
+            ..
+        } = suite.new_case();
+        let listener = Handler::try_from(&suite.local_app_data).unwrap();
+
+        std::fs::write(project.path.join("test.txt"), "test")?;
+        let events = listener.handle("test.txt", &project.id)?;
+        let session = gb_repository.get_current_session()?.unwrap();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            events::Event::SessionStarted(project_id, started) if *project_id == project.id && started.id == session.id
+        )));
+
+        std::fs::write(project.path.join("test.txt"), "test2")?;
+        let events = listener.handle("test.txt", &project.id)?;
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, events::Event::SessionStarted(_, _))));
+
+        Ok(())
+    }
+
     #[test]
     fn test_register_binfile() -> Result<()> {
         let suite = Suite::default();
@@ -373,6 +498,36 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_register_file_without_deltas() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            gb_repository,
+            project,
+            ..
+        } = suite.new_case();
+        let listener = Handler::try_from(&suite.local_app_data).unwrap();
+
+        std::fs::write(project.path.join("app.min.map"), "generated")?;
+
+        let events = listener.handle("app.min.map", &project.id)?;
+        assert_eq!(events.len(), 2);
+
+        let session = gb_repository.get_current_session()?.unwrap();
+        let session_reader = sessions::Reader::open(&gb_repository, &session)?;
+        let deltas_reader = deltas::Reader::new(&session_reader);
+        assert!(deltas_reader.read_file("app.min.map")?.is_none());
+        assert_eq!(
+            std::fs::read_to_string(gb_repository.session_wd_path().join("app.min.map"))?,
+            "generated"
+        );
+
+        // unchanged files are ignored
+        assert!(listener.handle("app.min.map", &project.id)?.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_register_empty_new_file() -> Result<()> {
         let suite = Suite::default();
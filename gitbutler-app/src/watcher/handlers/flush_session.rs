@@ -1,16 +1,33 @@
-use std::{path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path,
+    sync::{Arc, RwLock},
+};
 
 use anyhow::{Context, Result};
 use tauri::{AppHandle, Manager};
 use tokio::sync::Mutex;
 
-use crate::{gb_repository, project_repository, projects, projects::ProjectId, sessions, users};
+use crate::{
+    events as app_events, gb_repository, project_repository, projects, projects::ProjectId,
+    sessions, users,
+};
 
 use super::events;
 
+// called with every session the watcher commits
+pub type SessionCommittedHook =
+    Arc<dyn Fn(&ProjectId, &sessions::Session) -> Result<()> + Send + Sync>;
+
+// index cache use of the last capture, by project
+type CaptureStatsByProject = Arc<RwLock<HashMap<ProjectId, gb_repository::CaptureStats>>>;
+
 #[derive(Clone)]
 pub struct Handler {
     inner: Arc<Mutex<HandlerInner>>,
+    hooks: Arc<RwLock<Vec<SessionCommittedHook>>>,
+    trailer_providers: Arc<RwLock<Vec<gb_repository::TrailerProvider>>>,
+    capture_stats: CaptureStatsByProject,
 }
 
 impl TryFrom<&AppHandle> for Handler {
@@ -36,27 +53,106 @@ impl TryFrom<&AppHandle> for Handler {
 impl Handler {
     fn new(inner: HandlerInner) -> Handler {
         Handler {
+            capture_stats: Arc::clone(&inner.capture_stats),
             inner: Arc::new(Mutex::new(inner)),
+            hooks: Arc::new(RwLock::new(vec![])),
+            trailer_providers: Arc::new(RwLock::new(vec![])),
         }
     }
 
+    // how the index cache did during the last capture of the project, None if the project wasn't
+    // captured with the index cache enabled since the watcher started
+    pub fn capture_stats(&self, project_id: &ProjectId) -> Option<gb_repository::CaptureStats> {
+        self.capture_stats
+            .read()
+            .expect("capture stats lock poisoned")
+            .get(project_id)
+            .copied()
+    }
+
+    pub fn on_session_committed(&self, hook: SessionCommittedHook) {
+        self.hooks
+            .write()
+            .expect("session hooks lock poisoned")
+            .push(hook);
+    }
+
+    pub fn add_trailer_provider(&self, provider: gb_repository::TrailerProvider) {
+        self.trailer_providers
+            .write()
+            .expect("trailer providers lock poisoned")
+            .push(provider);
+    }
+
     pub fn handle(
         &self,
         project_id: &ProjectId,
         session: &sessions::Session,
     ) -> Result<Vec<events::Event>> {
-        if let Ok(inner) = self.inner.try_lock() {
-            inner.handle(project_id, session)
-        } else {
-            Ok(vec![])
+        let Ok(inner) = self.inner.try_lock() else {
+            return Ok(vec![]);
+        };
+        let trailer_providers = self
+            .trailer_providers
+            .read()
+            .expect("trailer providers lock poisoned")
+            .clone();
+        let (session, secret_files) = match inner.flush(project_id, session, trailer_providers)? {
+            Flushed::Committed(session, secret_files) => (session, secret_files),
+            Flushed::Skipped(reason) => {
+                return Ok(vec![events::Event::Skipped(
+                    *project_id,
+                    session.id,
+                    reason,
+                )])
+            }
+        };
+        drop(inner);
+
+        // by now the session is committed and the current session is removed
+        for hook in self
+            .hooks
+            .read()
+            .expect("session hooks lock poisoned")
+            .iter()
+        {
+            if let Err(error) = hook(project_id, &session) {
+                tracing::error!(
+                    %project_id,
+                    session_id = %session.id,
+                    ?error,
+                    "session committed hook failed"
+                );
+            }
+        }
+
+        let mut events = vec![
+            events::Event::Session(*project_id, session),
+            events::Event::PushGitbutlerData(*project_id),
+            events::Event::PushProjectToGitbutler(*project_id),
+        ];
+        if !secret_files.is_empty() {
+            events.push(events::Event::Emit(app_events::Event::secrets(
+                project_id,
+                &secret_files,
+            )));
         }
+        Ok(events)
     }
 }
 
+// what came out of flushing a session
+enum Flushed {
+    // the session was committed, together with the files in it that seem to have secrets
+    Committed(sessions::Session, Vec<path::PathBuf>),
+    Skipped(events::SkipReason),
+}
+
 struct HandlerInner {
     local_data_dir: path::PathBuf,
     project_store: projects::Controller,
     users: users::Controller,
+    capture_stats: CaptureStatsByProject,
 }
 
 impl HandlerInner {
@@ -69,14 +165,16 @@ impl HandlerInner {
             local_data_dir,
             project_store,
             users,
+            capture_stats: Arc::default(),
         }
     }
 
-    pub fn handle(
+    fn flush(
         &self,
         project_id: &ProjectId,
         session: &sessions::Session,
-    ) -> Result<Vec<events::Event>> {
+        trailer_providers: Vec<gb_repository::TrailerProvider>,
+    ) -> Result<Flushed> {
         let project = self
             .project_store
             .get(project_id)
@@ -85,21 +183,145 @@ impl HandlerInner {
         let user = self.users.get_user()?;
         let project_repository =
             project_repository::Repository::open(&project).context("failed to open repository")?;
-        let gb_repo = gb_repository::Repository::open(
+        let mut gb_repo = gb_repository::Repository::open(
             &self.local_data_dir,
             &project_repository,
             user.as_ref(),
         )
         .context("failed to open repository")?;
+        for provider in trailer_providers {
+            gb_repo.add_trailer_provider(provider);
+        }
 
-        let session = gb_repo
-            .flush_session(&project_repository, session, user.as_ref())
-            .context(format!("failed to flush session {}", session.id))?;
+        // a session that is the same as the last one, for example after a checkout that only
+        // bumped mtimes, is still committed unless gitbutler.skipUnchangedSessions is set
+        let skip_unchanged_sessions = project_repository
+            .config()
+            .skip_unchanged_sessions()
+            .context("failed to read gitbutler.skipUnchangedSessions")?;
+        let flushed = if skip_unchanged_sessions {
+            gb_repo.flush_session_if_changed(&project_repository, session, user.as_ref())
+        } else {
+            gb_repo
+                .flush_session(&project_repository, session, user.as_ref())
+                .map(Some)
+        };
+        self.record_capture_stats(project_id, gb_repo.take_capture_stats());
 
-        Ok(vec![
-            events::Event::Session(*project_id, session),
-            events::Event::PushGitbutlerData(*project_id),
-            events::Event::PushProjectToGitbutler(*project_id),
-        ])
+        let session = match flushed {
+            Ok(Some(session)) => session,
+            // the session was discarded, there is nothing to index or push
+            Ok(None) => return Ok(Flushed::Skipped(events::SkipReason::NoChanges)),
+            Err(error) => {
+                match error
+                    .chain()
+                    .find_map(|error| error.downcast_ref::<gb_repository::Error>())
+                {
+                    // the session writer is still busy with the session, try again on the next tick
+                    Some(gb_repository::Error::SessionLocked(path)) => {
+                        tracing::warn!(
+                            %project_id,
+                            session_id = %session.id,
+                            path = %path.display(),
+                            "session file is locked, postponing flush"
+                        );
+                        return Ok(Flushed::Skipped(events::SkipReason::SessionLocked {
+                            path: path.clone(),
+                        }));
+                    }
+                    // capturing now could store a partially written file
+                    Some(gb_repository::Error::FileNotSettled(path)) => {
+                        tracing::debug!(
+                            %project_id,
+                            session_id = %session.id,
+                            path = %path.display(),
+                            "file is still being written, postponing flush"
+                        );
+                        return Ok(Flushed::Skipped(events::SkipReason::FileSettling {
+                            path: path
+                                .strip_prefix(&project.path)
+                                .unwrap_or(path.as_path())
+                                .into(),
+                        }));
+                    }
+                    _ => {}
+                }
+                return Err(error).context(format!("failed to flush session {}", session.id));
+            }
+        };
+
+        Ok(Flushed::Committed(session, gb_repo.take_secret_files()))
+    }
+
+    fn record_capture_stats(
+        &self,
+        project_id: &ProjectId,
+        capture_stats: gb_repository::CaptureStats,
+    ) {
+        let Some(hit_ratio) = capture_stats.hit_ratio() else {
+            return;
+        };
+        tracing::debug!(
+            %project_id,
+            hits = capture_stats.fast_path_hits,
+            misses = capture_stats.fast_path_misses,
+            hit_ratio,
+            "index cache use"
+        );
+        self.capture_stats
+            .write()
+            .expect("capture stats lock poisoned")
+            .insert(*project_id, capture_stats);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::{Case, Suite};
+
+    use super::*;
+
+    #[test]
+    fn test_flush_unchanged_session() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            gb_repository,
+            project_repository,
+            project,
+            ..
+        } = suite.new_case();
+        let inner = HandlerInner::new(
+            suite.local_app_data.clone(),
+            suite.projects.clone(),
+            suite.users.clone(),
+        );
+
+        let session = gb_repository.get_or_create_current_session()?;
+        assert!(matches!(
+            inner.flush(&project.id, &session, vec![])?,
+            Flushed::Committed(..)
+        ));
+
+        // nothing changed since the last session, it's committed anyway by default
+        let session = gb_repository.get_or_create_current_session()?;
+        assert!(matches!(
+            inner.flush(&project.id, &session, vec![])?,
+            Flushed::Committed(..)
+        ));
+        assert_eq!(gb_repository.get_sessions_iterator()?.count(), 2);
+
+        project_repository
+            .git_repository
+            .config()?
+            .set_bool("gitbutler.skipUnchangedSessions", true)?;
+        let session = gb_repository.get_or_create_current_session()?;
+        assert!(matches!(
+            inner.flush(&project.id, &session, vec![])?,
+            Flushed::Skipped(events::SkipReason::NoChanges)
+        ));
+        assert!(gb_repository.get_current_session()?.is_none());
+        assert_eq!(gb_repository.get_sessions_iterator()?.count(), 2);
+
+        Ok(())
     }
 }
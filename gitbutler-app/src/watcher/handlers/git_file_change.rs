@@ -79,7 +79,7 @@ impl Handler {
                 )
                 .context("failed to open repository")?;
 
-                let file_path = project.path.join(".git/GB_FLUSH");
+                let file_path = project_repository.git_repository.path().join("GB_FLUSH");
 
                 if file_path.exists() {
                     if let Err(e) = std::fs::remove_file(&file_path) {
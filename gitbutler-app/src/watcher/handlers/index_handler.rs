@@ -77,7 +77,11 @@ impl Handler {
         Ok(())
     }
 
-    pub fn reindex(&self, project_id: &ProjectId) -> Result<Vec<events::Event>> {
+    pub fn reindex(
+        &self,
+        project_id: &ProjectId,
+        progress: Option<sessions::ProgressCallback<'_>>,
+    ) -> Result<Vec<events::Event>> {
         let user = self.users.get_user()?;
         let project = self.projects.get(project_id)?;
         let project_repository =
@@ -89,11 +93,23 @@ impl Handler {
         )
         .context("failed to open repository")?;
 
-        let sessions_iter = gb_repository.get_sessions_iterator()?;
+        // reindexing happens on startup, sessions that failed to commit before are committed
+        // first so that they are indexed too
+        if let Err(error) = gb_repository.flush_pending_sessions(user.as_ref()) {
+            tracing::warn!(%project_id, ?error, "failed to flush pending sessions");
+        }
+
+        let sessions = gb_repository
+            .get_sessions_iterator()?
+            .collect::<Result<Vec<_>>>()?;
+        let mut reporter = sessions::Reporter::new(progress);
+        reporter.add_total(sessions.len());
         let mut events = vec![];
-        for session in sessions_iter {
-            events.extend(self.process_session(&gb_repository, &session?)?);
+        for session in &sessions {
+            events.extend(self.process_session(&gb_repository, session)?);
+            reporter.advance(1, 0);
         }
+        reporter.finish();
         Ok(events)
     }
 
@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    path,
+    sync::{Arc, Mutex, PoisonError},
+    time,
+};
+
+use anyhow::Result;
+
+use crate::{events as app_events, projects::ProjectId};
+
+use super::events;
+
+// same path is reported at most once within this window, unless the kind of change is different
+const DEBOUNCE_WINDOW: time::Duration = time::Duration::from_millis(500);
+
+type LastEmitted = HashMap<(ProjectId, path::PathBuf), (time::SystemTime, events::ChangeKind)>;
+
+#[derive(Clone, Default)]
+pub struct Handler {
+    last_emitted: Arc<Mutex<LastEmitted>>,
+}
+
+impl Handler {
+    pub fn handle(
+        &self,
+        project_id: &ProjectId,
+        path: &path::Path,
+        kind: events::ChangeKind,
+        now: time::SystemTime,
+    ) -> Result<Vec<events::Event>> {
+        let mut events = vec![
+            events::Event::CalculateDeltas(*project_id, path.to_path_buf()),
+            events::Event::CalculateVirtualBranches(*project_id),
+        ];
+
+        if self.should_emit(project_id, path, kind, now) {
+            events.push(events::Event::Emit(app_events::Event::file_changed(
+                project_id,
+                path,
+                kind.as_str(),
+            )));
+        }
+
+        Ok(events)
+    }
+
+    fn should_emit(
+        &self,
+        project_id: &ProjectId,
+        path: &path::Path,
+        kind: events::ChangeKind,
+        now: time::SystemTime,
+    ) -> bool {
+        // the map is only ever updated entry by entry, so it's still usable after a panic
+        let mut last_emitted = self
+            .last_emitted
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        // forget about paths that are out of the window, so that the map doesn't grow forever
+        last_emitted.retain(|_, (timestamp, _)| is_within_window(*timestamp, now));
+
+        let key = (*project_id, path.to_path_buf());
+        if let Some((_, last_kind)) = last_emitted.get(&key) {
+            if *last_kind == kind {
+                return false;
+            }
+        }
+        last_emitted.insert(key, (now, kind));
+        true
+    }
+}
+
+fn is_within_window(timestamp: time::SystemTime, now: time::SystemTime) -> bool {
+    now.duration_since(timestamp)
+        .map_or(true, |elapsed| elapsed < DEBOUNCE_WINDOW)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitted(events: &[events::Event]) -> usize {
+        events
+            .iter()
+            .filter(|event| matches!(event, events::Event::Emit(_)))
+            .count()
+    }
+
+    #[test]
+    fn test_debounce() -> Result<()> {
+        let handler = Handler::default();
+        let project_id = ProjectId::generate();
+        let path = path::Path::new("file.txt");
+        let now = time::SystemTime::now();
+
+        let modified = events::ChangeKind::Modified;
+        assert_eq!(
+            emitted(&handler.handle(&project_id, path, modified, now)?),
+            1
+        );
+
+        // same change within the window is not emitted again
+        let soon = now + time::Duration::from_millis(100);
+        assert_eq!(
+            emitted(&handler.handle(&project_id, path, modified, soon)?),
+            0
+        );
+
+        // but deltas are still calculated
+        assert_eq!(handler.handle(&project_id, path, modified, soon)?.len(), 2);
+
+        // different paths are debounced separately
+        let other_path = path::Path::new("other.txt");
+        assert_eq!(
+            emitted(&handler.handle(&project_id, other_path, modified, soon)?),
+            1
+        );
+
+        // different kind of change is emitted right away
+        let removed = events::ChangeKind::Removed;
+        assert_eq!(
+            emitted(&handler.handle(&project_id, path, removed, soon)?),
+            1
+        );
+
+        // same change after the window is emitted again
+        let later = now + DEBOUNCE_WINDOW * 2;
+        assert_eq!(
+            emitted(&handler.handle(&project_id, path, removed, later)?),
+            1
+        );
+
+        Ok(())
+    }
+}
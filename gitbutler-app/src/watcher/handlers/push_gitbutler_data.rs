@@ -44,7 +44,9 @@ impl Handler {
     pub fn handle(&self, project_id: &ProjectId) -> Result<Vec<events::Event>> {
         match self.inner.try_lock() {
             Ok(inner) => inner.handle(project_id),
-            Err(TryLockError::Poisoned(_)) => Err(anyhow::anyhow!("mutex poisoned")),
+            // the handler keeps no state between pushes, so a push that panicked doesn't
+            // leave anything behind
+            Err(TryLockError::Poisoned(inner)) => inner.into_inner().handle(project_id),
             Err(TryLockError::WouldBlock) => Ok(vec![]),
         }
     }
@@ -1,12 +1,19 @@
-use std::{path, time};
+use std::{
+    collections::HashMap,
+    path,
+    sync::{Arc, Mutex, PoisonError},
+    time,
+};
 
 use anyhow::{Context, Result};
+use serde::Serialize;
 use tauri::{AppHandle, Manager};
 
 use crate::{
     gb_repository, project_repository,
     projects::{self, FetchResult, ProjectId},
-    sessions, users,
+    sessions::{self, SessionId},
+    users,
 };
 
 use super::events;
@@ -16,6 +23,9 @@ pub struct Handler {
     local_data_dir: path::PathBuf,
     projects: projects::Controller,
     users: users::Controller,
+    // the git operation a flush was last skipped for, by project. it's reported once, when it
+    // starts, and forgotten once it's done.
+    skipped_operations: Arc<Mutex<HashMap<ProjectId, (SessionId, &'static str)>>>,
 }
 
 impl TryFrom<&AppHandle> for Handler {
@@ -52,6 +62,7 @@ impl Handler {
             local_data_dir,
             projects,
             users,
+            skipped_operations: Arc::default(),
         }
     }
 
@@ -60,22 +71,10 @@ impl Handler {
         project_id: &ProjectId,
         now: &time::SystemTime,
     ) -> Result<Vec<events::Event>> {
-        let user = self.users.get_user()?;
-
         let project = self.projects.get(project_id)?;
-        let project_repository = match project_repository::Repository::open(&project) {
-            Ok(project_repository) => Ok(project_repository),
-            Err(project_repository::OpenError::NotFound(_)) => return Ok(vec![]),
-            Err(error) => Err(error),
-        }
-        .context("failed to open project repository")?;
-
-        let gb_repo = gb_repository::Repository::open(
-            &self.local_data_dir,
-            &project_repository,
-            user.as_ref(),
-        )
-        .context("failed to open repository")?;
+        let Some((project_repository, gb_repo)) = self.open(&project)? else {
+            return Ok(vec![]);
+        };
 
         let mut events = vec![];
 
@@ -103,13 +102,55 @@ impl Handler {
             }
         }
 
-        if let Some(current_session) = gb_repo
-            .get_current_session()
-            .context("failed to get current session")?
-        {
-            if should_flush(now, &current_session)? {
+        match next_flush(now, &project_repository, &gb_repo)? {
+            (FlushStatus::Ready, Some(current_session)) => {
                 events.push(events::Event::Flush(*project_id, current_session));
             }
+            (FlushStatus::WaitingIdle { seconds_remaining }, Some(current_session)) => {
+                tracing::debug!(
+                    %project_id,
+                    session_id = %current_session.id,
+                    seconds_remaining,
+                    "session is active, waiting to flush"
+                );
+            }
+            (FlushStatus::WaitingQuietHours { seconds_remaining }, Some(current_session)) => {
+                tracing::debug!(
+                    %project_id,
+                    session_id = %current_session.id,
+                    seconds_remaining,
+                    "quiet hours, not flushing"
+                );
+            }
+            (FlushStatus::WaitingOperation { operation }, Some(current_session)) => {
+                tracing::debug!(
+                    %project_id,
+                    session_id = %current_session.id,
+                    operation,
+                    "git operation in progress, deferring flush"
+                );
+                let reported = self
+                    .skipped_operations
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(*project_id, (current_session.id, operation));
+                if reported != Some((current_session.id, operation)) {
+                    events.push(events::Event::Skipped(
+                        *project_id,
+                        current_session.id,
+                        events::SkipReason::OperationInProgress {
+                            operation: operation.to_string(),
+                        },
+                    ));
+                }
+            }
+            _ => {}
+        }
+        if operation_in_progress(&project_repository).is_none() {
+            self.skipped_operations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(project_id);
         }
 
         let should_push_code = project_repository.project().is_sync_enabled()
@@ -130,67 +171,298 @@ impl Handler {
 
         Ok(events)
     }
+
+    // reports what the next tick would do with the current session, without flushing it
+    pub fn flush_status(
+        &self,
+        project_id: &ProjectId,
+        now: &time::SystemTime,
+    ) -> Result<FlushStatus> {
+        let project = self.projects.get(project_id)?;
+        let Some((project_repository, gb_repo)) = self.open(&project)? else {
+            return Ok(FlushStatus::WaitingNoSession);
+        };
+        let (status, _) = next_flush(now, &project_repository, &gb_repo)?;
+        Ok(status)
+    }
+
+    // returns None if the project repository is gone
+    fn open(
+        &self,
+        project: &projects::Project,
+    ) -> Result<Option<(project_repository::Repository, gb_repository::Repository)>> {
+        let user = self.users.get_user()?;
+
+        let project_repository = match project_repository::Repository::open(project) {
+            Ok(project_repository) => Ok(project_repository),
+            Err(project_repository::OpenError::NotFound(_)) => return Ok(None),
+            Err(error) => Err(error),
+        }
+        .context("failed to open project repository")?;
+
+        let gb_repo = gb_repository::Repository::open(
+            &self.local_data_dir,
+            &project_repository,
+            user.as_ref(),
+        )
+        .context("failed to open repository")?;
+
+        Ok(Some((project_repository, gb_repo)))
+    }
 }
 
-fn should_flush(now: &time::SystemTime, session: &sessions::Session) -> Result<bool> {
-    Ok(!is_session_active(now, session)? || is_session_too_old(now, session)?)
+// decides what the tick does with the current session, it's flushed when the status is ready
+fn next_flush(
+    now: &time::SystemTime,
+    project_repository: &project_repository::Repository,
+    gb_repo: &gb_repository::Repository,
+) -> Result<(FlushStatus, Option<sessions::Session>)> {
+    let settings = project_repository
+        .settings()
+        .context("failed to read project settings")?;
+    let current_session = gb_repo
+        .get_current_session()
+        .context("failed to get current session")?;
+
+    // nothing is flushed in the background during quiet hours, not even sessions that are too
+    // old. they are flushed on the first tick after.
+    if current_session.is_some() {
+        if let Some(remaining) = settings.quiet_remaining(now) {
+            return Ok((
+                FlushStatus::WaitingQuietHours {
+                    seconds_remaining: seconds_rounded_up(remaining),
+                },
+                current_session,
+            ));
+        }
+    }
+
+    let status = flush_status(now, current_session.as_ref(), settings.idle_timeout)?;
+
+    if let (FlushStatus::Ready, Some(session)) = (status, current_session.as_ref()) {
+        // snapshots of a half-done merge or rebase are not useful, wait for it to finish
+        if let Some(operation) = operation_in_progress(project_repository) {
+            if !is_session_too_old(now, session)? {
+                return Ok((FlushStatus::WaitingOperation { operation }, current_session));
+            }
+        }
+    }
+
+    Ok((status, current_session))
 }
 
-const ONE_HOUR: time::Duration = time::Duration::new(60 * 60, 0);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum FlushStatus {
+    // session is idle or too old, it should be flushed now
+    Ready,
+    // session is still active, it will be flushed when it's idle or too old, whichever comes first
+    WaitingIdle {
+        #[serde(rename = "secondsRemaining")]
+        seconds_remaining: u64,
+    },
+    // session is ready, but a git operation is in progress. it's flushed when the operation is
+    // done, or when the session is too old.
+    WaitingOperation {
+        operation: &'static str,
+    },
+    // it's quiet hours, the session is flushed when they are over
+    WaitingQuietHours {
+        #[serde(rename = "secondsRemaining")]
+        seconds_remaining: u64,
+    },
+    // there is no current session, nothing to flush
+    WaitingNoSession,
+}
+
+pub fn flush_status(
+    now: &time::SystemTime,
+    session: Option<&sessions::Session>,
+    idle_timeout: time::Duration,
+) -> Result<FlushStatus> {
+    let Some(session) = session else {
+        return Ok(FlushStatus::WaitingNoSession);
+    };
+
+    if !is_session_active(now, session, idle_timeout)? || is_session_too_old(now, session)? {
+        return Ok(FlushStatus::Ready);
+    }
+
+    let until_idle = (session_last_update(session)? + idle_timeout)
+        .duration_since(*now)
+        .unwrap_or_default();
+    let until_too_old = (session_start(session)? + ONE_HOUR)
+        .duration_since(*now)
+        .unwrap_or_default();
+    let remaining = until_idle.min(until_too_old);
+
+    Ok(FlushStatus::WaitingIdle {
+        seconds_remaining: seconds_rounded_up(remaining),
+    })
+}
+
+// round up, so that zero is only reported when the session is ready
+fn seconds_rounded_up(duration: time::Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+// returns the name of the git operation that is in progress in the project repository, if any
+fn operation_in_progress(
+    project_repository: &project_repository::Repository,
+) -> Option<&'static str> {
+    match project_repository.git_repository.state() {
+        git2::RepositoryState::Clean => None,
+        git2::RepositoryState::Merge => Some("merge"),
+        git2::RepositoryState::Revert | git2::RepositoryState::RevertSequence => Some("revert"),
+        git2::RepositoryState::CherryPick | git2::RepositoryState::CherryPickSequence => {
+            Some("cherry-pick")
+        }
+        git2::RepositoryState::Bisect => Some("bisect"),
+        git2::RepositoryState::Rebase
+        | git2::RepositoryState::RebaseInteractive
+        | git2::RepositoryState::RebaseMerge => Some("rebase"),
+        git2::RepositoryState::ApplyMailbox | git2::RepositoryState::ApplyMailboxOrRebase => {
+            Some("am")
+        }
+    }
+}
+
+const ONE_HOUR: time::Duration = sessions::MAX_SESSION_AGE;
+
+fn session_start(session: &sessions::Session) -> Result<time::SystemTime> {
+    Ok(time::UNIX_EPOCH + time::Duration::from_millis(session.meta.start_timestamp_ms.try_into()?))
+}
 
 fn is_session_too_old(now: &time::SystemTime, session: &sessions::Session) -> Result<bool> {
-    let session_start =
-        time::UNIX_EPOCH + time::Duration::from_millis(session.meta.start_timestamp_ms.try_into()?);
-    Ok(session_start + ONE_HOUR < *now)
+    session.is_too_old(*now)
 }
 
-const FIVE_MINUTES: time::Duration = time::Duration::new(5 * 60, 0);
+fn session_last_update(session: &sessions::Session) -> Result<time::SystemTime> {
+    Ok(time::UNIX_EPOCH + time::Duration::from_millis(session.meta.last_timestamp_ms.try_into()?))
+}
 
-fn is_session_active(now: &time::SystemTime, session: &sessions::Session) -> Result<bool> {
-    let session_last_update =
-        time::UNIX_EPOCH + time::Duration::from_millis(session.meta.last_timestamp_ms.try_into()?);
-    Ok(session_last_update + FIVE_MINUTES > *now)
+fn is_session_active(
+    now: &time::SystemTime,
+    session: &sessions::Session,
+    idle_timeout: time::Duration,
+) -> Result<bool> {
+    Ok(session_last_update(session)? + idle_timeout > *now)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::sessions::SessionId;
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use crate::{
+        sessions::SessionId,
+        test_utils::{Case, Suite},
+    };
 
     use super::*;
 
     const ONE_MILLISECOND: time::Duration = time::Duration::from_millis(1);
+    const FIVE_MINUTES: time::Duration = time::Duration::new(5 * 60, 0);
+
+    fn waiting(seconds_remaining: u64) -> FlushStatus {
+        FlushStatus::WaitingIdle { seconds_remaining }
+    }
 
     #[test]
-    fn test_should_flush() {
+    fn test_flush_status() {
         let now = time::SystemTime::now();
+        let one_minute = time::Duration::from_secs(60);
         for (start, last, expected) in vec![
-            (now, now, false),                // just created
-            (now - FIVE_MINUTES, now, false), // active
+            (now, now, waiting(5 * 60)),                // just created
+            (now - FIVE_MINUTES, now, waiting(5 * 60)), // active
             (
                 now - FIVE_MINUTES - ONE_MILLISECOND,
                 now - FIVE_MINUTES,
-                true,
+                FlushStatus::Ready,
             ), // almost not active
             (
                 now - FIVE_MINUTES - ONE_MILLISECOND,
                 now - FIVE_MINUTES - ONE_MILLISECOND,
-                true,
+                FlushStatus::Ready,
             ), // not active
-            (now - ONE_HOUR, now, true),      // almost too old
-            (now - ONE_HOUR - ONE_MILLISECOND, now, true), // too old
+            (now - ONE_HOUR, now, FlushStatus::Ready),  // almost too old
+            (now - ONE_HOUR - ONE_MILLISECOND, now, FlushStatus::Ready), // too old
+            (now - ONE_HOUR + one_minute, now, waiting(60)), // too old before idle
+            (now, now - one_minute - ONE_MILLISECOND, waiting(4 * 60)), // rounded up
         ] {
             let session = sessions::Session {
                 id: SessionId::generate(),
                 hash: None,
                 meta: sessions::Meta {
+                    version: sessions::META_VERSION,
                     start_timestamp_ms: start.duration_since(time::UNIX_EPOCH).unwrap().as_millis(),
                     last_timestamp_ms: last.duration_since(time::UNIX_EPOCH).unwrap().as_millis(),
                     branch: None,
                     commit: None,
+                    detached: false,
+                    stashes: vec![],
+                    sparse_checkout: None,
+                    metadata: BTreeMap::new(),
                 },
             };
-            assert_eq!(should_flush(&now, &session).unwrap(), expected);
+            assert_eq!(
+                flush_status(&now, Some(&session), FIVE_MINUTES).unwrap(),
+                expected
+            );
         }
+
+        assert_eq!(
+            flush_status(&now, None, FIVE_MINUTES).unwrap(),
+            FlushStatus::WaitingNoSession
+        );
+    }
+
+    #[test]
+    fn test_flush_status_with_clock() -> Result<()> {
+        let Case {
+            mut gb_repository, ..
+        } = Suite::default().new_case();
+        let clock = gb_repository::MockClock::new(
+            time::UNIX_EPOCH + time::Duration::from_secs(1_700_000_000),
+        );
+        gb_repository.set_clock(Arc::new(clock.clone()));
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let start = clock.now();
+        assert_eq!(session_start(&session)?, start);
+
+        // ready exactly when the session turns idle
+        clock.advance(FIVE_MINUTES - ONE_MILLISECOND);
+        assert_eq!(
+            flush_status(&clock.now(), Some(&session), FIVE_MINUTES)?,
+            waiting(1)
+        );
+        clock.advance(ONE_MILLISECOND);
+        assert_eq!(
+            flush_status(&clock.now(), Some(&session), FIVE_MINUTES)?,
+            FlushStatus::Ready
+        );
+
+        // a session that is kept active is ready exactly when it turns an hour old
+        for _ in 0..13 {
+            gb_repository.mark_active_session()?;
+            clock.advance(time::Duration::from_secs(4 * 60));
+        }
+        gb_repository.mark_active_session()?;
+        let session = gb_repository
+            .get_current_session()?
+            .context("no current session")?;
+        clock.set(start + ONE_HOUR - ONE_MILLISECOND);
+        assert_eq!(
+            flush_status(&clock.now(), Some(&session), FIVE_MINUTES)?,
+            waiting(1)
+        );
+        clock.set(start + ONE_HOUR);
+        assert_eq!(
+            flush_status(&clock.now(), Some(&session), FIVE_MINUTES)?,
+            FlushStatus::Ready
+        );
+
+        Ok(())
     }
 }
 
@@ -230,11 +502,7 @@ mod test_handler {
             })
             .await?;
 
-        let listener = Handler {
-            local_data_dir: suite.local_app_data,
-            projects: suite.projects,
-            users: suite.users,
-        };
+        let listener = Handler::new(suite.local_app_data, suite.projects, suite.users);
 
         let result = listener.handle(&project.id, &SystemTime::now()).unwrap();
 
@@ -245,16 +513,236 @@ mod test_handler {
         Ok(())
     }
 
+    #[test]
+    fn test_operation_in_progress() {
+        let suite = Suite::default();
+        let Case {
+            project_repository, ..
+        } = suite.new_case();
+
+        assert_eq!(operation_in_progress(&project_repository), None);
+
+        let head = project_repository.get_head().unwrap().target().unwrap();
+        std::fs::write(
+            project_repository.path().join(".git/MERGE_HEAD"),
+            format!("{}\n", head),
+        )
+        .unwrap();
+        assert_eq!(operation_in_progress(&project_repository), Some("merge"));
+
+        std::fs::remove_file(project_repository.path().join(".git/MERGE_HEAD")).unwrap();
+        std::fs::write(project_repository.path().join(".git/BISECT_LOG"), "").unwrap();
+        assert_eq!(operation_in_progress(&project_repository), Some("bisect"));
+    }
+
+    #[test]
+    fn test_handler_flush_status() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            project,
+            project_repository,
+            gb_repository,
+            ..
+        } = suite.new_case();
+
+        let listener = Handler::new(
+            suite.local_app_data.clone(),
+            suite.projects.clone(),
+            suite.users.clone(),
+        );
+
+        let now = SystemTime::now();
+        assert_eq!(
+            listener.flush_status(&project.id, &now)?,
+            FlushStatus::WaitingNoSession
+        );
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let last_update = session_last_update(&session)?;
+        assert_eq!(
+            listener.flush_status(&project.id, &last_update)?,
+            FlushStatus::WaitingIdle {
+                seconds_remaining: 5 * 60
+            }
+        );
+
+        let idle = last_update + time::Duration::from_secs(10 * 60);
+        assert_eq!(
+            listener.flush_status(&project.id, &idle)?,
+            FlushStatus::Ready
+        );
+
+        let head = project_repository.get_head()?.target().unwrap();
+        std::fs::write(
+            project_repository.path().join(".git/MERGE_HEAD"),
+            format!("{}\n", head),
+        )?;
+        assert_eq!(
+            listener.flush_status(&project.id, &idle)?,
+            FlushStatus::WaitingOperation { operation: "merge" }
+        );
+        let too_old = session_start(&session)? + ONE_HOUR + time::Duration::from_secs(1);
+        assert_eq!(
+            listener.flush_status(&project.id, &too_old)?,
+            FlushStatus::Ready
+        );
+
+        // nothing was flushed
+        assert_eq!(
+            gb_repository
+                .get_current_session()?
+                .map(|session| session.id),
+            Some(session.id)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_corrupted_session_meta() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            project,
+            gb_repository,
+            ..
+        } = suite.new_case();
+
+        let listener = Handler::new(
+            suite.local_app_data.clone(),
+            suite.projects.clone(),
+            suite.users.clone(),
+        );
+
+        let session = gb_repository.get_or_create_current_session()?;
+        // files truncated by a crash while they were written
+        std::fs::write(gb_repository.session_path().join("meta/start"), "")?;
+        std::fs::write(gb_repository.session_path().join("meta/last"), "")?;
+
+        let too_old = session_start(&session)? + ONE_HOUR + time::Duration::from_secs(1);
+        let events = listener.handle(&project.id, &too_old)?;
+        assert!(events.iter().any(|event| matches!(
+            event,
+            events::Event::Flush(_, flushed) if flushed.id == session.id
+        )));
+        assert_eq!(
+            gb_repository
+                .get_current_session()?
+                .map(|current| current.id),
+            Some(session.id)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quiet_hours() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            project,
+            project_repository,
+            gb_repository,
+            ..
+        } = suite.new_case();
+
+        let listener = Handler::new(
+            suite.local_app_data.clone(),
+            suite.projects.clone(),
+            suite.users.clone(),
+        );
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let too_old = session_start(&session)? + ONE_HOUR + time::Duration::from_secs(60);
+        let local = chrono::DateTime::<chrono::Local>::from(too_old);
+        let window = format!(
+            "{}-{}",
+            (local - chrono::Duration::hours(1)).format("%H:%M"),
+            (local + chrono::Duration::hours(1)).format("%H:%M")
+        );
+        project_repository.git_repository.config()?.set_multivar(
+            "gitbutler.quietHours",
+            "^$",
+            &window,
+        )?;
+
+        assert!(matches!(
+            listener.flush_status(&project.id, &too_old)?,
+            FlushStatus::WaitingQuietHours { seconds_remaining } if seconds_remaining <= 60 * 60
+        ));
+        let events = listener.handle(&project.id, &too_old)?;
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, events::Event::Flush(_, _))));
+
+        // too old is decided again when the quiet hours are over
+        let after = too_old + time::Duration::from_secs(2 * 60 * 60);
+        assert_eq!(
+            listener.flush_status(&project.id, &after)?,
+            FlushStatus::Ready
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_skipped_during_operation() -> Result<()> {
+        let suite = Suite::default();
+        let Case {
+            project,
+            project_repository,
+            gb_repository,
+            ..
+        } = suite.new_case();
+
+        let listener = Handler::new(
+            suite.local_app_data.clone(),
+            suite.projects.clone(),
+            suite.users.clone(),
+        );
+
+        let session = gb_repository.get_or_create_current_session()?;
+        let idle = session_last_update(&session)? + time::Duration::from_secs(10 * 60);
+        let head = project_repository.get_head()?.target().unwrap();
+        std::fs::write(
+            project_repository.path().join(".git/MERGE_HEAD"),
+            format!("{}\n", head),
+        )?;
+
+        let events = listener.handle(&project.id, &idle)?;
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, events::Event::Flush(_, _))));
+        let skipped = events::Event::Skipped(
+            project.id,
+            session.id,
+            events::SkipReason::OperationInProgress {
+                operation: "merge".to_string(),
+            },
+        );
+        assert!(events.contains(&skipped));
+
+        // it's only reported when the operation starts
+        let events = listener.handle(&project.id, &idle)?;
+        assert!(!events.contains(&skipped));
+
+        // and again for the next one
+        std::fs::remove_file(project_repository.path().join(".git/MERGE_HEAD"))?;
+        listener.handle(&project.id, &idle)?;
+        std::fs::write(
+            project_repository.path().join(".git/MERGE_HEAD"),
+            format!("{}\n", head),
+        )?;
+        let events = listener.handle(&project.id, &idle)?;
+        assert!(events.contains(&skipped));
+
+        Ok(())
+    }
+
     #[test]
     fn test_no_fetch_triggered() {
         let suite = Suite::default();
         let Case { project, .. } = suite.new_case();
 
-        let listener = Handler {
-            local_data_dir: suite.local_app_data,
-            projects: suite.projects,
-            users: suite.users,
-        };
+        let listener = Handler::new(suite.local_app_data, suite.projects, suite.users);
 
         let result = listener.handle(&project.id, &SystemTime::now()).unwrap();
 
@@ -0,0 +1,160 @@
+#[cfg(target_family = "unix")]
+use std::{io, os::unix::fs::MetadataExt};
+use std::{path, time};
+
+use crate::git;
+
+// how long a suspended watcher waits before looking at the workdir again, doubling up to the max
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+const MAX_POLL_INTERVAL: time::Duration = time::Duration::from_secs(30);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Availability {
+    Available,
+    // the workdir is gone with the filesystem it is on, for example an external drive that was
+    // disconnected. it might come back.
+    Unavailable,
+    // the workdir was deleted from a filesystem that is still there
+    Deleted,
+}
+
+// tells an unmounted workdir apart from a deleted one by the device of the filesystem it is on
+pub struct Workdir {
+    path: path::PathBuf,
+    #[cfg(target_family = "unix")]
+    device: Option<u64>,
+}
+
+impl Workdir {
+    pub fn new(path: &path::Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            #[cfg(target_family = "unix")]
+            device: path.metadata().ok().map(|metadata| metadata.dev()),
+        }
+    }
+
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    #[cfg(target_family = "unix")]
+    pub fn availability(&mut self) -> Availability {
+        match self.path.metadata() {
+            Ok(metadata) if self.device.map_or(true, |device| device == metadata.dev()) => {
+                self.device = Some(metadata.dev());
+                Availability::Available
+            }
+            // either the mount point that is left behind on unmount, or the drive was mounted
+            // again under a different device
+            Ok(metadata) => {
+                if git::Repository::open(&self.path).is_ok() {
+                    self.device = Some(metadata.dev());
+                    Availability::Available
+                } else {
+                    Availability::Unavailable
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let parent_device = self
+                    .path
+                    .ancestors()
+                    .skip(1)
+                    .find_map(|ancestor| ancestor.metadata().ok())
+                    .map(|metadata| metadata.dev());
+                match (parent_device, self.device) {
+                    (Some(parent_device), Some(device)) if parent_device == device => {
+                        Availability::Deleted
+                    }
+                    // without a device to compare to, the project can only be told gone
+                    (Some(_), None) => Availability::Deleted,
+                    _ => Availability::Unavailable,
+                }
+            }
+            // stale network mounts and disconnected drives fail with io errors
+            Err(_) => Availability::Unavailable,
+        }
+    }
+
+    // there is no device to compare to, so a workdir that can't be read is never told deleted,
+    // it might come back
+    #[cfg(not(target_family = "unix"))]
+    pub fn availability(&mut self) -> Availability {
+        match self.path.metadata() {
+            Ok(_) => Availability::Available,
+            Err(_) => Availability::Unavailable,
+        }
+    }
+
+    // capture can resume once the workdir is there again and the repository opens cleanly
+    pub fn is_ready(&mut self) -> bool {
+        self.availability() == Availability::Available && git::Repository::open(&self.path).is_ok()
+    }
+}
+
+// intervals between the checks of a suspended watcher
+pub struct Backoff {
+    next: time::Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            next: POLL_INTERVAL,
+        }
+    }
+}
+
+impl Backoff {
+    pub fn interval(&mut self) -> time::Duration {
+        let interval = self.next;
+        self.next = self.next.saturating_mul(2).min(MAX_POLL_INTERVAL);
+        interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_availability() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project");
+        std::fs::create_dir(&path).unwrap();
+
+        let mut workdir = Workdir::new(&path);
+        assert_eq!(workdir.availability(), Availability::Available);
+
+        std::fs::remove_dir(&path).unwrap();
+        assert_eq!(workdir.availability(), Availability::Deleted);
+
+        // the project was on a filesystem that is not there anymore
+        let mut workdir = Workdir {
+            path: path.clone(),
+            device: workdir.device.map(|device| device.wrapping_add(1)),
+        };
+        assert_eq!(workdir.availability(), Availability::Unavailable);
+
+        // the mount point is left behind, it is not the project
+        std::fs::create_dir(&path).unwrap();
+        assert_eq!(workdir.availability(), Availability::Unavailable);
+        assert!(!workdir.is_ready());
+
+        git2::Repository::init(&path).unwrap();
+        assert!(workdir.is_ready());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::default();
+        assert_eq!(
+            (0..7).map(|_| backoff.interval()).collect::<Vec<_>>(),
+            [1, 2, 4, 8, 16, 30, 30]
+                .into_iter()
+                .map(time::Duration::from_secs)
+                .collect::<Vec<_>>()
+        );
+    }
+}